@@ -157,6 +157,10 @@ pub mod cmd {
   pub const INDEX_VAR_NAME: [u8; 4] = *b"VarN";
   /// `"HypN"` is the magic number for the hypothesis name table.
   pub const INDEX_HYP_NAME: [u8; 4] = *b"HypN";
+  /// `"DocC"` is the magic number for the doc comment table.
+  pub const INDEX_DOC_COMMENT: [u8; 4] = *b"DocC";
+  /// `"Span"` is the magic number for the source span table.
+  pub const INDEX_SPAN: [u8; 4] = *b"Span";
 }
 
 #[inline]
@@ -290,7 +294,7 @@ impl TryFrom<u8> for StmtCmd {
 /// * `S: Stack<StackEl>`: The main stack, which most operations push and pop from.
 /// * `HS: Vec<Expr>`: The hypothesis list, which grows only on [`Hyp`](ProofCmd::Hyp)
 ///   operations and collects the hypotheses of the theorem.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProofCmd {
   /// ```text
   /// Term t: H; S, e1, ..., en --> H; S, (t e1 .. en)
@@ -655,3 +659,20 @@ pub struct NameEntry {
   /// A pointer to the entity's name as a UTF-8 C string.
   pub p_name: U64<LE>,
 }
+
+/// An individual source span entry in the index, giving the location of the declaration
+/// in the source file it was compiled from.
+#[repr(C, align(8))]
+#[derive(Debug, Clone, Copy, FromBytes, IntoBytes, Immutable)]
+pub struct SpanEntry {
+  /// A pointer to the path of the source file as a UTF-8 C string, or 0 if not known.
+  pub p_file: U64<LE>,
+  /// The byte range of the name of the declaration in the source file.
+  pub span: [U32<LE>; 2],
+  /// The byte range of the whole declaration in the source file.
+  pub full: [U32<LE>; 2],
+  /// The (zero-based) line and column of the start of the name.
+  pub start: [U32<LE>; 2],
+  /// The (zero-based) line and column of the end of the name.
+  pub end: [U32<LE>; 2],
+}
@@ -1,7 +1,7 @@
 //! Parser for MMB binary proof files.
 use crate::{
-  Arg, Header, NameEntry, NumdStmtCmd, ProofCmd, SortData, StmtCmd, TableEntry, TermEntry,
-  ThmEntry, UnifyCmd, cmd, cstr_from_bytes_prefix, exhausted, u32_as_usize, u64_as_usize,
+  Arg, Header, NameEntry, NumdStmtCmd, ProofCmd, SortData, SpanEntry, StmtCmd, TableEntry,
+  TermEntry, ThmEntry, UnifyCmd, cmd, cstr_from_bytes_prefix, exhausted, u32_as_usize, u64_as_usize,
 };
 use mm0_util::{SortId, TermId, ThmId};
 use std::borrow::Cow;
@@ -32,6 +32,8 @@ pub struct MmbFile<'a, X = BasicIndex<'a>> {
 pub type BasicMmbFile<'a> = MmbFile<'a, BasicIndex<'a>>;
 /// An MMB file parser with no index parser.
 pub type BareMmbFile<'a> = MmbFile<'a, ()>;
+/// An MMB file parser with the full debugging index, including doc comments and source spans.
+pub type DebugMmbFile<'a> = MmbFile<'a, DebugIndex<'a>>;
 
 /// A trait for populating the `data` field on the index `X` of an [`MmbFile`] given a table entry.
 pub trait MmbIndexBuilder<'a>: Default {
@@ -150,6 +152,8 @@ make_index_trait! {
 }
 impl NoSymbolNames for Option<VarNames<'_>> {}
 impl NoSymbolNames for Option<HypNames<'_>> {}
+impl NoSymbolNames for Option<DocComments<'_>> {}
+impl NoSymbolNames for Option<SourceSpans<'_>> {}
 
 /// This index subcomponent supplies variable names for terms and theorems.
 #[derive(Debug)]
@@ -183,6 +187,8 @@ make_index_trait! {
 }
 impl NoVarNames for Option<SymbolNames<'_>> {}
 impl NoVarNames for Option<HypNames<'_>> {}
+impl NoVarNames for Option<DocComments<'_>> {}
+impl NoVarNames for Option<SourceSpans<'_>> {}
 
 /// This index subcomponent supplies hypothesis names for theorems.
 #[derive(Debug)]
@@ -212,10 +218,93 @@ make_index_trait! {
 }
 impl NoHypNames for Option<SymbolNames<'_>> {}
 impl NoHypNames for Option<VarNames<'_>> {}
+impl NoHypNames for Option<DocComments<'_>> {}
+impl NoHypNames for Option<SourceSpans<'_>> {}
+
+/// This index subcomponent supplies doc comments for sorts, terms, and theorems.
+#[derive(Debug)]
+pub struct DocComments<'a> {
+  /// Pointers to the doc comments for the sorts
+  sorts: &'a [U64<LE>],
+  /// Pointers to the doc comments for the terms
+  terms: &'a [U64<LE>],
+  /// Pointers to the doc comments for the theorems
+  thms: &'a [U64<LE>],
+}
+
+impl<'a> MmbIndexBuilder<'a> for Option<DocComments<'a>> {
+  fn build<X>(&mut self, f: &mut MmbFile<'a, X>, e: &'a TableEntry) -> Result<(), ParseError> {
+    if e.id == cmd::INDEX_DOC_COMMENT {
+      let rest = f.buf.get(u64_as_usize(e.ptr)..).ok_or_else(|| f.bad_index_parse())?;
+      let (sorts, rest) =
+        new_slice_prefix(rest, f.sorts.len()).ok_or_else(|| f.bad_index_parse())?;
+      let (terms, rest) =
+        new_slice_prefix(rest, f.terms.len()).ok_or_else(|| f.bad_index_parse())?;
+      let (thms, _) = new_slice_prefix(rest, f.thms.len()).ok_or_else(|| f.bad_index_parse())?;
+      if self.replace(DocComments { sorts, terms, thms }).is_some() {
+        return Err(ParseError::DuplicateIndexTable {
+          p_index: u64_as_usize(f.header.p_index),
+          id: e.id,
+        })
+      }
+    }
+    Ok(())
+  }
+}
+
+make_index_trait! {
+  [<'a>, DocComments, HasDocComments, NoDocComments, get_doc_comments, get_doc_comments_mut]
+}
+impl NoDocComments for Option<SymbolNames<'_>> {}
+impl NoDocComments for Option<VarNames<'_>> {}
+impl NoDocComments for Option<HypNames<'_>> {}
+impl NoDocComments for Option<SourceSpans<'_>> {}
+
+/// This index subcomponent supplies the source locations of sorts, terms, and theorems.
+#[derive(Debug)]
+pub struct SourceSpans<'a> {
+  /// The source spans for the sorts
+  sorts: &'a [SpanEntry],
+  /// The source spans for the terms
+  terms: &'a [SpanEntry],
+  /// The source spans for the theorems
+  thms: &'a [SpanEntry],
+}
+
+impl<'a> MmbIndexBuilder<'a> for Option<SourceSpans<'a>> {
+  fn build<X>(&mut self, f: &mut MmbFile<'a, X>, e: &'a TableEntry) -> Result<(), ParseError> {
+    if e.id == cmd::INDEX_SPAN {
+      let rest = f.buf.get(u64_as_usize(e.ptr)..).ok_or_else(|| f.bad_index_parse())?;
+      let (sorts, rest) =
+        new_slice_prefix(rest, f.sorts.len()).ok_or_else(|| f.bad_index_parse())?;
+      let (terms, rest) =
+        new_slice_prefix(rest, f.terms.len()).ok_or_else(|| f.bad_index_parse())?;
+      let (thms, _) = new_slice_prefix(rest, f.thms.len()).ok_or_else(|| f.bad_index_parse())?;
+      if self.replace(SourceSpans { sorts, terms, thms }).is_some() {
+        return Err(ParseError::DuplicateIndexTable {
+          p_index: u64_as_usize(f.header.p_index),
+          id: e.id,
+        })
+      }
+    }
+    Ok(())
+  }
+}
+
+make_index_trait! {
+  [<'a>, SourceSpans, HasSourceSpans, NoSourceSpans, get_source_spans, get_source_spans_mut]
+}
+impl NoSourceSpans for Option<SymbolNames<'_>> {}
+impl NoSourceSpans for Option<VarNames<'_>> {}
+impl NoSourceSpans for Option<HypNames<'_>> {}
+impl NoSourceSpans for Option<DocComments<'_>> {}
 
 /// A basic index, usable for getting names of declarations and variables.
 pub type BasicIndex<'a> = (Option<SymbolNames<'a>>, (Option<VarNames<'a>>, Option<HypNames<'a>>));
 
+/// An index with all the debugging data: names, as well as doc comments and source spans.
+pub type DebugIndex<'a> = (BasicIndex<'a>, (Option<DocComments<'a>>, Option<SourceSpans<'a>>));
+
 /// Parse a single command.
 ///
 /// Returns the raw command data (a pair `[(u8, u32)]`)
@@ -1031,6 +1120,118 @@ impl<'a, X: HasHypNames<'a>> MmbFile<'a, X> {
   }
 }
 
+impl<'a, X: HasDocComments<'a>> MmbFile<'a, X> {
+  fn doc(&self, p: U64<LE>) -> Option<&'a str> {
+    if p.get() == 0 { return None }
+    cstr_from_bytes_prefix(self.buf.get(u64_as_usize(p)..)?)?.0.to_str().ok()
+  }
+
+  /// Get the doc comment of a sort, if present.
+  #[must_use]
+  pub fn sort_doc(&self, n: SortId) -> Option<&'a str> {
+    self.doc(*self.index.get_doc_comments()?.sorts.get(usize::from(n.0))?)
+  }
+
+  /// Get the doc comment of a term, if present.
+  #[must_use]
+  pub fn term_doc(&self, n: TermId) -> Option<&'a str> {
+    self.doc(*self.index.get_doc_comments()?.terms.get(u32_as_usize(n.0))?)
+  }
+
+  /// Get the doc comment of a theorem, if present.
+  #[must_use]
+  pub fn thm_doc(&self, n: ThmId) -> Option<&'a str> {
+    self.doc(*self.index.get_doc_comments()?.thms.get(u32_as_usize(n.0))?)
+  }
+
+  /// Convenience function for getting a doc comment without having to destructure
+  /// the [`StmtCmd`] every time.
+  #[must_use]
+  pub fn stmt_doc(&self, stmt: NumdStmtCmd) -> Option<&'a str> {
+    use crate::NumdStmtCmd::{Axiom, Sort, TermDef, Thm};
+    match stmt {
+      Sort { sort_id } => self.sort_doc(sort_id),
+      Axiom { thm_id } | Thm { thm_id, .. } => self.thm_doc(thm_id),
+      TermDef { term_id, .. } => self.term_doc(term_id),
+    }
+  }
+}
+
+/// A handle to a source span entry in the index.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct SpanEntryRef<'a> {
+  /// The full file
+  pub buf: &'a [u8],
+  /// The span entry
+  pub entry: &'a SpanEntry,
+}
+
+impl<'a> SpanEntryRef<'a> {
+  /// The path of the source file, if present.
+  #[must_use]
+  pub fn file(&self) -> Option<&'a str> {
+    if self.entry.p_file.get() == 0 { return None }
+    let s = self.buf.get(u64_as_usize(self.entry.p_file)..)?;
+    cstr_from_bytes_prefix(s)?.0.to_str().ok()
+  }
+
+  /// The byte range of the name of the declaration in the source file.
+  #[must_use]
+  pub fn span(&self) -> Range<usize> {
+    u32_as_usize(self.entry.span[0].get())..u32_as_usize(self.entry.span[1].get())
+  }
+
+  /// The byte range of the whole declaration in the source file.
+  #[must_use]
+  pub fn full(&self) -> Range<usize> {
+    u32_as_usize(self.entry.full[0].get())..u32_as_usize(self.entry.full[1].get())
+  }
+
+  /// The (zero-based) line and column of the start and end of the name.
+  #[must_use]
+  pub fn line_col(&self) -> ((u32, u32), (u32, u32)) {
+    let [l1, c1] = self.entry.start;
+    let [l2, c2] = self.entry.end;
+    ((l1.get(), c1.get()), (l2.get(), c2.get()))
+  }
+}
+
+impl<'a, X: HasSourceSpans<'a>> MmbFile<'a, X> {
+  /// Get the source span of a sort, if present.
+  #[must_use]
+  pub fn sort_span(&self, n: SortId) -> Option<SpanEntryRef<'a>> {
+    let entry = self.index.get_source_spans()?.sorts.get(usize::from(n.0))?;
+    Some(SpanEntryRef { buf: self.buf, entry })
+  }
+
+  /// Get the source span of a term, if present.
+  #[must_use]
+  pub fn term_span(&self, n: TermId) -> Option<SpanEntryRef<'a>> {
+    let entry = self.index.get_source_spans()?.terms.get(u32_as_usize(n.0))?;
+    Some(SpanEntryRef { buf: self.buf, entry })
+  }
+
+  /// Get the source span of a theorem, if present.
+  #[must_use]
+  pub fn thm_span(&self, n: ThmId) -> Option<SpanEntryRef<'a>> {
+    let entry = self.index.get_source_spans()?.thms.get(u32_as_usize(n.0))?;
+    Some(SpanEntryRef { buf: self.buf, entry })
+  }
+
+  /// Convenience function for getting a source span without having to destructure
+  /// the [`StmtCmd`] every time.
+  #[must_use]
+  pub fn stmt_span(&self, stmt: NumdStmtCmd) -> Option<SpanEntryRef<'a>> {
+    use crate::NumdStmtCmd::{Axiom, Sort, TermDef, Thm};
+    match stmt {
+      Sort { sort_id } => self.sort_span(sort_id),
+      Axiom { thm_id } | Thm { thm_id, .. } => self.thm_span(thm_id),
+      TermDef { term_id, .. } => self.term_span(term_id),
+    }
+  }
+}
+
 impl<'a> TermRef<'a> {
   /// Returns true if this is a `def`, false for a `term`.
   #[inline]
@@ -0,0 +1,114 @@
+use mm0_util::SortId;
+use mm0b_parser::{cmd, DebugMmbFile, NumdStmtCmd, ParseError, SpanEntry, TableEntry};
+use zerocopy::{IntoBytes, LE, U32, U64};
+
+fn u32s<const N: usize>(xs: [u32; N]) -> [U32<LE>; N] { xs.map(U32::new) }
+
+fn span_entry(p_file: u64, span: [u32; 2], full: [u32; 2], start: [u32; 2], end: [u32; 2]) -> SpanEntry {
+  SpanEntry {
+    p_file: U64::new(p_file),
+    span: u32s(span),
+    full: u32s(full),
+    start: u32s(start),
+    end: u32s(end),
+  }
+}
+
+/// Writes the contents of an index table, given the positions of the strings
+/// `"A sort"` and `"foo.mm1"`.
+type Table = fn(u64, u64) -> Vec<u8>;
+
+/// Build a file with two sorts and no terms or theorems, with the given index tables.
+fn build(tables: &[([u8; 4], Table)]) -> Vec<u8> {
+  const P_PROOF: u32 = 48;
+  const P_DOC: u64 = 56;
+  const P_FILE: u64 = 63;
+  let mut buf = vec![];
+  buf.extend_from_slice(&cmd::MM0B_MAGIC);
+  buf.extend_from_slice(&[cmd::MM0B_VERSION, 2, 0, 0]);
+  buf.extend_from_slice(&0_u32.to_le_bytes()); // num_terms
+  buf.extend_from_slice(&0_u32.to_le_bytes()); // num_thms
+  buf.extend_from_slice(&P_PROOF.to_le_bytes()); // p_terms
+  buf.extend_from_slice(&P_PROOF.to_le_bytes()); // p_thms
+  buf.extend_from_slice(&P_PROOF.to_le_bytes()); // p_proof
+  buf.extend_from_slice(&[0; 4]);
+  let p_index_fixup = buf.len();
+  buf.extend_from_slice(&[0; 8]);
+  buf.extend_from_slice(&[0, 0]); // sorts
+  buf.resize(P_PROOF as usize, 0);
+  buf.resize(P_DOC as usize, 0); // empty proof stream
+  buf.extend_from_slice(b"A sort\0");
+  assert_eq!(buf.len() as u64, P_FILE);
+  buf.extend_from_slice(b"foo.mm1\0");
+  let mut entries = vec![];
+  for &(id, f) in tables {
+    buf.resize(buf.len().next_multiple_of(8), 0);
+    let ptr = buf.len() as u64;
+    buf.extend_from_slice(&f(P_DOC, P_FILE));
+    entries.push(TableEntry { id, data: U32::new(0), ptr: U64::new(ptr) });
+  }
+  buf.resize(buf.len().next_multiple_of(8), 0);
+  let p_index = buf.len() as u64;
+  buf[p_index_fixup..p_index_fixup + 8].copy_from_slice(&p_index.to_le_bytes());
+  buf.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+  buf.extend_from_slice(entries.as_bytes());
+  buf
+}
+
+fn doc_table(p_doc: u64, _: u64) -> Vec<u8> { [p_doc, 0].map(U64::<LE>::new).as_bytes().to_vec() }
+
+fn span_table(_: u64, p_file: u64) -> Vec<u8> {
+  [
+    span_entry(p_file, [5, 8], [0, 9], [1, 5], [1, 8]),
+    span_entry(0, [0; 2], [0; 2], [0; 2], [0; 2]),
+  ].as_bytes().to_vec()
+}
+
+const SORT0: NumdStmtCmd = NumdStmtCmd::Sort { sort_id: SortId(0) };
+const SORT1: NumdStmtCmd = NumdStmtCmd::Sort { sort_id: SortId(1) };
+
+#[test]
+fn doc_comments_and_spans() {
+  let buf = build(&[(cmd::INDEX_DOC_COMMENT, doc_table), (cmd::INDEX_SPAN, span_table)]);
+  let file = DebugMmbFile::parse(&buf).unwrap();
+  assert_eq!(file.stmt_doc(SORT0), Some("A sort"));
+  assert_eq!(file.stmt_doc(SORT1), None);
+  let e = file.stmt_span(SORT0).unwrap();
+  assert_eq!(e.file(), Some("foo.mm1"));
+  assert_eq!(e.span(), 5..8);
+  assert_eq!(e.full(), 0..9);
+  assert_eq!(e.line_col(), ((1, 5), (1, 8)));
+  let e = file.stmt_span(SORT1).unwrap();
+  assert_eq!(e.file(), None);
+  assert_eq!(e.span(), 0..0);
+  assert!(file.sort_span(SortId(2)).is_none());
+}
+
+#[test]
+fn missing_index_tables() {
+  let buf = build(&[(cmd::INDEX_SPAN, span_table)]);
+  let file = DebugMmbFile::parse(&buf).unwrap();
+  assert_eq!(file.stmt_doc(SORT0), None);
+  assert_eq!(file.stmt_span(SORT0).unwrap().file(), Some("foo.mm1"));
+  let buf = build(&[(cmd::INDEX_DOC_COMMENT, doc_table)]);
+  let file = DebugMmbFile::parse(&buf).unwrap();
+  assert_eq!(file.stmt_doc(SORT0), Some("A sort"));
+  assert!(file.stmt_span(SORT0).is_none());
+}
+
+#[test]
+fn duplicate_index_tables() {
+  let buf = build(&[(cmd::INDEX_SPAN, span_table), (cmd::INDEX_SPAN, span_table)]);
+  let err = DebugMmbFile::parse(&buf).unwrap_err();
+  assert!(matches!(err, ParseError::DuplicateIndexTable { id: cmd::INDEX_SPAN, .. }));
+}
+
+#[test]
+fn truncated_span_table() {
+  let mut buf = build(&[(cmd::INDEX_SPAN, span_table)]);
+  // Point the span table at the end of the file
+  let n = buf.len();
+  buf[n - 8..].copy_from_slice(&(n as u64 - 8).to_le_bytes());
+  let err = DebugMmbFile::parse(&buf).unwrap_err();
+  assert!(matches!(err, ParseError::BadIndexParse { .. }));
+}
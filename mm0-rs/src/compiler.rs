@@ -8,21 +8,24 @@
 //!
 //! [`mm0_rs::server`]: crate::server
 //! [`mm0-c`]: https://github.com/digama0/mm0/tree/master/mm0-c
-use std::sync::{atomic::{AtomicBool, AtomicU8, Ordering}, Arc, LazyLock, Mutex};
+use std::sync::{atomic::{AtomicBool, AtomicU8, Ordering}, Arc, LazyLock, Mutex, OnceLock};
 use std::collections::{HashMap, hash_map::Entry};
 use std::{io, fs};
+use std::path::Path;
 use futures::{FutureExt, future::BoxFuture};
 use futures::channel::oneshot::{Sender as FSender, channel};
 use futures::executor::{ThreadPool, block_on};
 use futures::lock::Mutex as FMutex;
 use annotate_snippets::{Message, Snippet, Level, Renderer};
+use serde_json::{json, Value};
 use typed_arena::Arena;
 #[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
-use mm1_parser::{parse, ErrorLevel, ParseError};
-use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder};
+use mm1_parser::{ast::Ast, parse, ErrorLevel, ParseError};
+use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder, lisp::{profile::Profile, promise::Spawner}};
 use crate::{ArcList, FileRef, FileSpan, FrozenEnv, LinedString, MutexExt, Position, Range, Span};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
+use crate::mm::import::elab as mm_elab;
 use crate::mmb::export::Exporter as MmbExporter;
 
 /// The thread pool (used for running MM1 files in parallel, when possible)
@@ -33,7 +36,11 @@ static POOL: LazyLock<ThreadPool> = LazyLock::new(||
 static VFS: LazyLock<Vfs> = LazyLock::new(|| Vfs(Mutex::new(HashMap::new())));
 
 static QUIET: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+static VERIFY_IMPORTS: AtomicBool = AtomicBool::new(true);
 static MAX_EMITTED_ERROR: AtomicU8 = AtomicU8::new(0);
+/// The profile of lisp evaluation in all files, if `--profile` was passed.
+static PROFILE: OnceLock<Arc<Mutex<Profile>>> = OnceLock::new();
 
 /// The cached [`Environment`](crate::elab::Environment) representing a
 /// completed parse, or an incomplete parse.
@@ -80,7 +87,7 @@ impl FileContents {
     Self::Bin(data.into())
   }
 
-  pub(crate) fn new_bin_from_file(path: &std::path::Path) -> io::Result<Self> {
+  pub(crate) fn new_bin_from_file(path: &Path) -> io::Result<Self> {
     #[cfg(not(target_arch = "wasm32"))] {
       let file = fs::File::open(path)?;
       // Safety: Well, memory mapping files is never totally safe, but we're assuming
@@ -94,6 +101,15 @@ impl FileContents {
     }
   }
 
+  /// Reads the file at `path`, which is memory mapped if it is an `.mmb` file.
+  pub(crate) fn from_file(path: &FileRef) -> io::Result<Self> {
+    if path.has_extension("mmb") {
+      Self::new_bin_from_file(path.path())
+    } else {
+      Ok(Self::new(fs::read_to_string(path.path())?))
+    }
+  }
+
   pub(crate) fn try_ascii(&self) -> Option<&Arc<LinedString>> {
     if let Self::Ascii(e) = self {Some(e)} else {None}
   }
@@ -164,11 +180,7 @@ impl Vfs {
       Entry::Occupied(e) => Ok((e.key().clone(), e.get().clone())),
       Entry::Vacant(e) => {
         let path = e.key().clone();
-        let fc = if path.has_extension("mmb") {
-          FileContents::new_bin_from_file(path.path())?
-        } else {
-          FileContents::new(fs::read_to_string(path.path())?)
-        };
+        let fc = FileContents::from_file(&path)?;
         let val = e.insert(Arc::new(VirtualFile::new(fc))).clone();
         Ok((path, val))
       }
@@ -179,13 +191,65 @@ impl Vfs {
 fn mk_to_range() -> impl FnMut(&FileSpan) -> Option<Range> {
   let mut srcs = HashMap::new();
   move |fsp: &FileSpan| -> Option<Range> {
+    // Spans from imported .mmb files can point to source files that were not elaborated
     srcs.entry(fsp.file.ptr())
-      .or_insert_with(|| VFS.0.ulock()[&fsp.file].text.clone())
-      .try_ascii().map(|f| f.to_range(fsp.span))
+      .or_insert_with(|| VFS.get_or_insert(fsp.file.clone()).ok().map(|(_, f)| f.text.clone()))
+      .as_ref()?.try_ascii().map(|f| f.to_range(fsp.span))
   }
 }
 
+/// Convert a [`Range`] to a JSON object with zero-based `line` and `character` fields.
+fn range_to_json(r: Range) -> Value {
+  json!({
+    "start": {"line": r.start.line, "character": r.start.character},
+    "end": {"line": r.end.line, "character": r.end.character},
+  })
+}
+
+/// Create a JSON diagnostic record for `--message-format=json`.
+///
+/// # Parameters
+///
+/// - `path`: The file that sourced the error
+/// - `file`: The file contents, if it is a text file
+/// - `pos`: The position of the error
+/// - `msg`: The error message
+/// - `level`: The error level
+/// - `related`: The related information records (calculated by [`ElabErrorKind::to_json`])
+fn make_json(path: &FileRef, file: Option<&LinedString>, pos: Span,
+    msg: &str, level: ErrorLevel, related: Vec<Value>) -> Value {
+  json!({
+    "reason": "diagnostic",
+    "level": level.to_string(),
+    "message": msg,
+    "file": path.rel(),
+    "span": {"start": pos.start, "end": pos.end},
+    "range": file.map(|file| range_to_json(file.to_range(pos))),
+    "related": Value::Array(related),
+  })
+}
+
+/// Print a JSON record on its own line.
+fn print_json(v: &Value) { println!("{v}") }
+
 impl ElabErrorKind {
+  /// Convert the related information of an elaboration error to a list of JSON objects,
+  /// each with `file`, `span`, `range` (if available) and `message` fields.
+  ///
+  /// - `to_range`: a function for converting (index-based) spans to (line/col) ranges
+  pub fn to_json(&self, mut to_range: impl FnMut(&FileSpan) -> Option<Range>) -> Vec<Value> {
+    let info = match self {
+      ElabErrorKind::Boxed(_, Some(info)) => &**info,
+      _ => &[],
+    };
+    info.iter().map(|(fs, e)| json!({
+      "file": fs.file.rel(),
+      "span": {"start": fs.span.start, "end": fs.span.end},
+      "range": to_range(fs).map(range_to_json),
+      "message": e.to_string(),
+    })).collect()
+  }
+
   /// Convert the payload of an elaboration error to the footer data
   /// of a [`Snippet`].
   ///
@@ -270,7 +334,7 @@ impl ElabError {
   ///
   /// - `path`: The location of the error
   /// - `f`: The function to pass the constructed snippet
-  fn to_snippet_no_source<T>(&self, path: &FileRef, span: Span,
+  pub(crate) fn to_snippet_no_source<T>(&self, path: &FileRef, span: Span,
       f: impl for<'a> FnOnce(Message<'a>) -> T) -> T {
     let s = if span.end == span.start {
       format!("{path}:{:#x}: {}", span.start, self.kind.msg())
@@ -279,6 +343,37 @@ impl ElabError {
     };
     f(self.level.to_annotation_type().title(&s))
   }
+
+  /// Create a JSON diagnostic record from this error, for `--message-format=json`.
+  ///
+  /// # Parameters
+  ///
+  /// - `path`: The file that sourced the error
+  /// - `file`: The file contents, or `None` for binary files
+  /// - `to_range`: a function for converting (index-based) spans to (line/col) ranges
+  fn to_json(&self, path: &FileRef, file: Option<&LinedString>,
+      to_range: impl FnMut(&FileSpan) -> Option<Range>) -> Value {
+    make_json(path, file, self.pos, &self.kind.msg(), self.level, self.kind.to_json(to_range))
+  }
+
+  /// Print this error, as a snippet or as a JSON record depending on `--message-format`.
+  ///
+  /// # Parameters
+  ///
+  /// - `path`: The file that sourced the error
+  /// - `file`: The file contents, or `None` for binary files
+  /// - `to_range`: a function for converting (index-based) spans to (line/col) ranges
+  fn print(&self, path: &FileRef, file: Option<&LinedString>,
+      to_range: impl FnMut(&FileSpan) -> Option<Range>) {
+    fn print(s: Message<'_>) { println!("{}\n", Renderer::styled().render(s)) }
+    if JSON.load(Ordering::Relaxed) {
+      print_json(&self.to_json(path, file, to_range))
+    } else if let Some(file) = file {
+      self.to_snippet(path, file, to_range, print)
+    } else {
+      self.to_snippet_no_source(path, self.pos, print)
+    }
+  }
 }
 
 /// Create a [`Message`] from this error. See [`ElabError::to_snippet`] for information
@@ -288,6 +383,32 @@ fn to_snippet<T>(err: &ParseError, path: &FileRef, file: &LinedString,
   f(make_snippet(path, file, err.pos, &format!("{}", err.msg), err.level, vec![]))
 }
 
+/// Print an error message that is not attached to a source location.
+fn print_message(level: ErrorLevel, msg: &str) {
+  if JSON.load(Ordering::Relaxed) {
+    print_json(&json!({"reason": "diagnostic", "level": level.to_string(), "message": msg}))
+  } else {
+    println!("{}\n", Renderer::styled().render(level.to_annotation_type().title(msg)))
+  }
+}
+
+/// Print a progress message, `reason` being one of `elab` or `elabbed`.
+/// The JSON record has the form `{"reason": "elab", "file": path}`.
+fn log_progress(reason: &str, path: &FileRef) {
+  if JSON.load(Ordering::Relaxed) {
+    #[allow(unused_mut)]
+    let mut v = json!({"reason": reason, "file": path.rel()});
+    #[cfg(feature = "memory")]
+    match crate::get_memory_usage() {
+      0 => {}
+      n => v["memory"] = json!(n),
+    }
+    print_json(&v)
+  } else {
+    log_msg(format!("{reason} {path}"))
+  }
+}
+
 fn log_msg(#[allow(unused_mut)] mut s: String) {
   #[cfg(feature = "memory")]
   match crate::get_memory_usage() {
@@ -334,12 +455,9 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
     }
   }
   let text = file.text.clone();
-  let (cyc, errors, env) = if path.has_extension("mmb") {
-    let (error, env) = mmb_elab(&path, &text);
-    (None, if let Err(e) = error {vec![e]} else {vec![]}, FrozenEnv::new(env))
-  } else if path.has_extension("mmu") {
-    let (error, env) = mmu_elab(&path, &text);
-    (None, if let Err(e) = error {vec![e]} else {vec![]}, FrozenEnv::new(env))
+  let (cyc, errors, env) = if let Some((errors, env)) =
+    elab_proof_file(&path, &text, VERIFY_IMPORTS.load(Ordering::Relaxed)) {
+    (None, errors, env)
   } else {
     let (_, ast) = parse(text.ascii().clone(), None);
     if !ast.errors.is_empty() {
@@ -347,23 +465,22 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
       let r = Renderer::styled();
       for e in &ast.errors {
         level = level.max(e.level as u8);
-        to_snippet(e, &path, &ast.source, |s| println!("{}", r.render(s)))
+        if JSON.load(Ordering::Relaxed) {
+          print_json(&make_json(&path, Some(&ast.source), e.pos, &e.msg.to_string(), e.level, vec![]))
+        } else {
+          to_snippet(e, &path, &ast.source, |s| println!("{}", r.render(s)))
+        }
       }
       MAX_EMITTED_ERROR.fetch_max(level, Ordering::Relaxed);
     }
     let ast = Arc::new(ast);
-    if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elab {path}")) }
+    if !QUIET.load(Ordering::Relaxed) { log_progress("elab", &path) }
     let rd = rd.push(path.clone());
     let fut =
       ElaborateBuilder {
-        ast: &ast,
-        path: path.clone(),
-        mm0_mode: path.has_extension("mm0"),
-        options: crate::get_options(),
-        report_upstream_errors: false,
-        cancel: Arc::default(),
-        old: None,
-        recv_dep: |p| {
+        profile: PROFILE.get().cloned(),
+        spawner: Some(Spawner::new(|job| POOL.spawn_ok(async move { job() }))),
+        ..elab_builder(&ast, path.clone(), |p| {
           let p = VFS.get_or_insert(p)?.0;
           let (send, recv) = channel();
           if rd.contains(&p) {
@@ -372,27 +489,19 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
             POOL.spawn_ok(elaborate_and_send(p, send, rd.clone()));
           }
           Ok(recv)
-        },
-        recv_goal: None,
+        })
       }.elab();
     let (cyc, _, errors, env) = fut.await;
     (cyc, errors, env)
   };
-  if !QUIET.load(Ordering::Relaxed) { log_msg(format!("elabbed {path}")) }
+  if !QUIET.load(Ordering::Relaxed) { log_progress("elabbed", &path) }
   let errors: Option<Arc<[_]>> = if errors.is_empty() { None } else {
-    fn print(s: Message<'_>) { println!("{}\n", Renderer::styled().render(s)) }
     let mut to_range = mk_to_range();
     let mut level = 0;
-    if let FileContents::Ascii(text) = &file.text {
-      for e in &errors {
-        level = level.max(e.level as u8);
-        e.to_snippet(&path, text, &mut to_range, print)
-      }
-    } else {
-      for e in &errors {
-        level = level.max(e.level as u8);
-        e.to_snippet_no_source(&path, e.pos, print)
-      }
+    let text = file.text.try_ascii().map(|text| &**text);
+    for e in &errors {
+      level = level.max(e.level as u8);
+      e.print(&path, text, &mut to_range)
     }
     MAX_EMITTED_ERROR.fetch_max(level, Ordering::Relaxed);
     Some(errors.into())
@@ -413,6 +522,43 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
   Ok(res)
 }
 
+/// Elaborate a proof file that is imported rather than parsed as MM1 (`.mmb`, `.mmu` or `.mm`),
+/// returning the errors and the environment, or `None` if `path` is an MM0 or MM1 file.
+/// If `verify` is false, the proofs in an `.mmb` file are trusted.
+pub(crate) fn elab_proof_file(path: &FileRef, text: &[u8], verify: bool
+) -> Option<(Vec<ElabError>, FrozenEnv)> {
+  let (error, env) = if path.has_extension("mmb") {
+    mmb_elab(path, text, verify)
+  } else if path.has_extension("mmu") {
+    mmu_elab(path, text)
+  } else if path.has_extension("mm") {
+    mm_elab(path, text)
+  } else {
+    return None
+  };
+  Some((error.err().into_iter().collect(), FrozenEnv::new(env)))
+}
+
+/// The [`ElaborateBuilder`] for the MM0 or MM1 file `path` with syntax tree `ast`,
+/// which resolves imports using `recv_dep`. Goals, debugging, profiling and `async`
+/// are disabled, and can be enabled by overriding the corresponding fields.
+pub(crate) fn elab_builder<F>(ast: &Arc<Ast>, path: FileRef, recv_dep: F) -> ElaborateBuilder<'_, F> {
+  ElaborateBuilder {
+    ast,
+    mm0_mode: path.has_extension("mm0"),
+    path,
+    options: crate::get_options(),
+    report_upstream_errors: false,
+    cancel: Arc::default(),
+    old: None,
+    recv_dep,
+    recv_goal: None,
+    debugger: None,
+    profile: None,
+    spawner: None,
+  }
+}
+
 /// Elaborate a file, and pass the [`Environment`](crate::elab::Environment)
 /// result to a [`Sender`](FSender).
 ///
@@ -428,6 +574,23 @@ fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<
   }.boxed()
 }
 
+/// Print an error located in the file `path`, loading the file into the [`VFS`] if needed
+/// to display the source.
+pub(crate) fn print_error(path: &FileRef, e: &ElabError) -> io::Result<()> {
+  let file = VFS.get_or_insert(path.clone())?.1;
+  e.print(path, file.text.try_ascii().map(|text| &**text), mk_to_range());
+  MAX_EMITTED_ERROR.fetch_max(e.level as u8, Ordering::Relaxed);
+  Ok(())
+}
+
+/// Set whether progress messages (`elab foo.mm1`) are suppressed.
+pub(crate) fn set_quiet(quiet: bool) { QUIET.store(quiet, Ordering::Relaxed) }
+
+/// Returns true if an error (not just a warning) has been reported so far.
+pub(crate) fn has_errors() -> bool {
+  MAX_EMITTED_ERROR.load(Ordering::Relaxed) >= ErrorLevel::Error as u8
+}
+
 /// Elaborate a file, and return the completed [`FrozenEnv`] result, along with the
 /// file contents.
 pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option<FrozenEnv>)> {
@@ -439,6 +602,22 @@ pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option
   Ok((file.text.clone(), env))
 }
 
+/// The format of diagnostics and progress messages printed by `mm0-rs compile`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+  /// Human-readable messages, with source snippets for errors.
+  #[default]
+  Human,
+  /// One JSON object per line. Each object has a `reason` field, which is one of:
+  ///
+  /// - `diagnostic`: an error, with `level`, `message`, and where available `file`,
+  ///   `span` (byte offsets), `range` (zero-based line/character positions),
+  ///   and `related` (a list of notes with `file`, `span`, `range` and `message`)
+  /// - `elab`, `elabbed`: the start and end of elaboration of `file`
+  /// - `stats`: the number of `sorts`, `terms` and `thms` in the result
+  Json,
+}
+
 /// Compile MM1 files into MMB
 #[allow(clippy::struct_excessive_bools)]
 #[derive(clap::Args, Debug)]
@@ -455,15 +634,31 @@ pub struct Args {
   /// Don't add debugging data to .mmb files
   #[clap(short, long)]
   pub strip: bool,
+  /// Write normal instead of compressed proofs to .mm files
+  #[clap(long)]
+  pub normal_proofs: bool,
   /// Report error code 1 for warnings
   #[clap(short = 'W', long)]
   pub warn_as_error: bool,
+  /// Don't verify the proofs in imported .mmb files
+  #[clap(long)]
+  pub no_verify_imports: bool,
+  /// Output format for diagnostics and progress messages
+  #[clap(long, value_enum, value_name = "FMT", default_value_t)]
+  pub message_format: MessageFormat,
+  /// Profile lisp evaluation, and write the samples to a file in folded stacks format
+  /// (for use with flamegraph tools). A summary of each statement is printed to stderr.
+  #[clap(long, value_name = "FILE")]
+  pub profile: Option<std::ffi::OsString>,
+  /// The number of functions to list for each statement in the profile summary
+  #[clap(long, value_name = "N", default_value_t = 10)]
+  pub profile_top: usize,
   /// Print 'output' commands to a file (use '-' to print to stdout)
   #[clap(short, long = "output", value_name = "FILE")]
   pub output_str: Option<std::ffi::OsString>,
   /// Sets the input file (.mm1 or .mm0)
   pub input: String,
-  /// Sets the output file (.mmb or .mmu)
+  /// Sets the output file (.mmb, .mmu or .mm)
   pub output: Option<String>,
 }
 
@@ -475,41 +670,58 @@ impl Args {
   /// `mm0-rs compile <in.mm1> [out.mmb]`, where:
   ///
   /// - `in.mm1` is the MM1 (or MM0) file to elaborate
-  /// - `out.mmb` (or `out.mmu`) is the MMB file to generate, if the elaboration is
+  /// - `out.mmb` (or `out.mmu`, `out.mm`) is the MMB file to generate, if the elaboration is
   ///   successful. The file extension is used to determine if we are outputting
-  ///   binary. If this argument is omitted, the input is only elaborated.
+  ///   binary, MMU or Metamath. If this argument is omitted, the input is only elaborated.
   pub fn main(self) -> io::Result<()> {
     let path: FileRef = fs::canonicalize(self.input)?.into();
     QUIET.store(self.quiet, Ordering::Relaxed);
+    JSON.store(self.message_format == MessageFormat::Json, Ordering::Relaxed);
+    VERIFY_IMPORTS.store(!self.no_verify_imports, Ordering::Relaxed);
+    if self.profile.is_some() { PROFILE.get_or_init(Default::default); }
     let (file, env) = elab_for_result(path.clone())?;
+    if let (Some(out), Some(profile)) = (self.profile, PROFILE.get()) {
+      let profile = profile.ulock();
+      let mut w = io::BufWriter::new(fs::File::create(out)?);
+      profile.write_folded(&mut w)?;
+      io::Write::flush(&mut w)?;
+      profile.write_top(self.profile_top, io::stderr().lock())?;
+    }
     let env = env.unwrap_or_else(|| std::process::exit(1));
     if let Some(s) = self.output_str {
       if let Err((fsp, e)) =
         if s == "-" { env.run_output(io::stdout()) }
         else { env.run_output(fs::File::create(s)?) }
       {
-        let e = ElabError::new_e(fsp.span, e);
-        let file = VFS.get_or_insert(fsp.file.clone())?.1;
-        e.to_snippet(&fsp.file, file.text.ascii(), &mut mk_to_range(),
-          |s| println!("{}\n", Renderer::styled().render(s)));
+        print_error(&fsp.file, &ElabError::new_e(fsp.span, e))?;
         std::process::exit(1);
       }
     }
     if !self.quiet {
-      println!("{} sorts, {} term/def, {} ax/thm",
-        env.sorts().len(), env.terms().len(), env.thms().len());
+      if self.message_format == MessageFormat::Json {
+        print_json(&json!({"reason": "stats",
+          "sorts": env.sorts().len(), "terms": env.terms().len(), "thms": env.thms().len()}))
+      } else {
+        println!("{} sorts, {} term/def, {} ax/thm",
+          env.sorts().len(), env.terms().len(), env.thms().len());
+      }
     }
     if let Some(out) = self.output {
       use {fs::File, io::BufWriter};
       let w = BufWriter::new(File::create(&out)?);
-      if out.rsplit('.').next().is_some_and(|ext| ext.eq_ignore_ascii_case("mmu")) {
+      let ext = out.rsplit('.').next();
+      if ext.is_some_and(|ext| ext.eq_ignore_ascii_case("mmu")) {
         env.export_mmu(w)?;
+      } else if ext.is_some_and(|ext| ext.eq_ignore_ascii_case("mm")) {
+        env.export_mm(w, !self.normal_proofs)?;
       } else {
         let mut report = |lvl: ErrorLevel, err: &str| {
-          println!("{}\n", Renderer::styled().render(lvl.to_annotation_type().title(err)));
+          print_message(lvl, err);
           MAX_EMITTED_ERROR.fetch_max(lvl as u8, Ordering::Relaxed);
         };
-        let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, w);
+        let mut to_range = mk_to_range();
+        let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, w)
+          .with_ranges(&mut to_range).with_output(Path::new(&out));
         ex.run(!self.strip)?;
         ex.finish()?;
       }
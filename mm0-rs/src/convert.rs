@@ -0,0 +1,248 @@
+//! Conversion between the proof file formats, used by `mm0-rs convert`.
+//!
+//! `mm0-rs convert in.mmu out.mmb` imports an MMU file with [`mmu::import`](crate::mmu::import)
+//! and writes it with the MMB [`Exporter`](crate::mmb::export::Exporter), and
+//! `mm0-rs convert in.mmb out.mmu` goes the other way, using [`mmb::import`](crate::mmb::import)
+//! and [`export_mmu`](crate::FrozenEnv::export_mmu). This makes it possible to store only the
+//! compact MMB file and regenerate the readable MMU file on demand.
+//!
+//! With `--check-roundtrip`, the output file is imported again and compared to the input,
+//! declaration by declaration. The comparison is structural: variables are compared by
+//! position, dummy variables up to renaming, and proofs up to the sharing of subterms and
+//! the placement of reflexivity steps, none of which survive the conversion. Everything else,
+//! including the order of the declarations and the steps of every proof, must agree.
+use std::collections::{HashMap, HashSet};
+use std::{fs, io};
+use std::path::Path;
+use annotate_snippets::Renderer;
+use crate::compiler;
+use crate::mmb::export::Exporter as MmbExporter;
+use crate::{AtomId, ErrorLevel, ExprNode, FileRef, FrozenEnv, ProofNode, TermKind, ThmKind};
+
+/// Convert between MMU and MMB proof files
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Hide diagnostic messages
+  #[clap(short, long)]
+  pub quiet: bool,
+  /// Don't add debugging data to .mmb files
+  #[clap(short, long)]
+  pub strip: bool,
+  /// Import the output again and check that it matches the input
+  #[clap(long)]
+  pub check_roundtrip: bool,
+  /// Sets the input file (.mmu or .mmb)
+  pub input: String,
+  /// Sets the output file (.mmb or .mmu)
+  pub output: String,
+}
+
+/// A structural comparison of two expressions or proofs, with the heap and store
+/// of each side.
+struct Cmp<'a, T> {
+  nargs: usize,
+  heap: (&'a [T], &'a [T]),
+  store: (&'a [T], &'a [T]),
+  /// The pairs of heap elements that are known to be equal.
+  memo: HashSet<(usize, usize)>,
+  /// The renaming of dummy variables, in both directions.
+  dummies: (HashMap<AtomId, AtomId>, HashMap<AtomId, AtomId>),
+}
+
+impl<'a, T> Cmp<'a, T> {
+  fn new(nargs: usize, heap: (&'a [T], &'a [T]), store: (&'a [T], &'a [T])) -> Self {
+    Self { nargs, heap, store, memo: HashSet::new(), dummies: Default::default() }
+  }
+
+  fn dummy(&mut self, a: AtomId, b: AtomId) -> bool {
+    *self.dummies.0.entry(a).or_insert(b) == b && *self.dummies.1.entry(b).or_insert(a) == a
+  }
+
+  /// Compare two heap references, where `f` compares the heap elements.
+  /// The heap element of a variable is a reference to itself, so if only one side is
+  /// a variable then `f` will dereference the other side and try again.
+  fn heap_ref(&mut self, i: usize, j: usize, f: impl FnOnce(&mut Self, &'a T, &'a T) -> bool) -> bool {
+    if i < self.nargs && j < self.nargs { return i == j }
+    if self.memo.contains(&(i, j)) { return true }
+    let ok = f(self, &self.heap.0[i], &self.heap.1[j]);
+    if ok { self.memo.insert((i, j)); }
+    ok
+  }
+}
+
+impl<'a> Cmp<'a, ExprNode> {
+  #[allow(clippy::many_single_char_names)]
+  fn eq(&mut self, a: &'a ExprNode, b: &'a ExprNode, env: &FrozenEnv) -> bool {
+    match (a, b) {
+      (&ExprNode::Ref(i), &ExprNode::Ref(j)) => self.heap_ref(i, j, |this, a, b| this.eq(a, b, env)),
+      (&ExprNode::Ref(i), _) if i >= self.nargs => self.eq(&self.heap.0[i], b, env),
+      (_, &ExprNode::Ref(j)) if j >= self.nargs => self.eq(a, &self.heap.1[j], env),
+      (&ExprNode::Dummy(a, s), &ExprNode::Dummy(b, t)) => s == t && self.dummy(a, b),
+      (&ExprNode::App(t, p), &ExprNode::App(u, q)) => t == u && {
+        let td = env.term(t);
+        let (es1, es2) = (td.unpack_app(&self.store.0[p..]), td.unpack_app(&self.store.1[q..]));
+        es1.iter().zip(es2).all(|(a, b)| self.eq(a, b, env))
+      },
+      _ => false,
+    }
+  }
+}
+
+impl<'a> Cmp<'a, ProofNode> {
+  fn all(&mut self, p: usize, q: usize, n: usize, env: &FrozenEnv) -> bool {
+    let (ps, qs) = (&self.store.0[p..][..n], &self.store.1[q..][..n]);
+    ps.iter().zip(qs).all(|(a, b)| self.eq(a, b, env))
+  }
+
+  #[allow(clippy::many_single_char_names)]
+  fn eq(&mut self, a: &'a ProofNode, b: &'a ProofNode, env: &FrozenEnv) -> bool {
+    match (a, b) {
+      (&ProofNode::Refl(i), _) => self.eq(&self.store.0[i], b, env),
+      (_, &ProofNode::Refl(j)) => self.eq(a, &self.store.1[j], env),
+      (&ProofNode::Ref(i), &ProofNode::Ref(j)) => self.heap_ref(i, j, |this, a, b| this.eq(a, b, env)),
+      (&ProofNode::Ref(i), _) if i >= self.nargs => self.eq(&self.heap.0[i], b, env),
+      (_, &ProofNode::Ref(j)) if j >= self.nargs => self.eq(a, &self.heap.1[j], env),
+      (&ProofNode::Dummy(a, s), &ProofNode::Dummy(b, t)) => s == t && self.dummy(a, b),
+      (&(ProofNode::Term(t, p) | ProofNode::Cong(t, p)),
+       &(ProofNode::Term(u, q) | ProofNode::Cong(u, q))) =>
+        t == u && self.all(p, q, env.term(t).args.len(), env),
+      (&ProofNode::Hyp(i, _), &ProofNode::Hyp(j, _)) => i == j,
+      (&ProofNode::Thm(t, p), &ProofNode::Thm(u, q)) => t == u && {
+        let td = env.thm(t);
+        self.all(p, q, 1 + td.args.len() + td.hyps.len(), env)
+      },
+      (&ProofNode::Conv(p), &ProofNode::Conv(q)) => self.all(p, q, 3, env),
+      (&ProofNode::Sym(p), &ProofNode::Sym(q)) => self.all(p, q, 1, env),
+      (&ProofNode::Unfold(t, p), &ProofNode::Unfold(u, q)) =>
+        t == u && self.all(p, q, 2 + env.term(t).args.len(), env),
+      _ => false,
+    }
+  }
+}
+
+/// Compare the declarations of two environments, returning a description of each difference.
+fn compare(old: &FrozenEnv, new: &FrozenEnv) -> Vec<String> {
+  let mut out = vec![];
+  let counts = |env: &FrozenEnv| (env.sorts().len(), env.terms().len(), env.thms().len());
+  if counts(old) != counts(new) {
+    let ((s1, t1, a1), (s2, t2, a2)) = (counts(old), counts(new));
+    out.push(format!("expected {s1} sorts, {t1} term/def, {a1} ax/thm, got {s2}, {t2}, {a2}"));
+    return out
+  }
+  let name = |env: &FrozenEnv, a: AtomId| env.data()[a].name().to_string();
+  for (sd1, sd2) in old.sorts().iter().zip(new.sorts().iter()) {
+    if sd1.name != sd2.name { out.push(format!("sort {}: renamed to {}", sd1.name, sd2.name)) }
+    else if sd1.mods != sd2.mods { out.push(format!("sort {}: modifiers differ", sd1.name)) }
+  }
+  for (td1, td2) in old.terms().iter().zip(new.terms().iter()) {
+    let (n1, n2) = (name(old, td1.atom), name(new, td2.atom));
+    let what = if n1 != n2 { Some(format!("renamed to {n2}")) }
+    else if td1.vis != td2.vis { Some("visibility differs".into()) }
+    else if td1.args.iter().map(|p| p.1).ne(td2.args.iter().map(|p| p.1)) ||
+      td1.ret != td2.ret { Some("type differs".into()) }
+    else {
+      match (&td1.kind, &td2.kind) {
+        (TermKind::Term, TermKind::Term) | (TermKind::Def(None), TermKind::Def(None)) => None,
+        (TermKind::Def(Some(e1)), TermKind::Def(Some(e2))) => {
+          let mut cmp = Cmp::new(td1.args.len(), (&e1.heap, &e2.heap), (&e1.store, &e2.store));
+          (!cmp.eq(e1.head(), e2.head(), old)).then(|| "value differs".into())
+        }
+        _ => Some("kind differs".into()),
+      }
+    };
+    if let Some(what) = what { out.push(format!("term {n1}: {what}")) }
+  }
+  for (td1, td2) in old.thms().iter().zip(new.thms().iter()) {
+    let (n1, n2) = (name(old, td1.atom), name(new, td2.atom));
+    let what = if n1 != n2 { Some(format!("renamed to {n2}")) }
+    else if td1.vis != td2.vis { Some("visibility differs".into()) }
+    else if td1.args.iter().map(|p| p.1).ne(td2.args.iter().map(|p| p.1)) ||
+      td1.hyps.len() != td2.hyps.len() { Some("binders differ".into()) }
+    else if {
+      let mut cmp = Cmp::new(td1.args.len(), (&td1.heap, &td2.heap), (&td1.store, &td2.store));
+      !td1.hyps.iter().zip(&*td2.hyps).all(|(h1, h2)| cmp.eq(&h1.1, &h2.1, old)) ||
+      !cmp.eq(&td1.ret, &td2.ret, old)
+    } { Some("statement differs".into()) }
+    else {
+      match (&td1.kind, &td2.kind) {
+        (ThmKind::Axiom, ThmKind::Axiom) | (ThmKind::Thm(None), ThmKind::Thm(None)) => None,
+        (ThmKind::Thm(Some(p1)), ThmKind::Thm(Some(p2))) => {
+          let mut cmp = Cmp::new(td1.args.len(), (&p1.heap, &p2.heap), (&p1.store, &p2.store));
+          (!cmp.eq(p1.head(), p2.head(), old)).then(|| "proof differs".into())
+        }
+        _ => Some("kind differs".into()),
+      }
+    };
+    if let Some(what) = what { out.push(format!("theorem {n1}: {what}")) }
+  }
+  out
+}
+
+/// Import a proof file, exiting if it has errors.
+fn load(path: FileRef) -> io::Result<(compiler::FileContents, FrozenEnv)> {
+  match compiler::elab_for_result(path)? {
+    (file, Some(env)) if !compiler::has_errors() => Ok((file, env)),
+    _ => std::process::exit(1),
+  }
+}
+
+/// Returns the extension of a proof file, or an error if it is not `mmu` or `mmb`.
+fn proof_ext(path: &str) -> io::Result<&'static str> {
+  match path.rsplit('.').next() {
+    Some(ext) if ext.eq_ignore_ascii_case("mmu") => Ok("mmu"),
+    Some(ext) if ext.eq_ignore_ascii_case("mmb") => Ok("mmb"),
+    _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+      format!("{path}: expected an .mmu or .mmb file"))),
+  }
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs convert` subcommand.
+  ///
+  /// See the [module documentation](self) for the purpose of this command.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs convert [--check-roundtrip] <in.mmu> <out.mmb>`, where:
+  ///
+  /// - `in.mmu` (or `in.mmb`) is the proof file to convert
+  /// - `out.mmb` (or `out.mmu`) is the file to generate. The file extensions are used
+  ///   to determine the formats.
+  /// - `--check-roundtrip` imports `out.mmb` and checks that it has the same declarations
+  ///   and proofs as `in.mmu`.
+  ///
+  /// The process exits with code 1 if the input has errors or the round trip check fails.
+  pub fn main(self) -> io::Result<()> {
+    compiler::set_quiet(self.quiet);
+    proof_ext(&self.input)?;
+    let out_ext = proof_ext(&self.output)?;
+    let path: FileRef = fs::canonicalize(&self.input)?.into();
+    let (file, env) = load(path.clone())?;
+    {
+      use {fs::File, io::BufWriter};
+      let w = BufWriter::new(File::create(&self.output)?);
+      if out_ext == "mmu" {
+        env.export_mmu(w)?;
+      } else {
+        let mut failed = false;
+        let mut report = |lvl: ErrorLevel, err: &str| {
+          println!("{}\n", Renderer::styled().render(lvl.to_annotation_type().title(err)));
+          failed |= lvl >= ErrorLevel::Error;
+        };
+        let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, w)
+          .with_output(Path::new(&self.output));
+        ex.run(!self.strip)?;
+        ex.finish()?;
+        if failed { std::process::exit(1) }
+      }
+    }
+    if self.check_roundtrip {
+      let (_, new) = load(fs::canonicalize(&self.output)?.into())?;
+      let diffs = compare(&env, &new);
+      for d in &diffs { println!("{d}") }
+      if !diffs.is_empty() { std::process::exit(1) }
+      if !self.quiet { println!("round trip ok") }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,457 @@
+//! A [Debug Adapter Protocol] server for MM1 lisp code, run by `mm0-rs debug`.
+//!
+//! The debugger elaborates one MM1 file, and can stop lisp evaluation in it before any
+//! function application, either at a breakpoint line or while stepping. While stopped, it
+//! reports the lisp call stack (the same frames that appear in lisp backtraces), the local
+//! variables of each frame, and the current proof state as reported by `get-goals`
+//! and `local-ctx`.
+//!
+//! Imported files are elaborated without stopping, but functions defined in them
+//! stop at their breakpoints when they are called from the main file.
+//!
+//! [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, mpsc, atomic::{AtomicBool, AtomicI64, Ordering}};
+use std::{fs, thread};
+use futures::channel::oneshot::channel;
+use futures::executor::block_on;
+use mm1_parser::parse;
+use serde_json::{json, Value};
+use crate::elab::{ElabError, ElabResult, ElaborateBuilder, GoalState, lisp::eval::{DebugFrame, Debugger}};
+use crate::compiler::{elab_builder, elab_proof_file, FileContents};
+use crate::{ArcList, Elaborator, ErrorLevel, FileRef, FileSpan, LinedString, MutexExt,
+  Position, Range, Span};
+
+/// The only thread reported to the client.
+const THREAD_ID: i64 = 1;
+/// The variables reference of the goals scope.
+const GOALS_REF: usize = 1;
+/// The variables reference of the proof context scope.
+const HYPS_REF: usize = 2;
+/// The variables reference of the locals of frame `i` is `LOCALS_REF + i`.
+const LOCALS_REF: usize = 3;
+
+/// Read a message from `r`, returning `None` at the end of the input.
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+  let mut len = None;
+  loop {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 { return Ok(None) }
+    let line = line.trim_end();
+    if line.is_empty() { break }
+    if let Some(n) = line.strip_prefix("Content-Length:") {
+      len = Some(n.trim().parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    }
+  }
+  let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+  let mut buf = vec![0; len];
+  r.read_exact(&mut buf)?;
+  Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// The output half of the connection, shared by the request loop and the elaboration thread.
+#[derive(Debug)]
+struct Output {
+  seq: AtomicI64,
+  out: Mutex<io::Stdout>,
+}
+
+impl Output {
+  fn send(&self, mut msg: Value) {
+    msg["seq"] = self.seq.fetch_add(1, Ordering::Relaxed).into();
+    let msg = msg.to_string();
+    let mut out = self.out.ulock();
+    write!(out, "Content-Length: {}\r\n\r\n{msg}", msg.len())
+      .and_then(|()| out.flush()).expect("failed to write to stdout")
+  }
+
+  fn event(&self, event: &str, body: &Value) {
+    self.send(json!({"type": "event", "event": event, "body": body}))
+  }
+
+  fn respond(&self, req: &Value, res: Result<Value, String>) {
+    let mut msg = json!({
+      "type": "response",
+      "request_seq": req["seq"],
+      "command": req["command"],
+      "success": res.is_ok(),
+    });
+    match res {
+      Ok(Value::Null) => {}
+      Ok(body) => msg["body"] = body,
+      Err(e) => msg["message"] = e.into(),
+    }
+    self.send(msg)
+  }
+}
+
+/// How to proceed after evaluation stops.
+#[derive(Clone, Copy, Debug)]
+enum Resume {
+  /// Run until the next breakpoint.
+  Continue,
+  /// Stop at the next function application.
+  StepIn,
+  /// Stop at the next function application in the same frame or an outer one.
+  Next,
+  /// Stop at the next function application in an outer frame.
+  StepOut,
+}
+
+/// A stack frame, rendered when evaluation stops.
+#[derive(Debug)]
+struct FrameInfo {
+  name: String,
+  file: FileRef,
+  range: Range,
+  /// The names and values of the local variables.
+  locals: Vec<(String, String)>,
+}
+
+/// The state of a stopped evaluation, which is read by `stackTrace`, `scopes`
+/// and `variables` requests.
+#[derive(Debug)]
+struct Snapshot {
+  frames: Vec<FrameInfo>,
+  goals: GoalState,
+}
+
+/// The breakpoints in each file, as byte ranges of lines.
+type Breakpoints = Vec<(FileRef, Vec<(usize, usize)>)>;
+
+/// The state shared by the request loop and the elaboration thread.
+#[derive(Debug)]
+struct Shared {
+  out: Output,
+  /// True if lines and columns are numbered from 1, rather than 0.
+  one_based: (bool, bool),
+  breakpoints: Mutex<Breakpoints>,
+  /// Set by a `pause` request, to stop at the next function application.
+  pause: AtomicBool,
+  /// The current state, if evaluation is stopped.
+  stopped: Mutex<Option<Snapshot>>,
+  /// Set to abandon the elaboration.
+  cancel: Arc<AtomicBool>,
+}
+
+impl Shared {
+  fn to_dap(&self, pos: Position) -> (u32, u32) {
+    (pos.line + u32::from(self.one_based.0), pos.character + u32::from(self.one_based.1))
+  }
+
+  fn source(file: &FileRef) -> Value {
+    json!({"name": file.rel(), "path": file.path()})
+  }
+
+  fn set_breakpoints(&self, args: &Value) -> Result<Value, String> {
+    let path = args["source"]["path"].as_str().ok_or("missing source path")?;
+    let file = FileRef::from(fs::canonicalize(path).map_err(|e| e.to_string())?);
+    let text = LinedString::from(fs::read_to_string(file.path()).map_err(|e| e.to_string())?);
+    let mut lines = vec![];
+    let bps = args["breakpoints"].as_array().map_or(&[][..], |v| v).iter().map(|bp| {
+      let line = bp["line"].as_u64().and_then(|n| u32::try_from(n).ok())
+        .and_then(|n| n.checked_sub(u32::from(self.one_based.0)));
+      let start = line.and_then(|line| text.to_idx(Position {line, character: 0}));
+      if let (Some(line), Some(start)) = (line, start) {
+        let end = text.to_idx(Position {line: line + 1, character: 0}).unwrap_or(text.len());
+        lines.push((start, end));
+      }
+      json!({"verified": start.is_some(), "line": bp["line"]})
+    }).collect::<Vec<_>>();
+    let mut g = self.breakpoints.ulock();
+    g.retain(|(f, _)| *f != file);
+    if !lines.is_empty() { g.push((file, lines)) }
+    Ok(json!({"breakpoints": bps}))
+  }
+
+  fn stack_trace(&self) -> Result<Value, String> {
+    let g = self.stopped.ulock();
+    let snap = g.as_ref().ok_or("not stopped")?;
+    let frames = snap.frames.iter().enumerate().map(|(i, frame)| {
+      let (line, column) = self.to_dap(frame.range.start);
+      let (end_line, end_column) = self.to_dap(frame.range.end);
+      json!({
+        "id": i, "name": frame.name, "source": Self::source(&frame.file),
+        "line": line, "column": column, "endLine": end_line, "endColumn": end_column,
+      })
+    }).collect::<Vec<_>>();
+    Ok(json!({"stackFrames": frames, "totalFrames": snap.frames.len()}))
+  }
+
+  fn scopes(&self, args: &Value) -> Result<Value, String> {
+    let frame = args["frameId"].as_u64().and_then(|n| usize::try_from(n).ok()).ok_or("bad frame id")?;
+    let g = self.stopped.ulock();
+    let snap = g.as_ref().ok_or("not stopped")?;
+    if frame >= snap.frames.len() { return Err("bad frame id".into()) }
+    Ok(json!({"scopes": [
+      {"name": "Locals", "variablesReference": LOCALS_REF + frame,
+        "namedVariables": snap.frames[frame].locals.len(), "expensive": false},
+      {"name": "Goals", "variablesReference": GOALS_REF,
+        "namedVariables": snap.goals.goals.len(), "expensive": false},
+      {"name": "Proof context", "variablesReference": HYPS_REF,
+        "namedVariables": snap.goals.hyps.len(), "expensive": false},
+    ]}))
+  }
+
+  fn variables(&self, args: &Value) -> Result<Value, String> {
+    let r = args["variablesReference"].as_u64().and_then(|n| usize::try_from(n).ok())
+      .ok_or("bad variables reference")?;
+    let g = self.stopped.ulock();
+    let snap = g.as_ref().ok_or("not stopped")?;
+    let var = |name: String, value: &str| json!({"name": name, "value": value, "variablesReference": 0});
+    let vars = match r {
+      GOALS_REF => snap.goals.goals.iter().enumerate()
+        .map(|(i, g)| var(format!("|- {}", i + 1), g)).collect(),
+      HYPS_REF => snap.goals.hyps.iter().map(|(h, e)| var(h.clone(), e)).collect(),
+      _ => snap.frames.get(r.wrapping_sub(LOCALS_REF)).ok_or("bad variables reference")?
+        .locals.iter().map(|(x, e)| var(x.clone(), e)).collect(),
+    };
+    Ok(json!({"variables": Value::Array(vars)}))
+  }
+
+  /// Print an error in `file` to the debug console.
+  fn report(&self, file: &FileRef, text: Option<&LinedString>, pos: Span, level: ErrorLevel, msg: &str) {
+    let loc = match text {
+      Some(text) => {
+        let (line, col) = self.to_dap(text.to_pos(pos.start));
+        format!("{}:{line}:{col}", file.rel())
+      }
+      None => file.rel().to_owned(),
+    };
+    let category = if level == ErrorLevel::Info { "stdout" } else { "stderr" };
+    self.out.event("output", &json!({"category": category, "output": format!("{loc}: {level}: {msg}\n")}))
+  }
+}
+
+/// The [`Debugger`] attached to the elaboration of the main file.
+#[derive(Debug)]
+struct Hook {
+  shared: Arc<Shared>,
+  resume: mpsc::Receiver<Resume>,
+  /// If set, stop at the next function application with at most this many stack frames.
+  step: Option<usize>,
+  /// The reason to give for the next stop caused by `step`.
+  step_reason: &'static str,
+  /// The breakpoint line (file, line start, depth) of the last function application, so that
+  /// a line with several applications on it only stops once.
+  last_line: Option<(FileRef, usize, usize)>,
+  /// The reason and depth of the current stop.
+  stop: (&'static str, usize),
+  /// The contents of files containing stack frames.
+  sources: HashMap<FileRef, Option<LinedString>>,
+}
+
+impl Hook {
+  /// Returns true if there is a breakpoint on the line of `sp`, which has not already been
+  /// stopped at.
+  fn breakpoint(&mut self, file: &FileRef, sp: Span, depth: usize) -> bool {
+    let bps = self.shared.breakpoints.ulock();
+    let Some((_, lines)) = bps.iter().find(|(f, _)| f == file) else { return false };
+    let line = lines.iter().find(|&&(start, end)| start <= sp.start && sp.start < end)
+      .map(|&(start, _)| (file.clone(), start, depth));
+    drop(bps);
+    let hit = line.is_some() && line != self.last_line;
+    self.last_line = line;
+    hit
+  }
+
+  fn range(&mut self, fsp: &FileSpan) -> Range {
+    self.sources.entry(fsp.file.clone())
+      .or_insert_with(|| fs::read_to_string(fsp.file.path()).ok().map(LinedString::from))
+      .as_ref().map_or_else(Range::default, |text| text.to_range(fsp.span))
+  }
+}
+
+impl Debugger for Hook {
+  fn should_stop(&mut self, file: &FileRef, sp: Span, depth: usize) -> bool {
+    let breakpoint = self.breakpoint(file, sp, depth);
+    let reason = if self.shared.pause.swap(false, Ordering::Relaxed) { "pause" }
+      else if self.step.is_some_and(|n| depth <= n) { self.step_reason }
+      else if breakpoint { "breakpoint" }
+      else { return false };
+    self.stop = (reason, depth);
+    true
+  }
+
+  fn stop(&mut self, elab: &Elaborator, frames: Vec<DebugFrame>) {
+    let frames = frames.into_iter().map(|frame| FrameInfo {
+      range: self.range(&frame.span),
+      locals: frame.locals.iter().enumerate().map(|(i, (x, e))| (
+        x.map_or_else(|| format!("x{i}"), |x| elab.data[x].name.to_string()),
+        elab.print(e).to_string()
+      )).collect(),
+      name: frame.name,
+      file: frame.span.file,
+    }).collect();
+    *self.shared.stopped.ulock() = Some(Snapshot {frames, goals: elab.goal_state()});
+    let (reason, depth) = self.stop;
+    self.shared.out.event("stopped",
+      &json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}));
+    // If the client goes away, then finish the elaboration without stopping
+    let resume = self.resume.recv().unwrap_or(Resume::Continue);
+    self.step = match resume {
+      Resume::Continue => None,
+      Resume::StepIn => Some(usize::MAX),
+      Resume::Next => Some(depth),
+      Resume::StepOut => depth.checked_sub(1),
+    };
+    self.step_reason = "step";
+  }
+}
+
+/// Elaborate `path` and its imports, reporting errors to the debug console.
+/// The `debugger` is attached to the elaboration of `path` itself.
+#[allow(clippy::mutable_key_type)]
+fn elaborate(shared: &Shared, cache: &mut HashMap<FileRef, ElabResult<()>>,
+  path: FileRef, debugger: Option<Box<dyn Debugger>>, rd: ArcList<FileRef>,
+) -> ElabResult<()> {
+  if let Some(res) = cache.get(&path) { return res.clone() }
+  let report = |text: Option<&LinedString>, e: &ElabError|
+    shared.report(&path, text, e.pos, e.level, &e.kind.msg());
+  let text = match FileContents::from_file(&path) {
+    Ok(text) => text,
+    Err(e) => {
+      report(None, &ElabError::new_e(Span::default(), e));
+      return ElabResult::Canceled
+    }
+  };
+  let (text, errors, env) = if let Some((errors, env)) = elab_proof_file(&path, &text, true) {
+    (None, errors, env)
+  } else {
+    let text = text.ascii().clone();
+    let ast = Arc::new(parse(text.clone(), None).1);
+    for e in &ast.errors { shared.report(&path, Some(&text), e.pos, e.level, &e.msg.to_string()) }
+    let rd = rd.push(path.clone());
+    let fut = ElaborateBuilder {
+      cancel: shared.cancel.clone(),
+      debugger,
+      ..elab_builder(&ast, path.clone(), |p| {
+        let (send, recv) = channel();
+        drop(send.send(if rd.contains(&p) {
+          ElabResult::ImportCycle(rd.clone())
+        } else {
+          elaborate(shared, cache, p, None, rd.clone())
+        }));
+        Ok(recv)
+      })
+    }.elab();
+    let (cyc, _, errors, env) = block_on(fut);
+    if let Some(cyc) = cyc { return ElabResult::ImportCycle(cyc) }
+    (Some(text), errors, env)
+  };
+  for e in &errors { report(text.as_deref(), e) }
+  let res = ElabResult::Ok((), (!errors.is_empty()).then(|| errors.into()), env);
+  cache.insert(path, res.clone());
+  res
+}
+
+/// Debug MM1 lisp code, using the Debug Adapter Protocol over stdin and stdout
+#[derive(clap::Args, Clone, Copy, Debug)]
+pub struct Args {
+  /// Disable proof checking until (check-proofs #t)
+  #[clap(short, long)]
+  pub no_proofs: bool,
+}
+
+impl Args {
+  /// Main entry point for the `mm0-rs debug` subcommand.
+  ///
+  /// The file to debug is given by the `program` field of the `launch` request, and
+  /// evaluation stops at the first function application if `stopOnEntry` is set.
+  pub fn main(self) -> io::Result<()> {
+    if self.no_proofs { crate::set_check_proofs(false) }
+    let mut input = io::stdin().lock();
+    let out = Output { seq: AtomicI64::new(1), out: Mutex::new(io::stdout()) };
+    let Some(init) = read_message(&mut input)? else { return Ok(()) };
+    if init["command"] != "initialize" {
+      out.respond(&init, Err("expected 'initialize' request".into()));
+      return Ok(())
+    }
+    let one_based = |key| init["arguments"][key].as_bool().unwrap_or(true);
+    let shared = Arc::new(Shared {
+      out,
+      one_based: (one_based("linesStartAt1"), one_based("columnsStartAt1")),
+      breakpoints: Mutex::default(),
+      pause: AtomicBool::new(false),
+      stopped: Mutex::default(),
+      cancel: Arc::default(),
+    });
+    shared.out.respond(&init, Ok(json!({"supportsConfigurationDoneRequest": true})));
+    shared.out.event("initialized", &Value::Null);
+
+    let (send, recv) = mpsc::channel();
+    let mut start = Some(recv);
+    let (mut program, mut stop_on_entry, mut configured) = (None, false, false);
+    while let Some(req) = read_message(&mut input)? {
+      if req["type"] != "request" { continue }
+      let args = &req["arguments"];
+      let resume = |r| {
+        if shared.stopped.ulock().take().is_none() { return Err("not stopped".to_owned()) }
+        let _ = send.send(r);
+        Ok(Value::Null)
+      };
+      let res = match req["command"].as_str().unwrap_or_default() {
+        "launch" => match args["program"].as_str().map(fs::canonicalize) {
+          Some(Ok(path)) => {
+            program = Some(FileRef::from(path));
+            stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+            Ok(Value::Null)
+          }
+          Some(Err(e)) => Err(e.to_string()),
+          None => Err("missing 'program'".into()),
+        },
+        "configurationDone" => { configured = true; Ok(Value::Null) }
+        "setBreakpoints" => shared.set_breakpoints(args),
+        "setExceptionBreakpoints" => Ok(json!({"breakpoints": []})),
+        "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+        "stackTrace" => shared.stack_trace(),
+        "scopes" => shared.scopes(args),
+        "variables" => shared.variables(args),
+        "continue" => resume(Resume::Continue).map(|_| json!({"allThreadsContinued": true})),
+        "next" => resume(Resume::Next),
+        "stepIn" => resume(Resume::StepIn),
+        "stepOut" => resume(Resume::StepOut),
+        "pause" => { shared.pause.store(true, Ordering::Relaxed); Ok(Value::Null) }
+        "disconnect" | "terminate" => {
+          shared.cancel.store(true, Ordering::Relaxed);
+          drop(resume(Resume::Continue));
+          shared.out.respond(&req, Ok(Value::Null));
+          return Ok(())
+        }
+        cmd => Err(format!("unsupported request '{cmd}'")),
+      };
+      shared.out.respond(&req, res);
+      if let (true, Some(path)) = (configured, &program) {
+        if let Some(resume) = start.take() {
+          let hook = Hook {
+            shared: shared.clone(),
+            resume,
+            step: stop_on_entry.then_some(usize::MAX),
+            step_reason: "entry",
+            last_line: None,
+            stop: ("entry", 0),
+            sources: HashMap::new(),
+          };
+          let (shared, path) = (shared.clone(), path.clone());
+          thread::spawn(move || {
+            let res = elaborate(&shared, &mut HashMap::new(), path, Some(Box::new(hook)), ArcList::default());
+            let failed = match res {
+              ElabResult::Ok((), errors, _) =>
+                errors.is_some_and(|es| es.iter().any(|e| e.level == ErrorLevel::Error)),
+              ElabResult::Canceled => true,
+              ElabResult::ImportCycle(_) => {
+                shared.out.event("output", &json!({"category": "stderr", "output": "import cycle\n"}));
+                true
+              }
+            };
+            shared.out.event("exited", &json!({"exitCode": i32::from(failed)}));
+            shared.out.event("terminated", &Value::Null);
+          });
+        }
+      }
+    }
+    Ok(())
+  }
+}
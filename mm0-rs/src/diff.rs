@@ -0,0 +1,436 @@
+//! A structural diff between two proof files, used by `mm0-rs diff`.
+//!
+//! `mm0-rs diff a.mmb b.mmb` aligns the sorts, terms and theorems of the two files by name,
+//! and reports declarations that were added, removed or moved relative to the others of the
+//! same kind. Declarations that appear in both files are compared in two ways:
+//!
+//! * A *statement change* is a change to anything that is visible in a specification:
+//!   sort modifiers, the kind and visibility of a declaration, the binders, the return type
+//!   of a term, the value of a definition, and the hypotheses and conclusion of a theorem.
+//!   Variables are compared by position, so renaming a binder is not a change.
+//! * A *proof change* is a statement that is unchanged, but whose proof stream is different.
+//!   The sorts, terms and theorems referenced by the old proof are renumbered by name to
+//!   their indices in the new file first, so moving a declaration does not change the
+//!   proofs that use it. Proof sizes are reported in bytes of the MMB proof stream.
+//!
+//! Either file may also be an `.mmu` file, in which case it is imported with
+//! [`mmu::import`](crate::mmu::import) and then exported to MMB in memory, so that the
+//! proofs are comparable. Declarations are named using the index of the MMB file, so
+//! files compiled with `--strip` can only be compared with each other.
+//!
+//! The process exits with code 1 if the files differ, like `diff`.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::{fs, io};
+use annotate_snippets::Renderer;
+use mm0b_parser::{Arg, BasicMmbFile, MmbFile, NumdStmtCmd, ProofCmd, UnifyCmd, UnifyIter};
+use crate::compiler::{self, FileContents};
+use crate::mmb::export::Exporter as MmbExporter;
+use crate::{ErrorLevel, FileRef, Modifiers, SortId, TermId, ThmId, u32_as_usize};
+
+/// Show the differences between two MMB or MMU files
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Don't list the changed proofs individually
+  #[clap(long)]
+  pub no_proofs: bool,
+  /// Sets the old file (.mmb or .mmu)
+  pub old: String,
+  /// Sets the new file (.mmb or .mmu)
+  pub new: String,
+}
+
+/// The kind of a declaration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind { Sort, Term, Def, Axiom, Thm }
+
+impl Kind {
+  const fn name(self) -> &'static str {
+    match self {
+      Kind::Sort => "sort",
+      Kind::Term => "term",
+      Kind::Def => "def",
+      Kind::Axiom => "axiom",
+      Kind::Thm => "theorem",
+    }
+  }
+}
+
+/// The parts of a declaration which are compared.
+#[derive(Debug)]
+struct Decl {
+  name: String,
+  kind: Kind,
+  local: bool,
+  /// The sort modifiers, for sorts
+  mods: Modifiers,
+  /// The binders of a term or theorem, with variables named by position
+  args: Vec<String>,
+  /// The return type of a term
+  ret: String,
+  /// The hypotheses of a theorem
+  hyps: Vec<String>,
+  /// The conclusion of a theorem, or the value of a definition
+  concl: String,
+  /// The size of the proof stream in bytes
+  proof: usize,
+  /// The proof stream, or the value of a definition
+  cmds: Vec<ProofCmd>,
+}
+
+impl Decl {
+  fn new(name: String, kind: Kind) -> Self {
+    Self {
+      name, kind, local: false, mods: Modifiers::NONE,
+      args: vec![], ret: String::new(), hyps: vec![], concl: String::new(), proof: 0,
+      cmds: vec![],
+    }
+  }
+
+  /// Returns the list of statement changes between `self` (old) and `new`,
+  /// as a name for the changed part and its old and new values.
+  fn changes(&self, new: &Self) -> Vec<(&'static str, String, String)> {
+    let mut out = vec![];
+    let mut cmp = |part, old: String, new: String| if old != new { out.push((part, old, new)) };
+    cmp("kind", self.kind.name().into(), new.kind.name().into());
+    let vis = |local| if local { "local" } else { "pub" }.to_owned();
+    cmp("visibility", vis(self.local), vis(new.local));
+    cmp("modifiers", self.mods.to_string(), new.mods.to_string());
+    cmp("binders", self.args.join(" "), new.args.join(" "));
+    cmp("return type", self.ret.clone(), new.ret.clone());
+    cmp("hypotheses", self.hyps.join(", "), new.hyps.join(", "));
+    let concl = if new.kind == Kind::Def { "value" } else { "conclusion" };
+    cmp(concl, self.concl.clone(), new.concl.clone());
+    out
+  }
+}
+
+/// The declarations of a file, by kind.
+#[derive(Debug, Default)]
+struct Decls {
+  sorts: Vec<Decl>,
+  terms: Vec<Decl>,
+  thms: Vec<Decl>,
+}
+
+fn bad(msg: String) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg) }
+
+fn binders(file: &BasicMmbFile<'_>, args: &[Arg]) -> Vec<String> {
+  let mut bvs = vec![];
+  args.iter().enumerate().map(|(i, arg)| {
+    let sort = file.sort_name(arg.sort());
+    if arg.bound() {
+      bvs.push(i);
+      format!("{{v{i}: {sort}}}")
+    } else {
+      let mut s = format!("(v{i}: {sort}");
+      deps(&mut s, &bvs, arg.deps_unchecked());
+      s.push(')');
+      s
+    }
+  }).collect()
+}
+
+fn deps(s: &mut String, bvs: &[usize], deps: u64) {
+  for (j, &i) in bvs.iter().enumerate() {
+    if deps & (1 << j) != 0 { write!(s, " v{i}").expect("writing to a string") }
+  }
+}
+
+/// A parser for the expressions in a unify stream, which renders them as s-expressions.
+struct ExprParser<'a> {
+  file: &'a BasicMmbFile<'a>,
+  it: UnifyIter<'a>,
+  heap: Vec<String>,
+  dummies: usize,
+}
+
+impl<'a> ExprParser<'a> {
+  fn new(file: &'a BasicMmbFile<'a>, it: UnifyIter<'a>, nargs: usize) -> Self {
+    Self { file, it, heap: (0..nargs).map(|i| format!("v{i}")).collect(), dummies: 0 }
+  }
+
+  fn expr(&mut self) -> Option<String> {
+    Some(match self.it.next()?.ok()? {
+      UnifyCmd::Term { tid, save } => {
+        let slot = save.then(|| { self.heap.push(String::new()); self.heap.len() - 1 });
+        let n = self.file.term(tid)?.args().len();
+        let mut s = self.file.term_name(tid).into_owned();
+        if n != 0 {
+          s.insert(0, '(');
+          for _ in 0..n { s.push(' '); s += &self.expr()? }
+          s.push(')');
+        }
+        if let Some(i) = slot { self.heap[i].clone_from(&s) }
+        s
+      }
+      UnifyCmd::Ref(i) => self.heap.get(u32_as_usize(i))?.clone(),
+      UnifyCmd::Dummy(sort) => {
+        let name = format!("_{}", self.dummies);
+        self.dummies += 1;
+        let s = format!("{name}:{}", self.file.sort_name(sort));
+        self.heap.push(name);
+        s
+      }
+      UnifyCmd::Hyp => return None,
+    })
+  }
+
+  /// Parse the unify stream of a theorem, returning the hypotheses and the conclusion.
+  fn thm(mut self) -> Option<(Vec<String>, String)> {
+    let concl = self.expr()?;
+    let mut hyps = vec![];
+    while let Some(cmd) = self.it.next() {
+      let UnifyCmd::Hyp = cmd.ok()? else { return None };
+      hyps.push(self.expr()?);
+    }
+    hyps.reverse();
+    Some((hyps, concl))
+  }
+}
+
+impl Decls {
+  fn new(file: &BasicMmbFile<'_>) -> io::Result<Self> {
+    let mut decls = Self::default();
+    for (i, &sd) in file.sorts.iter().enumerate() {
+      let s = SortId(i.try_into().expect("too many sorts"));
+      let mut d = Decl::new(file.sort_name(s).into_owned(), Kind::Sort);
+      d.mods = Modifiers::new(sd.0);
+      decls.sorts.push(d)
+    }
+    for i in 0..file.header.num_terms.get() {
+      let t = TermId(i);
+      let name = file.term_name(t).into_owned();
+      let td = file.term(t).ok_or_else(|| bad(format!("bad term entry for {name}")))?;
+      let mut d = Decl::new(name, if td.def() { Kind::Def } else { Kind::Term });
+      d.args = binders(file, td.args());
+      d.ret = file.sort_name(td.sort()).into_owned();
+      let bvs = td.args().iter().enumerate().filter(|p| p.1.bound()).map(|p| p.0).collect::<Vec<_>>();
+      deps(&mut d.ret, &bvs, td.ret().deps_unchecked());
+      if td.def() {
+        let mut p = ExprParser::new(file, td.unify(), td.args().len());
+        d.concl = p.expr().filter(|_| p.it.next().is_none())
+          .ok_or_else(|| bad(format!("bad unify stream for {}", d.name)))?;
+      }
+      decls.terms.push(d)
+    }
+    for i in 0..file.header.num_thms.get() {
+      let t = ThmId(i);
+      let name = file.thm_name(t).into_owned();
+      let td = file.thm(t).ok_or_else(|| bad(format!("bad theorem entry for {name}")))?;
+      let mut d = Decl::new(name, Kind::Axiom);
+      d.args = binders(file, td.args());
+      (d.hyps, d.concl) = ExprParser::new(file, td.unify(), td.args().len()).thm()
+        .ok_or_else(|| bad(format!("bad unify stream for {}", d.name)))?;
+      decls.thms.push(d)
+    }
+    for e in file.proof() {
+      let (stmt, pf) = e.map_err(|e| bad(e.to_string()))?;
+      let d = match stmt {
+        NumdStmtCmd::Sort { .. } => continue,
+        NumdStmtCmd::TermDef { term_id, .. } => decls.terms.get_mut(u32_as_usize(term_id.0)),
+        NumdStmtCmd::Axiom { thm_id } | NumdStmtCmd::Thm { thm_id, .. } =>
+          decls.thms.get_mut(u32_as_usize(thm_id.0)),
+      };
+      let d = d.ok_or_else(|| bad(format!("bad statement {stmt:?}")))?;
+      if let NumdStmtCmd::Thm { .. } = stmt { d.kind = Kind::Thm }
+      d.local = stmt.is_local();
+      d.proof = pf.ends_at - pf.pos;
+      if !pf.is_null() {
+        d.cmds = pf.collect::<Result<_, _>>().map_err(|e| bad(format!("{}: {e}", d.name)))?;
+      }
+    }
+    Ok(decls)
+  }
+}
+
+/// Maps the indices of the old declarations to the indices of the new declarations
+/// with the same name.
+fn index_map(old: &[Decl], new: &[Decl]) -> Vec<Option<usize>> {
+  let new_idx = new.iter().enumerate().map(|(i, d)| (&*d.name, i)).collect::<HashMap<_, _>>();
+  old.iter().map(|d| new_idx.get(&*d.name).copied()).collect()
+}
+
+/// Renumbers the commands of an old proof stream to the indices of the new file.
+struct Remap {
+  sorts: Vec<Option<usize>>,
+  terms: Vec<Option<usize>>,
+  thms: Vec<Option<usize>>,
+}
+
+impl Remap {
+  fn new(old: &Decls, new: &Decls) -> Self {
+    Self {
+      sorts: index_map(&old.sorts, &new.sorts),
+      terms: index_map(&old.terms, &new.terms),
+      thms: index_map(&old.thms, &new.thms),
+    }
+  }
+
+  /// Renumber a proof command, or return `None` if it refers to a declaration
+  /// that is not in the new file.
+  fn cmd(&self, cmd: ProofCmd) -> Option<ProofCmd> {
+    let get = |map: &[Option<usize>], i| map.get(i).copied().flatten();
+    Some(match cmd {
+      ProofCmd::Term { tid, save } =>
+        ProofCmd::Term { tid: TermId(get(&self.terms, u32_as_usize(tid.0))?.try_into().ok()?), save },
+      ProofCmd::Thm { tid, save } =>
+        ProofCmd::Thm { tid: ThmId(get(&self.thms, u32_as_usize(tid.0))?.try_into().ok()?), save },
+      ProofCmd::Dummy(s) => ProofCmd::Dummy(SortId(get(&self.sorts, s.0.into())?.try_into().ok()?)),
+      cmd => cmd,
+    })
+  }
+
+  /// True if the old proof `old` is the same as the new proof `new`, after renumbering.
+  fn same_proof(&self, old: &[ProofCmd], new: &[ProofCmd]) -> bool {
+    old.len() == new.len() && old.iter().zip(new).all(|(&c1, &c2)| self.cmd(c1) == Some(c2))
+  }
+}
+
+/// Returns a mask of the elements of `seq` which are in a longest increasing subsequence.
+/// The other elements are the ones that we consider to have moved.
+fn longest_increasing(seq: &[usize]) -> Vec<bool> {
+  // tails[k] is the index of the smallest tail of an increasing subsequence of length k + 1
+  let mut tails: Vec<usize> = vec![];
+  let mut prev = vec![None; seq.len()];
+  for (i, &x) in seq.iter().enumerate() {
+    let k = tails.partition_point(|&j| seq[j] < x);
+    if k > 0 { prev[i] = Some(tails[k - 1]) }
+    if k == tails.len() { tails.push(i) } else { tails[k] = i }
+  }
+  let mut mask = vec![false; seq.len()];
+  let mut cur = tails.last().copied();
+  while let Some(i) = cur { mask[i] = true; cur = prev[i] }
+  mask
+}
+
+/// A summary of the differences in one table.
+#[derive(Debug, Default)]
+struct Stats {
+  added: usize,
+  removed: usize,
+  moved: usize,
+  stmts: usize,
+  proofs: usize,
+}
+
+impl Stats {
+  const fn is_empty(&self) -> bool {
+    self.added + self.removed + self.moved + self.stmts + self.proofs == 0
+  }
+}
+
+fn diff_table(
+  out: &mut String, old: &[Decl], new: &[Decl], remap: &Remap, show_proofs: bool,
+) -> Stats {
+  let mut stats = Stats::default();
+  let new_idx = new.iter().enumerate().map(|(i, d)| (&*d.name, i)).collect::<HashMap<_, _>>();
+  let old_idx = old.iter().enumerate().map(|(i, d)| (&*d.name, i)).collect::<HashMap<_, _>>();
+  for d in old {
+    if !new_idx.contains_key(&*d.name) {
+      stats.removed += 1;
+      writeln!(out, "- {} {}", d.kind.name(), d.name).expect("writing to a string")
+    }
+  }
+  for d in new {
+    if !old_idx.contains_key(&*d.name) {
+      stats.added += 1;
+      writeln!(out, "+ {} {}", d.kind.name(), d.name).expect("writing to a string")
+    }
+  }
+  let common = old.iter().filter_map(|d| Some((d, &new[*new_idx.get(&*d.name)?])))
+    .collect::<Vec<_>>();
+  let seq = common.iter().map(|(_, d)| new_idx[&*d.name]).collect::<Vec<_>>();
+  for ((d, _), in_order) in common.iter().zip(longest_increasing(&seq)) {
+    if !in_order {
+      stats.moved += 1;
+      writeln!(out, "> {} {}: moved", d.kind.name(), d.name).expect("writing to a string")
+    }
+  }
+  for &(d1, d2) in &common {
+    let changes = d1.changes(d2);
+    if !changes.is_empty() {
+      stats.stmts += 1;
+      let parts = changes.iter().map(|c| c.0).collect::<Vec<_>>().join(", ");
+      writeln!(out, "! {} {}: statement changed ({parts})", d2.kind.name(), d2.name)
+        .expect("writing to a string");
+      for (part, old, new) in changes {
+        writeln!(out, "    {part}:\n    - {old}\n    + {new}").expect("writing to a string")
+      }
+    } else if !remap.same_proof(&d1.cmds, &d2.cmds) {
+      stats.proofs += 1;
+      if show_proofs {
+        #[allow(clippy::cast_possible_wrap)]
+        let delta = d2.proof as isize - d1.proof as isize;
+        writeln!(out, "* {} {}: proof {} -> {} bytes ({delta:+})",
+          d2.kind.name(), d2.name, d1.proof, d2.proof).expect("writing to a string")
+      }
+    }
+  }
+  stats
+}
+
+/// Load a proof file as MMB. `.mmu` files are imported and exported to MMB in memory.
+fn load(path: &str) -> io::Result<FileContents> {
+  let path: FileRef = fs::canonicalize(path)?.into();
+  if !path.has_extension("mmu") {
+    return FileContents::new_bin_from_file(path.path())
+  }
+  let (file, env) = compiler::elab_for_result(path.clone())?;
+  let env = match env {
+    Some(env) if !compiler::has_errors() => env,
+    _ => std::process::exit(1),
+  };
+  let mut report = |lvl: ErrorLevel, err: &str| {
+    println!("{}\n", Renderer::styled().render(lvl.to_annotation_type().title(err)))
+  };
+  let mut w = io::Cursor::new(vec![]);
+  let mut ex = MmbExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, &mut report, &mut w);
+  ex.run(true)?;
+  ex.finish()?;
+  Ok(FileContents::new_bin(w.into_inner().into_boxed_slice()))
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs diff` subcommand.
+  ///
+  /// See the [module documentation](self) for the purpose of this command.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs diff [--no-proofs] <old.mmb> <new.mmb>`, where:
+  ///
+  /// - `old.mmb` and `new.mmb` (or `.mmu`) are the proof files to compare.
+  ///   The file extension is used to determine the format.
+  /// - `--no-proofs` only counts the proof changes, instead of listing them.
+  pub fn main(self) -> io::Result<()> {
+    compiler::set_quiet(true);
+    let (old, new) = (load(&self.old)?, load(&self.new)?);
+    let parse = |buf| MmbFile::parse(buf).map_err(|e| bad(e.to_string()));
+    let (old_file, new_file) = (parse(&old)?, parse(&new)?);
+    let (old, new) = (Decls::new(&old_file)?, Decls::new(&new_file)?);
+    let remap = Remap::new(&old, &new);
+    let mut out = String::new();
+    let mut same = true;
+    for (what, old, new) in [
+      ("sorts", &old.sorts, &new.sorts),
+      ("terms", &old.terms, &new.terms),
+      ("theorems", &old.thms, &new.thms),
+    ] {
+      let stats = diff_table(&mut out, old, new, &remap, !self.no_proofs);
+      same &= stats.is_empty();
+      let size = |ds: &[Decl]| ds.iter().map(|d| d.proof).sum::<usize>();
+      let Stats { added, removed, moved, stmts, proofs } = stats;
+      write!(out, "{what}: {added} added, {removed} removed, {moved} moved, \
+        {stmts} statements changed, {proofs} proofs changed").expect("writing to a string");
+      if what != "sorts" {
+        write!(out, ", proof size {} -> {} bytes", size(old), size(new))
+          .expect("writing to a string");
+      }
+      out.push_str("\n\n");
+    }
+    print!("{out}");
+    if !same { std::process::exit(1) }
+    Ok(())
+  }
+}
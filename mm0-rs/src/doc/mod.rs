@@ -1,14 +1,14 @@
 //! Build documentation pages for MM1/MM0 files
 use std::{collections::{hash_map::Entry, HashMap}, hash::Hash, path::PathBuf};
-use bit_set::BitSet;
 use url::Url;
 use pulldown_cmark_escape::IoWriter;
 use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
 use std::mem;
+use crate::elab::axiom_use::AxiomUse;
 use crate::{lisp::pretty::Annot, ArcString, AtomData, AtomId, DeclKey, DocComment, EnvMergeIter,
   Environment, ExprNode, FileRef, FormatEnv, LinedString, LispVal, Proof, ProofNode, SliceUninit,
-  StmtTrace, TermId, Thm, ThmId, ThmKind, Type, LispKind, Uncons};
+  StmtTrace, TermId, Thm, ThmId, ThmKind, Type};
 
 const PP_WIDTH: usize = 160;
 
@@ -98,93 +98,6 @@ impl<'b, W: Write> pretty::RenderAnnotated<'b, Annot> for HtmlPrinter<'_, W> {
   }
 }
 
-struct AxiomUse {
-  axiom_use: HashMap<ThmId, BitSet>,
-  axiom_sets: Vec<(AtomId, String, BitSet)>,
-}
-
-impl AxiomUse {
-  fn new(env: &Environment) -> (Vec<ThmId>, Self) {
-    let mut axiom_use = HashMap::new();
-    let mut to_tid = vec![ThmId(u32::MAX)];
-    for (tid, td) in env.thms.enum_iter() {
-      if matches!(td.kind, ThmKind::Axiom) {
-        let axid = to_tid.len();
-        to_tid.push(tid);
-        let mut bs = BitSet::new();
-        bs.insert(axid);
-        axiom_use.insert(tid, bs);
-      }
-    }
-    let mut axiom_sets = vec![];
-    if let Some(data) = &env.data[AtomId::AXIOM_SETS].lisp {
-      data.val.unwrapped(|e| if let LispKind::AtomMap(m) = e {
-        for (&a, u) in m {
-          let mut axiom_set = BitSet::new();
-          let mut it = Uncons::new(u.clone()).peekable();
-          let doc = it.peek().and_then(|s| s.unwrapped(|s| match s {
-            LispKind::String(s) => Some(format!("{s}")),
-            _ => None
-          }));
-          if doc.is_some() { it.next(); }
-          for e in it {
-            if let Some(a) = e.as_atom() {
-              if let Some(DeclKey::Thm(tid)) = env.data[a].decl {
-                if let Some(bs) = axiom_use.get(&tid) { axiom_set.union_with(bs) }
-              }
-            }
-          }
-          if !axiom_set.is_empty() {
-            axiom_sets.push((a, doc.unwrap_or_default(), axiom_set))
-          }
-        }
-      })
-    }
-    axiom_sets.sort_by_cached_key(|set| set.2.iter().map(|i| to_tid[i].0).max());
-    (to_tid, AxiomUse { axiom_use, axiom_sets })
-  }
-
-  fn accumulate(&mut self, env: &Environment, bs: &mut BitSet, store: &[ProofNode], node: &ProofNode) {
-    match node {
-      ProofNode::Ref(_) |
-      ProofNode::Dummy(_, _) |
-      ProofNode::Term(..) |
-      ProofNode::Hyp(_, _) |
-      ProofNode::Refl(_) |
-      ProofNode::Sym(_) |
-      ProofNode::Cong(..) |
-      ProofNode::Unfold(..) => {}
-      ProofNode::Conv(p) => self.accumulate(env, bs, store, &store[p+2]),
-      &ProofNode::Thm(tid, p) => {
-        let (_, _, pfs) = env.thms[tid].unpack_thm(&store[p..]);
-        bs.union_with(self.get(env, tid));
-        for p in pfs { self.accumulate(env, bs, store, p) }
-      }
-    }
-  }
-
-  fn get<'a>(&'a mut self, env: &Environment, tid: ThmId) -> &'a BitSet {
-    if let Some(bs) = self.axiom_use.get(&tid) {
-      #[allow(clippy::useless_transmute, clippy::transmute_ptr_to_ptr)]
-      // Safety: This is the same issue that comes up in Spans::insert.
-      // We are performing a lifetime cast here because rust can't see that
-      // in the None case it is safe to drop the borrow of `self.axuse`.
-      return unsafe { std::mem::transmute::<&BitSet, &BitSet>(bs) }
-    }
-    let mut bs = BitSet::new();
-    let td = &env.thms[tid];
-    match &td.kind {
-      ThmKind::Axiom => unreachable!(),
-      ThmKind::Thm(None) => {bs.insert(0);}
-      ThmKind::Thm(Some(pf)) => {
-        for p in &pf.heap[td.args.len()..] { self.accumulate(env, &mut bs, &pf.store, p) }
-        self.accumulate(env, &mut bs, &pf.store, pf.head())
-      }
-    }
-    self.axiom_use.entry(tid).or_insert(bs)
-  }
-}
-
 #[derive(Debug, Clone)]
 enum LineKind {
   Hyp(Option<AtomId>),
@@ -786,7 +699,11 @@ impl Args {
     let mut bd = BuildDoc {
       source: fc.ascii(),
       base_url, order: self.order,
-      axuse: AxiomUse::new(&env),
+      axuse: {
+        let (to_tid, mut axuse) = AxiomUse::new(&env);
+        axuse.load_axiom_sets(&env, &to_tid);
+        (to_tid, axuse)
+      },
       thm_folder: dir, env, index,
       mangler: Mangler::default(),
     };
@@ -16,13 +16,14 @@ pub mod refine;
 pub mod proof;
 pub mod inout;
 pub mod verify;
+pub mod axiom_use;
 
 
 use std::collections::HashMap;
 use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
-use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex};
 use std::{future::Future, pin::Pin, task::{Context, Poll, ready}};
 use std::time::Duration;
 use instant::Instant;
@@ -33,7 +34,7 @@ use crate::ast::{self, Ast, DeclKind, Delimiter, GenNota, Literal as ALiteral,
   LocalKind, SExpr, SExprKind, SimpleNota, SimpleNotaKind, Stmt, StmtKind};
 use inout::InoutHandlers;
 use environment::Literal as ELiteral;
-use lisp::LispVal;
+use lisp::{LispVal, eval::Debugger, profile::{Profile, Profiler}, promise::Spawner};
 use local_context::try_get_span_opt;
 use crate::{ArcList, ArcString, AtomId, BoxError, Coe, DeclKey, DocComment, EnvMergeIter,
   Environment, ErrorLevel, Expr, ExprNode, FileRef, FileSpan, FrozenEnv,
@@ -186,7 +187,12 @@ impl From<mm1_parser::ParseError> for ElabError {
 }
 
 impl From<mm0b_parser::ParseError> for ElabError {
-  fn from(e: mm0b_parser::ParseError) -> Self { Self::new_e(0, format!("{e:?}")) }
+  fn from(e: mm0b_parser::ParseError) -> Self {
+    match e {
+      mm0b_parser::ParseError::StrError(s, pos) => Self::new_e(pos, s),
+      e => Self::new_e(0, format!("{e:?}")),
+    }
+  }
 }
 
 /// Records the current reporting setting. A report that is suppressed by the reporting mode
@@ -231,6 +237,30 @@ impl std::fmt::Debug for GoalListener {
   }
 }
 
+/// A pretty printed snapshot of the proof state, as returned by [`Elaborator::goal_state`].
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct GoalState {
+  /// The hypotheses and subproofs in the local context, as `(name, statement)` pairs.
+  pub hyps: Vec<(String, String)>,
+  /// The statements of the open goals.
+  pub goals: Vec<String>,
+  /// The unassigned metavariables.
+  pub mvars: Vec<GoalMVar>,
+}
+
+/// An unassigned metavariable in a [`GoalState`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+pub struct GoalMVar {
+  /// The printed name of the metavariable, like `?a`.
+  pub name: String,
+  /// The sort of the metavariable, if known.
+  pub sort: Option<ArcString>,
+  /// True if the metavariable must be a bound variable.
+  pub bound: bool,
+}
+
 /// The persistent elaborator options (which can be set at the command line)
 #[derive(Copy, Clone, Debug)]
 pub struct ElabOptions {
@@ -288,6 +318,14 @@ pub struct Elaborator {
   arena: lisp::LispArena,
   /// A listener for goal view events.
   recv_goal: Option<GoalListener>,
+  /// The lisp debugger, if one is attached.
+  debugger: Option<Box<dyn Debugger>>,
+  /// The lisp profiler, if profiling is enabled.
+  profiler: Option<Profiler>,
+  /// The value passed to the last `raise`, until it is caught.
+  raised: Option<LispVal>,
+  /// The thread pool on which `async` calls are run, if any.
+  spawner: Option<Spawner>,
 }
 
 impl Deref for Elaborator {
@@ -313,9 +351,10 @@ impl Elaborator {
   /// - `cancel`: An atomic flag that can be flipped in another thread in order to cancel
   ///   the elaboration before completion.
   /// - `recv_goal`: A listener for goal view events.
+  /// - `debugger`: A lisp debugger to consult during evaluation.
   #[must_use] pub fn new(ast: Arc<Ast>, path: FileRef,
       mm0_mode: bool, options: ElabOptions, cancel: Arc<AtomicBool>,
-      recv_goal: Option<GoalListener>,
+      recv_goal: Option<GoalListener>, debugger: Option<Box<dyn Debugger>>,
     ) -> Elaborator {
     Elaborator {
       ast, path, cancel,
@@ -333,6 +372,10 @@ impl Elaborator {
       reporting: ReportMode::new(),
       arena: Default::default(),
       recv_goal,
+      debugger,
+      profiler: None,
+      raised: None,
+      spawner: None,
     }
   }
 
@@ -367,6 +410,25 @@ impl Elaborator {
     }
   }
 
+  /// The name of a statement, used as the root of profiling stacks: the name of the
+  /// declared item, or the file and line for other kinds of statement.
+  fn stmt_name(&self, stmt: &Stmt) -> Box<str> {
+    match &stmt.k {
+      StmtKind::Annot(_, s) | StmtKind::DocComment(_, s) => self.stmt_name(s),
+      StmtKind::Decl(d) => String::from_utf8_lossy(self.span(d.id)).into(),
+      &StmtKind::Sort(id, _) => String::from_utf8_lossy(self.span(id)).into(),
+      _ => format!("{}:{}", self.path.rel(), self.ast.source.to_pos(stmt.span.start).line + 1).into(),
+    }
+  }
+
+  /// The name of the statement containing position `pos`, as in [`stmt_name`](Self::stmt_name).
+  fn stmt_name_at(&self, pos: usize) -> Box<str> {
+    match self.ast.stmts.partition_point(|s| s.span.start <= pos).checked_sub(1) {
+      Some(i) => self.stmt_name(&self.ast.stmts[i]),
+      None => format!("{}:1", self.path.rel()).into(),
+    }
+  }
+
   fn elab_simple_nota(&mut self, n: &SimpleNota) -> Result<()> {
     let a = self.env.get_atom(self.ast.span(n.id));
     let term = self.term(a).ok_or_else(|| ElabError::new_e(n.id, "term not declared"))?;
@@ -591,6 +653,11 @@ impl Elaborator {
 
     self.cur_timeout = self.timeout.and_then(|d| Instant::now().checked_add(d));
     self.spans.set_stmt(span);
+    self.raised = None;
+    if self.profiler.is_some() {
+      let root = self.stmt_name(stmt);
+      if !self.profiler.as_mut().is_some_and(|p| p.set_root(root)) { self.profiler = None }
+    }
     match &stmt.k {
       &StmtKind::Sort(sp, sd) => {
         let a = self.env.get_atom(self.ast.span(sp));
@@ -682,6 +749,14 @@ pub struct ElaborateBuilder<'a, F> {
   pub recv_dep: F,
   /// A listener for goal view events.
   pub recv_goal: Option<GoalListener>,
+  /// A lisp debugger to consult while evaluating lisp code in this file (but not its imports).
+  pub debugger: Option<Box<dyn Debugger>>,
+  /// A profile to which samples of lisp evaluation in this file (but not its imports)
+  /// will be added.
+  pub profile: Option<Arc<Mutex<Profile>>>,
+  /// The thread pool on which `async` calls in this file will be run. If not provided,
+  /// they are evaluated immediately.
+  pub spawner: Option<Spawner>,
 }
 
 impl<T: Send, F> ElaborateBuilder<'_, F>
@@ -828,7 +903,9 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
     let mut recv_dep = self.recv_dep;
     let mut recv = HashMap::new();
     let mut elab = Elaborator::new(self.ast.clone(),
-      self.path, self.mm0_mode, self.options, self.cancel, self.recv_goal);
+      self.path, self.mm0_mode, self.options, self.cancel, self.recv_goal, self.debugger);
+    elab.profiler = self.profile.map(|p| Profiler::new(Some(p), "".into()));
+    elab.spawner = self.spawner;
     elab.arena.install_thread_local();
     for &(sp, ref f) in &self.ast.imports {
       (|| -> Result<_> {
@@ -0,0 +1,104 @@
+//! Computes the set of axioms each theorem transitively depends on.
+
+use std::collections::HashMap;
+use bit_set::BitSet;
+use crate::{AtomId, DeclKey, Environment, LispKind, ProofNode, ThmId, ThmKind, Uncons};
+
+/// A cache of the axioms used by each theorem.
+///
+/// Axioms are numbered from 1 in declaration order (see the `Vec<ThmId>` returned by [`AxiomUse::new`]), and the index 0 means that the theorem
+/// depends on a theorem with a missing proof (`sorry`).
+#[derive(Debug)]
+pub struct AxiomUse {
+  axiom_use: HashMap<ThmId, BitSet>,
+  /// The named axiom sets declared in the `axiom-sets` lisp global, with their documentation.
+  pub axiom_sets: Vec<(AtomId, String, BitSet)>,
+}
+
+impl AxiomUse {
+  /// Construct a new cache for the environment. The returned vector maps axiom indices
+  /// to theorem IDs. This does not read the axiom sets; see [`AxiomUse::load_axiom_sets`].
+  #[must_use] pub fn new(env: &Environment) -> (Vec<ThmId>, Self) {
+    let mut axiom_use = HashMap::new();
+    let mut to_tid = vec![ThmId(u32::MAX)];
+    for (tid, td) in env.thms.enum_iter() {
+      if matches!(td.kind, ThmKind::Axiom) {
+        let axid = to_tid.len();
+        to_tid.push(tid);
+        let mut bs = BitSet::new();
+        bs.insert(axid);
+        axiom_use.insert(tid, bs);
+      }
+    }
+    (to_tid, AxiomUse { axiom_use, axiom_sets: vec![] })
+  }
+
+  /// Read the named axiom sets from the `axiom-sets` lisp global.
+  pub fn load_axiom_sets(&mut self, env: &Environment, to_tid: &[ThmId]) {
+    if let Some(data) = &env.data[AtomId::AXIOM_SETS].lisp {
+      data.val.unwrapped(|e| if let LispKind::AtomMap(m) = e {
+        for (&a, u) in m {
+          let mut axiom_set = BitSet::new();
+          let mut it = Uncons::new(u.clone()).peekable();
+          let doc = it.peek().and_then(|s| s.unwrapped(|s| match s {
+            LispKind::String(s) => Some(format!("{s}")),
+            _ => None
+          }));
+          if doc.is_some() { it.next(); }
+          for e in it {
+            if let Some(a) = e.as_atom() {
+              if let Some(DeclKey::Thm(tid)) = env.data[a].decl {
+                if let Some(bs) = self.axiom_use.get(&tid) { axiom_set.union_with(bs) }
+              }
+            }
+          }
+          if !axiom_set.is_empty() {
+            self.axiom_sets.push((a, doc.unwrap_or_default(), axiom_set))
+          }
+        }
+      })
+    }
+    self.axiom_sets.sort_by_cached_key(|set| set.2.iter().map(|i| to_tid[i].0).max());
+  }
+
+  fn accumulate(&mut self, env: &Environment, bs: &mut BitSet, store: &[ProofNode], node: &ProofNode) {
+    match node {
+      ProofNode::Ref(_) |
+      ProofNode::Dummy(_, _) |
+      ProofNode::Term(..) |
+      ProofNode::Hyp(_, _) |
+      ProofNode::Refl(_) |
+      ProofNode::Sym(_) |
+      ProofNode::Cong(..) |
+      ProofNode::Unfold(..) => {}
+      ProofNode::Conv(p) => self.accumulate(env, bs, store, &store[p+2]),
+      &ProofNode::Thm(tid, p) => {
+        let (_, _, pfs) = env.thms[tid].unpack_thm(&store[p..]);
+        bs.union_with(self.get(env, tid));
+        for p in pfs { self.accumulate(env, bs, store, p) }
+      }
+    }
+  }
+
+  /// Get the set of axioms used by theorem `tid`.
+  pub fn get<'a>(&'a mut self, env: &Environment, tid: ThmId) -> &'a BitSet {
+    if let Some(bs) = self.axiom_use.get(&tid) {
+      #[allow(clippy::useless_transmute, clippy::transmute_ptr_to_ptr)]
+      // Safety: This is the same issue that comes up in Spans::insert.
+      // We are performing a lifetime cast here because rust can't see that
+      // in the None case it is safe to drop the borrow of `self.axuse`.
+      return unsafe { std::mem::transmute::<&BitSet, &BitSet>(bs) }
+    }
+    let mut bs = BitSet::new();
+    let td = &env.thms[tid];
+    match &td.kind {
+      ThmKind::Axiom => unreachable!(),
+      ThmKind::Thm(None) => {bs.insert(0);}
+      ThmKind::Thm(Some(pf)) => {
+        for p in &pf.heap[td.args.len()..] { self.accumulate(env, &mut bs, &pf.store, p) }
+        self.accumulate(env, &mut bs, &pf.store, pf.head())
+      }
+    }
+    self.axiom_use.entry(tid).or_insert(bs)
+  }
+}
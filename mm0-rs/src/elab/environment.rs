@@ -347,7 +347,7 @@ pub enum StmtTrace {
 /// A declaration is either a [`Term`] or a [`Thm`]. This is done because in MM1
 /// Terms and Thms share a namespace (although they are put in separate number-spaces
 /// for compilation to MM0).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DeclKey {
   /// A term or def, with its Id
   Term(TermId),
@@ -634,7 +634,7 @@ impl ObjectKind {
 }
 
 /// A way to track the unique element of a set.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub enum OneOrMore<T> {
   /// There are no elements.
@@ -740,6 +740,24 @@ pub struct Remapper {
   /// That way, a mutable cell is remapped to another mutable cell, but we can
   /// detect cycles and correctly remap them into cycles.
   pub(crate) refs: HashMap<*const FrozenLispRef, LispVal>,
+  /// Set if the copy will be sent to another thread. [`Proc::Dyn`](super::lisp::Proc::Dyn)
+  /// procedures may hold reference counted state which is not copied by [`Remap`],
+  /// so they are replaced by procedures that fail when called.
+  pub(crate) isolate: bool,
+}
+
+impl Remapper {
+  /// A remapper that maps every sort, term, theorem and atom of `env` to itself,
+  /// which is used to make deep copies of lisp data.
+  pub(crate) fn identity(env: &Environment) -> Self {
+    Remapper {
+      sort: env.sorts.enum_iter().map(|(s, _)| s).collect(),
+      term: env.terms.enum_iter().map(|(t, _)| t).collect(),
+      thm: env.thms.enum_iter().map(|(t, _)| t).collect(),
+      atom: env.data.enum_iter().map(|(a, _)| a).collect(),
+      ..Default::default()
+    }
+  }
 }
 
 /// A trait for types that can be remapped.
@@ -41,7 +41,7 @@ use num::BigInt;
 use crate::{mk_lisp_kind, ArcString, AtomData, AtomId, AtomVec, DeclKey, DocComment, Environment,
   FileSpan, LinedString, LispData, LispKind, LispVal, MergeStrategy, MergeStrategyInner, ParserEnv, Sort,
   SortId, SortVec, Span, StmtTrace, Term, TermId, TermVec, Thm, ThmId, ThmVec,
-  lisp::{print::FormatEnv, Annot, InferTarget, LispRef, LispWeak, Proc, Syntax}};
+  lisp::{print::FormatEnv, promise::Unsendable, Annot, HashKey, InferTarget, LispRef, LispWeak, Proc, Syntax}};
 use super::{ObjectKind, Remap, Remapper, Spans};
 
 /// A "frozen" environment, which is a thread-safe read only
@@ -361,6 +361,26 @@ impl FrozenLispKind {
     }
     None
   }
+
+  /// Make a fresh copy of an expression, for passing to functions that need a [`LispVal`].
+  /// Unlike [`FrozenLispVal::thaw`] followed by [`Clone`], this does not touch the
+  /// reference counts of the frozen data. Returns `None` if the value contains
+  /// something other than atoms, lists, metavariables and constants.
+  #[must_use] pub fn copy_expr(&self) -> Option<LispVal> {
+    Some(match self.unwrap() {
+      &FrozenLispKind::Atom(a) => LispVal::atom(a),
+      FrozenLispKind::List(es) =>
+        LispVal::list(es.iter().map(|e| e.copy_expr()).collect::<Option<Vec<_>>>()?),
+      FrozenLispKind::DottedList(es, r) => LispVal::dotted_list(
+        es.iter().map(|e| e.copy_expr()).collect::<Option<Vec<_>>>()?, r.copy_expr()?),
+      &FrozenLispKind::MVar(n, is) => LispVal::new(LispKind::MVar(n, is)),
+      FrozenLispKind::Number(n) => LispVal::number(n.clone()),
+      FrozenLispKind::String(s) => LispVal::string(s.clone()),
+      &FrozenLispKind::Bool(b) => LispVal::bool(b),
+      FrozenLispKind::Undef => LispVal::undef(),
+      _ => return None,
+    })
+  }
 }
 
 impl Deref for FrozenLispVal {
@@ -396,6 +416,43 @@ impl Remap for FrozenMergeStrategy {
   }
 }
 
+impl Environment {
+  /// Make a deep copy of the environment (except for the span information), which shares
+  /// no reference counted data with the original, so that it can be sent to another thread.
+  /// [`Proc::Dyn`] procedures cannot be copied, so they are replaced by procedures that
+  /// fail when called. The returned [`Remapper`] can be used to copy other lisp data into
+  /// the new environment, with the same guarantees.
+  pub(crate) fn deep_copy(&self) -> (Environment, Remapper) {
+    let mut r = Remapper::identity(self);
+    r.isolate = true;
+    let data = self.data.0.iter().map(|d| AtomData {
+      name: d.name.clone(),
+      lisp: d.lisp.as_ref().map(|ld| LispData {
+        src: ld.src.clone(),
+        doc: ld.doc.clone(),
+        val: ld.val.remap(&mut r),
+        // Safety: the environment is not modified during the copy
+        merge: unsafe { freeze_merge_strategy(&ld.merge) }.remap(&mut r),
+      }),
+      graveyard: d.graveyard.clone(),
+      sort: d.sort,
+      decl: d.decl,
+    }).collect();
+    let env = Environment {
+      sorts: self.sorts.clone(),
+      provable_sort: self.provable_sort.clone(),
+      pe: self.pe.clone(),
+      terms: self.terms.clone(),
+      thms: self.thms.clone(),
+      atoms: self.atoms.clone(),
+      data,
+      stmts: self.stmts.clone(),
+      spans: vec![],
+    };
+    (env, r)
+  }
+}
+
 impl Remap for FrozenLispData {
   type Target = LispData;
   fn remap(&self, r: &mut Remapper) -> LispData {
@@ -425,6 +482,9 @@ impl Remap for FrozenLispKind {
       FrozenLispKind::Annot(sp, m) => LispVal::new(LispKind::Annot(sp.clone(), m.remap(r))),
       FrozenLispKind::Proc(f) => LispVal::proc(f.remap(r)),
       FrozenLispKind::AtomMap(m) => LispVal::new(LispKind::AtomMap(m.remap(r))),
+      FrozenLispKind::HashMap(m) => LispVal::new(LispKind::HashMap(
+        m.iter().map(|(k, v)| (HashKey(k.0.remap(r)), v.remap(r))).collect())),
+      FrozenLispKind::Vector(es) => LispVal::new(LispKind::Vector(es.remap(r))),
       FrozenLispKind::Ref(m) => match r.refs.entry(std::ptr::from_ref(m)) {
         Entry::Occupied(e) => e.get().clone(),
         Entry::Vacant(e) => {
@@ -474,7 +534,11 @@ impl Remap for FrozenProc {
           Err(v) => Err(v.remap(r)),
         }
       )),
+      Proc::Dyn(_) if r.isolate => Proc::Dyn(RefCell::new(Box::new(Unsendable))),
       Proc::Dyn(c) => Proc::Dyn(c.remap(r)),
+      // Safety: the cell is frozen, so we must not change the borrow flag
+      Proc::Promise(p) => Proc::Promise(RefCell::new(
+        unsafe { p.try_borrow_unguarded() }.expect("failed to deref ref").remap(r))),
     }
   }
 }
@@ -9,9 +9,11 @@ pub mod eval;
 pub mod debug;
 pub mod print;
 pub mod pretty;
+pub mod profile;
+pub mod promise;
 
 use std::ops::{Deref, DerefMut};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::rc::{Rc, Weak};
 use std::cell::{Cell, RefCell};
 use std::sync::{Arc, Mutex};
@@ -35,7 +37,7 @@ macro_rules! str_enum {
   (@inner $to_str:expr, $from_str:expr, $from_bytes:expr;
       $(#[$doc:meta])* enum $name:ident {$($(#[doc=$doc2:expr])* $(#[cfg($($cfgs:tt)*)])* $e:ident: $s:expr,)*}) => {
     $(#[$doc])*
-    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
     pub enum $name { $($(#[doc=$doc2])* $(#[cfg($($cfgs)*)])* $e),* }
     crate::deep_size_0!($name);
 
@@ -319,6 +321,12 @@ macro_rules! mk_lisp_kind {
       /// A map from atoms to values. This can be used as a mutable map if it is behind a
       /// [`Ref`](Self::Ref).
       AtomMap(HashMap<AtomId, $val>),
+      /// A map from arbitrary values to values, using the structural equality of `==`
+      /// on keys. This can be used as a mutable map if it is behind a [`Ref`](Self::Ref).
+      HashMap(HashMap<HashKey<$val>, $val>),
+      /// A growable array of values, with constant time indexing. This can be used as a
+      /// mutable vector if it is behind a [`Ref`](Self::Ref).
+      Vector(Vec<$val>),
       /// A mutable reference. This is the only way to have mutable values in
       /// client code.
       Ref($ref_),
@@ -343,7 +351,10 @@ mk_lisp_kind! {
 
 impl LispVal {
   /// Make a [`LispVal`] from the inner enum type [`LispKind`].
-  #[must_use] pub fn new(e: LispKind) -> LispVal { LispVal(Rc::new(e)) }
+  #[must_use] pub fn new(e: LispKind) -> LispVal {
+    profile::count_alloc();
+    LispVal(Rc::new(e))
+  }
   /// Construct a [`LispVal`] for an atom.
   #[must_use] pub fn atom(a: AtomId) -> LispVal { LispVal::new(LispKind::Atom(a)) }
   /// Construct a [`LispVal`] for a list.
@@ -730,6 +741,14 @@ impl LispKind {
   pub fn is_map(&self) -> bool {
     self.unwrapped(|e| matches!(e, LispKind::AtomMap(_)))
   }
+  /// Returns true if this value is a hash map.
+  pub fn is_hash_map(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::HashMap(_)))
+  }
+  /// Returns true if this value is a vector.
+  pub fn is_vector(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::Vector(_)))
+  }
   /// Returns true if this value is not `#undef` or a reference to `#undef`.
   pub fn is_def(&self) -> bool {
     self.unwrapped(|e| !matches!(e, LispKind::Undef))
@@ -891,12 +910,68 @@ impl PartialEq<LispKind> for LispKind {
           }
         }
       }
-      _ => false // Goal, Proc, MVar, AtomMap all have only reference equality
+      _ => false // Goal, Proc, MVar, AtomMap, HashMap, Vector all have only reference equality
     }))
   }
 }
 impl Eq for LispKind {}
 
+/// A key in a [`LispKind::HashMap`].
+///
+/// Keys are compared using the structural equality of `==`, and hashed consistently with it,
+/// so a key should not be mutated (through a [`Ref`](LispKind::Ref)) while it is in a map.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "memory", derive(DeepSizeOf))]
+#[repr(transparent)]
+pub struct HashKey<V>(pub V);
+
+impl<V: debug::EnvDebug> debug::EnvDebug for HashKey<V> {
+  fn env_dbg(&self, fe: print::FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    self.0.env_dbg(fe, f)
+  }
+}
+
+impl PartialEq for HashKey<LispVal> {
+  fn eq(&self, other: &Self) -> bool { self.0 == other.0 }
+}
+impl Eq for HashKey<LispVal> {}
+
+impl Hash for HashKey<LispVal> {
+  fn hash<H: Hasher>(&self, h: &mut H) { self.0.hash_structural(h) }
+}
+
+impl LispKind {
+  /// Hash a value, consistently with its structural equality. Lists are hashed as
+  /// the flattened sequence of their elements, because `(a b . (c d))` is equal to
+  /// `(a b c d)`, and values that only have reference equality are hashed by address.
+  fn hash_structural<H: Hasher>(&self, h: &mut H) {
+    self.unwrapped(|e| match e {
+      LispKind::List(_) | LispKind::DottedList(..) => e.hash_list(h),
+      LispKind::Atom(a) => a.hash(h),
+      LispKind::Number(n) => n.hash(h),
+      LispKind::String(s) => s.hash(h),
+      LispKind::Bool(b) => b.hash(h),
+      LispKind::Syntax(s) => s.hash(h),
+      LispKind::Undef => {}
+      _ => std::ptr::from_ref(e).hash(h),
+    })
+  }
+
+  fn hash_list<H: Hasher>(&self, h: &mut H) {
+    self.unwrapped(|e| match e {
+      LispKind::List(es) => {
+        for e in &**es { e.hash_structural(h) }
+        0_u8.hash(h)
+      }
+      LispKind::DottedList(es, r) => {
+        for e in &**es { e.hash_structural(h) }
+        r.hash_list(h)
+      }
+      _ => { 1_u8.hash(h); e.hash_structural(h) }
+    })
+  }
+}
+
 /// An annotation, which is a tag placed on lisp values that is ignored by all
 /// the basic functions.
 #[derive(Clone, Debug, EnvDebug)]
@@ -990,7 +1065,9 @@ pub enum Proc {
   /// internal state here. See [`Compiler::call`].
   ///
   /// [`Compiler::call`]: crate::mmc::Compiler::call
-  Dyn(RefCell<Box<dyn LispProc>>) // TODO: use extern instead
+  Dyn(RefCell<Box<dyn LispProc>>), // TODO: use extern instead
+  /// The result of an `async` call, which waits for the result when called.
+  Promise(RefCell<promise::Promise>),
 }
 
 /// A procedure specification, which defines the number of arguments expected by the call.
@@ -1033,6 +1110,7 @@ impl Proc {
       &Proc::Lambda {spec, ..} => spec,
       Proc::MatchCont(_) |
       Proc::ProofThunk(_, _) => ProcSpec::AtLeast(0),
+      Proc::Promise(_) => ProcSpec::Exact(0),
       Proc::MergeMap(_) => ProcSpec::Exact(2),
       Proc::RefineCallback => ProcSpec::AtLeast(1),
       Proc::Dyn(proc) => proc.borrow().spec(),
@@ -1053,6 +1131,16 @@ str_enum! {
     Display: "display",
     /// `error` takes a string and throws an error with the given string as the message.
     Error: "error",
+    /// `(raise v)` throws an exception with the value `v`, which can be any lisp value.
+    /// If it is not caught, it is reported as an error.
+    Raise: "raise",
+    /// `(catch thunk handler)` calls `thunk` with no arguments and returns the result.
+    /// If an exception is thrown while running `thunk`, the proof state (the goals,
+    /// metavariable assignments and subproofs) is rolled back to what it was when `catch` was
+    /// called, and `(handler v)` is called instead, where `v` is the value passed to `raise`,
+    /// or the error message for other errors, such as a failure to elaborate or unify in
+    /// `refine`. Errors in `handler` are not caught. Timeouts and cancellation are not caught.
+    Catch: "catch",
     /// `print` takes an arbitrary expression and pretty-prints it.
     Print: "print",
     /// `(report-at sp type msg)` will report the message `msg` at a position
@@ -1203,8 +1291,8 @@ str_enum! {
     /// `(tl e)` returns the tail of the list, or right element of the cons expression.
     /// It is known as `cdr` in most lisps.
     Tail: "tl",
-    /// `(nth n e)` returns the `n`th element of the list, or `#undef` if out of range.
-    /// It fails if the input is not a list.
+    /// `(nth n e)` returns the `n`th element of the list or vector, or `#undef` if out of range.
+    /// It fails if the input is not a list or vector.
     Nth: "nth",
     /// `(map f '(a1 a2) '(b1 b2))` constructs the list `(list (f a1 b1) (f a2 b2))`,
     /// calling `f` on the heads of all the arguments, then the second elements and so on.
@@ -1249,13 +1337,24 @@ str_enum! {
     /// (Useful for targeted error reporting in scripts.)
     StackSpan: "stack-span",
     /// `(async f args)` evaluates `(f args)` on another thread, and returns a
-    /// procedure that will join on the thread to wait for the result.
+    /// promise, which is a procedure that will join on the thread to wait for the result.
+    /// The function runs on a copy of the environment, outside of any proof, so changes
+    /// it makes to global definitions or ref-cells are not visible to the caller.
     Async: "async",
+    /// `(await p)` waits for the `async` call that returned the promise `p` to finish,
+    /// and returns its result, or fails with its error. This is the same as `(p)`.
+    Await: "await",
     /// `(atom-map? m)` is true if the argument is an atom map.
     IsAtomMap: "atom-map?",
     /// `(atom-map! [k1 v1] [k2 v2] ...)` creates a new mutable atom map, a key-value store.
     NewAtomMap: "atom-map!",
-    /// * `(lookup m k)` gets the value stored in the atom map `m` at `k`, or `#undef` if not present.
+    /// `(hash-map? m)` is true if the argument is a hash map.
+    IsHashMap: "hash-map?",
+    /// `(hash-map! [k1 v1] [k2 v2] ...)` creates a new mutable hash map, a key-value store
+    /// whose keys can be arbitrary values, compared using `==`.
+    NewHashMap: "hash-map!",
+    /// * `(lookup m k)` gets the value stored in the atom map or hash map `m` at `k`,
+    ///   or `#undef` if not present.
     /// * `(lookup m k v)` will return `v` instead if the key is not present,
     ///   unless `v` is a procedure, in which case it will be called with no arguments on lookup failure.
     Lookup: "lookup",
@@ -1276,6 +1375,27 @@ str_enum! {
     /// * `(merge-map f old new)` or `((merge-map f) old new)` will use
     ///   `(f oldval newval)` to resolve keys that are present in both maps.
     MergeMap: "merge-map",
+    /// `(vector? v)` is true if the argument is a vector.
+    IsVector: "vector?",
+    /// `(vector! a b c)` creates a new mutable vector with the given elements.
+    /// Vectors can be indexed in constant time using `nth`.
+    NewVector: "vector!",
+    /// `(vector-len v)` returns the number of elements in the vector `v`.
+    VectorLen: "vector-len",
+    /// `(vector-set! v n e)` sets the `n`th element (zero-indexed) of the mutable vector `v`
+    /// to `e`, and returns `#undef`. It fails if `n` is out of range.
+    VectorSet: "vector-set!",
+    /// `(vector-push! v e)` adds `e` to the end of the mutable vector `v`, and returns `#undef`.
+    VectorPush: "vector-push!",
+    /// `(vector-pop! v)` removes and returns the last element of the mutable vector `v`,
+    /// or returns `#undef` if it is empty.
+    VectorPop: "vector-pop!",
+    /// `(vector->list v)` returns a list of the elements of the vector `v`.
+    VectorToList: "vector->list",
+    /// `(sort f xs)` sorts the list or vector `xs` using the comparison function `f`,
+    /// where `(f a b)` should be true if `a` is strictly less than `b`.
+    /// The sort is stable, and it returns a new list or mutable vector.
+    Sort: "sort",
     /// `(set-timeout n)` sets the timeout for running individual theorems and
     /// `do` blocks to `n` milliseconds. The default is 5 seconds.
     SetTimeout: "set-timeout",
@@ -1428,6 +1548,10 @@ str_enum! {
     /// * `(set-backtrace type b)` does the same but for specific error type `type`,
     ///   which can be `'error`, `'info` or `'warn`.
     SetBacktrace: "set-backtrace",
+    /// `(profile f)` calls `f` with no arguments and returns the result, while sampling the
+    /// lisp call stack. When it returns, it reports the named functions and builtins that
+    /// took the most time, along with the number of lisp values they allocated.
+    Profile: "profile",
     /// `refine-extra-args` can be called directly, but it simply returns an error. It is called
     /// by `refine` when elaborating a term with too many arguments, and is expected to be
     /// overridden by user code to provide a more useful behavior.
@@ -15,13 +15,14 @@ use crate::{ast::SExpr, ArcString, AtomData, AtomId, BoxError, DeclKey, ElabErro
   Elaborator, Environment, ErrorLevel, FileRef, FileSpan, LispData,
   MergeStrategy, MergeStrategyInner, ObjectKind, Span, StmtTrace,
   TermKind, ThmKind, ThmId};
-use crate::elab::local_context::{try_get_span, try_get_span_from, AwaitingProof, InferSort};
+use crate::elab::local_context::{try_get_span, try_get_span_from, AwaitingProof, InferSort, Snapshot};
 use crate::elab::{
   refine::{RStack, RState, RefineResult},
-  ElabErrorKind, ReportMode, Result};
+  ElabErrorKind, GoalMVar, GoalState, ReportMode, Result};
 use super::parser::{Ir, MVarPattern};
 use super::print::FormatEnv;
-use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal, Modifiers, Proc,
+use super::profile::Profiler;
+use super::{Arc, BuiltinProc, Cell, HashKey, InferTarget, LispKind, LispRef, LispVal, Modifiers, Proc,
   ProcPos, ProcSpec, QExpr, Rc, RefCell, Uncons};
 
 #[derive(Debug)]
@@ -33,6 +34,84 @@ struct MergeMapData {
   k: Option<AtomId>,
 }
 
+#[derive(Debug)]
+struct CatchData {
+  sp: (Span, Span),
+  /// The handler and the proof state to roll back to, or `None` once the handler has been called.
+  handler: Option<(LispVal, Snapshot)>,
+  /// The length of the call stack while running the `catch` frame.
+  depth: usize,
+  /// The length of the error list when `catch` was called.
+  errors: usize,
+}
+
+/// The state of a bottom-up merge sort, which is suspended whenever it needs to call the
+/// comparison function.
+#[derive(Debug)]
+struct SortData {
+  sp: (Span, Span),
+  /// The comparison function.
+  f: LispVal,
+  /// True if the input is a vector (and so the output should be one too).
+  vector: bool,
+  /// The input to the current pass, consisting of sorted runs of length `width`.
+  src: Vec<LispVal>,
+  /// The output of the current pass, consisting of sorted runs of length `2 * width`.
+  dst: Vec<LispVal>,
+  width: usize,
+  /// The start of the pair of runs being merged.
+  lo: usize,
+  /// The next elements of the left and right runs.
+  i: usize,
+  j: usize,
+}
+
+impl SortData {
+  fn new(sp: (Span, Span), f: LispVal, vector: bool, src: Vec<LispVal>) -> Self {
+    let j = src.len().min(1);
+    SortData { sp, f, vector, dst: Vec::with_capacity(src.len()), src, width: 1, lo: 0, i: 0, j }
+  }
+
+  /// Advance the sort, given the result `lt` of the last comparison, which was
+  /// `(f right left)`. Returns the arguments for the next comparison, or `None` if
+  /// the sort is complete.
+  fn step(&mut self, lt: Option<bool>) -> Option<Vec<LispVal>> {
+    let n = self.src.len();
+    match lt {
+      None => {}
+      Some(true) => { self.dst.push(self.src[self.j].clone()); self.j += 1 }
+      Some(false) => { self.dst.push(self.src[self.i].clone()); self.i += 1 }
+    }
+    loop {
+      if self.width >= n { return None }
+      let mid = (self.lo + self.width).min(n);
+      let hi = (self.lo + 2 * self.width).min(n);
+      if self.i < mid && self.j < hi {
+        return Some(vec![self.src[self.j].clone(), self.src[self.i].clone()])
+      }
+      self.dst.extend_from_slice(&self.src[self.i..mid]);
+      self.dst.extend_from_slice(&self.src[self.j..hi]);
+      self.lo = hi;
+      if self.lo >= n {
+        mem::swap(&mut self.src, &mut self.dst);
+        self.dst.clear();
+        self.width *= 2;
+        self.lo = 0;
+      }
+      self.i = self.lo;
+      self.j = (self.lo + self.width).min(n);
+    }
+  }
+
+  fn finish(self) -> LispVal {
+    if self.vector {
+      LispVal::new_ref(LispVal::new(LispKind::Vector(self.src)))
+    } else {
+      LispVal::list(self.src)
+    }
+  }
+}
+
 #[derive(Debug)]
 enum Stack {
   Undef,
@@ -51,6 +130,9 @@ enum Stack {
   Refine(Span, Vec<RStack>),
   Focus(Span, Vec<LispVal>),
   OnDecls(usize, (Span, Span)),
+  Catch(Box<CatchData>),
+  Profile(Span, usize),
+  Sort(Box<SortData>),
 }
 
 impl From<bool> for Stack {
@@ -122,6 +204,9 @@ impl crate::EnvDisplay for Stack {
       Stack::Refine(_, rs) => write!(f, "(refine {})", fe.to(rs)),
       Stack::Focus(_, es) => write!(f, "(focus {})", fe.to(es)),
       Stack::OnDecls(i, _) => write!(f, "(on-decls {i})"),
+      Stack::Catch(_) => write!(f, "(catch)"),
+      Stack::Profile(..) => write!(f, "(profile)"),
+      Stack::Sort(_) => write!(f, "(sort)"),
     }
   }
 }
@@ -135,43 +220,82 @@ impl LispKind {
     }
   }
 
-  fn make_map_mut<T>(&self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> (Option<T>, Option<LispVal>) {
+  fn make_container_mut<C: Container, T>(&self, f: impl FnOnce(&mut C) -> T) -> (Option<T>, Option<LispVal>) {
     match self {
-      LispKind::AtomMap(m) => {
-        let mut m = m.clone();
-        (Some(f(&mut m)), Some(LispVal::new(LispKind::AtomMap(m))))
-      }
-      LispKind::Annot(sp, e) => match e.make_map_mut(f) {
+      LispKind::Annot(sp, e) => match e.make_container_mut(f) {
         (r, None) => (r, None),
         (r, Some(e)) => (r, Some(LispVal::new(LispKind::Annot(sp.clone(), e)))),
       },
       LispKind::Ref(m) => {
         let mut f = Some(f);
-        match m.try_get_mut(|e| e.as_map_mut(f.take().expect("impossible"))) {
+        match m.try_get_mut(|e| e.as_container_mut(f.take().expect("impossible"))) {
           Some(r) => (r, None),
-          None => m.get(|e| e.make_map_mut(f.take().expect("impossible")))
+          None => m.get(|e| e.make_container_mut(f.take().expect("impossible")))
         }
       }
-      _ => (None, None)
+      _ => match C::project(self) {
+        Some(m) => {
+          let mut m = m.clone();
+          (Some(f(&mut m)), Some(LispVal::new(m.wrap())))
+        }
+        None => (None, None)
+      }
     }
   }
 }
 impl LispVal {
-  fn as_map_mut<T>(&mut self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> Option<T> {
+  fn as_container_mut<C: Container, T>(&mut self, f: impl FnOnce(&mut C) -> T) -> Option<T> {
     match self.get_mut() {
       None => {
-        let (r, new) = self.make_map_mut(f);
+        let (r, new) = self.make_container_mut(f);
         if let Some(e) = new {*self = e}
         r
       }
-      Some(LispKind::AtomMap(m)) => Some(f(m)),
-      Some(LispKind::Annot(_, e)) => Self::as_map_mut(e, f),
-      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_map_mut(e, f)),
-      Some(_) => None
+      Some(LispKind::Annot(_, e)) => Self::as_container_mut(e, f),
+      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_container_mut(e, f)),
+      Some(e) => C::project_mut(e).map(f)
     }
   }
 }
 
+/// A collection type stored in a [`LispKind`], which can be modified in place if it is
+/// behind a [`Ref`](LispKind::Ref) (or not shared), and is copied on write otherwise.
+trait Container: Clone {
+  fn project(e: &LispKind) -> Option<&Self>;
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self>;
+  fn wrap(self) -> LispKind;
+}
+
+impl Container for HashMap<AtomId, LispVal> {
+  fn project(e: &LispKind) -> Option<&Self> {
+    if let LispKind::AtomMap(m) = e {Some(m)} else {None}
+  }
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self> {
+    if let LispKind::AtomMap(m) = e {Some(m)} else {None}
+  }
+  fn wrap(self) -> LispKind { LispKind::AtomMap(self) }
+}
+
+impl Container for HashMap<HashKey<LispVal>, LispVal> {
+  fn project(e: &LispKind) -> Option<&Self> {
+    if let LispKind::HashMap(m) = e {Some(m)} else {None}
+  }
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self> {
+    if let LispKind::HashMap(m) = e {Some(m)} else {None}
+  }
+  fn wrap(self) -> LispKind { LispKind::HashMap(self) }
+}
+
+impl Container for Vec<LispVal> {
+  fn project(e: &LispKind) -> Option<&Self> {
+    if let LispKind::Vector(es) = e {Some(es)} else {None}
+  }
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self> {
+    if let LispKind::Vector(es) = e {Some(es)} else {None}
+  }
+  fn wrap(self) -> LispKind { LispKind::Vector(self) }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Dot { List(Option<usize>), DottedList }
 
@@ -286,6 +410,42 @@ impl Elaborator {
     })
   }
 
+  fn as_hash_map<T>(&self, e: &LispKind,
+    f: impl FnOnce(&HashMap<HashKey<LispVal>, LispVal>) -> SResult<T>
+  ) -> SResult<T> {
+    e.unwrapped(|e| match e {
+      LispKind::HashMap(m) => f(m),
+      _ => Err(format!("not a hash map: {}", self.print(e)))
+    })
+  }
+
+  fn as_vector<T>(&self, e: &LispKind, f: impl FnOnce(&Vec<LispVal>) -> SResult<T>) -> SResult<T> {
+    e.unwrapped(|e| match e {
+      LispKind::Vector(es) => f(es),
+      _ => Err(format!("not a vector: {}", self.print(e)))
+    })
+  }
+
+  /// Insert `v` at key `k` in the atom map or hash map `m`, or remove `k` if `v` is `None`.
+  /// Returns `None` if `m` is not a map.
+  fn map_insert(&mut self, m: &mut LispVal, k: &LispVal, v: Option<LispVal>) -> Option<SResult<()>> {
+    if m.is_hash_map() {
+      let k = HashKey(k.clone());
+      m.as_container_mut(|m: &mut HashMap<HashKey<LispVal>, LispVal>| {
+        if let Some(v) = v {m.insert(k, v);} else {m.remove(&k);}
+        Ok(())
+      })
+    } else {
+      let k = self.as_string_atom(k)
+        .ok_or_else(|| format!("expected an atom, got {}", self.print(k)));
+      m.as_container_mut(|m: &mut HashMap<AtomId, LispVal>| {
+        let k = k?;
+        if let Some(v) = v {m.insert(k, v);} else {m.remove(&k);}
+        Ok(())
+      })
+    }
+  }
+
   fn to_string(&self, e: &LispKind) -> ArcString {
     match e {
       LispKind::Ref(m) => m.get(|e| self.to_string(e)),
@@ -323,6 +483,26 @@ impl Elaborator {
     s
   }
 
+  /// Returns a structured version of [`stat`](Self::stat), with all expressions
+  /// pretty printed.
+  pub fn goal_state(&self) -> GoalState {
+    let fe = self.format_env();
+    GoalState {
+      hyps: self.lc.proof_order.iter()
+        .map(|(a, e, _)| (self.data[*a].name.to_string(), fe.pp(e, 80).to_string())).collect(),
+      goals: self.lc.goals.iter()
+        .filter_map(|g| Some(fe.pp(&g.goal_type()?, 80).to_string())).collect(),
+      mvars: self.lc.mvars.iter().filter_map(|e| e.unwrapped(|r| match *r {
+        LispKind::MVar(_, tgt) => Some(GoalMVar {
+          name: self.print(e).to_string(),
+          sort: tgt.sort().map(|s| self.data[s].name.clone()),
+          bound: tgt.bound(),
+        }),
+        _ => None,
+      })).collect(),
+    }
+  }
+
   fn head_err(&self, e: &LispKind) -> SResult<LispVal> {
     e.unwrapped(|e| match e {
       LispKind::List(es) if es.is_empty() => Err("evaluating 'hd ()'".into()),
@@ -357,6 +537,7 @@ impl Elaborator {
         Some(e) => Ok(e.clone()),
         None => self.nth(r, i - es.len()),
       },
+      LispKind::Vector(es) => Ok(es.get(i).cloned().unwrap_or_else(LispVal::undef)),
       _ => Err(format!("expected a list, got {}", self.print(e)))
     })
   }
@@ -476,6 +657,49 @@ fn set_report_mode(fe: FormatEnv<'_>, mode: &mut ReportMode, args: &[LispVal]) -
   } else {Err("invalid arguments".into())}
 }
 
+/// A stack frame of a paused lisp evaluation, as reported to a [`Debugger`].
+#[derive(Debug)]
+pub struct DebugFrame {
+  /// The name of the function running in this frame, as it appears in backtraces.
+  pub name: String,
+  /// The location being evaluated in this frame: the current function application
+  /// for the innermost frame, and the call site of the next frame for the others.
+  pub span: FileSpan,
+  /// The local variables of this frame, in order of declaration,
+  /// with their names if they are known.
+  pub locals: Vec<(Option<AtomId>, LispVal)>,
+}
+
+/// The names of the variables in the context of the function application just before `ip`
+/// in `code`, which is where a paused frame is or where it called the next frame.
+fn app_names(code: &[Ir], ip: usize) -> &[AtomId] {
+  match ip.checked_sub(1).and_then(|i| code.get(i)) {
+    Some(Ir::App(_, sp, _) | Ir::BuiltinApp(_, _, sp, _)) => &sp.2,
+    _ => &[],
+  }
+}
+
+/// Pair the local variables `ctx` with their `names`.
+fn named_locals(names: &[AtomId], ctx: &[LispVal]) -> Vec<(Option<AtomId>, LispVal)> {
+  ctx.iter().enumerate().map(|(i, e)| {
+    (names.get(i).copied().filter(|&a| a != AtomId::UNDER), e.clone())
+  }).collect()
+}
+
+/// A lisp debugger, which is consulted before each function application and can
+/// pause evaluation to inspect the stack.
+pub trait Debugger: std::fmt::Debug {
+  /// Called before every function application, with the span `sp` of the application
+  /// in `file` and the number `depth` of stack frames above the top level.
+  /// Returns true if evaluation should stop here.
+  fn should_stop(&mut self, file: &FileRef, sp: Span, depth: usize) -> bool;
+
+  /// Called when evaluation stops, with the stack frames from innermost to outermost.
+  /// Evaluation resumes when this function returns, and the time spent here does not
+  /// count towards the evaluation timeout.
+  fn stop(&mut self, elab: &Elaborator, frames: Vec<DebugFrame>);
+}
+
 #[derive(Debug)]
 struct CallStack<'a> {
   parent_file: FileRef,
@@ -534,6 +758,7 @@ impl<'a> Evaluator<'a> {
   fn new(elab: &'a mut Elaborator, orig_span: Span, code: &'a [Ir]) -> Evaluator<'a> {
     // println!("new:\n{}", elab.print(&IrList(1, code)));
     let file = elab.path.clone();
+    if let Some(p) = &mut elab.profiler { p.start() }
     Evaluator {
       elab,
       ctx: vec![],
@@ -559,11 +784,7 @@ impl<'a> Evaluator<'a> {
     let mut old = sp.map(|(sp, good)| (self.fspan(sp), good, base));
     let mut info = vec![];
     for frame in self.call_stack.iter().rev() {
-      let x = match frame.pos {
-        ProcPos::Named(_, _, a) => format!("({})", self.data[a].name).into(),
-        ProcPos::Unnamed(_) => "[fn]".into(),
-        ProcPos::Builtin(p) => format!("({p})").into()
-      };
+      let x = self.frame_name(&frame.pos).into();
       if let Some((sp, good, base)) = old.take() {
         let (sp, osp) = if good {(sp, frame.span.clone())} else {(frame.span.clone(), sp)};
         info.push((osp, base));
@@ -581,6 +802,128 @@ impl<'a> Evaluator<'a> {
     // if e.level == ErrorLevel::Error { panic!("{}", e.kind.msg()); }
   }
 
+  /// The name of a stack frame running `pos`, for backtraces.
+  fn frame_name(&self, pos: &ProcPos) -> String {
+    match *pos {
+      ProcPos::Named(_, _, a) => format!("({})", self.data[a].name),
+      ProcPos::Unnamed(_) => "[fn]".into(),
+      ProcPos::Builtin(p) => format!("({p})")
+    }
+  }
+
+  /// Give the debugger, if any, a chance to stop before the function application at `sp`.
+  fn debug_step(&mut self, sp: Span) {
+    let Some(mut dbg) = self.elab.debugger.take() else { return };
+    if dbg.should_stop(&self.file, sp, self.call_stack.len()) {
+      let mut frames = vec![];
+      let (mut span, mut locals) = (self.fspan(sp), named_locals(app_names(self.code, self.ip), &self.ctx));
+      for frame in self.call_stack.iter().rev() {
+        let name = self.frame_name(&frame.pos);
+        frames.push(DebugFrame { name, span, locals });
+        span = frame.span.clone();
+        locals = named_locals(app_names(frame.parent_code, frame.parent_ip), &frame.parent_ctx);
+      }
+      frames.push(DebugFrame { name: "[top]".into(), span, locals });
+      let start = Instant::now();
+      dbg.stop(self.elab, frames);
+      if let Some(t) = &mut self.elab.cur_timeout { *t += start.elapsed() }
+    }
+    self.elab.debugger = Some(dbg);
+  }
+
+  /// Take a profiling sample, if profiling is enabled and a sample is due (or `force` is set).
+  /// `builtin` is the builtin that is being evaluated, if it is not on the call stack.
+  fn profile(&mut self, builtin: Option<BuiltinProc>, force: bool) {
+    if !self.elab.profiler.as_ref().is_some_and(|p| force || p.due()) { return }
+    let mut frames = Vec::<Box<str>>::new();
+    for frame in &self.call_stack {
+      frames.push(match frame.pos {
+        ProcPos::Named(_, _, a) => format!("{}", self.data[a].name).into(),
+        ProcPos::Unnamed(_) => continue,
+        ProcPos::Builtin(p) => p.to_str().into(),
+      })
+    }
+    if let Some(p) = builtin {
+      if frames.last().is_none_or(|f| **f != *p.to_str()) { frames.push(p.to_str().into()) }
+    }
+    if let Some(p) = &mut self.elab.profiler { p.sample(frames) }
+  }
+
+  /// Remove the errors (but not warnings or info messages) reported after the first `n`
+  /// reports, and return the first of them.
+  fn take_errors(&mut self, n: usize) -> Option<ElabError> {
+    let mut first = None;
+    for e in self.elab.errors.split_off(n) {
+      if e.level == ErrorLevel::Error { first.get_or_insert(e); } else { self.elab.errors.push(e) }
+    }
+    first
+  }
+
+  fn sort_resume(&mut self) -> Result<()> {
+    let lt = self.pop_lisp().truthy();
+    stack_match!(let Some(Stack::Sort(data)) = self.stack.last_mut());
+    if let Some(args) = data.step(Some(lt)) {
+      let (sp, f) = (data.sp, data.f.clone());
+      self.ip -= 1;
+      return self.app(false, &sp, &f, args)
+    }
+    stack_match!(let Some(Stack::Sort(data)) = self.stack.pop());
+    self.stack.push(data.finish().into());
+    Ok(())
+  }
+
+  fn catch_resume(&mut self) -> Result<()> {
+    let ret = self.pop_lisp();
+    stack_match!(let Some(Stack::Catch(mut c)) = self.stack.pop());
+    if let Some((handler, snapshot)) = c.handler.take() {
+      if let Some(err) = self.take_errors(c.errors) {
+        self.lc.restore(snapshot);
+        let sp = c.sp;
+        self.stack.push(Stack::Catch(c));
+        self.ip -= 1;
+        return self.app(false, &sp, &handler, vec![LispVal::string(err.kind.msg().into())])
+      }
+    }
+    self.stack.push(ret.into());
+    Ok(())
+  }
+
+  /// Unwind the stack to the innermost `catch` whose handler has not yet been called,
+  /// and roll back the proof state. Returns the handler and the value to call it with,
+  /// or the original error if it cannot be caught. An uncaught `raise` value is kept,
+  /// so that a caller outside this evaluation (such as `await`) can pass it on.
+  fn unwind(&mut self, err: ElabError) -> Result<(LispVal, (Span, Span), LispVal)> {
+    if self.cancel.load(Ordering::Relaxed) || self.cur_timeout.is_some_and(|t| t < Instant::now()) {
+      return Err(err)
+    }
+    let Some(i) = self.stack.iter().rposition(|s|
+      matches!(s, Stack::Catch(c) if c.handler.is_some())) else { return Err(err) };
+    let raised = self.elab.raised.take();
+    stack_match!(let Stack::Catch(c) = &mut self.stack[i]);
+    let (handler, snapshot) = c.handler.take().expect("checked above");
+    let (sp, depth, errors) = (c.sp, c.depth, c.errors);
+    while self.call_stack.len() > depth { self.ret() }
+    self.stack.truncate(i + 1);
+    self.take_errors(errors);
+    self.lc.restore(snapshot);
+    let val = raised.unwrap_or_else(|| LispVal::string(err.kind.msg().into()));
+    Ok((handler, sp, val))
+  }
+
+  fn profile_resume(&mut self) {
+    let ret = self.pop_lisp();
+    stack_match!(let Some(Stack::Profile(sp, n)) = self.stack.pop());
+    self.profile(None, true);
+    if let Some(p) = &mut self.elab.profiler {
+      let (profile, done) = p.pop_local(n);
+      if done { self.elab.profiler = None }
+      let mut table = vec![];
+      profile.write_top(20, &mut table).expect("writing to a vec");
+      self.info(sp, false, "profile", String::from_utf8_lossy(&table).trim_end().to_owned());
+    }
+    self.stack.push(ret.into())
+  }
+
   fn stack_span(&self, mut n: usize) -> Option<FileSpan> {
     for frame in self.call_stack.iter().rev() {
       match n.checked_sub(1) {
@@ -630,7 +973,7 @@ impl<'a> Evaluator<'a> {
       LispKind::Undef => Ok(Some(old)),
       LispKind::AtomMap(newmap) => {
         if newmap.is_empty() { return Ok(Some(old)) }
-        let mut opt = Some(old.as_map_mut(mem::take).ok_or_else(||
+        let mut opt: Option<HashMap<AtomId, LispVal>> = Some(old.as_container_mut(mem::take).ok_or_else(||
           self.err(Some((sp, false)), "merge-map: not an atom-map"))?);
         let oldmap = opt.as_mut().expect("impossible");
         let mut todo = vec![];
@@ -646,7 +989,7 @@ impl<'a> Evaluator<'a> {
         }
         if todo.is_empty() {
           Ok(Some({
-            if old.is_ref() && old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
+            if old.is_ref() && old.as_container_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
             else { LispVal::new(LispKind::AtomMap(opt.take().expect("impossible"))) }
           }))
         } else {
@@ -679,7 +1022,7 @@ impl<'a> Evaluator<'a> {
       let Some(Stack::MergeMap(data)) = self.stack.pop() else { unreachable!() };
       let MergeMapData { mut old, map, .. } = *data;
       let mut opt = Some(map);
-      if !old.is_ref() || old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_none() {
+      if !old.is_ref() || old.as_container_mut(|m| *m = opt.take().expect("impossible")).is_none() {
         old = LispVal::new(LispKind::AtomMap(opt.take().expect("impossible")))
       }
       self.stack.push(old.into());
@@ -1082,8 +1425,16 @@ make_builtins! { self, tail, sp1, sp2, args,
   Async: AtLeast(1) => {
     let proc = args.remove(0);
     let sp = proc.fspan().map_or(sp2, |fsp| fsp.span);
-    // TODO: actually async this
-    return self.app(tail, &(sp1, sp), &proc, args)
+    LispVal::proc(Proc::Promise(RefCell::new(self.elab.spawn(sp, &proc, &args)))).into()
+  },
+  Await: Exact(1) => {
+    match args[0].unwrapped(|e| match e {
+      LispKind::Proc(Proc::Promise(p)) => Some(self.elab.await_promise(p)),
+      _ => None
+    }) {
+      Some(e) => e?.into(),
+      None => try1!(Err(format!("expected a promise, got {}", self.print(&args[0]))))
+    }
   },
   IsAtomMap: Exact(1) => args[0].is_map().into(),
   NewAtomMap: AtLeast(0) => {
@@ -1099,49 +1450,47 @@ make_builtins! { self, tail, sp1, sp2, args,
     }
     LispVal::new_ref(LispVal::new(LispKind::AtomMap(m))).into()
   },
+  IsHashMap: Exact(1) => args[0].is_hash_map().into(),
+  NewHashMap: AtLeast(0) => {
+    #[allow(clippy::mutable_key_type)]
+    let mut m = HashMap::new();
+    for e in args {
+      let mut u = Uncons::from(e);
+      let k = HashKey(try1!(u.next().ok_or("invalid arguments")));
+      let ret = u.next();
+      if !u.exactly(0) {try1!(Err("invalid arguments"))}
+      if let Some(v) = ret {m.insert(k, v);} else {m.remove(&k);}
+    }
+    LispVal::new_ref(LispVal::new(LispKind::HashMap(m))).into()
+  },
   Lookup: AtLeast(2) => {
-    match self.as_string_atom(&args[1]) {
-      None => Stack::Undef,
-      Some(k) => {
-        let e = try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())));
-        if let Some(e) = e {e} else {
-          let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
-          if v.is_proc() {
-            let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
-            return self.app(tail, &(sp1, sp), &v, vec![])
-          }
-          v
-        }.into()
+    let e = if args[0].is_hash_map() {
+      let k = HashKey(args[1].clone());
+      try1!(self.as_hash_map(&args[0], |m| Ok(m.get(&k).cloned())))
+    } else if let Some(k) = self.as_string_atom(&args[1]) {
+      try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())))
+    } else {
+      Some(LispVal::undef())
+    };
+    if let Some(e) = e {e} else {
+      let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
+      if v.is_proc() {
+        let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
+        return self.app(tail, &(sp1, sp), &v, vec![])
       }
-    }
+      v
+    }.into()
   },
   Insert: AtLeast(2) => {
-    try1!(try1!(args[0].as_ref_mut(|r| {
-      r.as_map_mut(|m| -> SResult<_> {
-        let k = self.as_string_atom(&args[1])
-          .ok_or_else(|| format!("expected an atom, got {}", self.print(&args[1])))?;
-        match args.get(2) {
-          Some(v) => {m.insert(k, v.clone());}
-          None => {m.remove(&k);}
-        }
-        Ok(())
-      })
-    }).unwrap_or(None).ok_or("expected a mutable map")));
+    try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], args.get(2).cloned()))
+      .flatten().ok_or("expected a mutable map")));
     Stack::Undef
   },
   InsertNew: AtLeast(2) => {
     let mut it = args.into_iter();
     let mut m = it.next().unwrap();
     let k = it.next().unwrap();
-    let k = self.as_string_atom(&k)
-      .ok_or_else(|| format!("expected an atom, got {}", self.print(&k)));
-    try1!(try1!(m.as_map_mut(|m| -> SResult<_> {
-      match it.next() {
-        Some(v) => {m.insert(k?, v);}
-        None => {m.remove(&k?);}
-      }
-      Ok(())
-    }).ok_or("expected a map")));
+    try1!(try1!(self.map_insert(&mut m, &k, it.next()).ok_or("expected a map")));
     Stack::Undef
   },
   MergeMap: AtLeast(0) => {
@@ -1156,6 +1505,57 @@ make_builtins! { self, tail, sp1, sp2, args,
       } else { LispVal::proc(Proc::MergeMap(arg1.into_merge_strategy())) }
     } else { LispVal::proc(Proc::MergeMap(None)) }.into()
   },
+  IsVector: Exact(1) => args[0].is_vector().into(),
+  NewVector: AtLeast(0) => LispVal::new_ref(LispVal::new(LispKind::Vector(args))).into(),
+  VectorLen: Exact(1) => LispVal::number(try1!(self.as_vector(&args[0], |es| Ok(es.len()))).into()).into(),
+  VectorSet: Exact(3) => {
+    let n = try1!(args[1].as_int(|n| n.to_usize().unwrap_or(usize::MAX))
+      .ok_or("expected a number"));
+    let v = args.pop().unwrap();
+    try1!(try1!(args[0].as_ref_mut(|r| r.as_container_mut(|es: &mut Vec<LispVal>| -> SResult<_> {
+      let len = es.len();
+      *es.get_mut(n).ok_or_else(|| format!("index {n} out of range for vector of length {len}"))? = v;
+      Ok(())
+    })).flatten().ok_or("expected a mutable vector")));
+    Stack::Undef
+  },
+  VectorPush: Exact(2) => {
+    let v = args.pop().unwrap();
+    try1!(args[0].as_ref_mut(|r| r.as_container_mut(|es: &mut Vec<LispVal>| es.push(v)))
+      .flatten().ok_or("expected a mutable vector"));
+    Stack::Undef
+  },
+  VectorPop: Exact(1) => {
+    try1!(args[0].as_ref_mut(|r| r.as_container_mut(Vec::pop))
+      .flatten().ok_or("expected a mutable vector")).unwrap_or_else(LispVal::undef).into()
+  },
+  VectorToList: Exact(1) => LispVal::list(try1!(self.as_vector(&args[0], |es| Ok(es.clone())))).into(),
+  Sort: Exact(2) => {
+    let es = args.pop().unwrap();
+    let f = args.pop().unwrap();
+    let mut data = Box::new(match es.unwrapped(|e| match e {
+      LispKind::Vector(es) => Some(es.clone()),
+      _ => None
+    }) {
+      Some(es) => SortData::new((sp1, sp2), f, true, es),
+      None => {
+        let mut u = Uncons::from(es.clone());
+        let vec = (&mut u).collect();
+        if !u.is_empty() { try1!(Err(format!("sort: not a list: {}", self.print(&es)))) }
+        SortData::new((sp1, sp2), f, false, vec)
+      }
+    });
+    match data.step(None) {
+      None => data.finish().into(),
+      Some(cmp) => {
+        let fsp = self.fspan(sp1);
+        self.call(tail, &[Ir::Sort], None, fsp, ProcPos::Builtin(BuiltinProc::Sort), vec![]);
+        let f = data.f.clone();
+        self.stack.push(Stack::Sort(data));
+        return self.app(false, &(sp1, sp2), &f, cmp)
+      }
+    }
+  },
   SetTimeout: Exact(1) => {
     match try1!(args[0].as_int(BigInt::to_u64).ok_or("expected a number")) {
       None | Some(0) => {self.timeout = None; self.cur_timeout = None},
@@ -1250,6 +1650,41 @@ make_builtins! { self, tail, sp1, sp2, args,
     let x = try1!(args[0].as_atom().ok_or("expected an atom"));
     self.get_decl(args[0].fspan(), x).into()
   },
+  Profile: Exact(1) => {
+    let proc = args.pop().unwrap();
+    self.profile(None, true);
+    let fsp = self.fspan(sp1);
+    self.call(tail, &[Ir::Profile], None, fsp, ProcPos::Builtin(BuiltinProc::Profile), vec![]);
+    let p = match &mut self.elab.profiler {
+      Some(p) => p,
+      None => {
+        let mut p = Profiler::new(None, self.elab.stmt_name_at(self.orig.span.start));
+        p.start();
+        self.elab.profiler.insert(p)
+      }
+    };
+    let n = p.push_local();
+    self.stack.push(Stack::Profile(sp1, n));
+    return self.app(false, &(sp1, sp2), &proc, vec![])
+  },
+  Raise: Exact(1) => {
+    let msg = format!("uncaught exception: {}", self.print(&args[0]));
+    self.elab.raised = args.pop();
+    try1!(Err(msg))
+  },
+  Catch: Exact(2) => {
+    let handler = args.pop().unwrap();
+    let thunk = args.pop().unwrap();
+    let fsp = self.fspan(sp1);
+    self.call(tail, &[Ir::Catch], None, fsp, ProcPos::Builtin(BuiltinProc::Catch), vec![]);
+    self.stack.push(Stack::Catch(Box::new(CatchData {
+      sp: (sp1, sp2),
+      handler: Some((handler, self.lc.snapshot())),
+      depth: self.call_stack.len(),
+      errors: self.errors.len(),
+    })));
+    return self.app(false, &(sp1, sp2), &thunk, vec![])
+  },
   OnDecls: Exact(1) => {
     let proc = args.pop().unwrap();
     let sp = proc.fspan().map_or(sp2, |fsp| fsp.span);
@@ -1500,7 +1935,10 @@ impl<'a> Evaluator<'a> {
       let spec = func.spec();
       if !spec.valid(args.len()) { throw!(sp.0, spec.arity_error()) }
       match func {
-        &Proc::Builtin(func) => self.evaluate_builtin(tail, sp, func, args)?,
+        &Proc::Builtin(func) => {
+          self.evaluate_builtin(tail, sp, func, args)?;
+          self.profile(Some(func), false)
+        }
         Proc::Lambda {pos, env, code, ..} => {
           #[allow(clippy::useless_transmute)]
           // Safety: Unfortunately we're fighting the borrow checker here. The problem is that
@@ -1576,6 +2014,10 @@ impl<'a> Evaluator<'a> {
           let ret = c.borrow_mut().call(self, sp, args)?;
           self.stack.push(ret.into())
         }
+        Proc::Promise(p) => {
+          let ret = self.elab.await_promise(p)?;
+          self.stack.push(ret.into())
+        }
       }
       Ok(())
     })
@@ -1592,9 +2034,13 @@ impl<'a> Evaluator<'a> {
   }
 
   fn call_refine(&mut self, tail: bool, state: RState) -> Result<()> {
+    let val = self.stack.last_mut().expect("underflow");
+    stack_match!(let Stack::Refine(_, ref mut stack) = *val);
+    let res = self.elab.run_refine(self.orig.span, stack, state);
+    self.profile(Some(BuiltinProc::Refine), false);
     let val = self.stack.last_mut().expect("underflow");
     stack_match!(let Stack::Refine(sp, ref mut stack) = *val);
-    match self.elab.run_refine(self.orig.span, stack, state) {
+    match res {
       Err(e) => return Err(self.err(Some((e.pos, true)), e.kind.msg())),
       Ok(RefineResult::Ret(e)) => {
         self.elab.lc.clean_mvars();
@@ -1760,8 +2206,8 @@ impl<'a> Evaluator<'a> {
         Ir::ArityError(..) | Ir::FocusStart(_) | Ir::RefineGoal(_) | Ir::FocusFinish |
         Ir::SetMergeStrategy(..) | Ir::LocalDef(_) | Ir::GlobalDef(..) | Ir::SetDoc(..) |
         Ir::Lambda(..) | Ir::Branch(..) | Ir::TestPatternResume | Ir::BranchFail(_) |
-        Ir::Map | Ir::Have | Ir::RefineResume | Ir::AddThm | Ir::MergeMap | Ir::OnDecls
-        => panic!("unexpected in pattern mode"),
+        Ir::Map | Ir::Have | Ir::RefineResume | Ir::AddThm | Ir::MergeMap | Ir::OnDecls |
+        Ir::Catch | Ir::Profile | Ir::Sort => panic!("unexpected in pattern mode"),
       };
       self.ip += 1;
     }
@@ -1960,8 +2406,24 @@ impl<'a> Evaluator<'a> {
     Ok(())
   }
 
-  #[allow(clippy::never_loop, clippy::many_single_char_names)]
+  /// Run the evaluator until it returns a value. An error is passed to the handler of
+  /// the innermost enclosing `catch`, if there is one, and evaluation continues from there.
   fn run(&mut self) -> Result<LispVal> {
+    let mut res = self.run_core();
+    loop {
+      match res {
+        Ok(e) => return Ok(e),
+        Err(err) => {
+          let (handler, sp, val) = self.unwind(err)?;
+          res = self.app(false, &sp, &handler, vec![val]).and_then(|()| self.run_core())
+        }
+      }
+    }
+  }
+
+  /// Run the evaluator until it returns a value or throws an error.
+  #[allow(clippy::never_loop, clippy::many_single_char_names)]
+  fn run_core(&mut self) -> Result<LispVal> {
     macro_rules! throw {($sp:expr, $e:expr) => {{
       let err = $e;
       return Err(self.err(Some(($sp, false)), err))
@@ -2022,15 +2484,20 @@ impl<'a> Evaluator<'a> {
           }
           Ir::DottedList(n) => self.dotted_list(n),
           Ir::App(tail, ref sp, n) => {
+            self.debug_step(sp.0);
+            self.profile(None, false);
             let args = self.popn(n).map(Stack::into_lisp).collect();
             let func = self.pop_lisp();
-            self.app(tail, sp, &func, args)?
+            self.app(tail, &(sp.0, sp.1), &func, args)?
           }
           Ir::BuiltinApp(tail, func, ref sp, n) => {
             debug_assert!(func.spec().valid(n));
+            self.debug_step(sp.0);
+            self.profile(None, false);
             let args = self.popn(n).map(Stack::into_lisp).collect();
             self.heartbeat()?;
-            self.evaluate_builtin(tail, sp, func, args)?
+            self.evaluate_builtin(tail, &(sp.0, sp.1), func, args)?;
+            self.profile(Some(func), false)
           }
           Ir::ArityError(sp, spec) => throw!(sp, spec.arity_error()),
           Ir::AppHead(sp) => {
@@ -2092,6 +2559,9 @@ impl<'a> Evaluator<'a> {
           Ir::AddThm => self.add_thm_resume()?,
           Ir::MergeMap => self.merge_map_resume()?,
           Ir::OnDecls => self.on_decls_resume()?,
+          Ir::Catch => self.catch_resume()?,
+          Ir::Profile => self.profile_resume(),
+          Ir::Sort => self.sort_resume()?,
 
           // Listing the instructions explicitly so that we get missing match arm errors
           Ir::PatternResult(_) | Ir::PatternAtom(_) | Ir::PatternQuoteAtom(_) |
@@ -2109,7 +2579,11 @@ impl<'a> Evaluator<'a> {
             if let Some(e) = e { self.stack.push(e.into()) }
           }
           Some(_) => panic!("stack type error"),
-          None => return Ok(e.expect("stack type error"))
+          None => {
+            self.profile(None, true);
+            if let Some(p) = &mut self.elab.profiler { p.stop() }
+            return Ok(e.expect("stack type error"))
+          }
         }
       }
     }
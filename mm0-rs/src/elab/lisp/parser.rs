@@ -53,12 +53,17 @@ pub enum Ir {
   /// and put the result on the stack.
   /// * `app: [f, a1, ..., an] -> [f(a1, ..., an)]`
   /// * `tail-app: [ret, ..., f, a1, ..., an] -> [ret, f(a1, ..., an)]`
-  App(bool, Box<(Span, Span)>, usize),
+  ///
+  /// The box contains the spans of the application and the head, and the names of the
+  /// variables in the context (which are only used by the debugger).
+  App(bool, Box<(Span, Span, Arc<[AtomId]>)>, usize),
   /// Function application: given `n` arguments on the stack, call the builtin procedure `f`
   /// and put the result on the stack.
   /// * `app: [a1, ..., an] -> [f(a1, ..., an)]`
   /// * `tail-app: [ret, ..., a1, ..., an] -> [ret, f(a1, ..., an)]`
-  BuiltinApp(bool, BuiltinProc, Box<(Span, Span)>, usize),
+  ///
+  /// The box is the same as in [`App`](Self::App).
+  BuiltinApp(bool, BuiltinProc, Box<(Span, Span, Arc<[AtomId]>)>, usize),
   /// A constant-propagated arity error in a builtin application.
   ArityError(Span, ProcSpec),
   /// Applies the head of the stack to the element under it. `[x, f] -> [f(x)]`
@@ -146,6 +151,19 @@ pub enum Ir {
   /// * if `decls(i)` exists, `-> [f, (on-decls (i+1))]` and loop and evaluate `f(decls(i))`
   /// * otherwise `-> [#undef]`
   OnDecls,
+  /// Receive the result of the thunk or the handler called by `catch`. `[(catch), ret] ->`:
+  /// * if the thunk reported an error, `-> [(catch)]` and roll back, evaluate `handler(err)`,
+  ///   and loop
+  /// * otherwise `-> [ret]`
+  Catch,
+  /// Receive the result of the function called by `profile`.
+  /// `[(profile), ret] -> [ret]` and report the collected profile.
+  Profile,
+  /// Implementation of the `sort` inner loop. `[(sort data), lt] ->`:
+  /// * `-> [(sort data')]` and loop and evaluate `f(right, left)`, if another comparison
+  ///   is needed, where `data'` has consumed the result `lt` of the last comparison
+  /// * otherwise `-> [sorted]`
+  Sort,
 
   /// A pattern that always returns the given result.
   /// * `PatternResult(false) := fail`
@@ -277,6 +295,9 @@ impl Ir {
       Ir::AddThm => write!(f, "add-thm"),
       Ir::MergeMap => write!(f, "merge-map"),
       Ir::OnDecls => write!(f, "on-decls"),
+      Ir::Catch => write!(f, "catch"),
+      Ir::Profile => write!(f, "profile"),
+      Ir::Sort => write!(f, "sort"),
       Ir::PatternResult(false) => write!(f, "> fail"),
       Ir::PatternResult(true) => write!(f, "> skip"),
       Ir::PatternAtom(n) => write!(f, "> var {n}"),
@@ -338,6 +359,9 @@ impl Remap for Ir {
       Ir::Const(v) => Ir::Const(unsafe { v.freeze() }.remap(r)),
       &Ir::SetMergeStrategy(sp, a) => Ir::SetMergeStrategy(sp, a.remap(r)),
       &Ir::GlobalDef(sp, sp2, a) => Ir::GlobalDef(sp, sp2, a.remap(r)),
+      &Ir::App(tail, ref sp, n) => Ir::App(tail, Box::new((sp.0, sp.1, sp.2.remap(r))), n),
+      &Ir::BuiltinApp(tail, p, ref sp, n) =>
+        Ir::BuiltinApp(tail, p, Box::new((sp.0, sp.1, sp.2.remap(r))), n),
       &Ir::Lambda(name, ref args) => Ir::Lambda(name, Box::new((args.0, args.1, args.2.remap(r)))),
       &Ir::PatternQuoteAtom(a) => Ir::PatternQuoteAtom(a.remap(r)),
       &Ir::PatternQExprAtom(a) => Ir::PatternQExprAtom(a.remap(r)),
@@ -385,6 +409,9 @@ struct LispParser<'a> {
   elab: &'a mut Elaborator,
   ctx: LocalCtx,
   code: Vec<Ir>,
+  /// The names of the variables in the context at the last function application,
+  /// which are shared between applications in the same context.
+  names: Arc<[AtomId]>,
 }
 impl Deref for LispParser<'_> {
   type Target = Elaborator;
@@ -432,7 +459,13 @@ impl ExprCtx {
 
 impl<'a> LispParser<'a> {
   fn new(elab: &'a mut Elaborator) -> Self {
-    Self { elab, ctx: LocalCtx::new(), code: vec![] }
+    Self { elab, ctx: LocalCtx::new(), code: vec![], names: Arc::new([]) }
+  }
+
+  /// The span information for a function application, see [`Ir::App`].
+  fn app_info(&mut self, sp: Span, head: Span) -> (Span, Span, Arc<[AtomId]>) {
+    if *self.names != *self.ctx.ctx { self.names = self.ctx.ctx.as_slice().into() }
+    (sp, head, self.names.clone())
   }
 
   fn push_def(&mut self,
@@ -735,14 +768,16 @@ impl LispParser<'_> {
         let ((sp, x, stk), e2) = self.let_var(l)?;
         let n = self.ctx.push(x);
         self.code.push(Ir::Undef);
-        self.code.push(Ir::BuiltinApp(false, BuiltinProc::NewRef, Box::new((sp, sp)), 1));
+        let info = Box::new(self.app_info(sp, sp));
+        self.code.push(Ir::BuiltinApp(false, BuiltinProc::NewRef, info, 1));
         self.code.push(Ir::LocalDef(n));
         ds.push((sp, x, stk, e2, n));
       }
       for (sp, x, stk, e2, n) in ds {
         self.code.push(Ir::Local(n));
         self.def_ir(sp, true, false, e2, stk)?;
-        self.code.push(Ir::BuiltinApp(false, BuiltinProc::SetWeak, Box::new((sp, sp)), 2));
+        let info = Box::new(self.app_info(sp, sp));
+        self.code.push(Ir::BuiltinApp(false, BuiltinProc::SetWeak, info, 2));
         let m = self.ctx.push(x);
         self.code.push(Ir::LocalDef(m));
       }
@@ -848,7 +883,8 @@ impl LispParser<'_> {
                   self.code.push(Ir::PatternTestPause);
                   self.expr(ExprCtx::EVAL.mask_def(), test)?;
                   if let Some(p) = self.pop_builtin() {
-                    self.code.push(Ir::BuiltinApp(false, p, Box::new((test.span, test.span)), 1));
+                    let info = Box::new(self.app_info(test.span, test.span));
+                    self.code.push(Ir::BuiltinApp(false, p, info, 1));
                   } else {
                     self.code.push(Ir::AppHead(test.span));
                   }
@@ -1148,12 +1184,14 @@ impl LispParser<'_> {
               local = false;
               let spec = p.spec();
               if spec.valid(n) {
-                self.code.push(Ir::BuiltinApp(ctx.tail, p, Box::new((e.span, es[0].span)), n));
+                let info = Box::new(self.app_info(e.span, es[0].span));
+                self.code.push(Ir::BuiltinApp(ctx.tail, p, info, n));
               } else {
                 self.code.push(Ir::ArityError(e.span, spec));
               }
             } else {
-              self.code.push(Ir::App(ctx.tail, Box::new((e.span, es[0].span)), n));
+              let info = Box::new(self.app_info(e.span, es[0].span));
+              self.code.push(Ir::App(ctx.tail, info, n));
             }
             self.spans.insert(es[0].span, if local {
               ObjectKind::LispVar(false, true, x)
@@ -1271,7 +1309,8 @@ impl LispParser<'_> {
       } else {
         self.expr(ExprCtx::EVAL.mask_def(), &es[0])?;
         let n = self.exprs(ExprsCtx::App, &es[1..])?;
-        self.code.push(Ir::App(ctx.tail, Box::new((e.span, es[0].span)), n));
+        let info = Box::new(self.app_info(e.span, es[0].span));
+        self.code.push(Ir::App(ctx.tail, info, n));
         if !ctx.keep { self.code.push(Ir::Drop(1)) }
       },
       &SExprKind::Formula(f) => {
@@ -82,7 +82,9 @@ impl LispKind {
       LispKind::List(es) => es.is_empty(),
       LispKind::DottedList(..) |
       LispKind::AtomMap(..) |
+      LispKind::HashMap(..) |
       LispKind::Goal(..) => false,
+      LispKind::Vector(es) => es.is_empty(),
       LispKind::Atom(..) |
       LispKind::MVar(..) |
       LispKind::Proc(..) |
@@ -175,11 +175,22 @@ impl EnvDisplay for LispKind {
       LispKind::Proc(Proc::ProofThunk(x, _)) => write!(f, "#<proof of {}>", fe.to(x)),
       LispKind::Proc(Proc::MergeMap(_)) => write!(f, "#<merge-map>"),
       LispKind::Proc(Proc::Dyn(c)) => EnvDisplay::fmt(&**c.borrow(), fe, f),
+      LispKind::Proc(Proc::Promise(_)) => write!(f, "#<promise>"),
       LispKind::AtomMap(m) => {
         write!(f, "(atom-map!")?;
         for (a, v) in m {write!(f, " [{} {}]", fe.data[*a].name, fe.to(v))?}
         write!(f, ")")
       }
+      LispKind::HashMap(m) => {
+        write!(f, "(hash-map!")?;
+        for (k, v) in m {write!(f, " [{} {}]", fe.to(&k.0), fe.to(v))?}
+        write!(f, ")")
+      }
+      LispKind::Vector(es) => {
+        write!(f, "(vector!")?;
+        for e in es {write!(f, " {}", fe.to(e))?}
+        write!(f, ")")
+      }
       LispKind::Ref(m) if m.too_many_readers() => write!(f, "#<ref>"),
       LispKind::Ref(m) => m.get(|e| e.fmt(fe, f)),
       &LispKind::MVar(n, _) => write!(f, "?{}", alphanumber(n)),
@@ -0,0 +1,253 @@
+//! A sampling profiler for lisp evaluation.
+//!
+//! When a [`Profiler`] is attached to the elaborator, the evaluator checks the clock at each
+//! function application and after each builtin, and once every [`SAMPLE_INTERVAL`] it charges
+//! the time and the number of lisp values allocated since the previous sample to the current
+//! call stack. A stack consists of the name of the statement being elaborated, followed by the
+//! named lisp procedures and builtins that are running. Unification and type inference are
+//! timed separately, and charged to `unify` and `infer-type` frames on top of the stack.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+use std::time::Duration;
+use instant::Instant;
+use crate::{Elaborator, MutexExt};
+
+/// The minimum time between two samples.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+thread_local!(static ALLOCS: Cell<u64> = const { Cell::new(0) });
+
+/// The number of [`Profiler`]s in existence. Allocations are only counted while this is
+/// nonzero, so that they cost nothing more than a load when nothing is being profiled.
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Count the allocation of a lisp value on the current thread, if anything is being profiled.
+#[inline] pub(crate) fn count_alloc() {
+  if ACTIVE.load(Ordering::Relaxed) != 0 { ALLOCS.with(|n| n.set(n.get().wrapping_add(1))) }
+}
+
+/// The current time and allocation count, from which a [`Cost`] can be measured.
+fn now() -> (Instant, u64) { (Instant::now(), ALLOCS.with(Cell::get)) }
+
+/// The resources spent in a call stack.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cost {
+  /// The elapsed time.
+  pub time: Duration,
+  /// The number of lisp values allocated.
+  pub allocs: u64,
+}
+
+impl AddAssign for Cost {
+  fn add_assign(&mut self, other: Self) {
+    self.time += other.time;
+    self.allocs += other.allocs;
+  }
+}
+
+impl Cost {
+  /// The resources spent since `start` (as returned by [`now`]).
+  fn since(start: (Instant, u64)) -> (Self, (Instant, u64)) {
+    let end = now();
+    (Cost { time: end.0 - start.0, allocs: end.1.wrapping_sub(start.1) }, end)
+  }
+
+  fn saturating_sub(self, other: Self) -> Self {
+    Cost {
+      time: self.time.saturating_sub(other.time),
+      allocs: self.allocs.saturating_sub(other.allocs),
+    }
+  }
+
+  fn is_zero(&self) -> bool { self.time.is_zero() && self.allocs == 0 }
+}
+
+/// Native operations that are timed separately from the lisp code that calls them.
+#[derive(Clone, Copy, Debug)]
+pub enum Native {
+  /// Unification of expressions, during `refine`.
+  Unify,
+  /// Type inference for proofs, during `refine` and `infer-type`.
+  InferType,
+}
+
+impl Native {
+  const ALL: [Native; 2] = [Native::Unify, Native::InferType];
+
+  /// The name of the stack frame for this operation.
+  #[must_use] pub fn name(self) -> &'static str {
+    match self {
+      Native::Unify => "unify",
+      Native::InferType => "infer-type",
+    }
+  }
+}
+
+/// A collection of samples, keyed by call stack (outermost frame first).
+#[derive(Debug, Default)]
+pub struct Profile(HashMap<Box<[Box<str>]>, Cost>);
+
+impl Profile {
+  fn add(&mut self, stack: &[Box<str>], cost: Cost) {
+    if let Some(c) = self.0.get_mut(stack) { *c += cost } else { self.0.insert(stack.into(), cost); }
+  }
+
+  /// Write the profile in the "folded stacks" format accepted by `flamegraph.pl` and `inferno`:
+  /// one line per call stack, with the frames separated by `;`, followed by the time
+  /// spent in that stack in microseconds.
+  pub fn write_folded(&self, mut w: impl Write) -> io::Result<()> {
+    let mut stacks = self.0.iter().collect::<Vec<_>>();
+    stacks.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    for (stack, cost) in stacks {
+      let us = cost.time.as_micros();
+      if us != 0 { writeln!(w, "{} {us}", stack.join(";"))? }
+    }
+    Ok(())
+  }
+
+  /// Write a table of the `n` functions with the highest self time for each statement.
+  /// Statements are listed in decreasing order of total time. The total time of a function
+  /// includes the functions it calls, while the self time and allocations do not.
+  pub fn write_top(&self, n: usize, mut w: impl Write) -> io::Result<()> {
+    #[derive(Default)]
+    struct Row { self_cost: Cost, total: Duration }
+    let mut roots = HashMap::<&str, (Cost, HashMap<&str, Row>)>::new();
+    for (stack, &cost) in &self.0 {
+      let Some((root, frames)) = stack.split_first() else { continue };
+      let (total, rows) = roots.entry(root).or_default();
+      *total += cost;
+      let leaf = frames.last().map_or("[top]", |s| s);
+      rows.entry(leaf).or_default().self_cost += cost;
+      for (i, f) in frames.iter().enumerate() {
+        if !frames[..i].contains(f) { rows.entry(f).or_default().total += cost.time }
+      }
+      rows.entry("[top]").or_default().total += cost.time;
+    }
+    let ms = |d: Duration| format!("{}.{:03}ms", d.as_millis(), d.subsec_micros() % 1000);
+    let mut roots = roots.into_iter().collect::<Vec<_>>();
+    roots.sort_unstable_by(|a, b| b.1.0.time.cmp(&a.1.0.time).then(a.0.cmp(b.0)));
+    for (root, (total, rows)) in roots {
+      writeln!(w, "{root}: {}, {} allocs", ms(total.time), total.allocs)?;
+      writeln!(w, "  {:>10} {:>10} {:>10}  function", "self", "total", "allocs")?;
+      let mut rows = rows.into_iter().collect::<Vec<_>>();
+      rows.sort_unstable_by(|a, b| b.1.self_cost.time.cmp(&a.1.self_cost.time).then(a.0.cmp(b.0)));
+      for (name, row) in rows.into_iter().take(n) {
+        writeln!(w, "  {:>10} {:>10} {:>10}  {name}",
+          ms(row.self_cost.time), ms(row.total), row.self_cost.allocs)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The profiling state of an elaborator.
+#[derive(Debug)]
+pub struct Profiler {
+  /// The profile shared with the elaborators of other files, if any.
+  shared: Option<Arc<Mutex<Profile>>>,
+  /// The profiles being collected by `(profile)` calls in progress, innermost last.
+  local: Vec<Profile>,
+  /// The name of the statement being elaborated, which is the root of every stack.
+  root: Box<str>,
+  /// True if lisp evaluation is in progress.
+  running: bool,
+  /// The time and allocation count at the last sample.
+  last: (Instant, u64),
+  /// The resources spent in each [`Native`] operation since the last sample.
+  native: Cell<[Cost; 2]>,
+}
+
+impl Profiler {
+  /// Create a new profiler which records samples in `shared`, if provided.
+  #[must_use] pub fn new(shared: Option<Arc<Mutex<Profile>>>, root: Box<str>) -> Self {
+    ACTIVE.fetch_add(1, Ordering::Relaxed);
+    Profiler { shared, local: vec![], root, running: false, last: now(), native: Cell::default() }
+  }
+
+  /// Start elaborating a new statement named `root`. Any `(profile)` calls that were
+  /// interrupted by an error are abandoned. Returns false if nothing is being profiled anymore.
+  pub(crate) fn set_root(&mut self, root: Box<str>) -> bool {
+    self.root = root;
+    self.running = false;
+    self.local.clear();
+    self.shared.is_some()
+  }
+
+  /// Note the start of a lisp evaluation. Time spent between evaluations is not counted.
+  pub(crate) fn start(&mut self) {
+    if !mem::replace(&mut self.running, true) { self.last = now() }
+  }
+
+  /// Note the end of a lisp evaluation.
+  pub(crate) fn stop(&mut self) { self.running = false }
+
+  /// Returns true if it is time to take a sample.
+  pub(crate) fn due(&self) -> bool { self.last.0.elapsed() >= SAMPLE_INTERVAL }
+
+  /// Record a sample with the given stack frames (not including the root), charging them
+  /// with the resources spent since the last sample.
+  pub(crate) fn sample(&mut self, frames: impl IntoIterator<Item=Box<str>>) {
+    let (mut cost, last) = Cost::since(self.last);
+    self.last = last;
+    let mut stack = vec![self.root.clone()];
+    stack.extend(frames);
+    for (op, c) in Native::ALL.into_iter().zip(self.native.take()) {
+      if c.is_zero() { continue }
+      cost = cost.saturating_sub(c);
+      if stack.last().is_some_and(|f| **f == *op.name()) {
+        self.add(&stack, c)
+      } else {
+        stack.push(op.name().into());
+        self.add(&stack, c);
+        stack.pop();
+      }
+    }
+    self.add(&stack, cost)
+  }
+
+  fn add(&mut self, stack: &[Box<str>], cost: Cost) {
+    if let Some(p) = &self.shared { p.ulock().add(stack, cost) }
+    for p in &mut self.local { p.add(stack, cost) }
+  }
+
+  /// Start collecting a local profile, for the `(profile)` builtin.
+  /// Returns the index of the new profile.
+  pub(crate) fn push_local(&mut self) -> usize {
+    self.local.push(Profile::default());
+    self.local.len() - 1
+  }
+
+  /// Finish collecting the local profile with index `n` (and any profiles started after it
+  /// that were abandoned). Returns the profile, and true if there is nothing left to profile.
+  pub(crate) fn pop_local(&mut self, n: usize) -> (Profile, bool) {
+    self.local.truncate(n + 1);
+    let p = self.local.pop().unwrap_or_default();
+    (p, self.shared.is_none() && self.local.is_empty())
+  }
+}
+
+impl Drop for Profiler {
+  fn drop(&mut self) { ACTIVE.fetch_sub(1, Ordering::Relaxed); }
+}
+
+impl Elaborator {
+  /// Begin timing a native operation, if profiling is enabled.
+  /// The result should be passed to [`end_native`](Self::end_native).
+  #[must_use] pub(crate) fn start_native(&self) -> Option<(Instant, u64)> {
+    self.profiler.as_ref().map(|_| now())
+  }
+
+  /// Finish timing a native operation started by [`start_native`](Self::start_native).
+  pub(crate) fn end_native(&self, op: Native, start: Option<(Instant, u64)>) {
+    if let (Some(p), Some(start)) = (&self.profiler, start) {
+      let mut native = p.native.get();
+      native[op as usize] += Cost::since(start).0;
+      p.native.set(native);
+    }
+  }
+}
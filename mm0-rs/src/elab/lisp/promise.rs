@@ -0,0 +1,277 @@
+//! Parallel evaluation of lisp functions, for the `async` and `await` builtins.
+//!
+//! Lisp data is not thread safe, so `(async f args)` first makes a deep copy of the
+//! environment, `f` and `args`, which shares no reference counted data with the elaborator.
+//! Procedures with their own state, like the `mmc-compiler`, cannot be copied this way,
+//! so the job gets a procedure that fails when called in their place.
+//! The copy is moved to a job on the thread pool, which evaluates `(f args)` in a fresh
+//! elaborator. The result is sent back along with the environment it lives in, and `await`
+//! copies it into the elaborator's environment. The job cannot modify the elaborator: changes
+//! it makes to lisp globals are discarded, and it runs outside of any proof.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::{Arc, Mutex, mpsc::{channel, Receiver}};
+use crate::{Elaborator, ElabError, Environment, LispKind, LispProc, LispVal, MutexExt, Remap, Remapper, Span};
+use crate::elab::Result;
+use super::{Proc, ProcSpec, debug::EnvDebug, print::{EnvDisplay, FormatEnv}};
+
+/// A function that runs a job on a thread pool. If the elaborator has no spawner,
+/// `async` jobs are run immediately on the current thread.
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct Spawner(Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>);
+
+impl Spawner {
+  /// Creates a new [`Spawner`] from a function that runs its argument on a thread pool.
+  pub fn new(f: impl Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static) -> Self { Self(Arc::new(f)) }
+}
+
+impl std::fmt::Debug for Spawner {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "Spawner".fmt(f)
+  }
+}
+
+/// A value whose lisp data is not reachable from any other thread, so it can be sent to one.
+struct Isolated<T>(T);
+// Safety: An `Isolated` value is only constructed from data made by `Environment::deep_copy`
+// and its `Remapper` (after the remapper is dropped), or by an `async` job from its own
+// elaborator (after the elaborator is dropped). Remapping allocates new `Rc`s for all lisp
+// data, and replaces `Proc::Dyn` procedures, which are the only values whose remap clones
+// an `Rc` instead (see `Remapper::isolate`). So the `Rc`s inside are only reachable from the
+// value itself, and moving the whole value to another thread cannot race with any other thread.
+#[allow(clippy::non_send_fields_in_send_ty)]
+unsafe impl<T> Send for Isolated<T> {}
+
+impl<T> Isolated<T> {
+  /// Takes the value out. (Closures must capture the whole [`Isolated`] value,
+  /// not the fields of its contents, so they cannot destructure it directly.)
+  fn into_inner(self) -> T { self.0 }
+}
+
+/// The result of an `async` job: the job's environment, the messages it reported,
+/// the value passed to `raise` if it raised an exception, and the return value.
+type Outcome = Isolated<(Environment, Vec<ElabError>, Option<LispVal>, Result<LispVal>)>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Takes the job out of its slot and runs it, unless another thread already took it.
+fn run_job(slot: &Mutex<Option<Job>>) {
+  let job = slot.ulock().take();
+  if let Some(job) = job { job() }
+}
+
+/// An `async` job which has not yet been awaited.
+pub struct Pending {
+  /// The span of the `async` call.
+  sp: Span,
+  /// The job, until a thread starts running it.
+  job: Arc<Mutex<Option<Job>>>,
+  /// The channel on which the job will send its outcome.
+  recv: Receiver<Outcome>,
+  /// The number of sorts, terms and theorems in the environment when the job was started.
+  decls: (usize, usize, usize),
+}
+
+impl std::fmt::Debug for Pending {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Pending").field("sp", &self.sp).finish_non_exhaustive()
+  }
+}
+
+/// The state of an `async` call, which is a procedure that waits for the result when called.
+pub enum Promise {
+  /// The job is running or waiting to be run.
+  Pending(Box<Pending>),
+  /// The job is complete, and returned a value or failed with an error message
+  /// (and the value passed to `raise`, if the error was an exception).
+  Done(std::result::Result<LispVal, (Span, String, Option<LispVal>)>),
+}
+crate::deep_size_0!({!Copy} Promise);
+
+impl std::fmt::Debug for Promise {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Promise::Pending(_) => write!(f, "Pending"),
+      Promise::Done(r) => f.debug_tuple("Done").field(r).finish(),
+    }
+  }
+}
+
+impl EnvDebug for Promise {
+  fn env_dbg(&self, fe: FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Promise::Pending(_) => write!(f, "Pending"),
+      Promise::Done(Ok(e)) => f.debug_tuple("Done").field(&fe.to(e)).finish(),
+      Promise::Done(Err((_, msg, _))) => f.debug_tuple("Failed").field(msg).finish(),
+    }
+  }
+}
+
+/// Stands in for a [`Proc::Dyn`] procedure in the copy of the environment given to an
+/// `async` job, see [`Remapper::isolate`](crate::Remapper).
+#[derive(Debug)]
+pub(crate) struct Unsendable;
+crate::deep_size_0!({!Copy} Unsendable);
+
+impl EnvDebug for Unsendable {
+  fn env_dbg(&self, _: FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    std::fmt::Debug::fmt(self, f)
+  }
+}
+
+impl EnvDisplay for Unsendable {
+  fn fmt(&self, _: FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "#<unsendable>")
+  }
+}
+
+impl LispProc for Unsendable {
+  fn spec(&self) -> ProcSpec { ProcSpec::AtLeast(0) }
+
+  fn call(&mut self, _: &mut Elaborator, sp: Span, _: Vec<LispVal>) -> Result<LispVal> {
+    Err(ElabError::new_e(sp, "this procedure cannot be used in async"))
+  }
+
+  fn box_remap(&self, _: &mut Remapper) -> Box<dyn LispProc> { Box::new(Unsendable) }
+}
+
+/// Pending promises cannot be copied to another environment, because their jobs report
+/// to the original elaborator, so they become failed promises. (Promises passed directly
+/// to `async` are awaited first, see [`Elaborator::spawn`].)
+impl Remap for Promise {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    match self {
+      Promise::Pending(p) => Promise::Done(Err((p.sp,
+        "async result was not awaited before it was copied to another file or async job".into(),
+        None))),
+      Promise::Done(Ok(e)) => Promise::Done(Ok(e.remap(r))),
+      Promise::Done(Err((sp, msg, raised))) =>
+        Promise::Done(Err((*sp, msg.clone(), raised.as_ref().map(|e| e.remap(r))))),
+    }
+  }
+}
+
+/// Collect the pending promises reachable from `e` into `out`.
+fn pending_promises(e: &LispVal, seen: &mut HashSet<*const LispKind>, out: &mut Vec<LispVal>) {
+  if !seen.insert(&raw const **e) { return }
+  match &**e {
+    LispKind::List(es) => for e in es { pending_promises(e, seen, out) },
+    LispKind::Vector(es) => for e in es { pending_promises(e, seen, out) },
+    LispKind::DottedList(es, r) => {
+      for e in es { pending_promises(e, seen, out) }
+      pending_promises(r, seen, out)
+    }
+    LispKind::Annot(_, e) | LispKind::Goal(e) => pending_promises(e, seen, out),
+    LispKind::AtomMap(m) => for e in m.values() { pending_promises(e, seen, out) },
+    LispKind::HashMap(m) => for (k, e) in m {
+      pending_promises(&k.0, seen, out);
+      pending_promises(e, seen, out)
+    },
+    LispKind::Ref(m) => m.get(|e| pending_promises(e, seen, out)),
+    LispKind::Proc(Proc::Lambda {env, ..}) => for e in env { pending_promises(e, seen, out) },
+    LispKind::Proc(Proc::Promise(p)) => match &*p.borrow() {
+      Promise::Pending(_) => out.push(e.clone()),
+      Promise::Done(Ok(e)) => pending_promises(e, seen, out),
+      Promise::Done(Err(_)) => {}
+    },
+    _ => {}
+  }
+}
+
+impl Elaborator {
+  /// Start evaluating `(f args)` on the thread pool, for the `async` builtin.
+  /// The environment is copied on the current thread, so this takes time proportional
+  /// to the size of the environment.
+  ///
+  /// Pending promises reachable from `f` and `args` are awaited first, because only a
+  /// finished promise can be copied to the job.
+  pub(crate) fn spawn(&mut self, sp: Span, f: &LispVal, args: &[LispVal]) -> Promise {
+    let mut pending = vec![];
+    let seen = &mut HashSet::new();
+    for e in std::iter::once(f).chain(args) { pending_promises(e, seen, &mut pending) }
+    for p in pending {
+      // A failure is reported when the job calls the promise, not here
+      let raised = self.raised.take();
+      p.unwrapped(|e| if let LispKind::Proc(Proc::Promise(p)) = e { drop(self.await_promise(p)) });
+      self.raised = raised;
+    }
+    let (env, mut r) = self.env.deep_copy();
+    let input = Isolated((env, f.remap(&mut r), args.iter().map(|e| e.remap(&mut r)).collect()));
+    // The remapper holds references to the copied data, which now belongs to the job
+    drop(r);
+    let decls = (self.sorts.len(), self.terms.len(), self.thms.len());
+    let (ast, path, options, cancel) =
+      (self.ast.clone(), self.path.clone(), self.options, self.cancel.clone());
+    let (mm0_mode, timeout, cur_timeout, stack_limit, spawner) =
+      (self.mm0_mode, self.timeout, self.cur_timeout, self.stack_limit, self.spawner.clone());
+    let (send, recv) = channel();
+    let job: Job = Box::new(move || {
+      let (env, f, args) = input.into_inner();
+      let mut elab = Elaborator::new(ast, path, mm0_mode, options, cancel, None, None);
+      elab.env = env;
+      elab.timeout = timeout;
+      elab.cur_timeout = cur_timeout;
+      elab.stack_limit = stack_limit;
+      elab.spawner = spawner;
+      let ret = elab.call_func(sp, &f, args);
+      let (env, errors, raised) =
+        (mem::take(&mut elab.env), mem::take(&mut elab.errors), elab.raised.take());
+      // Everything else that can refer to the job's data must be dropped before it is sent
+      drop((f, elab));
+      drop(send.send(Isolated((env, errors, raised, ret))));
+    });
+    let job = Arc::new(Mutex::new(Some(job)));
+    if let Some(Spawner(spawn)) = &self.spawner {
+      let job = job.clone();
+      spawn(Box::new(move || run_job(&job)))
+    } else { run_job(&job) }
+    Promise::Pending(Box::new(Pending { sp, job, recv, decls }))
+  }
+
+  /// Wait for the result of an `async` call. If no thread has started running the job yet,
+  /// it is run on the current thread, so that waiting cannot deadlock the thread pool.
+  pub(crate) fn await_promise(&mut self, p: &RefCell<Promise>) -> Result<LispVal> {
+    let g = p.borrow();
+    let pending = match &*g {
+      Promise::Pending(pending) => pending,
+      Promise::Done(Ok(e)) => return Ok(e.clone()),
+      &Promise::Done(Err((sp, ref msg, ref raised))) => {
+        self.raised.clone_from(raised);
+        return Err(ElabError::new_e(sp, msg.clone()))
+      }
+    };
+    run_job(&pending.job);
+    let ret = match pending.recv.recv() {
+      Ok(outcome) => self.receive(pending.sp, pending.decls, outcome),
+      Err(_) => Err(ElabError::new_e(pending.sp, "async job failed to complete")),
+    };
+    drop(g);
+    *p.borrow_mut() = Promise::Done(match &ret {
+      Ok(e) => Ok(e.clone()),
+      Err(e) => Err((e.pos, e.kind.msg(), self.raised.clone())),
+    });
+    ret
+  }
+
+  /// Copy the outcome of an `async` job into this environment.
+  fn receive(&mut self, sp: Span, decls: (usize, usize, usize), outcome: Outcome) -> Result<LispVal> {
+    let (env, errors, raised, ret) = outcome.into_inner();
+    for e in errors { self.report(e) }
+    if decls != (env.sorts.len(), env.terms.len(), env.thms.len()) {
+      return Err(ElabError::new_e(sp, "async: an async job cannot add declarations"))
+    }
+    let mut r = Remapper::identity(&env);
+    r.atom = env.data.iter().map(|d| self.env.get_atom_arc(d.name.clone())).collect();
+    match ret {
+      Ok(e) => Ok(e.remap(&mut r)),
+      Err(e) => {
+        self.raised = raised.map(|e| e.remap(&mut r));
+        Err(e)
+      }
+    }
+  }
+}
@@ -14,7 +14,7 @@ use crate::{AddItemError, AtomId, BoxError, DeclKey, FileSpan, Span, TermKind, T
 use crate::ast::{Decl, Type, DepType, LocalKind};
 use super::{Coe, DeclKind, DerefMut, DocComment, ElabError, Elaborator, Environment,
   Expr, Modifiers, ObjectKind, OneOrMore, Proof, Result, SExprKind, SortId, Term, TermId, Thm};
-use super::lisp::{LispVal, LispKind, Uncons, InferTarget, print::FormatEnv};
+use super::lisp::{LispVal, LispKind, LispRef, Uncons, InferTarget, print::FormatEnv};
 use super::proof::{NodeHasher, ProofKind, ProofHash, build, Dedup};
 
 /// The contexts in which an unknown var can appear.
@@ -143,6 +143,19 @@ fn new_mvar(mvars: &mut Vec<LispVal>, tgt: InferTarget, sp: Option<FileSpan>) ->
   e
 }
 
+/// A saved copy of the proof state in a [`LocalContext`], created by
+/// [`LocalContext::snapshot`].
+#[derive(Debug)]
+pub struct Snapshot {
+  /// The metavariables, paired with their values at the time of the snapshot.
+  mvars: Vec<(LispVal, LispVal)>,
+  /// The goals, paired with their values at the time of the snapshot.
+  goals: Vec<(LispVal, LispVal)>,
+  proofs: HashMap<AtomId, usize>,
+  proof_order: Vec<(AtomId, LispVal, LispVal)>,
+  closer: LispVal,
+}
+
 impl LocalContext {
   /// Create a new local context.
   #[must_use] pub fn new() -> LocalContext { Default::default() }
@@ -159,6 +172,35 @@ impl LocalContext {
     self.closer = LispVal::undef();
   }
 
+  /// Save the goals, metavariables (including the current contents of their reference cells),
+  /// subproofs and closer, so that they can be restored by [`restore`](Self::restore).
+  /// The variable context is not saved.
+  #[must_use] pub fn snapshot(&self) -> Snapshot {
+    let save = |es: &[LispVal]| es.iter()
+      .map(|e| (e.clone(), e.as_lref(LispRef::unref).unwrap_or_else(|| e.clone()))).collect();
+    Snapshot {
+      mvars: save(&self.mvars),
+      goals: save(&self.goals),
+      proofs: self.proofs.clone(),
+      proof_order: self.proof_order.clone(),
+      closer: self.closer.clone(),
+    }
+  }
+
+  /// Restore the proof state saved by [`snapshot`](Self::snapshot), undoing any
+  /// metavariable and goal assignments made since then.
+  pub fn restore(&mut self, s: Snapshot) {
+    let load = |es: Vec<(LispVal, LispVal)>| es.into_iter().map(|(e, v)| {
+      e.as_ref_(|r| *r = v);
+      e
+    }).collect();
+    self.mvars = load(s.mvars);
+    self.goals = load(s.goals);
+    self.proofs = s.proofs;
+    self.proof_order = s.proof_order;
+    self.closer = s.closer;
+  }
+
   /// Set the list of goals to `gs`, after filtering the elements that are not
   /// goals or are already instantiated.
   pub fn set_goals(&mut self, gs: impl IntoIterator<Item=LispVal>) {
@@ -11,6 +11,7 @@ use crate::{AtomId, TermKind, DeclKey, Modifiers,
   ObjectKind, SortId, TermId, ThmId, Type};
 use super::lisp::{InferTarget, LispKind, LispRef, LispVal, Uncons, RefineSyntax,
   print::{FormatEnv, EnvDisplay}, eval::SResult};
+use super::lisp::profile::Native;
 use super::local_context::{InferSort, try_get_span, try_get_span_opt};
 use super::proof::Subst;
 
@@ -587,6 +588,13 @@ impl Elaborator {
 
   /// Get the type of the proof `e` (with only minimal type-checking).
   pub fn infer_type(&self, sp: Span, e: &LispVal) -> Result<LispVal> {
+    let start = self.start_native();
+    let res = self.infer_type_core(sp, e);
+    self.end_native(Native::InferType, start);
+    res
+  }
+
+  fn infer_type_core(&self, sp: Span, e: &LispVal) -> Result<LispVal> {
     macro_rules! err {
       ($e:expr, $err:expr) => {ElabError::new_e(try_get_span(&self.fspan(sp), &$e), $err)}
     }
@@ -679,7 +687,10 @@ impl Elaborator {
   /// Unify expressions `e1` and `e2`. Returns a conversion proof
   /// `u: e1 = e2`, with `#undef` meaning that `e1` and `e2` are equal after unification.
   fn unify(&mut self, sp: Span, e1: &LispVal, e2: &LispVal) -> LispVal {
-    self.unify1(e1, e2).unwrap_or_else(|e| {
+    let start = self.start_native();
+    let res = self.unify1(e1, e2);
+    self.end_native(Native::Unify, start);
+    res.unwrap_or_else(|e| {
       self.report(ElabError::new_e(sp, e));
       LispVal::atom(AtomId::SORRY)
     })
@@ -171,11 +171,13 @@ impl<T> Spans<T> {
       let j = spans.binary_search_by_key(&range.end.saturating_sub(1), |s| s.stmt().start)
         .unwrap_or_else(|i| i.saturating_sub(1));
       let spans = &spans[..=j];
-      spans.iter().for_each(|sp| sp.data.range(range.clone())
-        .for_each(|(_, v)| v.iter().for_each(|x| f(sp, x))))
+      for sp in spans {
+        sp.data.range(range.clone()).for_each(|(_, v)| v.iter().for_each(|x| f(sp, x)))
+      }
     } else {
-      spans.iter().for_each(|sp| sp.data.iter()
-        .for_each(|(_, v)| v.iter().for_each(|x| f(sp, x))))
+      for sp in spans {
+        sp.data.iter().for_each(|(_, v)| v.iter().for_each(|x| f(sp, x)))
+      }
     }
   }
 
@@ -295,6 +295,11 @@ pub struct Bound {
 macro_rules! vassert { ($e:expr, $v:expr) => { if !$e { return Err($v) } }}
 
 impl Bound {
+  /// A bound which only allows the sorts, terms and theorems strictly before the given ones.
+  #[must_use] pub fn new(sort: SortId, term: TermId, thm: ThmId) -> Self {
+    Bound { sort: Some(sort), term: Some(term), thm: Some(thm) }
+  }
+
   fn check_sort<'a>(&self, s: SortId) -> Result<(), VerifyError<'a>> {
     match self.sort {
       Some(s2) if s2 <= s => Err(VerifyError::FwdReferenceSort(s)),
@@ -0,0 +1,488 @@
+//! The MM1 source formatter, used by `mm0-rs fmt` and by the server to answer
+//! `textDocument/formatting` requests.
+//!
+//! The formatter works statement by statement on the [`Ast`] produced by the parser, and lays
+//! out each statement using the [`pretty`] crate with the same conventions as the
+//! [lisp pretty printer](crate::elab::lisp::pretty::Pretty::pp_lisp): lists are indented by
+//! two spaces, and once a list does not fit on a line, every argument from the second
+//! non-atomic one on goes on its own line.
+//!
+//! * `term`, `axiom`, `def` and `theorem` headers are put on one line if they fit, and
+//!   otherwise the binders are wrapped and the type is moved to the next line.
+//!   A theorem proof that does not fit after the `=` starts on a new line at column 0,
+//!   and a `def` value on a new line indented by two spaces.
+//! * `do` blocks put each s-expression on its own line.
+//! * Curly lists `{a + b}` are printed as written, because the parser reorders them.
+//! * Math strings are normalized to the form `$ a + b $`, with single spaces between tokens.
+//!   Line breaks inside math strings are kept.
+//! * Other statements (sorts, notations, imports and so on) only have their spacing normalized.
+//! * Comments and doc comments are kept in place, and single blank lines between statements
+//!   and between the items of a `do` block are preserved.
+//!
+//! Only whitespace is ever changed. As a safety net, each formatted statement is tokenized and
+//! compared with the original, and statements which do not match, or which overlap a parse
+//! error, are left unchanged.
+use std::{fs, io};
+use pretty::RcDoc;
+use mm1_parser::{parse, ast::{Ast, SExpr, SExprKind, Stmt, StmtKind, Decl, DeclKind}};
+use crate::{LinedString, Span};
+
+/// The preferred maximum line width of formatted code.
+const WIDTH: usize = 100;
+
+type Doc<'a> = RcDoc<'a, ()>;
+
+/// A token, for the purpose of checking that formatting did not change the meaning of the code.
+/// Whitespace is not significant, except to separate chunks, and the whitespace inside
+/// math strings is normalized.
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+  Delim(u8),
+  Chunk(&'a str),
+  Str(&'a str),
+  Math(Vec<&'a str>),
+  Comment(&'a str),
+}
+
+/// Split a piece of source text into [`Token`]s.
+fn tokens(s: &str) -> Vec<Token<'_>> {
+  let b = s.as_bytes();
+  let mut out = vec![];
+  let mut i = 0;
+  while i < b.len() {
+    match b[i] {
+      b' ' | b'\n' | b'\t' | b'\r' => i += 1,
+      c @ (b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';') => { out.push(Token::Delim(c)); i += 1 }
+      b'-' if b.get(i + 1) == Some(&b'-') => {
+        let j = b[i..].iter().position(|&c| c == b'\n').map_or(b.len(), |j| i + j);
+        out.push(Token::Comment(s[i..j].trim_end()));
+        i = j
+      }
+      b'"' => {
+        let mut j = i + 1;
+        while j < b.len() && b[j] != b'"' { j += if b[j] == b'\\' { 2 } else { 1 } }
+        let j = (j + 1).min(b.len());
+        out.push(Token::Str(&s[i..j]));
+        i = j
+      }
+      b'$' => {
+        let j = b[i + 1..].iter().position(|&c| c == b'$').map_or(b.len(), |j| i + 1 + j);
+        out.push(Token::Math(s[i + 1..j].split_ascii_whitespace().collect()));
+        i = (j + 1).min(b.len())
+      }
+      _ => {
+        let j = b[i..].iter().position(|&c| matches!(c,
+          b' ' | b'\n' | b'\t' | b'\r' | b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';' | b'"' | b'$'
+        )).map_or(b.len(), |j| i + j);
+        out.push(Token::Chunk(&s[i..j]));
+        i = j
+      }
+    }
+  }
+  out
+}
+
+/// The comments and blank lines in the whitespace between two syntax elements.
+struct Gap<'a> {
+  /// The comments, with the number of line breaks before each one.
+  comments: Vec<(usize, &'a str)>,
+  /// The number of line breaks after the last comment.
+  newlines: usize,
+}
+
+struct Formatter<'a> {
+  src: &'a str,
+}
+
+impl<'a> Formatter<'a> {
+  fn text(&self, sp: Span) -> &'a str { &self.src[sp.start..sp.end] }
+
+  fn byte(&self, i: usize) -> u8 { self.src.as_bytes()[i] }
+
+  fn gap(&self, start: usize, end: usize) -> Gap<'a> {
+    let mut gap = Gap { comments: vec![], newlines: 0 };
+    for line in self.text((start..end).into()).split_inclusive('\n') {
+      let s = line.trim();
+      if !s.is_empty() { gap.comments.push((std::mem::take(&mut gap.newlines), s)) }
+      if line.ends_with('\n') { gap.newlines += 1 }
+    }
+    gap
+  }
+
+  /// The comments in a gap, each preceded by a space or line break as in the source.
+  /// If `keep_blank` is set, a blank line in the source is kept as a single blank line.
+  fn comments(gap: &Gap<'a>, keep_blank: bool) -> Doc<'a> {
+    Doc::concat(gap.comments.iter().map(|&(n, c)| Self::breaks(n, keep_blank).append(c)))
+  }
+
+  fn breaks(n: usize, keep_blank: bool) -> Doc<'a> {
+    match n {
+      0 => Doc::text(" "),
+      n if n > 1 && keep_blank => Doc::hardline().append(Doc::hardline()),
+      _ => Doc::hardline(),
+    }
+  }
+
+  /// The separator between two elements of a list or block. `line` is the separator
+  /// to use if there are no comments or blank lines (with `keep_blank`) in between.
+  fn sep(&self, start: usize, end: usize, line: Doc<'a>, keep_blank: bool) -> Doc<'a> {
+    let gap = self.gap(start, end);
+    if gap.comments.is_empty() && !(keep_blank && gap.newlines > 1) { return line }
+    Self::comments(&gap, keep_blank).append(Self::breaks(gap.newlines.max(1), keep_blank))
+  }
+
+  /// Format a math string `$ ... $`, normalizing the spacing but keeping line breaks.
+  fn math(&self, sp: Span) -> Doc<'a> {
+    let inner = self.text((sp.start + 1..sp.end - 1).into());
+    let mut lines = inner.lines().filter_map(|l| {
+      let words = l.split_ascii_whitespace().collect::<Vec<_>>();
+      (!words.is_empty()).then(|| Doc::intersperse(words, " "))
+    });
+    let Some(first) = lines.next() else { return Doc::text("$ $") };
+    let rest = Doc::concat(lines.map(|l| Doc::hardline().append(l)));
+    Doc::text("$ ").append(first).append(rest.nest(2)).append(" $")
+  }
+
+  /// Format a sequence of tokens which are not s-expressions, such as the header of a
+  /// declaration. Whitespace between the tokens is collapsed to a single space, or removed
+  /// after an open bracket and before a close bracket. The returned items are the groups of
+  /// tokens that were separated by whitespace outside of brackets, with their spans.
+  fn words(&self, start: usize, end: usize) -> Vec<(Span, Doc<'a>)> {
+    let b = self.src.as_bytes();
+    let mut words = vec![];
+    let mut cur = (start, Doc::nil());
+    let (mut depth, mut i, mut last) = (0_usize, start, None);
+    while i < end {
+      let (j, word) = match b[i] {
+        b' ' | b'\n' => { i += 1; continue }
+        b'$' => {
+          let j = b[i + 1..end].iter().position(|&c| c == b'$').map_or(end, |j| i + j + 2);
+          (j, self.math((i..j).into()))
+        }
+        b'"' => {
+          let mut j = i + 1;
+          while j < end && b[j] != b'"' { j += if b[j] == b'\\' { 2 } else { 1 } }
+          let j = (j + 1).min(end);
+          (j, Doc::text(self.text((i..j).into())))
+        }
+        b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';' => (i + 1, Doc::text(self.text((i..=i).into()))),
+        _ => {
+          let j = b[i..end].iter().position(|&c| matches!(c,
+            b' ' | b'\n' | b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';' | b'"' | b'$'
+          )).map_or(end, |j| i + j);
+          (j, Doc::text(self.text((i..j).into())))
+        }
+      };
+      if let Some(last) = last {
+        if last < i && !matches!(b[last - 1], b'(' | b'[' | b'{') && !matches!(b[i], b')' | b']' | b'}') {
+          if depth == 0 {
+            let (w_start, w) = std::mem::replace(&mut cur, (i, Doc::nil()));
+            words.push(((w_start..last).into(), w))
+          } else {
+            cur.1 = cur.1.append(" ")
+          }
+        }
+      }
+      match b[i] {
+        b'(' | b'[' | b'{' => depth += 1,
+        b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+        _ => {}
+      }
+      cur.1 = cur.1.append(word);
+      last = Some(j);
+      i = j;
+    }
+    if let Some(last) = last { words.push(((cur.0..last).into(), cur.1)) }
+    words
+  }
+
+  /// Returns true if the text has a `:` outside of brackets and math strings.
+  fn has_colon(&self, sp: Span) -> bool {
+    let (mut depth, mut math) = (0_usize, false);
+    self.text(sp).bytes().any(|c| {
+      match c {
+        b'$' => math = !math,
+        _ if math => {}
+        b'(' | b'[' | b'{' => depth += 1,
+        b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+        b':' => return depth == 0,
+        _ => {}
+      }
+      false
+    })
+  }
+
+  fn sexpr(&self, e: &SExpr) -> Doc<'a> {
+    match &e.k {
+      SExprKind::List(es) => self.list(e.span, es, None),
+      SExprKind::DottedList(es, r) => self.list(e.span, es, Some(r)),
+      SExprKind::Formula(f) => self.math(f.0),
+      SExprKind::DocComment(_, e2) => self.doc_comment(e.span.start, e2.span.start)
+        .append(self.sexpr(e2)),
+      _ => Doc::text(self.text(e.span)),
+    }
+  }
+
+  /// The lines of a doc comment, each followed by a hard line break.
+  fn doc_comment(&self, start: usize, end: usize) -> Doc<'a> {
+    Doc::concat(self.text((start..end).into()).lines()
+      .map(str::trim).filter(|l| !l.is_empty())
+      .map(|l| Doc::text(l).append(Doc::hardline())))
+  }
+
+  /// Print the source text as is, except that the indentation of lines after the first is
+  /// made relative to the current nesting level.
+  fn verbatim(&self, sp: Span) -> Doc<'a> {
+    let mut lines = self.text(sp).lines();
+    let first = Doc::text(lines.next().unwrap_or_default().trim_end());
+    let rest = lines.map(str::trim_end).filter(|l| !l.is_empty()).collect::<Vec<_>>();
+    let indent = rest.iter().map(|l| l.len() - l.trim_start().len()).min().unwrap_or(0);
+    first.append(Doc::concat(rest.into_iter().map(|l| Doc::hardline().append(&l[indent..]))))
+  }
+
+  /// Returns true if the expression is printed as a single token, so that it can share
+  /// a line with the expressions around it even when the enclosing list is broken.
+  fn small(&self, e: &SExpr) -> bool {
+    match &e.k {
+      SExprKind::List(es) if es.len() == 2 && matches!(self.byte(e.span.start), b'\'' | b',') =>
+        self.small(&es[1]),
+      SExprKind::List(_) | SExprKind::DottedList(..) | SExprKind::DocComment(..) => false,
+      _ => true,
+    }
+  }
+
+  fn list(&self, sp: Span, es: &[SExpr], dot: Option<&SExpr>) -> Doc<'a> {
+    let (open, close) = match self.byte(sp.start) {
+      b'\'' | b',' if dot.is_none() && es.len() == 2 =>
+        return Doc::text(self.text((sp.start..=sp.start).into())).append(self.sexpr(&es[1])),
+      b'(' => ("(", ")"),
+      b'[' => ("[", "]"),
+      // Curly lists are reordered by the parser, so we print them as written
+      _ => return self.verbatim(sp),
+    };
+    let (doc, end) = self.items(sp.start + 1, es, dot);
+    Doc::text(open).append(doc).append(self.sep(end, sp.end - 1, Doc::nil(), false))
+      .append(close).nest(2).group()
+  }
+
+  /// The elements of a list, starting after the open bracket at `start`. This follows the
+  /// layout of [`pp_lisp`](crate::elab::lisp::pretty::Pretty::pp_lisp): arguments are
+  /// separated by soft line breaks until the first argument after the first which is not small,
+  /// after which each argument goes on its own line if the list is broken. An `@` tail is laid out as
+  /// a group of its own. Returns the document and the end of the last element.
+  fn items(&self, mut start: usize, es: &[SExpr], dot: Option<&SExpr>) -> (Doc<'a>, usize) {
+    let mut doc = Doc::nil();
+    let mut big = false;
+    for (i, e) in es.iter().enumerate() {
+      big |= i > 1 && !self.small(e);
+      let line = if i == 0 { Doc::nil() } else if big { Doc::line() } else { Doc::softline() };
+      match &e.k {
+        // The span of an `@` tail includes the closing bracket of the enclosing list
+        SExprKind::List(tail) if i + 1 == es.len() && dot.is_none() && self.byte(e.span.start) == b'@' => {
+          // A comment after the `@` is already preceded by a space or line break
+          let at = match tail.first() {
+            Some(e2) if !self.gap(e.span.start + 1, e2.span.start).comments.is_empty() => "@",
+            _ => "@ ",
+          };
+          let (tail, end) = self.items(e.span.start + 1, tail, None);
+          let line = if i == 0 { Doc::nil() } else { Doc::softline() };
+          doc = doc.append(self.sep(start, e.span.start, line, false)).append(at).append(tail.group());
+          start = end;
+        }
+        _ => {
+          doc = doc.append(self.sep(start, e.span.start, line, false)).append(self.sexpr(e));
+          start = e.span.end;
+        }
+      }
+    }
+    if let Some(r) = dot {
+      doc = doc.append(" .").append(self.sep(start, r.span.start, Doc::line(), false)).append(self.sexpr(r));
+      start = r.span.end;
+    }
+    (doc, start)
+  }
+
+  fn decl(&self, sp: Span, d: &Decl) -> Doc<'a> {
+    let end = d.val.as_ref().map_or(sp.end - 1, |v| self.src[..v.span.start].rfind('=').unwrap_or(sp.end));
+    let words = self.words(sp.start, end);
+    let (kw, rest) = words.split_at((1 + d.mods.bits().count_ones() as usize).min(words.len()));
+    let mut doc = Doc::concat(kw.iter().map(|w| w.1.clone().append(" ")));
+    // The type is everything after the first word containing a `:`
+    let ty = rest.iter().position(|w| self.has_colon(w.0)).map_or(rest.len(), |i| i + 1);
+    let (bis, ty) = rest.split_at(ty);
+    if let Some(((_, name), bis)) = bis.split_first() {
+      let bis = Doc::concat(bis.iter().map(|w| Doc::softline().append(w.1.clone())));
+      let ty = Doc::concat(ty.iter().map(|w| Doc::softline().append(w.1.clone())));
+      doc = doc.append(name.clone().append(bis.nest(4)).append(ty.nest(2)).group());
+    }
+    match &d.val {
+      None => doc.append(";"),
+      Some(val) => {
+        let indent = if matches!(d.k, DeclKind::Def) { 2 } else { 0 };
+        doc.append(" =").append(Doc::line().append(self.sexpr(val)).nest(indent).group()).append(";")
+      }
+    }
+  }
+
+  fn stmt(&self, s: &Stmt) -> Doc<'a> {
+    match &s.k {
+      StmtKind::DocComment(_, s2) => self.doc_comment(s.span.start, s2.span.start).append(self.stmt(s2)),
+      StmtKind::Annot(e, s2) => {
+        let sep = if self.text((e.span.end..s2.span.start).into()).contains('\n') { Doc::hardline() } else { Doc::text(" ") };
+        Doc::text("@").append(self.sexpr(e)).append(sep).append(self.stmt(s2))
+      }
+      StmtKind::Decl(d) => self.decl(s.span, d),
+      StmtKind::Do(es) => {
+        let open = s.span.start + 2;
+        if !self.src[open..].trim_start().starts_with('{') {
+          return Doc::text("do ").append(es.first().map_or_else(Doc::nil, |e| self.sexpr(e))).append(";")
+        }
+        let open = open + self.src[open..].find('{').unwrap_or(0) + 1;
+        let close = self.src[..s.span.end].rfind('}').unwrap_or(s.span.end);
+        let mut doc = Doc::nil();
+        let mut prev = open;
+        for e in es {
+          doc = doc.append(self.sep(prev, e.span.start, Doc::hardline(), true)).append(self.sexpr(e));
+          prev = e.span.end;
+        }
+        let gap = self.gap(prev, close);
+        let doc = doc.append(Self::comments(&gap, true));
+        Doc::text("do {").append(doc.nest(2)).append(Doc::hardline()).append("};")
+      }
+      _ => {
+        let mut words = self.words(s.span.start, s.span.end).into_iter().map(|w| w.1);
+        let first = words.next().unwrap_or_else(Doc::nil);
+        first.append(Doc::concat(words.map(|w| Doc::softline().append(w))).nest(2)).group()
+      }
+    }
+  }
+
+  /// Format a statement, returning `None` if the result does not have the same tokens
+  /// as the original.
+  fn format_stmt(&self, s: &Stmt) -> Option<String> {
+    let mut out = String::new();
+    self.stmt(s).render_fmt(WIDTH, &mut out).ok()?;
+    let out = out.lines().map(str::trim_end).collect::<Vec<_>>().join("\n");
+    (tokens(&out) == tokens(self.text(s.span))).then_some(out)
+  }
+
+  /// Normalize the whitespace and comments between two statements: trailing whitespace
+  /// is removed, and runs of blank lines are collapsed to one. `first` and `last` are set
+  /// for the text before the first statement and after the last statement.
+  fn format_gap(&self, start: usize, end: usize, first: bool, last: bool) -> String {
+    let text = self.text((start..end).into());
+    let mut lines = text.split('\n');
+    let mut out = String::new();
+    if let Some(c) = lines.next().map(str::trim).filter(|c| !c.is_empty()) {
+      if !first { out.push(' ') }
+      out.push_str(c)
+    }
+    if !text.contains('\n') {
+      if last { if !(first && out.is_empty()) { out.push('\n') } } else if !first { out.push(' ') }
+      return out
+    }
+    let lines = lines.collect::<Vec<_>>();
+    let mut blank = false;
+    for (i, line) in lines.iter().enumerate() {
+      let line = line.trim_end();
+      if line.is_empty() {
+        // the last line is the indentation of the next statement
+        blank |= i + 1 < lines.len();
+        continue
+      }
+      if !(first && out.is_empty()) {
+        out.push('\n');
+        if blank { out.push('\n') }
+      }
+      blank = false;
+      out.push_str(line);
+    }
+    if !(first && out.is_empty()) {
+      out.push('\n');
+      if blank && !last { out.push('\n') }
+    }
+    out
+  }
+}
+
+/// Format an MM1 file, returning a list of edits, each of which replaces the text of the
+/// given span with the string. If `range` is set, only the statements which overlap this
+/// range are formatted.
+#[must_use] pub fn format(ast: &Ast, range: Option<Span>) -> Vec<(Span, String)> {
+  let f = Formatter { src: &ast.source };
+  let has_error = |sp: Span| ast.errors.iter().any(|e| e.pos.start < sp.end && sp.start <= e.pos.end);
+  let overlaps = |sp: Span| range.is_none_or(|r| r.start <= sp.end && sp.start <= r.end);
+  let mut edits = vec![];
+  let mut edit = |sp: Span, s: String| if f.text(sp) != s { edits.push((sp, s)) };
+  let mut prev = 0;
+  for (i, s) in ast.stmts.iter().enumerate() {
+    let gap = (prev..s.span.start).into();
+    if range.is_none() && !has_error(gap) { edit(gap, f.format_gap(prev, s.span.start, i == 0, false)) }
+    if overlaps(s.span) && !has_error(s.span) {
+      if let Some(out) = f.format_stmt(s) { edit(s.span, out) }
+    }
+    prev = s.span.end;
+  }
+  let gap = (prev..f.src.len()).into();
+  if range.is_none() && !has_error(gap) {
+    edit(gap, f.format_gap(prev, f.src.len(), ast.stmts.is_empty(), true))
+  }
+  edits
+}
+
+/// Apply a list of edits produced by [`format`] to the source text.
+#[must_use] pub fn apply_edits(src: &str, edits: &[(Span, String)]) -> String {
+  let mut out = String::with_capacity(src.len());
+  let mut prev = 0;
+  for (sp, s) in edits {
+    out.push_str(&src[prev..sp.start]);
+    out.push_str(s);
+    prev = sp.end;
+  }
+  out.push_str(&src[prev..]);
+  out
+}
+
+/// Format MM1 files
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Don't write the files, but exit with code 1 if any file is not formatted
+  #[clap(long)]
+  pub check: bool,
+  /// The files to format (.mm1 or .mm0)
+  #[clap(required = true)]
+  pub files: Vec<String>,
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs fmt` subcommand.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs fmt [--check] <file.mm1>...`, where:
+  ///
+  /// - `file.mm1` are the files to format in place. Statements with parse errors are
+  ///   left unchanged.
+  /// - `--check`: Instead of writing the files, print the names of the files that are not
+  ///   formatted, and exit with code 1 if there are any.
+  pub fn main(self) -> io::Result<()> {
+    let mut unformatted = false;
+    for file in &self.files {
+      let src = LinedString::from(fs::read_to_string(file)?);
+      let (_, ast) = parse(src.into(), None);
+      if !ast.errors.is_empty() {
+        eprintln!("{file}: parse errors; those statements were not formatted")
+      }
+      let edits = format(&ast, None);
+      if edits.is_empty() { continue }
+      if self.check {
+        println!("{file}");
+        unformatted = true;
+      } else {
+        fs::write(file, apply_edits(&ast.source, &edits))?;
+      }
+    }
+    if unformatted { std::process::exit(1) }
+    Ok(())
+  }
+}
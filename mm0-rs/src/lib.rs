@@ -10,9 +10,12 @@
 //!
 //! SUBCOMMANDS:
 //!     compile    Compile MM1 files into MMB
+//!     diff       Show the differences between two MMB or MMU files
 //!     help       Prints this message or the help of the given subcommand(s)
 //!     join       Join MM1/MM0 files with imports by concatenation
+//!     mmb-dump   Print the contents of an MMB file in human-readable form
 //!     server     MM1 LSP server
+//!     verify     Verify MMB or MMU proofs against an MM0 specification
 //! ```
 //!
 //! [`mm0-rs/README.md`]: https://github.com/digama0/mm0/blob/master/mm0-rs/README.md
@@ -60,7 +63,12 @@ pub use mm0_deepsize::deep_size_0;
 #[cfg(feature = "server")]
 pub mod server;
 pub mod compiler;
+pub mod debugger;
 pub mod joiner;
+pub mod verifier;
+pub mod diff;
+pub mod convert;
+pub mod formatter;
 pub mod elab;
 #[cfg(feature = "doc")]
 pub mod doc;
@@ -69,13 +77,19 @@ pub mod doc;
 /// See [`mm0-c/verifier.c`] for information on the MMB format.
 ///
 /// [`mm0-c/verifier.c`]: https://github.com/digama0/mm0/blob/master/mm0-c/verifier.c
-pub mod mmb { pub mod export; pub mod import; }
+pub mod mmb { pub mod export; pub mod import; pub mod verify; pub mod dump; }
 /// Import and export functionality for MMU ascii proof format
 ///
 /// See [The `.mmu` file format] for information on the MMU format.
 ///
 /// [The `.mmu` file format]: https://github.com/digama0/mm0/blob/master/mm0-hs/README.md#the-mmu-file-format
-pub mod mmu { pub mod import; pub mod export; }
+pub mod mmu { pub mod import; pub mod export; pub mod verify; }
+/// Import and export functionality for Metamath `.mm` databases
+///
+/// See [the Metamath book] for information on the Metamath language.
+///
+/// [the Metamath book]: https://us.metamath.org/downloads/metamath.pdf
+pub mod mm { pub mod import; pub mod export; }
 #[cfg(feature = "mmc")]
 pub mod mmc;
 
@@ -8,7 +8,13 @@ use clap::Parser;
 enum Cli {
   Compile(mm0_rs::compiler::Args),
   Join(mm0_rs::joiner::Args),
+  Verify(mm0_rs::verifier::Args),
+  MmbDump(mm0_rs::mmb::dump::Args),
+  Diff(mm0_rs::diff::Args),
+  Convert(mm0_rs::convert::Args),
+  Fmt(mm0_rs::formatter::Args),
   Doc(mm0_rs::doc::Args),
+  Debug(mm0_rs::debugger::Args),
   #[cfg(feature = "server")]
   Server(mm0_rs::server::Args),
 }
@@ -21,7 +27,13 @@ fn main() -> std::io::Result<()> {
       args.main()
     }
     Cli::Join(args) => args.main(),
+    Cli::Verify(args) => args.main(),
+    Cli::MmbDump(args) => args.main(),
+    Cli::Diff(args) => args.main(),
+    Cli::Convert(args) => args.main(),
+    Cli::Fmt(args) => args.main(),
     Cli::Doc(args) => args.main(),
+    Cli::Debug(args) => args.main(),
     #[cfg(feature = "server")]
     Cli::Server(args) => {
       if args.no_proofs { mm0_rs::set_check_proofs(false) }
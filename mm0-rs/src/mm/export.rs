@@ -0,0 +1,834 @@
+//! Metamath exporter, which produces `.mm` files from a [`FrozenEnv`].
+//!
+//! The translation only uses plain Metamath, so that the result can be checked by a Metamath
+//! verifier, and it can be read back by [`mm::import`](crate::mm::import), which re-verifies
+//! every proof:
+//!
+//! * Every sort `s` becomes a typecode `s`. Unless the sort is `strict`, bound variables of
+//!   sort `s` get their own typecode `{s}`, and the syntax axiom `cv-s $a s x $.` coerces
+//!   them to expressions, so that only variables can be substituted for bound variables.
+//! * Every term `foo` becomes a constant `foo` with the syntax axiom `t-foo`, written in
+//!   prefix notation `( foo x y )` (or just `foo` for terms with no arguments).
+//! * Axioms and theorems become `$a` and `$p` statements of typecode `|-`. Bound variables
+//!   are disjoint from each other and from the regular variables that do not depend on them,
+//!   and dummy variables are disjoint from everything.
+//! * Conversion proofs have no Metamath counterpart, so they are replaced by proofs of
+//!   equality statements `== a b`. These use the axioms `refl-s`, `sym-s` and `trans-s` for
+//!   each sort, `cong-foo` for each term, and `df-foo`, which states the definition of each
+//!   `def foo`. The axiom `conv-s` derives `|- a` from `== a b` and `|- b`.
+//! * Syntax axioms have no disjoint variable conditions, so the dependencies of the arguments
+//!   of a definition are recorded as `$d` conditions on `df-foo` between each regular argument
+//!   and the bound arguments it does not depend on.
+//!
+//! Names are kept where possible, but a name that would clash with another label or math
+//! symbol gets a `_n` suffix.
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::rc::Rc;
+use crate::{Type, AtomId, SortId, TermId, ThmId, SortVec, TermVec, ThmVec, TermKind, ThmKind,
+  ExprNode, ProofNode, StmtTrace, DeclKey, Modifiers, FrozenEnv, DocComment};
+use crate::mmu::export::build_unfold_map;
+
+/// An index into the table of labels.
+type Label = usize;
+
+/// The column at which long proofs are wrapped.
+const WIDTH: usize = 79;
+
+/// The math symbols and labels generated for a sort.
+struct SortNames {
+  /// The typecode of expressions of this sort.
+  tc: String,
+  /// The typecode of bound variables of this sort, and the syntax axiom that coerces them
+  /// to expressions. This is `None` for `strict` sorts.
+  bound: Option<(String, Label)>,
+  /// The axiom `== a a`.
+  refl: Label,
+  /// The axiom `== a b => == b a`.
+  sym: Label,
+  /// The axiom `== a b => == b c => == a c`.
+  trans: Label,
+  /// The axiom `== a b => |- b => |- a`, for `provable` sorts.
+  conv: Option<Label>,
+}
+
+/// The math symbols and labels generated for a term or definition.
+struct TermNames {
+  /// The constant for the term.
+  cnst: String,
+  /// The syntax axiom.
+  syntax: Label,
+  /// The congruence axiom, if the term has any regular arguments.
+  cong: Option<Label>,
+  /// The definitional axiom, and the dummy variables in its statement (in order),
+  /// for definitions. The dummies are filled in when the definition is exported.
+  df: Option<(Label, Rc<[AtomId]>)>,
+}
+
+/// A variable in a `${ ... $}` block.
+struct Var {
+  /// The name of the variable.
+  name: String,
+  /// The label of the `$f` statement for the variable.
+  float: Label,
+  /// The sort of the variable.
+  sort: SortId,
+  /// True if this is a bound variable.
+  bound: bool,
+}
+
+/// A `${ ... $}` block, which declares some variables and builds syntax and proof trees
+/// referring to them. The trees are deduplicated, so that structurally equal expressions
+/// get the same index.
+#[derive(Default)]
+struct Frame {
+  /// The prefix for the labels of the `$f` statements.
+  prefix: String,
+  /// The names of the variables, to avoid clashes.
+  names: HashSet<String>,
+  /// The variables, in declaration order.
+  vars: Vec<Var>,
+  /// The indexes of the dummy variables in `vars`.
+  dummies: HashMap<AtomId, usize>,
+  /// The proof steps, as a label applied to a list of earlier steps.
+  nodes: Vec<(Label, Box<[usize]>)>,
+  /// The inverse of `nodes`.
+  dedup: HashMap<(Label, Box<[usize]>), usize>,
+}
+
+impl Frame {
+  fn new(prefix: String) -> Self { Self { prefix, ..Self::default() } }
+
+  fn node(&mut self, l: Label, args: Box<[usize]>) -> usize {
+    let key = (l, args);
+    if let Some(&n) = self.dedup.get(&key) { return n }
+    let n = self.nodes.len();
+    self.nodes.push(key.clone());
+    self.dedup.insert(key, n);
+    n
+  }
+
+  fn dummy_names(&self, ds: &[AtomId]) -> Vec<&str> {
+    ds.iter().map(|a| &*self.vars[self.dummies[a]].name).collect()
+  }
+}
+
+/// The result of translating a [`ProofNode`].
+#[derive(Clone, Copy)]
+enum Val {
+  /// An expression with the given syntax proof and sort. When it is used as a conversion,
+  /// it is a proof of `== e e` by reflexivity.
+  Expr(usize, SortId),
+  /// A proof of `|- e`, given as the proof and the syntax proof of `e`.
+  Proof(usize, usize),
+  /// A proof of `== a b`, given as the proof, the syntax proofs of `a` and `b`, and their sort.
+  Conv(usize, usize, usize, SortId),
+}
+
+/// The context for translating a [`Proof`](crate::Proof).
+struct ProofCtx<'a> {
+  /// The variables for the arguments of the theorem.
+  vars: &'a [usize],
+  /// The proof steps and the expressions of the hypotheses.
+  hyps: &'a [(usize, usize)],
+  /// The proof heap.
+  heap: &'a [ProofNode],
+  /// The proof store.
+  store: &'a [ProofNode],
+  /// The translations of the heap elements.
+  memo: Vec<Option<Val>>,
+}
+
+/// Replace the characters that are not permitted in a label (if `label` is true)
+/// or math symbol.
+fn sanitize(s: &str, label: bool) -> String {
+  s.chars().map(|c| match c {
+    'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+    '!'..='~' if !label && c != '$' => c,
+    _ => '_',
+  }).collect()
+}
+
+/// Returns `base`, or `base_n` for the first `n` such that the result is not in either set.
+fn fresh(used: &HashSet<String>, local: &HashSet<String>, base: String) -> String {
+  let ok = |s: &String| !used.contains(s) && !local.contains(s);
+  if ok(&base) { return base }
+  for n in 1.. {
+    let s = format!("{base}_{n}");
+    if ok(&s) { return s }
+  }
+  unreachable!()
+}
+
+/// Encode a step number in the compressed proof format.
+fn encode(n: usize, out: &mut Vec<u8>) {
+  let mut n = n - 1;
+  #[allow(clippy::cast_possible_truncation)]
+  let mut s = vec![b'A' + (n % 20) as u8];
+  n /= 20;
+  while n > 0 {
+    n -= 1;
+    #[allow(clippy::cast_possible_truncation)]
+    s.push(b'U' + (n % 5) as u8);
+    n /= 5;
+  }
+  out.extend(s.iter().rev())
+}
+
+/// Mark the variables that occur in an expression.
+fn mark_vars(env: &FrozenEnv, heap: &[ExprNode], store: &[ExprNode], e: &ExprNode,
+  vars: &mut [bool], seen: &mut [bool],
+) {
+  match *e {
+    ExprNode::Ref(i) if i < vars.len() => vars[i] = true,
+    ExprNode::Ref(i) => if !std::mem::replace(&mut seen[i], true) {
+      mark_vars(env, heap, store, &heap[i], vars, seen)
+    },
+    ExprNode::Dummy(..) => {}
+    ExprNode::App(t, p) =>
+      for e in env.term(t).unpack_app(&store[p..]) { mark_vars(env, heap, store, e, vars, seen) }
+  }
+}
+
+/// Write the labels in a proof in normal (uncompressed) format.
+fn normal_proof<'a>(labels: &'a [String], f: &Frame, n: usize, out: &mut Vec<&'a str>) {
+  let (l, ref args) = f.nodes[n];
+  for &a in &**args { normal_proof(labels, f, a, out) }
+  out.push(&labels[l])
+}
+
+/// The state for writing a proof in compressed format.
+struct Compressor<'a> {
+  f: &'a Frame,
+  /// The number of times each step is used.
+  uses: Vec<u32>,
+  /// The labels in the parenthesized list, after the mandatory hypotheses.
+  list: Vec<Label>,
+  /// The number of each label in the compressed proof.
+  nums: HashMap<Label, usize>,
+  /// The number of each saved step.
+  saved: HashMap<usize, usize>,
+  /// The compressed proof.
+  out: Vec<u8>,
+}
+
+impl Compressor<'_> {
+  fn count(&mut self, n: usize) {
+    self.uses[n] += 1;
+    if self.uses[n] > 1 { return }
+    let (l, ref args) = self.f.nodes[n];
+    for &a in &**args { self.count(a) }
+    if !self.nums.contains_key(&l) {
+      self.list.push(l);
+      self.nums.insert(l, self.nums.len() + 1);
+    }
+  }
+
+  fn emit(&mut self, n: usize) {
+    if let Some(&k) = self.saved.get(&n) { return encode(k, &mut self.out) }
+    let (l, ref args) = self.f.nodes[n];
+    for &a in &**args { self.emit(a) }
+    encode(self.nums[&l], &mut self.out);
+    if self.uses[n] > 1 && !args.is_empty() {
+      self.out.push(b'Z');
+      self.saved.insert(n, self.nums.len() + self.saved.len() + 1);
+    }
+  }
+}
+
+/// Write a proof, wrapping the lines. `letters` is the compressed part of the proof.
+fn write_wrapped(w: &mut impl Write, tokens: &[&str], letters: &[u8]) -> io::Result<()> {
+  const INDENT: usize = 4;
+  write!(w, "{:INDENT$}", "")?;
+  let mut col = INDENT;
+  for (i, tok) in tokens.iter().enumerate() {
+    if i != 0 {
+      if col + 1 + tok.len() > WIDTH {
+        write!(w, "\n{:INDENT$}", "")?;
+        col = INDENT
+      } else {
+        write!(w, " ")?;
+        col += 1
+      }
+    }
+    write!(w, "{tok}")?;
+    col += tok.len()
+  }
+  let mut rest = letters;
+  if !rest.is_empty() { write!(w, " ")?; col += 1 }
+  while !rest.is_empty() {
+    if col >= WIDTH {
+      write!(w, "\n{:INDENT$}", "")?;
+      col = INDENT
+    }
+    let (line, r) = rest.split_at((WIDTH - col).min(rest.len()));
+    w.write_all(line)?;
+    col += line.len();
+    rest = r;
+  }
+  writeln!(w, " $.")
+}
+
+/// The state of the exporter.
+struct Exporter<'a, W> {
+  env: &'a FrozenEnv,
+  w: W,
+  /// True to write compressed proofs.
+  compressed: bool,
+  /// The labels and constants used so far.
+  used: HashSet<String>,
+  /// The names of all variables used so far. Variables are local to a block, but labels
+  /// must still be distinct from them.
+  var_names: HashSet<String>,
+  /// The table of labels.
+  labels: Vec<String>,
+  sorts: SortVec<SortNames>,
+  terms: TermVec<TermNames>,
+  /// The label of each theorem, and which of its arguments are mandatory variables
+  /// (that is, appear in the hypotheses or the conclusion).
+  thms: ThmVec<(Label, Box<[bool]>)>,
+}
+
+impl<W: Write> Exporter<'_, W> {
+  fn symbol(&mut self, base: &str) -> String {
+    let s = fresh(&self.used, &self.var_names, sanitize(base, false));
+    self.used.insert(s.clone());
+    s
+  }
+
+  fn label(&mut self, base: &str) -> Label {
+    let s = fresh(&self.used, &self.var_names, sanitize(base, true));
+    self.used.insert(s.clone());
+    self.labels.push(s);
+    self.labels.len() - 1
+  }
+
+  /// Assign the names of all sorts, terms and theorems. The names of the terms and theorems
+  /// are reserved first, so that they only change if they are not valid Metamath names.
+  fn init(&mut self) {
+    let env = self.env;
+    for td in env.terms().iter() {
+      let cnst = self.symbol(env.data()[td.atom].name().as_str());
+      self.terms.push(TermNames { cnst, syntax: 0, cong: None, df: None });
+    }
+    for td in env.thms().iter() {
+      let label = self.label(env.data()[td.atom].name().as_str());
+      self.thms.push((label, Box::new([])));
+    }
+    for sd in env.sorts().iter() {
+      let tc = self.symbol(sd.name.as_str());
+      let bound = if sd.mods.contains(Modifiers::STRICT) { None } else {
+        Some((self.symbol(&format!("{{{}}}", sd.name)), self.label(&format!("cv-{}", sd.name))))
+      };
+      let refl = self.label(&format!("refl-{}", sd.name));
+      let sym = self.label(&format!("sym-{}", sd.name));
+      let trans = self.label(&format!("trans-{}", sd.name));
+      let conv = sd.mods.contains(Modifiers::PROVABLE)
+        .then(|| self.label(&format!("conv-{}", sd.name)));
+      self.sorts.push(SortNames { tc, bound, refl, sym, trans, conv });
+    }
+    for (t, td) in env.terms().enum_iter() {
+      let name = env.data()[td.atom].name();
+      self.terms[t].syntax = self.label(&format!("t-{name}"));
+      if td.args.iter().any(|(_, ty)| !ty.bound()) {
+        self.terms[t].cong = Some(self.label(&format!("cong-{name}")))
+      }
+      if let TermKind::Def(Some(_)) = td.kind {
+        self.terms[t].df = Some((self.label(&format!("df-{name}")), Rc::new([])))
+      }
+    }
+  }
+
+  /// Add a variable to the frame.
+  fn var(&mut self, f: &mut Frame, base: &str, sort: SortId, bound: bool) -> usize {
+    let name = fresh(&self.used, &f.names, sanitize(base, false));
+    f.names.insert(name.clone());
+    self.var_names.insert(name.clone());
+    let float = self.label(&format!("{}.{name}", f.prefix));
+    f.vars.push(Var { name, float, sort, bound });
+    f.vars.len() - 1
+  }
+
+  /// Get the variable for a dummy, adding it to the frame if necessary.
+  fn dummy(&mut self, f: &mut Frame, a: AtomId, s: SortId) -> usize {
+    if let Some(&v) = f.dummies.get(&a) { return v }
+    let v = self.var(f, self.env.data()[a].name().as_str(), s, true);
+    f.dummies.insert(a, v);
+    v
+  }
+
+  /// The syntax proof of a variable. A bound variable in an expression position needs the
+  /// `cv-s` coercion.
+  fn var_node(&self, f: &mut Frame, v: usize, bound_pos: bool) -> usize {
+    let Var { float, sort, bound, .. } = f.vars[v];
+    let n = f.node(float, Box::new([]));
+    if bound && !bound_pos {
+      f.node(self.sorts[sort].bound.as_ref().expect("strict sort has no bound variables").1,
+        Box::new([n]))
+    } else { n }
+  }
+
+  /// Translate an expression in a statement to a syntax proof.
+  #[allow(clippy::too_many_arguments, clippy::many_single_char_names)]
+  fn expr(&mut self, f: &mut Frame, vars: &[usize], memo: &mut [Option<(usize, SortId)>],
+    heap: &[ExprNode], store: &[ExprNode], e: &ExprNode, bound_pos: bool,
+  ) -> (usize, SortId) {
+    match *e {
+      ExprNode::Ref(i) if i < vars.len() =>
+        (self.var_node(f, vars[i], bound_pos), f.vars[vars[i]].sort),
+      ExprNode::Ref(i) if bound_pos => self.expr(f, vars, memo, heap, store, &heap[i], true),
+      ExprNode::Ref(i) => {
+        if let Some(r) = memo[i] { return r }
+        let r = self.expr(f, vars, memo, heap, store, &heap[i], false);
+        memo[i] = Some(r);
+        r
+      }
+      ExprNode::Dummy(a, s) => {
+        let v = self.dummy(f, a, s);
+        (self.var_node(f, v, bound_pos), s)
+      }
+      ExprNode::App(t, p) => {
+        let td = self.env.term(t);
+        let args = td.args.iter().zip(td.unpack_app(&store[p..]))
+          .map(|(&(_, ty), e)| self.expr(f, vars, memo, heap, store, e, ty.bound()).0)
+          .collect();
+        (f.node(self.terms[t].syntax, args), td.ret.0)
+      }
+    }
+  }
+
+  /// Render an expression in a statement.
+  fn write_expr(&self, f: &Frame, vars: &[usize], heap: &[ExprNode], store: &[ExprNode],
+    e: &ExprNode, out: &mut String,
+  ) {
+    match *e {
+      ExprNode::Ref(i) if i < vars.len() => out.push_str(&f.vars[vars[i]].name),
+      ExprNode::Ref(i) => self.write_expr(f, vars, heap, store, &heap[i], out),
+      ExprNode::Dummy(a, _) => out.push_str(&f.vars[f.dummies[&a]].name),
+      ExprNode::App(t, p) => {
+        let td = self.env.term(t);
+        let args = td.unpack_app(&store[p..]);
+        if args.is_empty() { return out.push_str(&self.terms[t].cnst) }
+        out.push_str("( ");
+        out.push_str(&self.terms[t].cnst);
+        for e in args {
+          out.push(' ');
+          self.write_expr(f, vars, heap, store, e, out)
+        }
+        out.push_str(" )")
+      }
+    }
+  }
+
+  /// Get the syntax proof of a bound variable in a proof.
+  #[allow(clippy::many_single_char_names)]
+  fn bound_var(&mut self, f: &mut Frame, cx: &ProofCtx<'_>, e: &ProofNode) -> usize {
+    match *e {
+      ProofNode::Ref(i) if i < cx.vars.len() => self.var_node(f, cx.vars[i], true),
+      ProofNode::Ref(i) => self.bound_var(f, cx, &cx.heap[i]),
+      ProofNode::Refl(e) => self.bound_var(f, cx, &cx.store[e]),
+      ProofNode::Dummy(a, s) => {
+        let v = self.dummy(f, a, s);
+        self.var_node(f, v, true)
+      }
+      _ => unreachable!("expected a bound variable"),
+    }
+  }
+
+  fn proof_expr(&mut self, f: &mut Frame, cx: &mut ProofCtx<'_>, e: &ProofNode) -> (usize, SortId) {
+    match self.proof(f, cx, e) {
+      Val::Expr(n, s) => (n, s),
+      _ => unreachable!("expected an expression"),
+    }
+  }
+
+  fn proof_arg(&mut self, f: &mut Frame, cx: &mut ProofCtx<'_>, e: &ProofNode, ty: Type) -> usize {
+    if ty.bound() { self.bound_var(f, cx, e) } else { self.proof_expr(f, cx, e).0 }
+  }
+
+  /// Translate a proof, expression or conversion.
+  #[allow(clippy::many_single_char_names)]
+  fn proof(&mut self, f: &mut Frame, cx: &mut ProofCtx<'_>, e: &ProofNode) -> Val {
+    let env = self.env;
+    let store = cx.store;
+    match *e {
+      ProofNode::Ref(i) if i < cx.vars.len() =>
+        Val::Expr(self.var_node(f, cx.vars[i], false), f.vars[cx.vars[i]].sort),
+      ProofNode::Ref(i) => cx.memo[i].expect("forward reference in proof"),
+      ProofNode::Dummy(a, s) => {
+        let v = self.dummy(f, a, s);
+        Val::Expr(self.var_node(f, v, false), s)
+      }
+      ProofNode::Term(t, p) => {
+        let td = env.term(t);
+        let args = td.args.iter().zip(td.unpack_term(&store[p..]))
+          .map(|(&(_, ty), e)| self.proof_arg(f, cx, e, ty)).collect();
+        Val::Expr(f.node(self.terms[t].syntax, args), td.ret.0)
+      }
+      ProofNode::Hyp(i, _) => {
+        let (n, e) = cx.hyps[i];
+        Val::Proof(n, e)
+      }
+      ProofNode::Thm(t, p) => {
+        let td = env.thm(t);
+        let (res, args, hyps) = td.unpack_thm(&store[p..]);
+        let mut ns = vec![];
+        for (i, (&(_, ty), e)) in td.args.iter().zip(args).enumerate() {
+          if self.thms[t].1[i] { ns.push(self.proof_arg(f, cx, e, ty)) }
+        }
+        for e in hyps {
+          let Val::Proof(n, ..) = self.proof(f, cx, e) else { unreachable!("expected a proof") };
+          ns.push(n)
+        }
+        let (e, _) = self.proof_expr(f, cx, res);
+        Val::Proof(f.node(self.thms[t].0, ns.into()), e)
+      }
+      ProofNode::Conv(p) => {
+        let (tgt, c, p) = ProofNode::unpack_conv(&store[p..]);
+        let (tgt, s) = self.proof_expr(f, cx, tgt);
+        let c = self.proof(f, cx, c);
+        let Val::Proof(p, src) = self.proof(f, cx, p) else { unreachable!("expected a proof") };
+        match c {
+          Val::Expr(..) => Val::Proof(p, tgt),
+          Val::Conv(c, ..) => {
+            let conv = self.sorts[s].conv.expect("conversion in a non-provable sort");
+            Val::Proof(f.node(conv, Box::new([tgt, src, c, p])), tgt)
+          }
+          Val::Proof(..) => unreachable!("expected a conversion"),
+        }
+      }
+      ProofNode::Refl(e) => self.proof(f, cx, &store[e]),
+      ProofNode::Sym(c) => match self.proof(f, cx, &store[c]) {
+        Val::Conv(c, a, b, s) => Val::Conv(f.node(self.sorts[s].sym, Box::new([a, b, c])), b, a, s),
+        v => v,
+      }
+      ProofNode::Cong(t, p) => {
+        let td = env.term(t);
+        let (mut lhs, mut rhs, mut convs) = (vec![], vec![], vec![]);
+        for (&(_, ty), e) in td.args.iter().zip(td.unpack_term(&store[p..])) {
+          if ty.bound() {
+            lhs.push(self.bound_var(f, cx, e));
+            continue
+          }
+          match self.proof(f, cx, e) {
+            Val::Expr(n, s) => { lhs.push(n); rhs.push(n); convs.push(Err((n, s))) }
+            Val::Conv(c, a, b, _) => { lhs.push(a); rhs.push(b); convs.push(Ok(c)) }
+            Val::Proof(..) => unreachable!("expected a conversion"),
+          }
+        }
+        let names = &self.terms[t];
+        let l = f.node(names.syntax, lhs.clone().into());
+        if convs.iter().all(Result::is_err) { return Val::Expr(l, td.ret.0) }
+        let mut it = rhs.iter();
+        let r = td.args.iter().zip(&lhs)
+          .map(|(&(_, ty), &n)| if ty.bound() { n } else { *it.next().expect("impossible") })
+          .collect();
+        let r = f.node(names.syntax, r);
+        let cong = names.cong.expect("impossible");
+        lhs.extend(rhs);
+        for c in convs {
+          let c = c.unwrap_or_else(|(n, s)| f.node(self.sorts[s].refl, Box::new([n])));
+          lhs.push(c)
+        }
+        Val::Conv(f.node(cong, lhs.into()), l, r, td.ret.0)
+      }
+      ProofNode::Unfold(t, p) => {
+        let td = env.term(t);
+        let (sub_lhs, c, args) = td.unpack_unfold(&store[p..]);
+        let mut ns = td.args.iter().zip(args)
+          .map(|(&(_, ty), e)| self.proof_arg(f, cx, e, ty)).collect::<Vec<_>>();
+        let lhs = f.node(self.terms[t].syntax, ns.clone().into());
+        let (df, ds) = self.terms[t].df.clone().expect("unfolding a non-definition");
+        let mut m = HashMap::new();
+        if let TermKind::Def(Some(expr)) = &td.kind {
+          build_unfold_map(env, &mut m, &mut vec![false; expr.heap.len()],
+            &expr.heap, &expr.store, expr.head(), cx.heap, store, sub_lhs)
+        }
+        for a in &*ds { ns.push(self.bound_var(f, cx, m[a])) }
+        let (sub, s) = self.proof_expr(f, cx, sub_lhs);
+        let df = f.node(df, ns.into());
+        match self.proof(f, cx, c) {
+          Val::Expr(..) => Val::Conv(df, lhs, sub, s),
+          Val::Conv(c, _, rhs, _) =>
+            Val::Conv(f.node(self.sorts[s].trans, Box::new([lhs, sub, rhs, df, c])), lhs, rhs, s),
+          Val::Proof(..) => unreachable!("expected a conversion"),
+        }
+      }
+    }
+  }
+
+  fn write_doc(&mut self, doc: Option<&DocComment>, indent: &str) -> io::Result<()> {
+    if let Some(doc) = doc {
+      let doc = doc.trim().replace("$(", "$ (").replace("$)", "$ )");
+      let doc = doc.chars().map(|c| if c.is_ascii() { c } else { '?' }).collect::<String>();
+      write!(self.w, "{indent}$( ")?;
+      for (i, line) in doc.lines().enumerate() {
+        if i != 0 { write!(self.w, "\n{indent}   ")? }
+        write!(self.w, "{}", line.trim_end())?;
+      }
+      writeln!(self.w, " $)")?;
+    }
+    Ok(())
+  }
+
+  /// Write the `$v` and `$f` statements of a block.
+  fn write_vars(&mut self, f: &Frame) -> io::Result<()> {
+    write!(self.w, "  $v")?;
+    for v in &f.vars { write!(self.w, " {}", v.name)? }
+    writeln!(self.w, " $.")?;
+    for v in &f.vars {
+      let names = &self.sorts[v.sort];
+      let tc = if v.bound { &names.bound.as_ref().expect("impossible").0 } else { &names.tc };
+      writeln!(self.w, "  {} $f {tc} {} $.", self.labels[v.float], v.name)?;
+    }
+    Ok(())
+  }
+
+  #[allow(clippy::many_single_char_names)]
+  fn write_sort(&mut self, s: SortId) -> io::Result<()> {
+    let env = self.env;
+    self.write_doc(env.sort(s).doc.as_ref(), "")?;
+    let mut f = Frame::new(self.sorts[s].tc.clone());
+    let x = self.sorts[s].bound.is_some().then(|| self.var(&mut f, "x", s, true));
+    let [a, b, c] = ["a", "b", "c"].map(|n| self.var(&mut f, n, s, false));
+    let names = &self.sorts[s];
+    match &names.bound {
+      Some((bound, _)) => writeln!(self.w, "$c {} {bound} $.", names.tc)?,
+      None => writeln!(self.w, "$c {} $.", names.tc)?,
+    }
+    writeln!(self.w, "${{")?;
+    self.write_vars(&f)?;
+    let names = &self.sorts[s];
+    let l = &self.labels;
+    let [a, b, c] = [a, b, c].map(|v| &f.vars[v].name);
+    if let (Some((_, cv)), Some(x)) = (&names.bound, x) {
+      writeln!(self.w, "  {} $a {} {} $.", l[*cv], names.tc, f.vars[x].name)?;
+    }
+    writeln!(self.w, "  {} $a == {a} {a} $.", l[names.refl])?;
+    let sym = &l[names.sym];
+    writeln!(self.w, "  ${{\n    {sym}.1 $e == {a} {b} $.\n    {sym} $a == {b} {a} $.\n  $}}")?;
+    let trans = &l[names.trans];
+    writeln!(self.w, "  ${{\n    {trans}.1 $e == {a} {b} $.\n    {trans}.2 $e == {b} {c} $.\n    \
+      {trans} $a == {a} {c} $.\n  $}}")?;
+    if let Some(conv) = names.conv {
+      let conv = &l[conv];
+      writeln!(self.w, "  ${{\n    {conv}.1 $e == {a} {b} $.\n    {conv}.2 $e |- {b} $.\n    \
+        {conv} $a |- {a} $.\n  $}}")?;
+    }
+    writeln!(self.w, "$}}\n")
+  }
+
+  fn write_term(&mut self, t: TermId) -> io::Result<()> {
+    let env = self.env;
+    let td = env.term(t);
+    let cnst = self.terms[t].cnst.clone();
+    writeln!(self.w, "$c {cnst} $.")?;
+    let mut f = Frame::new(cnst.clone());
+    let vars = td.args.iter()
+      .map(|&(a, ty)| self.var(&mut f, name(env, a), ty.sort(), ty.bound()))
+      .collect::<Vec<_>>();
+    let app = |f: &Frame, vs: &mut dyn Iterator<Item=usize>| {
+      let mut s = String::new();
+      if td.args.is_empty() { s.push_str(&cnst) } else {
+        s.push_str("( ");
+        s.push_str(&cnst);
+        for v in vs { s.push(' '); s.push_str(&f.vars[v].name) }
+        s.push_str(" )");
+      }
+      s
+    };
+    let cong = self.terms[t].cong.map(|cong| {
+      let primes = td.args.iter().zip(&vars).filter(|((_, ty), _)| !ty.bound())
+        .map(|(&(a, ty), _)| self.var(&mut f, &format!("{}'", name(env, a)), ty.sort(), false))
+        .collect::<Vec<_>>();
+      (cong, primes)
+    });
+    let df = if let (TermKind::Def(Some(expr)), Some((df, _))) = (&td.kind, &self.terms[t].df) {
+      let df = *df;
+      let mut ds = HashMap::new();
+      for e in expr.heap.iter().chain(&*expr.store) {
+        if let ExprNode::Dummy(a, s) = *e { ds.insert(a, s); }
+      }
+      let mut ds = ds.into_iter().collect::<Vec<_>>();
+      ds.sort_by_key(|&(a, _)| &**env.data()[a].name());
+      for &(a, s) in &ds { self.dummy(&mut f, a, s); }
+      let ds = ds.into_iter().map(|(a, _)| a).collect::<Rc<[_]>>();
+      self.terms[t].df = Some((df, ds.clone()));
+      let mut rhs = String::new();
+      self.write_expr(&f, &vars, &expr.heap, &expr.store, expr.head(), &mut rhs);
+      Some((df, ds, rhs))
+    } else { None };
+    writeln!(self.w, "${{")?;
+    self.write_vars(&f)?;
+    self.write_doc(td.doc.as_ref(), "  ")?;
+    let lhs = app(&f, &mut vars.iter().copied());
+    writeln!(self.w, "  {} $a {} {lhs} $.", self.labels[self.terms[t].syntax], self.sorts[td.ret.0].tc)?;
+    if let Some((cong, primes)) = cong {
+      let cong = &self.labels[cong];
+      writeln!(self.w, "  ${{")?;
+      let regs = td.args.iter().zip(&vars).filter(|((_, ty), _)| !ty.bound());
+      for (i, ((_, v), &v2)) in regs.zip(&primes).enumerate() {
+        writeln!(self.w, "    {cong}.{} $e == {} {} $.", i + 1, f.vars[*v].name, f.vars[v2].name)?;
+      }
+      let mut it = primes.iter();
+      let rhs = app(&f, &mut td.args.iter().zip(&vars)
+        .map(|((_, ty), &v)| if ty.bound() { v } else { *it.next().expect("impossible") }));
+      writeln!(self.w, "    {cong} $a == {lhs} {rhs} $.\n  $}}")?;
+    }
+    if let Some((df, ds, rhs)) = df {
+      writeln!(self.w, "  ${{")?;
+      let bvs = td.args.iter().zip(&vars).filter(|((_, ty), _)| ty.bound()).map(|(_, &v)| v)
+        .collect::<Vec<_>>();
+      for (&(_, ty), &v) in td.args.iter().zip(&vars) {
+        if let Type::Reg(_, deps) = ty {
+          for (j, &b) in bvs.iter().enumerate() {
+            if deps & (1 << j) == 0 { writeln!(self.w, "    $d {} {} $.", f.vars[v].name, f.vars[b].name)? }
+          }
+        }
+      }
+      let ds = f.dummy_names(&ds);
+      if !ds.is_empty() {
+        if vars.is_empty() && ds.len() > 1 { writeln!(self.w, "    $d {} $.", ds.join(" "))? }
+        for &v in &vars { writeln!(self.w, "    $d {} {} $.", f.vars[v].name, ds.join(" "))? }
+      }
+      writeln!(self.w, "    {} $a == {lhs} {rhs} $.\n  $}}", self.labels[df])?;
+    }
+    writeln!(self.w, "$}}\n")
+  }
+
+  fn write_thm(&mut self, t: ThmId) -> io::Result<()> {
+    let env = self.env;
+    let td = env.thm(t);
+    let label = self.thms[t].0;
+    let mut f = Frame::new(self.labels[label].clone());
+    let vars = td.args.iter()
+      .map(|&(a, ty)| self.var(&mut f, name(env, a), ty.sort(), ty.bound()))
+      .collect::<Vec<_>>();
+    let mut mand = vec![false; td.args.len()];
+    let mut seen = vec![false; td.heap.len()];
+    for e in td.hyps.iter().map(|(_, e)| e).chain([&td.ret]) {
+      mark_vars(env, &td.heap, &td.store, e, &mut mand, &mut seen)
+    }
+    let mut memo = vec![None; td.heap.len()];
+    let mut hyps = vec![];
+    for (i, &(a, ref e)) in td.hyps.iter().enumerate() {
+      let (e, _) = self.expr(&mut f, &vars, &mut memo, &td.heap, &td.store, e, false);
+      let l = self.label(&match a {
+        Some(a) => format!("{}.{}", f.prefix, env.data()[a].name()),
+        None => format!("{}.{}", f.prefix, i + 1),
+      });
+      hyps.push((f.node(l, Box::new([])), e))
+    }
+    let (ret, _) = self.expr(&mut f, &vars, &mut memo, &td.heap, &td.store, &td.ret, false);
+    let proof = match &td.kind {
+      ThmKind::Axiom => None,
+      ThmKind::Thm(None) => Some(None),
+      ThmKind::Thm(Some(pf)) => {
+        let mut cx = ProofCtx {
+          vars: &vars, hyps: &hyps, heap: &pf.heap, store: &pf.store,
+          memo: vec![None; pf.heap.len()]
+        };
+        for i in vars.len()..pf.heap.len() {
+          cx.memo[i] = Some(self.proof(&mut f, &mut cx, &pf.heap[i]))
+        }
+        match self.proof(&mut f, &mut cx, pf.head()) {
+          Val::Proof(n, e) if e == ret => Some(Some(n)),
+          _ => return Err(io::Error::other(format!(
+            "the proof of {} does not prove its statement", env.data()[td.atom].name())))
+        }
+      }
+    };
+    let mut dvs = vec![];
+    let bvs = (0..vars.len()).filter(|&i| td.args[i].1.bound()).collect::<Vec<_>>();
+    let ds = f.vars[vars.len()..].iter().map(|v| &*v.name).collect::<Vec<_>>();
+    let bound = bvs.iter().map(|&i| &*f.vars[vars[i]].name).chain(ds.iter().copied()).collect::<Vec<_>>();
+    if bound.len() > 1 { dvs.push(bound) }
+    for (&(_, ty), &v) in td.args.iter().zip(&vars) {
+      if let Type::Reg(_, deps) = ty {
+        let mut dv = vec![&*f.vars[v].name];
+        for (j, &b) in bvs.iter().enumerate() {
+          if deps & (1 << j) == 0 { dv.push(&f.vars[vars[b]].name) }
+        }
+        dv.extend(ds.iter().copied());
+        if dv.len() > 1 { dvs.push(dv) }
+      }
+    }
+    writeln!(self.w, "${{")?;
+    if !f.vars.is_empty() { self.write_vars(&f)? }
+    for dv in dvs { writeln!(self.w, "  $d {} $.", dv.join(" "))? }
+    let mut text = String::new();
+    for ((_, e), &(n, _)) in td.hyps.iter().zip(&hyps) {
+      text.clear();
+      self.write_expr(&f, &vars, &td.heap, &td.store, e, &mut text);
+      writeln!(self.w, "  {} $e |- {text} $.", self.labels[f.nodes[n].0])?;
+    }
+    self.write_doc(td.doc.as_ref(), "  ")?;
+    text.clear();
+    self.write_expr(&f, &vars, &td.heap, &td.store, &td.ret, &mut text);
+    write!(self.w, "  {} ${} |- {text}", self.labels[label], if proof.is_some() {'p'} else {'a'})?;
+    match proof {
+      None => writeln!(self.w, " $.")?,
+      Some(None) => writeln!(self.w, " $= ? $.")?,
+      Some(Some(root)) => {
+        writeln!(self.w, " $=")?;
+        let mand = vars.iter().zip(&mand).filter(|p| *p.1).map(|(&v, _)| f.vars[v].float)
+          .chain(hyps.iter().map(|&(n, _)| f.nodes[n].0));
+        if self.compressed {
+          let mut c = Compressor {
+            f: &f, uses: vec![0; f.nodes.len()], list: vec![],
+            nums: mand.enumerate().map(|(i, l)| (l, i + 1)).collect(),
+            saved: HashMap::new(), out: vec![],
+          };
+          c.count(root);
+          c.emit(root);
+          let tokens = std::iter::once("(")
+            .chain(c.list.iter().map(|&l| &*self.labels[l]))
+            .chain([")"]).collect::<Vec<_>>();
+          write_wrapped(&mut self.w, &tokens, &c.out)?;
+        } else {
+          let mut tokens = vec![];
+          normal_proof(&self.labels, &f, root, &mut tokens);
+          write_wrapped(&mut self.w, &tokens, &[])?;
+        }
+      }
+    }
+    self.thms[t].1 = mand.into();
+    writeln!(self.w, "$}}\n")
+  }
+}
+
+/// The name of a variable, or `v` if it is anonymous.
+fn name(env: &FrozenEnv, a: Option<AtomId>) -> &str {
+  a.map_or("v", |a| env.data()[a].name().as_str())
+}
+
+impl FrozenEnv {
+  /// Write this environment into an `mm` file, with compressed or normal proofs.
+  /// See the [module documentation](crate::mm::export) for the translation.
+  pub fn export_mm(&self, w: impl Write, compressed: bool) -> io::Result<()> {
+    let mut ex = Exporter {
+      env: self, w, compressed,
+      used: ["(", ")", "|-", "=="].into_iter().map(String::from).collect(),
+      var_names: HashSet::new(),
+      labels: vec![],
+      sorts: SortVec::default(),
+      terms: TermVec::default(),
+      thms: ThmVec::default(),
+    };
+    ex.init();
+    writeln!(ex.w, "$( This file was generated by mm0-rs. The statement == a b means that a and b\n   \
+      are equal after unfolding definitions. $)\n\n$c ( ) |- == $.\n")?;
+    for s in self.stmts() {
+      match *s {
+        StmtTrace::Sort(a) => ex.write_sort(self.data()[a].sort().expect("expected a sort"))?,
+        StmtTrace::Decl(a) => match self.data()[a].decl().expect("expected a term/thm") {
+          DeclKey::Term(t) => ex.write_term(t)?,
+          DeclKey::Thm(t) => ex.write_thm(t)?,
+        }
+        StmtTrace::Global(_) => {}
+        StmtTrace::OutputString(_) => writeln!(ex.w, "$( output string $)\n")?,
+      }
+    }
+    ex.w.flush()
+  }
+}
@@ -0,0 +1,1380 @@
+//! Metamath importer, which produces an [`Environment`] object from a `.mm` file.
+//!
+//! The translation follows the one used by `mm0-hs from-mm`:
+//!
+//! * Every typecode used by a `$f` statement becomes a sort. Statements of the remaining
+//!   typecode (`|-` in `set.mm`) are provable statements; they are parsed as formulas of the
+//!   unique sort that accepts them, and that sort is marked `provable`.
+//! * Syntax axioms (`$a` statements whose typecode is a sort) become `term`s, and
+//!   provable statements are parsed using the grammar they define. A sort with no syntax
+//!   axioms (like `setvar`) is `pure`, and its variables become bound variables. Every other
+//!   variable depends on all bound variables of the statement that it is not disjoint from.
+//!   A syntax axiom whose statement is a single bound variable (like `cv $a class x $.`)
+//!   is a coercion, whose result depends on that variable.
+//! * `$a` and `$p` statements of the provable typecode become `axiom`s and `theorem`s,
+//!   and their proofs (normal or compressed) are translated into proof terms. Syntax theorems
+//!   (`$p` statements whose typecode is a sort) are unfolded where they are used.
+//! * Statements of the typecode `==` are read as the equality statements written by
+//!   `mm0-rs compile x.mm1 x.mm`, where `== a b` means that `a` and `b` are equal after
+//!   unfolding definitions. The reflexivity, symmetry, transitivity, conversion and
+//!   congruence axioms become the corresponding conversion proof steps, and a `df-foo`
+//!   axiom `== ( foo x y ) e` turns the preceding term `foo` into a definition with value `e`.
+//!   The `$d` conditions on `df-foo` between the arguments give their dependencies.
+//!
+//! The `$j syntax 'wff';`, `$j syntax '|-' as 'wff';` and `$j bound 'setvar';` commands
+//! can be used instead of inferring the sorts. Constructs that have no MM0 counterpart,
+//! such as ambiguous or left recursive grammars, syntax axioms with hypotheses or disjoint
+//! variable conditions, any other use of `==`, or applying a theorem with the same variable
+//! for two of its bound variables, are reported as errors. Disjoint variable conditions are
+//! not checked here; they are enforced by the MM0 verifier when the theorems are added to
+//! the environment.
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use crate::{Term, Thm, TermKind, ThmKind, AtomId, SortId, TermId, ThmId,
+  Environment, Modifiers, Type, Expr, Proof, SortVec, DocComment,
+  MAX_BOUND_VARS, Span, BoxError, FileRef, FileSpan};
+use crate::elab::{ElabError, Result,
+  proof::{IDedup, ExprHash, ProofKind, ProofHash, build}};
+use crate::mmu::import::Dedup;
+
+/// A declared math symbol.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Sym { Const, Var }
+
+/// The translation of a typecode.
+#[derive(Clone, Copy, Debug)]
+enum TypeCode {
+  /// A typecode of variables and syntax axioms, which is translated to a sort.
+  Sort(SortId),
+  /// The typecode of provable statements, which are formulas of the given sort
+  /// (or `None` if it has not been determined yet).
+  Provable(Option<SortId>),
+  /// The typecode `==` of equality statements.
+  Eq,
+}
+
+/// Information about a sort beyond what is stored in the [`Environment`].
+#[derive(Clone, Copy, Default, Debug)]
+struct SortInfo {
+  /// Whether variables of this sort are bound variables, if this has been decided.
+  bound: Option<bool>,
+  /// True if this is the sort of provable statements.
+  provable: bool,
+}
+
+/// A symbol in a grammar rule.
+#[derive(Clone, Copy, Debug)]
+enum RuleSym<'a> {
+  /// A constant symbol.
+  Const(&'a [u8]),
+  /// A variable of the given sort, which is the argument with the given index.
+  Var(SortId, usize),
+}
+
+/// A grammar rule, derived from a syntax axiom.
+#[derive(Debug)]
+struct Rule<'a> {
+  /// The term constructor of the syntax axiom.
+  term: TermId,
+  /// The symbols of the syntax axiom (after the typecode).
+  syms: Box<[RuleSym<'a>]>,
+}
+
+/// A parsed formula.
+#[derive(PartialEq, Eq, Debug)]
+enum Fmla<'a> {
+  /// A variable.
+  Var(&'a [u8]),
+  /// An application of a term constructor to its arguments (in MM0 order).
+  App(TermId, Box<[Rc<Fmla<'a>>]>),
+}
+
+/// A mandatory hypothesis of an assertion, as a position in the MM0 declaration.
+#[derive(Clone, Copy, Debug)]
+enum Slot {
+  /// The `$f` hypothesis for the variable with this index.
+  Arg(usize),
+  /// The `$e` hypothesis with this index.
+  Hyp(usize),
+}
+
+/// The unfolding of a syntax theorem.
+#[derive(Debug)]
+enum Template {
+  /// The argument with this index.
+  Arg(usize),
+  /// An application of a term constructor.
+  App(TermId, Box<[Template]>),
+}
+
+impl Template {
+  fn from_proof(de: &Dedup<ProofHash>, n: usize) -> Option<Self> {
+    match de[n] {
+      ProofHash::Ref(_, i) => Some(Template::Arg(i)),
+      ProofHash::Term(t, ref ns) =>
+        Some(Template::App(t, ns.iter().map(|&n| Self::from_proof(de, n)).collect::<Option<_>>()?)),
+      _ => None,
+    }
+  }
+
+  fn subst(&self, de: &mut Dedup<ProofHash>, ns: &[usize]) -> usize {
+    match *self {
+      Template::Arg(i) => de.reuse(ns[i]),
+      Template::App(t, ref ts) => {
+        let es = ts.iter().map(|tmpl| tmpl.subst(de, ns)).collect();
+        de.add(ProofHash::Term(t, es))
+      }
+    }
+  }
+}
+
+/// A statement which can appear in an equality axiom.
+#[derive(Clone, Debug)]
+enum EqStmt<'a> {
+  /// An equality statement `== a b`.
+  Eq(Rc<Fmla<'a>>, Rc<Fmla<'a>>),
+  /// A provable statement.
+  Provable(Rc<Fmla<'a>>),
+}
+
+/// The conversion rule that an equality axiom stands for.
+#[derive(Debug)]
+enum EqKind {
+  /// `== a a`
+  Refl,
+  /// `== a b => == b a`
+  Sym,
+  /// `== a b => == b c => == a c`, where the first step unfolds a definition
+  Trans,
+  /// `== a b => |- b => |- a`
+  Conv,
+  /// `== a1 b1 => ... => == ( foo a1 .. an ) ( foo b1 .. bn )`. The hypotheses are
+  /// in the order of the regular arguments of the term.
+  Cong(TermId),
+  /// `== ( foo x1 .. xn ) e`, the definition of `foo`
+  Def(TermId),
+}
+
+/// An axiom whose statement or hypotheses use the `==` typecode.
+#[derive(Debug)]
+struct EqAxiom<'a> {
+  kind: EqKind,
+  /// The mandatory variables, with their sorts.
+  vars: Box<[(&'a [u8], SortId)]>,
+  /// The mandatory hypotheses, where [`Slot::Arg`] indexes into `vars`.
+  slots: Box<[Slot]>,
+  /// The `$e` hypotheses.
+  hyps: Box<[EqStmt<'a>]>,
+  /// The conclusion.
+  concl: EqStmt<'a>,
+}
+
+/// A syntax theorem, that is, a `$p` statement whose typecode is a sort.
+#[derive(Debug)]
+struct SyntaxThm {
+  /// The sorts of the arguments.
+  args: Box<[SortId]>,
+  /// The sort of the result.
+  ret: SortId,
+  /// The mandatory hypotheses.
+  slots: Box<[Slot]>,
+  /// The expression that this syntax theorem abbreviates.
+  tmpl: Template,
+}
+
+/// The statement associated to a label.
+#[derive(Clone, Debug)]
+enum Label<'a> {
+  /// A `$f` hypothesis for the given variable.
+  Float(&'a [u8], SortId),
+  /// A `$e` hypothesis.
+  Ess(Rc<Fmla<'a>>),
+  /// A `$e` hypothesis of the `==` typecode.
+  EqHyp(Rc<Fmla<'a>>, Rc<Fmla<'a>>),
+  /// A syntax axiom, translated to a term constructor.
+  Term(TermId, Rc<[Slot]>),
+  /// A syntax theorem, which is unfolded where it is used.
+  Syntax(Rc<SyntaxThm>),
+  /// An axiom or theorem.
+  Thm(ThmId, Rc<[Slot]>),
+  /// An equality axiom, which is translated to a conversion rule.
+  Eq(Rc<EqAxiom<'a>>),
+}
+
+/// A `${ ... $}` block.
+#[derive(Debug)]
+struct Scope<'a> {
+  /// The variables declared in this block.
+  vars: Vec<&'a [u8]>,
+  /// The variables that were given a type in this block.
+  floats: Vec<&'a [u8]>,
+  /// The number of active hypotheses at the start of the block.
+  hyps: usize,
+  /// The number of active disjoint variable pairs at the start of the block.
+  dvs: usize,
+}
+
+/// The mandatory hypotheses of an assertion, translated to MM0 binders.
+#[derive(Debug)]
+struct Frame<'a> {
+  /// The MM0 binders; bound variables come first.
+  args: Vec<(Option<AtomId>, Type)>,
+  /// The index of each variable in `args`.
+  vars: HashMap<&'a [u8], usize>,
+  /// The `$e` hypotheses.
+  hyps: Vec<(&'a [u8], Rc<Fmla<'a>>)>,
+  /// The labels of the mandatory hypotheses, in Metamath order.
+  labels: Vec<&'a [u8]>,
+  /// The translation of the mandatory hypotheses, in Metamath order.
+  slots: Box<[Slot]>,
+  /// A disjoint variable condition between two regular variables, if any.
+  reg_dv: Option<(&'a [u8], &'a [u8])>,
+  /// True if there is any disjoint variable condition on the variables.
+  has_dv: bool,
+}
+
+/// An element of the proof stack.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+  /// An expression node, with its sort.
+  Expr(usize, SortId),
+  /// A proof node, with the node for its conclusion.
+  Proof(usize, usize),
+  /// A conversion proof node, with the nodes for its two sides.
+  Conv(usize, usize, usize),
+}
+
+impl Step {
+  fn reuse(self, de: &mut Dedup<ProofHash>) -> Self {
+    match self {
+      Step::Expr(n, s) => Step::Expr(de.reuse(n), s),
+      Step::Proof(n, e) => Step::Proof(de.reuse(n), e),
+      Step::Conv(n, l, r) => Step::Conv(de.reuse(n), l, r),
+    }
+  }
+}
+
+/// The result of parsing a formula of some sort, ending at some position.
+#[derive(Clone, Debug)]
+enum Parse<'a> {
+  /// A unique parse.
+  Ok(Rc<Fmla<'a>>),
+  /// More than one parse.
+  Ambiguous,
+}
+
+type Parses<'a> = Rc<[(usize, Parse<'a>)]>;
+
+/// A chart parser for formulas, which finds all parses of each sort at each position,
+/// so that ambiguous formulas are detected.
+struct FmlaParser<'a, 'b> {
+  /// The importer, which contains the grammar.
+  imp: &'b Importer<'a>,
+  /// The input tokens.
+  toks: &'b [&'a [u8]],
+  /// The parses found so far, keyed by sort and start position.
+  memo: HashMap<(SortId, usize), Parses<'a>>,
+}
+
+fn push_parse<'a>(out: &mut Vec<(usize, Parse<'a>)>, end: usize, p: Parse<'a>) {
+  if let Some(q) = out.iter_mut().find(|q| q.0 == end) {
+    match (&q.1, &p) {
+      (Parse::Ok(e1), Parse::Ok(e2)) if e1 == e2 => {}
+      _ => q.1 = Parse::Ambiguous,
+    }
+  } else {
+    out.push((end, p))
+  }
+}
+
+impl<'a> FmlaParser<'a, '_> {
+  fn parse(&mut self, s: SortId, pos: usize) -> Parses<'a> {
+    if let Some(r) = self.memo.get(&(s, pos)) { return r.clone() }
+    // A left recursive rule will see no parses here, rather than looping
+    self.memo.insert((s, pos), Rc::new([]));
+    let mut out = vec![];
+    let tk = self.toks.get(pos).copied();
+    if let Some(tk) = tk {
+      if self.imp.floats.get(tk).is_some_and(|&(_, s2)| s == s2) {
+        push_parse(&mut out, pos + 1, Parse::Ok(Rc::new(Fmla::Var(tk))))
+      }
+    }
+    let imp = self.imp;
+    let rules = tk.and_then(|tk| imp.rules.get(&(s, Some(tk)))).into_iter()
+      .chain(imp.rules.get(&(s, None))).flatten();
+    for rule in rules {
+      let mut args = vec![None; imp.env.terms[rule.term].args.len()];
+      self.rule(rule, 0, pos, &mut args, false, &mut out)
+    }
+    let out: Parses<'a> = out.into();
+    self.memo.insert((s, pos), out.clone());
+    out
+  }
+
+  fn rule(&mut self, rule: &Rule<'a>, i: usize, pos: usize,
+    args: &mut Vec<Option<Rc<Fmla<'a>>>>, ambiguous: bool,
+    out: &mut Vec<(usize, Parse<'a>)>,
+  ) {
+    match rule.syms.get(i) {
+      None => push_parse(out, pos, if ambiguous { Parse::Ambiguous } else {
+        Parse::Ok(Rc::new(Fmla::App(rule.term, args.iter().flatten().cloned().collect())))
+      }),
+      Some(&RuleSym::Const(c)) => if self.toks.get(pos) == Some(&c) {
+        self.rule(rule, i + 1, pos + 1, args, ambiguous, out)
+      },
+      Some(&RuleSym::Var(s, j)) => for (end, p) in self.parse(s, pos).iter() {
+        match p {
+          Parse::Ok(e) => {
+            args[j] = Some(e.clone());
+            self.rule(rule, i + 1, *end, args, ambiguous, out)
+          }
+          Parse::Ambiguous => self.rule(rule, i + 1, *end, args, true, out),
+        }
+      }
+    }
+  }
+}
+
+fn fmla_vars<'a>(e: &Fmla<'a>, out: &mut HashSet<&'a [u8]>) {
+  match e {
+    Fmla::Var(v) => { out.insert(v); }
+    Fmla::App(_, es) => for e in &**es { fmla_vars(e, out) }
+  }
+}
+
+fn expr(de: &mut Dedup<ExprHash>, vars: &HashMap<&[u8], usize>, e: &Fmla<'_>) -> usize {
+  let e = match e {
+    Fmla::Var(v) => ExprHash::Ref(ProofKind::Expr, vars[v]),
+    Fmla::App(t, es) => ExprHash::App(*t, es.iter().map(|e| expr(de, vars, e)).collect()),
+  };
+  de.add(e)
+}
+
+/// Substitutes expression nodes for the variables of a formula.
+fn inst(de: &mut Dedup<ProofHash>, vars: &HashMap<&[u8], usize>, e: &Fmla<'_>) -> usize {
+  match e {
+    Fmla::Var(v) => de.reuse(vars[v]),
+    Fmla::App(t, es) => {
+      let ns = es.iter().map(|e| inst(de, vars, e)).collect();
+      de.add(ProofHash::Term(*t, ns))
+    }
+  }
+}
+
+fn whitespace(c: u8) -> bool { matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c') }
+
+fn label_char(c: u8) -> bool { c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.') }
+
+/// The importer, which reads the input `.mm` file and builds an [`Environment`].
+#[derive(Debug)]
+pub struct Importer<'a> {
+  /// The input file name
+  file: &'a FileRef,
+  /// The input source text (as a byte slice)
+  source: &'a [u8],
+  /// The position in the input
+  idx: usize,
+  /// The environment under construction
+  env: Environment,
+  /// The declared constants and active variables
+  syms: HashMap<&'a [u8], Sym>,
+  /// The translation of each typecode
+  typecodes: HashMap<&'a [u8], TypeCode>,
+  /// Additional information on the sorts of the environment
+  sorts: SortVec<SortInfo>,
+  /// The grammar rules, indexed by sort and first symbol (or `None` if it is a variable)
+  rules: HashMap<(SortId, Option<&'a [u8]>), Vec<Rule<'a>>>,
+  /// True if the sorts are declared by `$j syntax` commands rather than inferred
+  j_syntax: bool,
+  /// The statements, indexed by label
+  labels: HashMap<&'a [u8], Label<'a>>,
+  /// The active `$f` hypothesis of each variable, and the sort of the variable
+  floats: HashMap<&'a [u8], (&'a [u8], SortId)>,
+  /// The labels of the active hypotheses, in order
+  hyps: Vec<&'a [u8]>,
+  /// The active disjoint variable pairs
+  dvs: Vec<(&'a [u8], &'a [u8])>,
+  /// The open `${` blocks
+  scopes: Vec<Scope<'a>>,
+  /// The last comment, which documents the next assertion
+  doc: Option<Span>,
+  /// The last term, if no assertion has been added since; only this term can get a
+  /// definition from a `df-foo` axiom
+  last_term: Option<TermId>,
+}
+
+impl<'a> Importer<'a> {
+  fn span(&self, s: Span) -> &'a [u8] { &self.source[s.start..s.end] }
+
+  fn fspan(&self, s: Span) -> FileSpan {
+    FileSpan {file: self.file.clone(), span: s}
+  }
+
+  fn token(&mut self) -> Option<Span> {
+    while self.idx < self.source.len() && whitespace(self.source[self.idx]) { self.idx += 1 }
+    let start = self.idx;
+    while self.idx < self.source.len() && !whitespace(self.source[self.idx]) { self.idx += 1 }
+    if self.idx == start { None } else { Some((start..self.idx).into()) }
+  }
+
+  fn err(&self, msg: BoxError) -> ElabError {
+    ElabError::new_e(self.idx..self.idx, msg)
+  }
+
+  /// Skips a comment, whose opening `$(` is at `start`, and returns the span of its contents.
+  fn comment(&mut self, start: Span) -> Result<Span> {
+    loop {
+      let t = self.token().ok_or_else(|| ElabError::new_e(start, "unclosed comment"))?;
+      if self.span(t) == b"$)" { return Ok((start.end..t.start).into()) }
+    }
+  }
+
+  /// Reads the tokens of a statement up to the keyword `end`, skipping comments.
+  /// Returns the tokens and the end of the statement.
+  fn stmt(&mut self, end: &str) -> Result<(Vec<Span>, usize)> {
+    let mut toks = vec![];
+    loop {
+      let t = self.token().ok_or_else(|| self.err(format!("expecting '{end}'").into()))?;
+      match self.span(t) {
+        b"$(" => { self.comment(t)?; }
+        s if s == end.as_bytes() => return Ok((toks, t.end)),
+        [b'$', ..] => return Err(ElabError::new_e(t, format!("expecting '{end}'"))),
+        _ => toks.push(t),
+      }
+    }
+  }
+
+  fn run(&mut self) -> Result<()> {
+    while let Some(t) = self.token() {
+      match self.span(t) {
+        b"$(" => {
+          let c = self.comment(t)?;
+          if !self.j_comment(c)? { self.doc = Some(c) }
+        }
+        b"$c" => {
+          self.doc = None;
+          if !self.scopes.is_empty() {
+            return Err(ElabError::new_e(t, "'$c' is only allowed in the outermost block"))
+          }
+          for c in self.stmt("$.")?.0 {
+            if self.syms.insert(self.span(c), Sym::Const).is_some() {
+              return Err(ElabError::new_e(c, "symbol is already declared"))
+            }
+          }
+        }
+        b"$v" => {
+          self.doc = None;
+          for v in self.stmt("$.")?.0 {
+            let x = self.span(v);
+            if self.syms.insert(x, Sym::Var).is_some() {
+              return Err(ElabError::new_e(v, "symbol is already declared"))
+            }
+            if let Some(sc) = self.scopes.last_mut() { sc.vars.push(x) }
+          }
+        }
+        b"$d" => {
+          let vs = self.stmt("$.")?.0;
+          for &v in &vs {
+            if self.syms.get(self.span(v)) != Some(&Sym::Var) {
+              return Err(ElabError::new_e(v, "expecting an active variable"))
+            }
+          }
+          for (i, &v1) in vs.iter().enumerate() {
+            for &v2 in &vs[..i] {
+              let (v1, v2) = (self.span(v1), self.span(v2));
+              if v1 == v2 { return Err(ElabError::new_e(t, "duplicate variable in '$d'")) }
+              self.dvs.push(if v1 < v2 { (v1, v2) } else { (v2, v1) })
+            }
+          }
+        }
+        b"${" => {
+          self.doc = None;
+          self.scopes.push(Scope {vars: vec![], floats: vec![], hyps: self.hyps.len(), dvs: self.dvs.len()})
+        }
+        b"$}" => {
+          self.doc = None;
+          let sc = self.scopes.pop().ok_or_else(|| ElabError::new_e(t, "unmatched '$}'"))?;
+          for v in sc.vars { self.syms.remove(v); }
+          for v in sc.floats { self.floats.remove(v); }
+          self.hyps.truncate(sc.hyps);
+          self.dvs.truncate(sc.dvs);
+        }
+        b"$[" => return Err(ElabError::new_e(t, "file inclusion is not supported")),
+        [b'$', ..] => return Err(ElabError::new_e(t, "unknown keyword")),
+        _ => self.labeled(t)?,
+      }
+    }
+    if !self.scopes.is_empty() { return Err(self.err("unclosed '${'".into())) }
+    Ok(())
+  }
+
+  fn labeled(&mut self, lsp: Span) -> Result<()> {
+    let label = self.span(lsp);
+    if !label.iter().all(|&c| label_char(c)) {
+      return Err(ElabError::new_e(lsp, "invalid label"))
+    }
+    if self.labels.contains_key(label) {
+      return Err(ElabError::new_e(lsp, "duplicate label"))
+    }
+    let kw = self.token().ok_or_else(|| self.err("expecting keyword".into()))?;
+    let kw = self.span(kw);
+    let end = if kw == b"$p" { "$=" } else { "$." };
+    let (toks, end) = self.stmt(end)?;
+    let full = (lsp.start..end).into();
+    let Some((&tc, fmla)) = toks.split_first() else {
+      return Err(ElabError::new_e(full, "expecting typecode"))
+    };
+    if self.syms.get(self.span(tc)) != Some(&Sym::Const) {
+      return Err(ElabError::new_e(tc, "expecting a constant"))
+    }
+    for &t in fmla {
+      match self.syms.get(self.span(t)) {
+        None => return Err(ElabError::new_e(t, "undeclared math symbol")),
+        Some(Sym::Var) if !self.floats.contains_key(self.span(t)) && kw != b"$f" =>
+          return Err(ElabError::new_e(t, "variable has no active '$f' statement")),
+        _ => {}
+      }
+    }
+    match kw {
+      b"$f" => { self.doc = None; self.float(lsp, tc, fmla, full) }
+      b"$e" => {
+        let l = if matches!(self.typecode(tc)?, TypeCode::Eq) {
+          let (_, a, b) = self.parse_eq(fmla, full)?;
+          Label::EqHyp(a, b)
+        } else {
+          Label::Ess(self.parse_provable(tc, fmla, full)?)
+        };
+        self.labels.insert(label, l);
+        self.hyps.push(label);
+        Ok(())
+      }
+      b"$a" => match self.typecode(tc)? {
+        TypeCode::Sort(s) => self.syntax_axiom(lsp, s, fmla, full),
+        TypeCode::Eq => self.eq_axiom(lsp, tc, fmla, full),
+        TypeCode::Provable(_) if self.hyps.iter().any(|h| matches!(self.labels[h], Label::EqHyp(..))) =>
+          self.eq_axiom(lsp, tc, fmla, full),
+        TypeCode::Provable(_) => self.assertion(lsp, tc, fmla, full, false),
+      }
+      b"$p" => match self.typecode(tc)? {
+        TypeCode::Sort(s) => self.syntax_thm(lsp, s, fmla, full),
+        TypeCode::Eq => Err(ElabError::new_e(tc, "theorems of the '==' typecode are not supported")),
+        TypeCode::Provable(_) => self.assertion(lsp, tc, fmla, full, true),
+      }
+      _ => Err(ElabError::new_e(full, "expecting '$f', '$e', '$a' or '$p'"))
+    }
+  }
+
+  /// Gets the translation of the typecode of an assertion. Unknown typecodes are
+  /// provable typecodes, unless the sorts are declared using `$j syntax`, except for `==`,
+  /// which is the typecode of equality statements.
+  fn typecode(&mut self, tc: Span) -> Result<TypeCode> {
+    let c = self.span(tc);
+    if let Some(&t) = self.typecodes.get(c) { return Ok(t) }
+    if c == b"==" {
+      self.typecodes.insert(c, TypeCode::Eq);
+      return Ok(TypeCode::Eq)
+    }
+    if self.j_syntax {
+      return Err(ElabError::new_e(tc, "typecode is not declared by a '$j syntax' command"))
+    }
+    self.typecodes.insert(c, TypeCode::Provable(None));
+    Ok(TypeCode::Provable(None))
+  }
+
+  fn add_sort(&mut self, sp: Span, full: Span) -> Result<SortId> {
+    let c = self.span(sp);
+    let a = self.env.get_atom(c);
+    let s = self.env.add_sort(a, self.fspan(sp), full, Modifiers::empty(), None)
+      .map_err(|e| e.into_elab_error(sp))?;
+    self.sorts.push(SortInfo::default());
+    self.typecodes.insert(c, TypeCode::Sort(s));
+    Ok(s)
+  }
+
+  /// Returns true if variables of sort `s` are bound variables. This is decided
+  /// the first time a variable of this sort is used in an assertion.
+  fn is_bound(&mut self, s: SortId) -> bool {
+    *self.sorts[s].bound.get_or_insert_with(|| {
+      let bound = !self.j_syntax;
+      if bound { self.env.sorts[s].mods |= Modifiers::PURE }
+      bound
+    })
+  }
+
+  /// Marks sort `s` as the result of a syntax axiom, so its variables are not bound variables.
+  fn set_syntax(&mut self, s: SortId, sp: Span) -> Result<()> {
+    match self.sorts[s].bound {
+      Some(true) => Err(ElabError::new_e(sp, format!(
+        "syntax axiom for sort '{}', whose variables have already been used as bound variables",
+        self.env.sorts[s].name))),
+      Some(false) => Ok(()),
+      None => { self.sorts[s].bound = Some(false); Ok(()) }
+    }
+  }
+
+  fn set_provable(&mut self, c: &'a [u8], s: SortId, sp: Span) -> Result<()> {
+    if self.sorts[s].provable {
+      return Err(ElabError::new_e(sp, format!(
+        "more than one provable typecode for sort '{}'", self.env.sorts[s].name)))
+    }
+    self.sorts[s].provable = true;
+    self.env.sorts[s].mods |= Modifiers::PROVABLE;
+    self.typecodes.insert(c, TypeCode::Provable(Some(s)));
+    Ok(())
+  }
+
+  fn float(&mut self, lsp: Span, tc: Span, fmla: &[Span], full: Span) -> Result<()> {
+    let &[v] = fmla else { return Err(ElabError::new_e(full, "expecting a single variable")) };
+    let x = self.span(v);
+    if self.syms.get(x) != Some(&Sym::Var) {
+      return Err(ElabError::new_e(v, "expecting a variable"))
+    }
+    if self.floats.contains_key(x) {
+      return Err(ElabError::new_e(v, "variable already has an active '$f' statement"))
+    }
+    let s = match self.typecodes.get(self.span(tc)) {
+      Some(&TypeCode::Sort(s)) => s,
+      Some(TypeCode::Provable(_)) =>
+        return Err(ElabError::new_e(tc, "variables of the provable typecode are not supported")),
+      Some(TypeCode::Eq) =>
+        return Err(ElabError::new_e(tc, "variables of the '==' typecode are not supported")),
+      None if self.j_syntax =>
+        return Err(ElabError::new_e(tc, "typecode is not declared by a '$j syntax' command")),
+      None => self.add_sort(tc, full)?,
+    };
+    let label = self.span(lsp);
+    self.floats.insert(x, (label, s));
+    if let Some(sc) = self.scopes.last_mut() { sc.floats.push(x) }
+    self.labels.insert(label, Label::Float(x, s));
+    self.hyps.push(label);
+    Ok(())
+  }
+
+  /// Builds the frame of an assertion, given the symbols of its statement.
+  fn frame(&mut self, fmla: &[Span], full: Span) -> Result<Frame<'a>> {
+    let mut mand = HashSet::new();
+    for &t in fmla {
+      let x = self.span(t);
+      if self.floats.contains_key(x) { mand.insert(x); }
+    }
+    let mut hyps = vec![];
+    let mut vars = vec![];
+    for &h in &self.hyps {
+      match &self.labels[h] {
+        Label::Ess(e) => { fmla_vars(e, &mut mand); hyps.push((h, e.clone())) }
+        &Label::Float(v, s) => vars.push((v, s)),
+        Label::EqHyp(..) => return Err(ElabError::new_e(full,
+          "hypotheses of the '==' typecode are only supported in equality axioms")),
+        _ => unreachable!(),
+      }
+    }
+    vars.retain(|&(v, _)| mand.contains(v));
+    let mut args = vec![];
+    let mut var_idx = HashMap::new();
+    for &(v, s) in &vars {
+      if self.is_bound(s) {
+        if args.len() >= MAX_BOUND_VARS {
+          return Err(ElabError::new_e(full,
+            format!("too many bound variables (max {MAX_BOUND_VARS})")))
+        }
+        var_idx.insert(v, args.len());
+        args.push((Some(self.env.get_atom(v)), Type::Bound(s)))
+      }
+    }
+    let dvs: HashSet<_> = self.dvs.iter()
+      .filter(|&&(v1, v2)| mand.contains(v1) && mand.contains(v2)).copied().collect();
+    let mut reg_dv = None;
+    let bound = args.len();
+    for &(v, s) in &vars {
+      if !var_idx.contains_key(v) {
+        let mut deps = 0;
+        for (&v2, &i) in &var_idx {
+          if i < bound && !dvs.contains(&if v < v2 { (v, v2) } else { (v2, v) }) {
+            deps |= 1 << i
+          }
+        }
+        if let Some((&v2, _)) = var_idx.iter()
+          .find(|&(&v2, &i)| i >= bound && dvs.contains(&if v < v2 { (v, v2) } else { (v2, v) })) {
+          reg_dv = Some((v2, v))
+        }
+        var_idx.insert(v, args.len());
+        args.push((Some(self.env.get_atom(v)), Type::Reg(s, deps)))
+      }
+    }
+    let mut labels = vec![];
+    let mut slots = vec![];
+    let mut n_hyps = 0;
+    for &h in &self.hyps {
+      match self.labels[h] {
+        Label::Ess(_) => { slots.push(Slot::Hyp(n_hyps)); n_hyps += 1 }
+        Label::Float(v, _) => match var_idx.get(v) {
+          Some(&i) => slots.push(Slot::Arg(i)),
+          None => continue,
+        },
+        _ => unreachable!(),
+      }
+      labels.push(h)
+    }
+    Ok(Frame {
+      args, vars: var_idx, hyps, labels, slots: slots.into(), reg_dv, has_dv: !dvs.is_empty()
+    })
+  }
+
+  /// Parses a formula of sort `s`.
+  fn parse(&self, s: SortId, fmla: &[Span], full: Span) -> Result<Rc<Fmla<'a>>> {
+    let toks: Vec<_> = fmla.iter().map(|&t| self.span(t)).collect();
+    let mut p = FmlaParser {imp: self, toks: &toks, memo: HashMap::new()};
+    match p.parse(s, 0).iter().find(|p| p.0 == toks.len()) {
+      Some((_, Parse::Ok(e))) => Ok(e.clone()),
+      Some((_, Parse::Ambiguous)) => Err(ElabError::new_e(full, format!(
+        "ambiguous formula of sort '{}'", self.env.sorts[s].name))),
+      None => Err(ElabError::new_e(full, format!(
+        "cannot parse formula as sort '{}'", self.env.sorts[s].name))),
+    }
+  }
+
+  /// Parses a statement of a provable typecode. If the sort for this typecode has not yet been
+  /// determined, it is the unique sort that accepts the statement.
+  fn parse_provable(&mut self, tc: Span, fmla: &[Span], full: Span) -> Result<Rc<Fmla<'a>>> {
+    match self.typecode(tc)? {
+      TypeCode::Sort(_) =>
+        Err(ElabError::new_e(tc, "hypotheses must have the provable typecode")),
+      TypeCode::Eq => Err(ElabError::new_e(tc, "expecting the provable typecode")),
+      TypeCode::Provable(Some(s)) => self.parse(s, fmla, full),
+      TypeCode::Provable(None) => {
+        let mut found = None;
+        for (s, _) in self.sorts.enum_iter() {
+          if let Ok(e) = self.parse(s, fmla, full) {
+            if let Some((s2, _)) = found {
+              return Err(ElabError::new_e(full, format!(
+                "cannot determine the sort of typecode '{}': the formula parses as both '{}' and '{}' \
+                (use a '$j syntax' command)",
+                String::from_utf8_lossy(self.span(tc)),
+                self.env.sorts[s2].name, self.env.sorts[s].name)))
+            }
+            found = Some((s, e))
+          }
+        }
+        let (s, e) = found.ok_or_else(|| ElabError::new_e(full, "cannot parse formula"))?;
+        self.set_provable(self.span(tc), s, tc)?;
+        Ok(e)
+      }
+    }
+  }
+
+  /// Parses an equality statement `== a b`, where `a` and `b` have the same sort.
+  fn parse_eq(&self, fmla: &[Span], full: Span) -> Result<(SortId, Rc<Fmla<'a>>, Rc<Fmla<'a>>)> {
+    let toks: Vec<_> = fmla.iter().map(|&t| self.span(t)).collect();
+    let mut p = FmlaParser {imp: self, toks: &toks, memo: HashMap::new()};
+    let mut found = None;
+    for (s, _) in self.sorts.enum_iter() {
+      for (mid, lhs) in p.parse(s, 0).iter() {
+        for (end, rhs) in p.parse(s, *mid).iter() {
+          if *end != toks.len() { continue }
+          match (found.is_some(), lhs, rhs) {
+            (false, Parse::Ok(a), Parse::Ok(b)) => found = Some((s, a.clone(), b.clone())),
+            _ => return Err(ElabError::new_e(full, "ambiguous equality statement")),
+          }
+        }
+      }
+    }
+    found.ok_or_else(|| ElabError::new_e(full, "cannot parse equality statement"))
+  }
+
+  fn syntax_axiom(&mut self, lsp: Span, s: SortId, fmla: &[Span], full: Span) -> Result<()> {
+    self.set_syntax(s, lsp)?;
+    let fr = self.frame(fmla, full)?;
+    if !fr.hyps.is_empty() {
+      return Err(ElabError::new_e(lsp, "syntax axioms with hypotheses are not supported"))
+    }
+    if fr.has_dv {
+      return Err(ElabError::new_e(lsp,
+        "syntax axioms with disjoint variable conditions are not supported"))
+    }
+    if fmla.first().and_then(|&t| fr.vars.get(self.span(t)))
+      .is_some_and(|&i| fr.args[i].1.sort() == s) {
+      return Err(ElabError::new_e(lsp, "left recursive syntax axioms are not supported"))
+    }
+    let mut seen = HashSet::new();
+    let syms = fmla.iter().map(|&t| {
+      let x = self.span(t);
+      match fr.vars.get(x) {
+        None => Ok(RuleSym::Const(x)),
+        Some(&i) if seen.insert(i) => Ok(RuleSym::Var(fr.args[i].1.sort(), i)),
+        Some(_) => Err(ElabError::new_e(t, "repeated variable in syntax axiom")),
+      }
+    }).collect::<Result<Box<[_]>>>()?;
+    // A syntax axiom whose statement is a single bound variable, like `cv $a class x $.`,
+    // is a coercion, and the result depends on the variable.
+    let ret_deps = match *syms {
+      [RuleSym::Var(_, i)] if fr.args[i].1.bound() => 1 << i,
+      _ => 0,
+    };
+    let label = self.span(lsp);
+    let atom = self.env.get_atom(label);
+    let (term, _) = self.env.add_term(Term {
+      atom,
+      span: self.fspan(lsp),
+      vis: Modifiers::empty(),
+      full,
+      doc: self.doc.take().map(|c| self.doc_comment(c)),
+      args: fr.args.into(),
+      ret: (s, ret_deps),
+      kind: TermKind::Term,
+    }).map_err(|e| e.into_elab_error(lsp))?;
+    self.last_term = Some(term);
+    let first = match syms.first() { Some(&RuleSym::Const(c)) => Some(c), _ => None };
+    self.rules.entry((s, first)).or_default().push(Rule {term, syms});
+    self.labels.insert(label, Label::Term(term, fr.slots.into()));
+    match *self.parse(s, fmla, full)? {
+      Fmla::App(t, _) if t == term => Ok(()),
+      _ => Err(ElabError::new_e(full, "syntax axiom makes the grammar ambiguous")),
+    }
+  }
+
+  fn syntax_thm(&mut self, lsp: Span, s: SortId, fmla: &[Span], full: Span) -> Result<()> {
+    self.doc = None;
+    let fr = self.frame(fmla, full)?;
+    if !fr.hyps.is_empty() {
+      return Err(ElabError::new_e(lsp, "syntax theorems with hypotheses are not supported"))
+    }
+    let stmt = self.parse(s, fmla, full)?;
+    let mut de = Dedup::new(&fr.args);
+    let ir = expr(&mut de, &fr.vars, &stmt);
+    let mut de = de.map_proof();
+    match self.proof(&mut de, &fr, &[])? {
+      (Step::Expr(n, _), _) if n == ir => {}
+      (_, sp) => return Err(ElabError::new_e(sp, "proof does not match the statement")),
+    }
+    let tmpl = Template::from_proof(&de, ir)
+      .ok_or_else(|| ElabError::new_e(lsp, "syntax theorems with dummy variables are not supported"))?;
+    let args = fr.args.iter().map(|a| a.1.sort()).collect();
+    self.labels.insert(self.span(lsp),
+      Label::Syntax(Rc::new(SyntaxThm {args, ret: s, slots: fr.slots, tmpl})));
+    Ok(())
+  }
+
+  fn assertion(&mut self, lsp: Span, tc: Span, fmla: &[Span], full: Span, is_thm: bool) -> Result<()> {
+    let stmt = self.parse_provable(tc, fmla, full)?;
+    let fr = self.frame(fmla, full)?;
+    if let (false, Some((v1, v2))) = (is_thm, fr.reg_dv) {
+      return Err(ElabError::new_e(lsp, format!(
+        "disjoint variable condition on non-bound variables '{}' and '{}' is not supported",
+        String::from_utf8_lossy(v1), String::from_utf8_lossy(v2))))
+    }
+    let mut de = Dedup::new(&fr.args);
+    let is: Vec<_> = fr.hyps.iter().map(|(_, e)| expr(&mut de, &fr.vars, e)).collect();
+    let ir = expr(&mut de, &fr.vars, &stmt);
+    let (mut ids, heap, store) = build(&de);
+    let hyps = fr.hyps.iter().zip(&is)
+      .map(|(&(h, _), &i)| (Some(self.env.get_atom(h)), ids[i].take())).collect();
+    let ret = ids[ir].take();
+    let (kind, end) = if is_thm {
+      let mut de = de.map_proof();
+      let hs: Vec<_> = is.iter().enumerate()
+        .map(|(i, &e)| Step::Proof(de.add(ProofHash::Hyp(i, e)), e)).collect();
+      let ip = match self.proof(&mut de, &fr, &hs)? {
+        (Step::Proof(n, e), _) if e == ir => n,
+        (_, sp) => return Err(ElabError::new_e(sp, "proof does not match the statement")),
+      };
+      let (mut ids, heap, mut store) = build(&de);
+      let hyps = hs.iter().filter_map(|&h| match h {
+        Step::Proof(n, _) => Some(ids[n].take()),
+        Step::Expr(..) | Step::Conv(..) => None,
+      }).collect();
+      store.push(ids[ip].take());
+      (ThmKind::Thm(Some(Proof {heap, hyps, store: store.into()})), self.idx)
+    } else {
+      (ThmKind::Axiom, full.end)
+    };
+    self.last_term = None;
+    let atom = self.env.get_atom(self.span(lsp));
+    let (t, _) = self.env.add_thm(Thm {
+      atom,
+      span: self.fspan(lsp),
+      vis: if is_thm {Modifiers::PUB} else {Modifiers::empty()},
+      full: (lsp.start..end).into(),
+      doc: self.doc.take().map(|c| self.doc_comment(c)),
+      args: fr.args.into(), heap, store: store.into(), hyps, ret, kind
+    }).map_err(|e| e.into_elab_error(lsp))?;
+    self.labels.insert(self.span(lsp), Label::Thm(t, fr.slots.into()));
+    Ok(())
+  }
+
+  /// Reads an axiom whose statement or hypotheses use the `==` typecode. These axioms do not
+  /// become MM0 axioms; instead they are recognized as one of the conversion rules written by
+  /// the exporter, and a definitional axiom `== ( foo x y ) e` sets the value of `foo`.
+  fn eq_axiom(&mut self, lsp: Span, tc: Span, fmla: &[Span], full: Span) -> Result<()> {
+    self.doc = None;
+    let concl = if matches!(self.typecode(tc)?, TypeCode::Eq) {
+      let (_, a, b) = self.parse_eq(fmla, full)?;
+      EqStmt::Eq(a, b)
+    } else {
+      EqStmt::Provable(self.parse_provable(tc, fmla, full)?)
+    };
+    let mut mand = HashSet::new();
+    let mut hyps = vec![];
+    let mut add = |st: &EqStmt<'a>| match st {
+      EqStmt::Eq(a, b) => { fmla_vars(a, &mut mand); fmla_vars(b, &mut mand) }
+      EqStmt::Provable(a) => fmla_vars(a, &mut mand),
+    };
+    add(&concl);
+    for &h in &self.hyps {
+      match &self.labels[h] {
+        Label::Ess(e) => hyps.push(EqStmt::Provable(e.clone())),
+        Label::EqHyp(a, b) => hyps.push(EqStmt::Eq(a.clone(), b.clone())),
+        _ => continue,
+      }
+      add(hyps.last().expect("impossible"))
+    }
+    let mut vars = vec![];
+    let mut slots = vec![];
+    let mut n_hyps = 0;
+    for &h in &self.hyps {
+      match self.labels[h] {
+        Label::Float(v, s) if mand.contains(v) => { slots.push(Slot::Arg(vars.len())); vars.push((v, s)) }
+        Label::Float(..) => {}
+        _ => { slots.push(Slot::Hyp(n_hyps)); n_hyps += 1 }
+      }
+    }
+    let unsupported = || ElabError::new_e(lsp,
+      "unsupported axiom of the '==' typecode: expecting one of the reflexivity, symmetry, \
+      transitivity, conversion, congruence or definition axioms written by 'mm0-rs compile'");
+    let kind = match (&concl, &*hyps) {
+      (EqStmt::Eq(a, b), []) if a == b => EqKind::Refl,
+      (EqStmt::Eq(b, a), [EqStmt::Eq(a2, b2)]) if a == a2 && b == b2 => EqKind::Sym,
+      (EqStmt::Eq(a, c), [EqStmt::Eq(a2, b), EqStmt::Eq(b2, c2)]) if a == a2 && b == b2 && c == c2 =>
+        EqKind::Trans,
+      (EqStmt::Provable(a), [EqStmt::Eq(a2, b), EqStmt::Provable(b2)]) if a == a2 && b == b2 =>
+        EqKind::Conv,
+      (EqStmt::Eq(lhs, rhs), _) => match (&**lhs, &**rhs) {
+        (Fmla::App(t, las), Fmla::App(t2, ras)) if t == t2 && !hyps.is_empty() => {
+          let td = &self.env.terms[*t];
+          let mut hs = hyps.iter();
+          for ((&(_, ty), a), b) in td.args.iter().zip(&**las).zip(&**ras) {
+            let ok = if ty.bound() { a == b } else {
+              matches!(hs.next(), Some(EqStmt::Eq(a2, b2)) if a == a2 && b == b2)
+            };
+            if !ok { return Err(unsupported()) }
+          }
+          if hs.next().is_some() { return Err(unsupported()) }
+          EqKind::Cong(*t)
+        }
+        (Fmla::App(t, las), _) if hyps.is_empty() => {
+          self.define(lsp, *t, las, rhs)?;
+          EqKind::Def(*t)
+        }
+        _ => return Err(unsupported()),
+      },
+      _ => return Err(unsupported()),
+    };
+    self.labels.insert(self.span(lsp), Label::Eq(Rc::new(EqAxiom {
+      kind, vars: vars.into(), slots: slots.into(), hyps: hyps.into(), concl
+    })));
+    Ok(())
+  }
+
+  /// Sets the value of the term `t`, given the statement `== ( t x1 .. xn ) e`
+  /// of a definitional axiom.
+  fn define(&mut self, lsp: Span, t: TermId, las: &[Rc<Fmla<'a>>], rhs: &Fmla<'a>) -> Result<()> {
+    if self.last_term != Some(t) {
+      return Err(ElabError::new_e(lsp, format!(
+        "definitional axiom for '{}' must directly follow its syntax axiom",
+        self.env.data[self.env.terms[t].atom].name)))
+    }
+    self.last_term = None;
+    let mut vars = HashMap::new();
+    for (i, a) in las.iter().enumerate() {
+      match **a {
+        Fmla::Var(v) if vars.insert(v, i).is_none() => {}
+        _ => return Err(ElabError::new_e(lsp,
+          "the left side of a definitional axiom must apply the term to distinct variables")),
+      }
+    }
+    // The syntax axiom has no disjoint variable conditions, so the dependencies of the
+    // arguments are given by the conditions on the definitional axiom instead.
+    let nbound = self.env.terms[t].args.iter().take_while(|(_, ty)| ty.bound()).count();
+    let mut args = self.env.terms[t].args.to_vec();
+    for (i, (_, ty)) in args.iter_mut().enumerate() {
+      if let Type::Reg(_, deps) = ty {
+        let Fmla::Var(v1) = *las[i] else { unreachable!() };
+        for (j, b) in las[..nbound].iter().enumerate() {
+          let Fmla::Var(v2) = **b else { unreachable!() };
+          if self.dvs.contains(&if v1 < v2 { (v1, v2) } else { (v2, v1) }) { *deps &= !(1 << j) }
+        }
+      }
+    }
+    self.env.terms[t].args = args.into();
+    let mut used = HashSet::new();
+    fmla_vars(rhs, &mut used);
+    for &v in &used {
+      if !vars.contains_key(v) && !self.is_bound(self.floats[v].1) {
+        return Err(ElabError::new_e(lsp, format!(
+          "variable '{}' in a definition is not an argument or a bound variable",
+          String::from_utf8_lossy(v))))
+      }
+    }
+    let ret_deps = self.fmla_deps(&self.env.terms[t].args, &vars, rhs);
+    let mut de = Dedup::new(&self.env.terms[t].args);
+    let i = self.def_expr(&mut de, &vars, rhs);
+    let (mut ids, heap, mut store) = build(&de);
+    store.push(ids[i].take());
+    let td = &mut self.env.terms[t];
+    td.kind = TermKind::Def(Some(Expr {heap, store: store.into()}));
+    td.ret.1 = ret_deps;
+    if let Err(e) = self.env.verify_termdef(&Default::default(), &self.env.terms[t]) {
+      let mut msg = format!("while adding the definition of {}: ",
+        self.env.data[self.env.terms[t].atom].name);
+      e.render(&self.env, &mut msg).expect("impossible");
+      return Err(ElabError::new_e(lsp, msg))
+    }
+    Ok(())
+  }
+
+  /// The bound arguments that an expression depends on, computed as in the verifier.
+  /// Dummy variables contribute nothing; if they escape, the verifier reports it.
+  fn fmla_deps(&self, args: &[(Option<AtomId>, Type)], vars: &HashMap<&[u8], usize>,
+    e: &Fmla<'_>,
+  ) -> u64 {
+    match e {
+      Fmla::Var(v) => match vars.get(v).map(|&i| args[i].1) {
+        Some(Type::Bound(_)) => 1 << vars[v],
+        Some(Type::Reg(_, deps)) => deps,
+        None => 0,
+      },
+      Fmla::App(t, es) => {
+        let td = &self.env.terms[*t];
+        let (mut bvs, mut accum) = (vec![], 0);
+        for (&(_, ty), e) in td.args.iter().zip(&**es) {
+          let d = self.fmla_deps(args, vars, e);
+          match ty {
+            Type::Bound(_) => bvs.push(d),
+            Type::Reg(_, d2) => accum |= bvs.iter().enumerate()
+              .filter(|&(i, _)| d2 & (1 << i) != 0).fold(d, |d, (_, &dep)| d & !dep),
+          }
+        }
+        for (i, &dep) in bvs.iter().enumerate() {
+          if td.ret.1 & (1 << i) != 0 { accum |= dep }
+        }
+        accum
+      }
+    }
+  }
+
+  /// Translates the value of a definition, where variables that are not arguments are dummies.
+  fn def_expr(&mut self, de: &mut Dedup<ExprHash>, vars: &HashMap<&[u8], usize>, e: &Fmla<'_>) -> usize {
+    let e = match e {
+      Fmla::Var(v) => match vars.get(v) {
+        Some(&i) => ExprHash::Ref(ProofKind::Expr, i),
+        None => ExprHash::Dummy(self.env.get_atom(v), self.floats[v].1),
+      },
+      Fmla::App(t, es) => ExprHash::App(*t, es.iter().map(|e| self.def_expr(de, vars, e)).collect()),
+    };
+    de.add(e)
+  }
+
+  fn doc_comment(&self, c: Span) -> DocComment {
+    String::from_utf8_lossy(self.span(c)).trim().into()
+  }
+
+  /// Reads and translates the proof of a `$p` statement. Returns the result of the proof,
+  /// and the span of the proof for error reporting.
+  fn proof(&mut self, de: &mut Dedup<ProofHash>, fr: &Frame<'a>, hyps: &[Step]) -> Result<(Step, Span)> {
+    let start = self.idx;
+    let (toks, end) = self.stmt("$.")?;
+    let sp = (start..end).into();
+    let mut stack = vec![];
+    if toks.first().is_some_and(|&t| self.span(t) == b"(") {
+      let close = toks.iter().position(|&t| self.span(t) == b")")
+        .ok_or_else(|| ElabError::new_e(sp, "expecting ')'"))?;
+      let labels: Vec<_> = fr.labels.iter().copied()
+        .chain(toks[1..close].iter().map(|&t| self.span(t))).collect();
+      let mut saved = vec![];
+      let mut n = 0_usize;
+      for &t in &toks[close + 1..] {
+        for &c in self.span(t) {
+          match c {
+            b'A'..=b'T' => {
+              let i = n.checked_mul(20).and_then(|n| n.checked_add((c - b'A').into()))
+                .ok_or_else(|| ElabError::new_e(t, "proof step out of range"))?;
+              n = 0;
+              if let Some(&l) = labels.get(i) {
+                self.proof_step(de, fr, hyps, &mut stack, t, l)?
+              } else {
+                let step = saved.get(i - labels.len())
+                  .ok_or_else(|| ElabError::new_e(t, "proof step out of range"))?;
+                stack.push(Step::reuse(*step, de))
+              }
+            }
+            b'U'..=b'Y' => n = n.checked_mul(5).and_then(|n| n.checked_add(usize::from(c - b'U') + 1))
+              .ok_or_else(|| ElabError::new_e(t, "proof step out of range"))?,
+            b'Z' if n == 0 => saved.push(*stack.last()
+              .ok_or_else(|| ElabError::new_e(t, "cannot save an empty stack"))?),
+            b'?' => return Err(ElabError::new_e(t, "incomplete proofs are not supported")),
+            _ => return Err(ElabError::new_e(t, "invalid character in compressed proof")),
+          }
+        }
+      }
+      if n != 0 { return Err(ElabError::new_e(sp, "unfinished proof step")) }
+    } else {
+      for t in toks {
+        let l = self.span(t);
+        if l == b"?" { return Err(ElabError::new_e(t, "incomplete proofs are not supported")) }
+        self.proof_step(de, fr, hyps, &mut stack, t, l)?
+      }
+    }
+    match *stack {
+      [step] => Ok((step, sp)),
+      _ => Err(ElabError::new_e(sp, "proof does not end with exactly one step on the stack")),
+    }
+  }
+
+  #[allow(clippy::many_single_char_names)]
+  fn proof_step(&mut self, de: &mut Dedup<ProofHash>, fr: &Frame<'a>, hyps: &[Step],
+    stack: &mut Vec<Step>, sp: Span, lab: &'a [u8],
+  ) -> Result<()> {
+    let label = self.labels.get(lab).ok_or_else(|| ElabError::new_e(sp, format!(
+      "unknown label '{}'", String::from_utf8_lossy(lab))))?.clone();
+    let nargs = match &label {
+      Label::Float(..) | Label::Ess(_) | Label::EqHyp(..) => 0,
+      Label::Term(_, slots) | Label::Thm(_, slots) => slots.len(),
+      Label::Syntax(st) => st.slots.len(),
+      Label::Eq(ax) => ax.slots.len(),
+    };
+    let args = stack.len().checked_sub(nargs)
+      .ok_or_else(|| ElabError::new_e(sp, "proof stack underflow"))?;
+    let args = stack.split_off(args);
+    let mismatch = || ElabError::new_e(sp, format!(
+      "type mismatch in application of '{}'", String::from_utf8_lossy(lab)));
+    let step = match label {
+      Label::Float(v, s) => {
+        if self.floats.get(v).is_none_or(|p| p.0 != lab) {
+          return Err(ElabError::new_e(sp, "hypothesis is not active"))
+        }
+        match fr.vars.get(v) {
+          Some(&i) => Step::Expr(de.reuse(i), s),
+          None => Step::Expr(de.add(ProofHash::Dummy(self.env.get_atom(v), s)), s),
+        }
+      }
+      Label::Ess(_) => {
+        let i = fr.hyps.iter().position(|h| h.0 == lab)
+          .ok_or_else(|| ElabError::new_e(sp, "hypothesis is not active"))?;
+        hyps[i].reuse(de)
+      }
+      Label::EqHyp(..) => return Err(ElabError::new_e(sp, "hypothesis is not active")),
+      Label::Eq(ax) => {
+        let mut vars = HashMap::new();
+        let mut hs = vec![None; ax.hyps.len()];
+        for (&slot, &arg) in ax.slots.iter().zip(&args) {
+          match (slot, arg) {
+            (Slot::Arg(i), Step::Expr(n, s)) if s == ax.vars[i].1 => { vars.insert(ax.vars[i].0, n); }
+            (Slot::Hyp(i), _) => hs[i] = Some(arg),
+            _ => return Err(mismatch()),
+          }
+        }
+        let hyp_mismatch = || ElabError::new_e(sp, format!(
+          "hypothesis mismatch in application of '{}'", String::from_utf8_lossy(lab)));
+        let mut convs = vec![];
+        for (h, step) in ax.hyps.iter().zip(hs) {
+          match (h, step.expect("impossible")) {
+            (EqStmt::Eq(a, b), Step::Conv(n, l, r)) => {
+              if inst(de, &vars, a) != l || inst(de, &vars, b) != r { return Err(hyp_mismatch()) }
+              convs.push(n)
+            }
+            (EqStmt::Provable(a), Step::Proof(n, e)) => {
+              if inst(de, &vars, a) != e { return Err(hyp_mismatch()) }
+              convs.push(n)
+            }
+            _ => return Err(mismatch()),
+          }
+        }
+        let (lhs, rhs) = match &ax.concl {
+          EqStmt::Eq(a, b) => (inst(de, &vars, a), inst(de, &vars, b)),
+          EqStmt::Provable(a) => { let a = inst(de, &vars, a); (a, a) }
+        };
+        match ax.kind {
+          EqKind::Refl => Step::Conv(de.add(ProofHash::Refl(lhs)), lhs, rhs),
+          EqKind::Sym => Step::Conv(de.add(ProofHash::Sym(convs[0])), lhs, rhs),
+          EqKind::Trans => match de[convs[0]] {
+            ProofHash::Refl(_) => Step::Conv(de.reuse(convs[1]), lhs, rhs),
+            ProofHash::Unfold(t, ref ns, l, sub, c) if matches!(de[c], ProofHash::Refl(_)) => {
+              let p = ProofHash::Unfold(t, ns.clone(), l, sub, convs[1]);
+              Step::Conv(de.add(p), lhs, rhs)
+            }
+            _ => return Err(ElabError::new_e(sp, format!(
+              "'{}' is only supported when the first step unfolds a definition",
+              String::from_utf8_lossy(lab)))),
+          },
+          EqKind::Conv => Step::Proof(de.add(ProofHash::Conv(lhs, convs[0], convs[1])), lhs),
+          EqKind::Cong(t) => {
+            let ProofHash::Term(_, ref ns) = de[lhs] else { unreachable!() };
+            let mut ns = ns.to_vec();
+            let mut it = convs.into_iter();
+            for (n, &(_, ty)) in ns.iter_mut().zip(&*self.env.terms[t].args) {
+              *n = if ty.bound() { ProofHash::as_conv(de, *n) } else { it.next().expect("impossible") }
+            }
+            Step::Conv(de.add(ProofHash::Cong(t, ns.into())), lhs, rhs)
+          }
+          EqKind::Def(t) => {
+            let ProofHash::Term(_, ref ns) = de[lhs] else { unreachable!() };
+            let ns = ns.clone();
+            let refl = de.add(ProofHash::Refl(rhs));
+            Step::Conv(de.add(ProofHash::Unfold(t, ns, lhs, rhs, refl)), lhs, rhs)
+          }
+        }
+      }
+      Label::Term(t, slots) => {
+        let td = &self.env.terms[t];
+        let mut ns = vec![0; td.args.len()];
+        for (&slot, &arg) in slots.iter().zip(&args) {
+          match (slot, arg) {
+            (Slot::Arg(i), Step::Expr(n, s)) if s == td.args[i].1.sort() => ns[i] = n,
+            _ => return Err(mismatch()),
+          }
+        }
+        Step::Expr(de.add(ProofHash::Term(t, ns.into())), td.ret.0)
+      }
+      Label::Syntax(st) => {
+        let mut ns = vec![0; st.args.len()];
+        for (&slot, &arg) in st.slots.iter().zip(&args) {
+          match (slot, arg) {
+            (Slot::Arg(i), Step::Expr(n, s)) if s == st.args[i] => ns[i] = n,
+            _ => return Err(mismatch()),
+          }
+        }
+        Step::Expr(st.tmpl.subst(de, &ns), st.ret)
+      }
+      Label::Thm(t, slots) => {
+        let td = &self.env.thms[t];
+        let nargs = td.args.len();
+        let mut ns = vec![0; nargs + td.hyps.len()];
+        let mut es = vec![0; td.hyps.len()];
+        for (&slot, &arg) in slots.iter().zip(&args) {
+          match (slot, arg) {
+            (Slot::Arg(i), Step::Expr(n, s)) if s == td.args[i].1.sort() => ns[i] = n,
+            (Slot::Hyp(i), Step::Proof(n, e)) => { ns[nargs + i] = n; es[i] = e }
+            _ => return Err(mismatch()),
+          }
+        }
+        let mut bvs = HashSet::new();
+        for (&(_, ty), &n) in td.args.iter().zip(&ns) {
+          if ty.bound() && !bvs.insert(n) {
+            return Err(ElabError::new_e(sp, format!(
+              "'{}' is applied with the same variable for two bound variables, which is not supported",
+              String::from_utf8_lossy(lab))))
+          }
+        }
+        let mut heap = vec![None; td.heap.len()];
+        for (h, &n) in heap.iter_mut().zip(&ns[..nargs]) { *h = Some(n) }
+        for ((_, h), &e) in td.hyps.iter().zip(&es) {
+          if ProofHash::subst(&self.env, de, &td.heap, &mut heap, &td.store, h) != e {
+            return Err(ElabError::new_e(sp, format!(
+              "hypothesis mismatch in application of '{}'", String::from_utf8_lossy(lab))))
+          }
+        }
+        let rhs = ProofHash::subst(&self.env, de, &td.heap, &mut heap, &td.store, &td.ret);
+        Step::Proof(de.add(ProofHash::Thm(t, ns.into(), rhs)), rhs)
+      }
+    };
+    stack.push(step);
+    Ok(())
+  }
+
+  /// Handles a `$j` comment (with contents `c`), and returns false if `c` is not a `$j` comment.
+  /// Only the `syntax` and `bound` commands are used, and the rest are ignored.
+  fn j_comment(&mut self, c: Span) -> Result<bool> {
+    let mut i = c.start;
+    while i < c.end && whitespace(self.source[i]) { i += 1 }
+    match self.source.get(i..i + 2) {
+      Some(b"$j") => {}
+      Some(b"$t") => return Ok(true),
+      _ => return Ok(false),
+    }
+    let mut toks = vec![];
+    while i < c.end {
+      let ch = self.source[i];
+      if whitespace(ch) { i += 1; continue }
+      let start = i;
+      if ch == b'\'' {
+        i += 1;
+        while i < c.end && self.source[i] != b'\'' { i += 1 }
+        if i == c.end { return Err(ElabError::new_e(start..c.end, "unclosed string")) }
+        i += 1;
+      } else if ch == b';' {
+        i += 1
+      } else {
+        while i < c.end && !whitespace(self.source[i]) && !matches!(self.source[i], b';' | b'\'') {
+          i += 1
+        }
+      }
+      toks.push(Span::from(start..i))
+    }
+    let Some((&first, toks)) = toks.split_first() else { return Ok(false) };
+    if self.span(first) != b"$j" { return Ok(false) }
+    let cmds: Vec<_> = toks.split(|&t| self.span(t) == b";").collect();
+    for cmd in cmds {
+      let Some((&kw, args)) = cmd.split_first() else { continue };
+      match (self.span(kw), args) {
+        (b"syntax", &[s]) => {
+          let s = self.j_string(s)?;
+          self.j_syntax = true;
+          self.j_sort(s)?;
+        }
+        (b"syntax", &[s1, as_, s2]) if self.span(as_) == b"as" => {
+          let (s1, s2) = (self.j_string(s1)?, self.j_string(s2)?);
+          self.j_syntax = true;
+          let s = self.j_sort(s2)?;
+          if self.typecodes.contains_key(self.span(s1)) {
+            return Err(ElabError::new_e(s1, "typecode is already declared"))
+          }
+          self.set_provable(self.span(s1), s, s1)?;
+        }
+        (b"bound", &[s]) => {
+          let sp = self.j_string(s)?;
+          let Some(&TypeCode::Sort(s)) = self.typecodes.get(self.span(sp)) else {
+            return Err(ElabError::new_e(sp, "expecting a sort"))
+          };
+          if self.sorts[s].bound == Some(false) {
+            return Err(ElabError::new_e(sp, "sort has syntax axioms and cannot be bound"))
+          }
+          self.sorts[s].bound = Some(true);
+          self.env.sorts[s].mods |= Modifiers::PURE;
+        }
+        (b"syntax" | b"bound", _) => return Err(ElabError::new_e(kw, "bad '$j' command")),
+        _ => {}
+      }
+    }
+    Ok(true)
+  }
+
+  /// Gets the contents of a string token in a `$j` comment.
+  fn j_string(&self, t: Span) -> Result<Span> {
+    match self.span(t) {
+      [b'\'', .., b'\''] => Ok((t.start + 1..t.end - 1).into()),
+      _ => Err(ElabError::new_e(t, "expecting string")),
+    }
+  }
+
+  /// Declares a sort with a `$j syntax` command.
+  fn j_sort(&mut self, sp: Span) -> Result<SortId> {
+    if self.syms.get(self.span(sp)) != Some(&Sym::Const) {
+      return Err(ElabError::new_e(sp, "expecting a constant"))
+    }
+    match self.typecodes.get(self.span(sp)) {
+      Some(&TypeCode::Sort(s)) => Ok(s),
+      Some(TypeCode::Provable(_) | TypeCode::Eq) => Err(ElabError::new_e(sp, "expecting a sort")),
+      None => self.add_sort(sp, sp),
+    }
+  }
+}
+
+/// Construct an [`Environment`] from a Metamath `.mm` file.
+pub fn elab(file: &FileRef, source: &[u8]) -> (Result<()>, Environment) {
+  let mut p = Importer {
+    file, source, idx: 0, env: Environment::new(),
+    syms: HashMap::new(), typecodes: HashMap::new(), sorts: SortVec::default(),
+    rules: HashMap::new(), j_syntax: false, labels: HashMap::new(), floats: HashMap::new(),
+    hyps: vec![], dvs: vec![], scopes: vec![], doc: None, last_term: None,
+  };
+  (p.run(), p.env)
+}
@@ -0,0 +1,616 @@
+//! A human-readable disassembler for MMB files, used by `mm0-rs mmb-dump`.
+//!
+//! This prints the header, the sort, term and theorem tables, and then every statement in the
+//! declaration stream, with its unify and proof commands one per line, together with the byte
+//! offset of the command and the depth of the stack after executing it. Names are resolved
+//! using the `SymbolNames`, `VarNames` and `HypNames` tables of the index when they are
+//! present, and otherwise fall back to the generated names `s0`, `t0`, `T0`, `v0` and `h0`.
+//! The source location of each statement is shown if the file has a `Span` table.
+//!
+//! The file is not verified; the stack depths are only computed for as long as the commands
+//! make sense, and are shown as `?` afterwards. With `--json`, the same information is
+//! printed as a JSON object, so that the output for two builds can be compared mechanically.
+use std::io::{self, Write};
+use serde_json::{json, Value};
+use mm0b_parser::{Arg, DebugMmbFile, MmbFile, NumdStmtCmd, ProofCmd, ProofIter, UnifyCmd,
+  UnifyIter, VarListRef};
+use crate::{Modifiers, SortId, TermId, ThmId, u32_as_usize};
+use crate::compiler::FileContents;
+
+/// Print the contents of an MMB file in human-readable form
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Print the output as JSON
+  #[clap(long)]
+  pub json: bool,
+  /// Sets the input file (.mmb)
+  pub input: String,
+}
+
+/// A single disassembled unify or proof command.
+#[derive(Debug)]
+struct Cmd {
+  /// The byte offset of the command in the file
+  pos: usize,
+  /// The depth of the stack after the command, if known
+  depth: Option<usize>,
+  /// The command name
+  op: &'static str,
+  /// The argument of the command, with names resolved
+  arg: Option<String>,
+}
+
+impl Cmd {
+  fn to_json(&self) -> Value {
+    json!({ "pos": self.pos, "depth": self.depth, "cmd": self.op, "arg": self.arg })
+  }
+}
+
+/// A disassembled command stream, which may end in an error.
+#[derive(Debug, Default)]
+struct Stream {
+  cmds: Vec<Cmd>,
+  error: Option<String>,
+}
+
+/// A binder in the signature of a term or theorem.
+#[derive(Debug)]
+struct Binder {
+  name: String,
+  bound: bool,
+  sort: String,
+  deps: Vec<String>,
+}
+
+/// The signature of a term: whether it is a definition, the arguments, and the return type.
+type TermSig = (bool, Vec<Binder>, Binder);
+
+/// A statement in the declaration stream.
+#[derive(Debug)]
+struct Stmt {
+  pos: usize,
+  kind: &'static str,
+  local: bool,
+  id: u32,
+  name: String,
+  /// The location in the source file, as `file:line:col` (one-based)
+  source: Option<String>,
+  /// The doc comment
+  doc: Option<String>,
+  unify: Option<Stream>,
+  /// The proof stream, if the statement has one (sorts and terms do not)
+  proof: Option<Stream>,
+}
+
+/// An element on the proof stack, used to track the stack depth. The `usize` values are
+/// indexes into [`ProofState::store`]. As in the verifier, a convertibility proof or
+/// obligation `e1 = e2` takes two stack slots, `e2, Conv(e1)` or `e2, CoConv(e1)`.
+#[derive(Clone, Copy, Debug)]
+enum StackEl {
+  Expr(usize),
+  Proof(usize),
+  Conv(usize),
+  CoConv(usize),
+}
+
+/// An element of the heap, where a convertibility proof takes only one slot.
+#[derive(Clone, Copy, Debug)]
+enum HeapEl {
+  Expr(usize),
+  Proof(usize),
+  Conv,
+}
+
+/// A simplified model of the proof checker, which only keeps track of the shape of the stack.
+/// Expressions are kept as a term head (`None` for variables) and a list of arguments.
+#[derive(Debug, Default)]
+struct ProofState {
+  store: Vec<(Option<TermId>, Box<[usize]>)>,
+  heap: Vec<HeapEl>,
+  stack: Vec<StackEl>,
+}
+
+impl ProofState {
+  fn new(nargs: usize) -> Self {
+    let mut st = Self::default();
+    for i in 0..nargs {
+      st.store.push((None, Box::new([])));
+      st.heap.push(HeapEl::Expr(i));
+    }
+    st
+  }
+
+  fn var(&mut self) -> usize { self.store.push((None, Box::new([]))); self.store.len() - 1 }
+
+  fn pop(&mut self) -> Option<StackEl> { self.stack.pop() }
+
+  fn pop_expr(&mut self) -> Option<usize> {
+    if let StackEl::Expr(e) = self.pop()? { Some(e) } else { None }
+  }
+
+  fn pop_conv(&mut self) -> Option<usize> {
+    if let StackEl::Conv(e) = self.pop()? { Some(e) } else { None }
+  }
+
+  fn pop_coconv(&mut self) -> Option<usize> {
+    if let StackEl::CoConv(e) = self.pop()? { Some(e) } else { None }
+  }
+
+  /// Execute a proof command, where `nargs` and `nhyps` look up the number of arguments of a
+  /// term and the number of hypotheses of a theorem. Returns `None` if the command does
+  /// not make sense in the current state.
+  fn step(&mut self, cmd: ProofCmd,
+    nargs: impl FnOnce(TermId) -> Option<usize>,
+    nhyps: impl FnOnce(ThmId) -> Option<(usize, usize)>,
+  ) -> Option<()> {
+    match cmd {
+      ProofCmd::Term { tid, save } => {
+        let n = nargs(tid)?;
+        let args = self.stack.len().checked_sub(n)
+          .and_then(|i| self.stack.drain(i..).map(|e| match e {
+            StackEl::Expr(e) => Some(e),
+            _ => None,
+          }).collect::<Option<Box<[_]>>>())?;
+        self.store.push((Some(tid), args));
+        let e = self.store.len() - 1;
+        if save { self.heap.push(HeapEl::Expr(e)) }
+        self.stack.push(StackEl::Expr(e))
+      }
+      ProofCmd::Ref(i) => match *self.heap.get(u32_as_usize(i))? {
+        HeapEl::Expr(e) => self.stack.push(StackEl::Expr(e)),
+        HeapEl::Proof(e) => self.stack.push(StackEl::Proof(e)),
+        HeapEl::Conv => { self.pop_coconv()?; self.pop_expr()?; }
+      },
+      ProofCmd::Dummy(_) => {
+        let e = self.var();
+        self.heap.push(HeapEl::Expr(e));
+        self.stack.push(StackEl::Expr(e))
+      }
+      ProofCmd::Thm { tid, save } => {
+        let (nargs, nhyps) = nhyps(tid)?;
+        let e = self.pop_expr()?;
+        for _ in 0..nargs { self.pop_expr()?; }
+        for _ in 0..nhyps {
+          if !matches!(self.pop()?, StackEl::Proof(_)) { return None }
+        }
+        if save { self.heap.push(HeapEl::Proof(e)) }
+        self.stack.push(StackEl::Proof(e))
+      }
+      ProofCmd::Hyp => {
+        let e = self.pop_expr()?;
+        self.heap.push(HeapEl::Proof(e))
+      }
+      ProofCmd::Conv => {
+        let StackEl::Proof(e2) = self.pop()? else { return None };
+        let e1 = self.pop_expr()?;
+        self.stack.extend([StackEl::Proof(e1), StackEl::Expr(e2), StackEl::CoConv(e1)])
+      }
+      ProofCmd::Refl => { self.pop_coconv()?; self.pop_expr()?; }
+      ProofCmd::Sym => {
+        let e1 = self.pop_coconv()?;
+        let e2 = self.pop_expr()?;
+        self.stack.extend([StackEl::Expr(e1), StackEl::CoConv(e2)])
+      }
+      ProofCmd::Cong => {
+        let e1 = self.pop_coconv()?;
+        let e2 = self.pop_expr()?;
+        let ((Some(t1), args1), (Some(t2), args2)) = (&self.store[e1], &self.store[e2])
+        else { return None };
+        if t1 != t2 || args1.len() != args2.len() { return None }
+        let new = args1.iter().zip(&**args2).rev()
+          .flat_map(|(&a1, &a2)| [StackEl::Expr(a2), StackEl::CoConv(a1)]);
+        self.stack.extend(new)
+      }
+      ProofCmd::Unfold => {
+        let e = self.pop_expr()?;
+        self.pop_coconv()?;
+        self.stack.push(StackEl::CoConv(e))
+      }
+      ProofCmd::ConvCut => {
+        let e1 = self.pop_coconv()?;
+        let e2 = self.pop_expr()?;
+        self.stack.extend([StackEl::Expr(e2), StackEl::Conv(e1), StackEl::Expr(e2), StackEl::CoConv(e1)])
+      }
+      ProofCmd::ConvSave => {
+        self.pop_conv()?;
+        self.pop_expr()?;
+        self.heap.push(HeapEl::Conv)
+      }
+      ProofCmd::Save => match *self.stack.last()? {
+        StackEl::CoConv(_) => return None,
+        StackEl::Conv(_) => {
+          self.stack.len().checked_sub(2)?;
+          self.heap.push(HeapEl::Conv)
+        }
+        StackEl::Expr(e) => self.heap.push(HeapEl::Expr(e)),
+        StackEl::Proof(e) => self.heap.push(HeapEl::Proof(e)),
+      },
+      ProofCmd::Sorry => match self.pop()? {
+        StackEl::Expr(e) => self.stack.push(StackEl::Proof(e)),
+        StackEl::Conv(_) => { self.pop_expr()?; }
+        _ => return None,
+      },
+    }
+    Some(())
+  }
+}
+
+struct Dumper<'a> {
+  file: &'a DebugMmbFile<'a>,
+  /// The number of arguments and hypotheses of each theorem, if they could be parsed.
+  thm_sizes: Vec<Option<(usize, usize)>>,
+}
+
+impl<'a> Dumper<'a> {
+  fn new(file: &'a DebugMmbFile<'a>) -> Self {
+    let thm_sizes = (0..file.header.num_thms.get()).map(|i| {
+      let td = file.thm(ThmId(i))?;
+      let mut nhyps = 0;
+      for cmd in td.unify() {
+        if matches!(cmd.ok()?, UnifyCmd::Hyp) { nhyps += 1 }
+      }
+      Some((td.args().len(), nhyps))
+    }).collect();
+    Self { file, thm_sizes }
+  }
+
+  fn sort_name(&self, s: SortId) -> String { self.file.sort_name(s).into_owned() }
+  fn term_name(&self, t: TermId) -> String { self.file.term_name(t).into_owned() }
+  fn thm_name(&self, t: ThmId) -> String { self.file.thm_name(t).into_owned() }
+
+  fn binders(&self, args: &[Arg], vars: VarListRef<'_>) -> Vec<Binder> {
+    let mut bvs = vec![];
+    args.iter().enumerate().map(|(i, arg)| {
+      let name = vars.get(i).into_owned();
+      let deps = if arg.bound() {
+        bvs.push(name.clone());
+        vec![]
+      } else {
+        Self::deps(&bvs, arg.deps_unchecked())
+      };
+      Binder { name, bound: arg.bound(), sort: self.sort_name(arg.sort()), deps }
+    }).collect()
+  }
+
+  fn deps(bvs: &[String], deps: u64) -> Vec<String> {
+    (0..64).filter(|i| deps & (1 << i) != 0)
+      .map(|i| bvs.get(i).cloned().unwrap_or_else(|| format!("?{i}"))).collect()
+  }
+
+  /// Disassemble a unify stream. `nargs` is the number of arguments of the declaration,
+  /// which are the initial elements of the unify heap.
+  fn unify(&self, mut it: UnifyIter<'_>, vars: VarListRef<'_>, nargs: usize) -> Stream {
+    let mut out = Stream::default();
+    let mut heap = (0..nargs).map(|i| Some(vars.get(i).into_owned())).collect::<Vec<_>>();
+    let mut next_var = nargs;
+    let mut depth = Some(1_usize);
+    loop {
+      let pos = it.pos;
+      let cmd = match it.next() {
+        None => break,
+        Some(Ok(cmd)) => cmd,
+        Some(Err(e)) => { out.error = Some(format!("{pos:#x}: {e}")); break }
+      };
+      let (op, arg) = match cmd {
+        UnifyCmd::Term { tid, save } => {
+          let n = self.file.term(tid).map(|td| td.args().len());
+          depth = depth.and_then(|d| d.checked_sub(1)).and_then(|d| Some(d + n?));
+          if save { heap.push(None) }
+          (if save { "term-save" } else { "term" }, Some(self.term_name(tid)))
+        }
+        UnifyCmd::Ref(i) => {
+          depth = depth.and_then(|d| d.checked_sub(1));
+          ("ref", Some(match heap.get(u32_as_usize(i)) {
+            Some(Some(name)) => format!("{i} {name}"),
+            _ => i.to_string(),
+          }))
+        }
+        UnifyCmd::Dummy(s) => {
+          depth = depth.and_then(|d| d.checked_sub(1));
+          let name = vars.get(next_var).into_owned();
+          next_var += 1;
+          let arg = format!("{name}: {}", self.sort_name(s));
+          heap.push(Some(name));
+          ("dummy", Some(arg))
+        }
+        UnifyCmd::Hyp => {
+          depth = depth.map(|d| d + 1);
+          ("hyp", None)
+        }
+      };
+      out.cmds.push(Cmd { pos, depth, op, arg })
+    }
+    out
+  }
+
+  /// Disassemble a proof stream. `nargs` is the number of arguments of the declaration,
+  /// which are the initial elements of the heap.
+  fn proof(&self, mut it: ProofIter<'_>, stmt: NumdStmtCmd, nargs: usize) -> Stream {
+    let mut out = Stream::default();
+    let vars = self.file.stmt_vars(stmt);
+    let hyps = self.file.stmt_hyps(stmt);
+    let mut heap = (0..nargs).map(|i| Some(vars.get(i).into_owned())).collect::<Vec<_>>();
+    let (mut next_var, mut next_hyp) = (nargs, 0);
+    let mut state = Some(ProofState::new(nargs));
+    loop {
+      let pos = it.pos;
+      let cmd = match it.next() {
+        None => break,
+        Some(Ok(cmd)) => cmd,
+        Some(Err(e)) => { out.error = Some(format!("{pos:#x}: {e}")); break }
+      };
+      if let Some(st) = &mut state {
+        let nargs = |t| Some(self.file.term(t)?.args().len());
+        let nhyps = |t: ThmId| *self.thm_sizes.get(u32_as_usize(t.0))?;
+        if st.step(cmd, nargs, nhyps).is_none() { state = None }
+      }
+      let (op, arg) = match cmd {
+        ProofCmd::Term { tid, save } => {
+          if save { heap.push(None) }
+          (if save { "term-save" } else { "term" }, Some(self.term_name(tid)))
+        }
+        ProofCmd::Ref(i) => ("ref", Some(match heap.get(u32_as_usize(i)) {
+          Some(Some(name)) => format!("{i} {name}"),
+          _ => i.to_string(),
+        })),
+        ProofCmd::Dummy(s) => {
+          let name = vars.get(next_var).into_owned();
+          next_var += 1;
+          let arg = format!("{name}: {}", self.sort_name(s));
+          heap.push(Some(name));
+          ("dummy", Some(arg))
+        }
+        ProofCmd::Thm { tid, save } => {
+          if save { heap.push(None) }
+          (if save { "thm-save" } else { "thm" }, Some(self.thm_name(tid)))
+        }
+        ProofCmd::Hyp => {
+          let name = hyps.get(next_hyp).into_owned();
+          next_hyp += 1;
+          heap.push(Some(name.clone()));
+          ("hyp", Some(name))
+        }
+        ProofCmd::Conv => ("conv", None),
+        ProofCmd::Refl => ("refl", None),
+        ProofCmd::Sym => ("symm", None),
+        ProofCmd::Cong => ("cong", None),
+        ProofCmd::Unfold => ("unfold", None),
+        ProofCmd::ConvCut => ("conv-cut", None),
+        ProofCmd::ConvSave => { heap.push(None); ("conv-save", None) }
+        ProofCmd::Save => { heap.push(None); ("save", None) }
+        ProofCmd::Sorry => ("sorry", None),
+      };
+      let depth = state.as_ref().map(|st| st.stack.len());
+      out.cmds.push(Cmd { pos, depth, op, arg })
+    }
+    out
+  }
+
+  fn stmt(&self, pos: usize, stmt: NumdStmtCmd, pf: ProofIter<'_>) -> Stmt {
+    let (kind, id, name, unify, nargs) = match stmt {
+      NumdStmtCmd::Sort { sort_id } =>
+        ("sort", sort_id.0.into(), self.sort_name(sort_id), None, 0),
+      NumdStmtCmd::TermDef { term_id, .. } => {
+        let td = self.file.term(term_id);
+        let kind = if td.is_some_and(|td| td.def()) { "def" } else { "term" };
+        let nargs = td.map_or(0, |td| td.args().len());
+        let unify = td.filter(mm0b_parser::TermRef::def)
+          .map(|td| self.unify(td.unify(), self.file.term_vars(term_id), nargs));
+        (kind, term_id.0, self.term_name(term_id), unify, nargs)
+      }
+      NumdStmtCmd::Axiom { thm_id } | NumdStmtCmd::Thm { thm_id, .. } => {
+        let kind = if let NumdStmtCmd::Axiom { .. } = stmt { "axiom" } else { "theorem" };
+        let td = self.file.thm(thm_id);
+        let nargs = td.map_or(0, |td| td.args().len());
+        let unify = td.map(|td| self.unify(td.unify(), self.file.thm_vars(thm_id), nargs));
+        (kind, thm_id.0, self.thm_name(thm_id), unify, nargs)
+      }
+    };
+    let proof = if pf.is_null() { None } else { Some(self.proof(pf, stmt, nargs)) };
+    let source = self.file.stmt_span(stmt).and_then(|e| {
+      let ((line, col), _) = e.line_col();
+      Some(format!("{}:{}:{}", e.file()?, line + 1, col + 1))
+    });
+    let doc = self.file.stmt_doc(stmt).map(str::to_owned);
+    Stmt { pos, kind, local: stmt.is_local(), id, name, source, doc, unify, proof }
+  }
+
+  fn stmts(&self) -> (Vec<Stmt>, Option<String>) {
+    let mut stmts = vec![];
+    let mut it = self.file.proof();
+    loop {
+      let pos = it.pos;
+      match it.next() {
+        None => return (stmts, None),
+        Some(Ok((stmt, pf))) => stmts.push(self.stmt(pos, stmt, pf)),
+        Some(Err(e)) => return (stmts, Some(format!("{pos:#x}: {e}"))),
+      }
+    }
+  }
+
+  fn sorts(&self) -> impl Iterator<Item = (SortId, String, Modifiers)> + '_ {
+    self.file.sorts.iter().enumerate().map(|(i, sd)| {
+      let s = SortId(i.try_into().expect("too many sorts"));
+      (s, self.sort_name(s), Modifiers::new(sd.0))
+    })
+  }
+
+  fn terms(&self) -> impl Iterator<Item = (TermId, String, Option<TermSig>)> + '_ {
+    (0..self.file.header.num_terms.get()).map(|i| {
+      let t = TermId(i);
+      let sig = self.file.term(t).map(|td| {
+        let vars = self.file.term_vars(t);
+        let mut args = self.binders(td.args_and_ret(), vars);
+        let mut ret = args.pop().expect("nonempty");
+        ret.name = String::new();
+        let bvs = args.iter().filter(|b| b.bound).map(|b| b.name.clone()).collect::<Vec<_>>();
+        ret.deps = Self::deps(&bvs, td.ret().deps_unchecked());
+        (td.def(), args, ret)
+      });
+      (t, self.term_name(t), sig)
+    })
+  }
+
+  fn thms(&self) -> impl Iterator<Item = (ThmId, String, Option<Vec<Binder>>)> + '_ {
+    (0..self.file.header.num_thms.get()).map(|i| {
+      let t = ThmId(i);
+      let sig = self.file.thm(t).map(|td| self.binders(td.args(), self.file.thm_vars(t)));
+      (t, self.thm_name(t), sig)
+    })
+  }
+
+  fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+    let h = &self.file.header;
+    writeln!(w, "header")?;
+    writeln!(w, "  magic     {}", String::from_utf8_lossy(&h.magic))?;
+    writeln!(w, "  version   {}", h.version)?;
+    writeln!(w, "  num_sorts {}", h.num_sorts)?;
+    writeln!(w, "  num_terms {}", h.num_terms)?;
+    writeln!(w, "  num_thms  {}", h.num_thms)?;
+    writeln!(w, "  p_terms   {:#x}", h.p_terms)?;
+    writeln!(w, "  p_thms    {:#x}", h.p_thms)?;
+    writeln!(w, "  p_proof   {:#x}", h.p_proof)?;
+    writeln!(w, "  p_index   {:#x}", h.p_index)?;
+    writeln!(w, "\nsorts")?;
+    for (s, name, mods) in self.sorts() {
+      writeln!(w, "  s{}: {mods}sort {name}", s.0)?
+    }
+    let binders = |w: &mut dyn Write, args: &[Binder]| -> io::Result<()> {
+      for b in args {
+        if b.bound { write!(w, " {{{}: {}}}", b.name, b.sort)? } else {
+          write!(w, " ({}: {}", b.name, b.sort)?;
+          for dep in &b.deps { write!(w, " {dep}")? }
+          write!(w, ")")?
+        }
+      }
+      Ok(())
+    };
+    writeln!(w, "\nterms")?;
+    for (t, name, sig) in self.terms() {
+      match sig {
+        None => writeln!(w, "  t{}: {name} <bad entry>", t.0)?,
+        Some((def, args, ret)) => {
+          write!(w, "  t{}: {} {name}", t.0, if def { "def" } else { "term" })?;
+          binders(w, &args)?;
+          write!(w, ": {}", ret.sort)?;
+          for dep in &ret.deps { write!(w, " {dep}")? }
+          writeln!(w)?
+        }
+      }
+    }
+    writeln!(w, "\ntheorems")?;
+    for (t, name, sig) in self.thms() {
+      match sig {
+        None => writeln!(w, "  T{}: {name} <bad entry>", t.0)?,
+        Some(args) => {
+          write!(w, "  T{}: {name}", t.0)?;
+          binders(w, &args)?;
+          writeln!(w)?
+        }
+      }
+    }
+    writeln!(w, "\nstatements")?;
+    let stream = |w: &mut dyn Write, header: &str, s: &Stream| -> io::Result<()> {
+      writeln!(w, "    {header}")?;
+      for cmd in &s.cmds {
+        write!(w, "      {:#010x} ", cmd.pos)?;
+        match cmd.depth {
+          Some(d) => write!(w, "[{d:>2}] ")?,
+          None => write!(w, "[ ?] ")?,
+        }
+        match &cmd.arg {
+          Some(arg) => writeln!(w, "{} {arg}", cmd.op)?,
+          None => writeln!(w, "{}", cmd.op)?,
+        }
+      }
+      if let Some(e) = &s.error { writeln!(w, "      error: {e}")? }
+      Ok(())
+    };
+    let (stmts, error) = self.stmts();
+    for stmt in &stmts {
+      let local = if stmt.local { "local " } else { "" };
+      let prefix = match stmt.kind { "sort" => "s", "axiom" | "theorem" => "T", _ => "t" };
+      writeln!(w, "  {:#010x}: {local}{} {prefix}{} {}", stmt.pos, stmt.kind, stmt.id, stmt.name)?;
+      if let Some(source) = &stmt.source { writeln!(w, "    source {source}")? }
+      if let Some(unify) = &stmt.unify { stream(w, "unify", unify)? }
+      if let Some(proof) = &stmt.proof { stream(w, "proof", proof)? }
+    }
+    if let Some(e) = error { writeln!(w, "  error: {e}")? }
+    Ok(())
+  }
+
+  fn to_json(&self) -> Value {
+    let binder = |b: &Binder| json!({
+      "name": b.name, "bound": b.bound, "sort": b.sort, "deps": b.deps
+    });
+    let stream = |s: &Stream| json!({
+      "cmds": s.cmds.iter().map(Cmd::to_json).collect::<Vec<_>>(),
+      "error": s.error,
+    });
+    let h = &self.file.header;
+    let (stmts, error) = self.stmts();
+    json!({
+      "header": {
+        "magic": String::from_utf8_lossy(&h.magic),
+        "version": h.version,
+        "num_sorts": h.num_sorts,
+        "num_terms": h.num_terms.get(),
+        "num_thms": h.num_thms.get(),
+        "p_terms": h.p_terms.get(),
+        "p_thms": h.p_thms.get(),
+        "p_proof": h.p_proof.get(),
+        "p_index": h.p_index.get(),
+      },
+      "sorts": self.sorts().map(|(s, name, mods)| json!({
+        "id": s.0, "name": name, "mods": mods.to_string().split_whitespace().collect::<Vec<_>>()
+      })).collect::<Vec<_>>(),
+      "terms": self.terms().map(|(t, name, sig)| match sig {
+        None => json!({ "id": t.0, "name": name }),
+        Some((def, args, ret)) => json!({
+          "id": t.0, "name": name, "def": def,
+          "args": args.iter().map(binder).collect::<Vec<_>>(),
+          "ret": { "sort": ret.sort, "deps": ret.deps },
+        }),
+      }).collect::<Vec<_>>(),
+      "thms": self.thms().map(|(t, name, sig)| match sig {
+        None => json!({ "id": t.0, "name": name }),
+        Some(args) => json!({
+          "id": t.0, "name": name, "args": args.iter().map(binder).collect::<Vec<_>>()
+        }),
+      }).collect::<Vec<_>>(),
+      "stmts": stmts.iter().map(|stmt| json!({
+        "pos": stmt.pos, "kind": stmt.kind, "local": stmt.local, "id": stmt.id,
+        "name": stmt.name, "source": stmt.source, "doc": stmt.doc, "unify": stmt.unify.as_ref().map(stream),
+        "proof": stmt.proof.as_ref().map(stream),
+      })).collect::<Vec<_>>(),
+      "error": error,
+    })
+  }
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs mmb-dump` subcommand.
+  ///
+  /// See the [module documentation](self) for the purpose of this command.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs mmb-dump [--json] <in.mmb>`, where:
+  ///
+  /// - `in.mmb` is the MMB file to print
+  /// - `--json` selects JSON output instead of text
+  pub fn main(self) -> io::Result<()> {
+    let buf = FileContents::new_bin_from_file(self.input.as_ref())?;
+    let file = MmbFile::parse(&buf)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let d = Dumper::new(&file);
+    let mut w = io::BufWriter::new(io::stdout().lock());
+    if self.json {
+      serde_json::to_writer_pretty(&mut w, &d.to_json())?;
+      writeln!(w)?
+    } else {
+      d.write_text(&mut w)?
+    }
+    w.flush()
+  }
+}
@@ -1,6 +1,8 @@
 //! MMB exporter, which produces `.mmb` binary proof files from an
 //! [`Environment`](crate::Environment) object.
 use std::mem;
+use std::path::{Path, PathBuf};
+use std::collections::{HashMap, hash_map::Entry};
 use std::io::{self, Write, Seek, SeekFrom};
 use byteorder::{LE, ByteOrder, WriteBytesExt};
 use mm0b_parser::MAX_BOUND_VARS;
@@ -8,7 +10,7 @@ use zerocopy::{IntoBytes, LE as ZLE, U32, U64};
 use crate::{
   Type, SortId, AtomId, AtomVec, TermKind, ThmKind,
   TermVec, ExprNode, ProofNode, StmtTrace, DeclKey, Modifiers,
-  FrozenEnv, FileRef, LinedString, ErrorLevel};
+  FrozenEnv, FileRef, FileSpan, LinedString, ErrorLevel, Range, Span};
 
 #[allow(clippy::wildcard_imports)]
 use mm0b_parser::{ProofCmd, UnifyCmd, cmd::*, write_cmd_bytes};
@@ -31,6 +33,9 @@ impl<T> Reorder<T> {
   }
 }
 
+/// A function for converting spans to line/column ranges.
+type ToRange<'a> = &'a mut dyn FnMut(&FileSpan) -> Option<Range>;
+
 /// The main exporter structure. This keeps track of the underlying writer,
 /// as well as tracking values that are written out of order.
 pub struct Exporter<'a, W> {
@@ -38,6 +43,12 @@ pub struct Exporter<'a, W> {
   file: FileRef,
   /// The source text of the input file. This is only used in the debugging data.
   source: Option<&'a LinedString>,
+  /// Converts spans in other files to line/column ranges. This is only used in the
+  /// debugging data, for declarations from imported files.
+  to_range: Option<ToRange<'a>>,
+  /// The directory containing the output file. Source paths in the debugging data are
+  /// written relative to this directory, or as absolute paths if it is not known.
+  out_dir: Option<PathBuf>,
   /// The input environment.
   env: &'a FrozenEnv,
   /// Error reporting.
@@ -61,6 +72,7 @@ impl<W: std::fmt::Debug> std::fmt::Debug for Exporter<'_, W> {
     f.debug_struct("Exporter")
       .field("file", &self.file)
       .field("source", &self.source)
+      .field("out_dir", &self.out_dir)
       .field("env", &self.env)
       .field("w", &self.w)
       .field("pos", &self.pos)
@@ -223,6 +235,17 @@ impl<W: Write> Drop for BigBuffer<W> {
   }
 }
 
+/// The contents of a source span entry in the index: the pointer to the file name, followed by
+/// the byte ranges of the name and the full declaration, and the line/column range of the name.
+/// Returns `None` if the positions don't fit in the entry.
+fn span_entry(p_file: u64, span: Span, full: Span, range: Range) -> Option<(u64, [u32; 8])> {
+  Some((p_file, [
+    span.start.try_into().ok()?, span.end.try_into().ok()?,
+    full.start.try_into().ok()?, full.end.try_into().ok()?,
+    range.start.line, range.start.character, range.end.line, range.end.character,
+  ]))
+}
+
 struct NameData {
   name: AtomId,
   p_proof: u64,
@@ -254,7 +277,32 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
   ) -> Self {
     Self {
       term_reord: TermVec(Vec::with_capacity(env.terms().len())),
-      file, source, env, report, w, pos: 0, fixups: vec![]
+      file, source, env, report, w, pos: 0, fixups: vec![], to_range: None, out_dir: None
+    }
+  }
+
+  /// Set the function used to find the line/column positions of declarations from
+  /// files other than the input file, for the source spans in the debugging data.
+  /// Without this, only declarations in the input file get a source span.
+  #[must_use]
+  pub fn with_ranges(mut self, to_range: ToRange<'a>) -> Self {
+    self.to_range = Some(to_range);
+    self
+  }
+
+  /// Set the path of the output file, so that source paths in the debugging data
+  /// can be made relative to it. Without this, source paths are absolute.
+  #[must_use]
+  pub fn with_output(mut self, out: &Path) -> Self {
+    self.out_dir = out.canonicalize().ok().and_then(|p| Some(p.parent()?.to_owned()));
+    self
+  }
+
+  /// Get the line/column range of a span, if the source text is known.
+  fn source_range(&mut self, fsp: &FileSpan) -> Option<Range> {
+    match self.source {
+      Some(src) if fsp.file == self.file => Some(src.to_range(fsp.span)),
+      _ => self.to_range.as_mut()?(fsp),
     }
   }
 
@@ -266,7 +314,7 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
     WriteBytesExt::write_u64::<LE>(self, n)
   }
 
-  fn write_str(&mut self, s: &'a [u8]) -> io::Result<()> {
+  fn write_str(&mut self, s: &[u8]) -> io::Result<()> {
     for &c in s {assert!(c != 0)}
     self.write_all(s)?;
     self.write_u8(0)
@@ -684,6 +732,45 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
       for &a in decls!().flat_map(|n| &n.1.vars) { add_atom(a)? }
       for &a in thm_names.iter().flat_map(|n| &n.1.vars) { add_atom(a)? }
 
+      // Doc comments and source spans, in the order sorts, terms, theorems
+      let env = self.env;
+      let decls = env.sorts().iter().map(|s| (&s.doc, &s.span, s.full))
+        .chain(env.terms().iter().map(|t| (&t.doc, &t.span, t.full)))
+        .chain(env.thms().iter().map(|t| (&t.doc, &t.span, t.full)));
+      let mut files = HashMap::new();
+      let mut docs = Vec::with_capacity(num_sorts + num_terms + num_thms);
+      let mut spans = Vec::with_capacity(num_sorts + num_terms + num_thms);
+      for (doc, fsp, full) in decls {
+        docs.push(match doc {
+          Some(doc) if !doc.contains('\0') => {
+            let pos = self.pos;
+            self.write_str(doc.as_bytes())?;
+            pos
+          }
+          _ => 0,
+        });
+        spans.push(match self.source_range(fsp) {
+          Some(range) => {
+            let p_file = match files.entry(fsp.file.ptr()) {
+              Entry::Occupied(e) => *e.get(),
+              Entry::Vacant(e) => {
+                let path = match &self.out_dir {
+                  Some(dir) => pathdiff::diff_paths(fsp.file.path(), dir),
+                  None => Some(fsp.file.path().clone()),
+                };
+                let pos = self.pos;
+                match path.as_deref().and_then(Path::to_str) {
+                  Some(path) => { self.write_str(path.as_bytes())?; *e.insert(Some(pos)) }
+                  None => *e.insert(None),
+                }
+              }
+            };
+            p_file.and_then(|p_file| span_entry(p_file, fsp.span, full, range))
+          }
+          None => None,
+        });
+      }
+
       self.align_to(8)?;
       let mut write_vd = |vd: &mut VarData| -> io::Result<()> {
         vd.p_vars = self.pos;
@@ -706,8 +793,21 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
       let p_hyps = self.pos;
       for (_, hs) in &thm_names { self.write_u64(hs.p_vars)? }
 
+      let p_docs = self.pos;
+      for p in docs { self.write_u64(p)? }
+
+      let p_spans = self.pos;
+      for e in spans {
+        let (p_file, data) = e.unwrap_or_default();
+        self.write_u64(p_file)?;
+        for n in data { self.write_u32(n)? }
+      }
+
       p_index.commit(self);
-      let index = [(INDEX_NAME, p_names), (INDEX_VAR_NAME, p_vars), (INDEX_HYP_NAME, p_hyps)];
+      let index = [
+        (INDEX_NAME, p_names), (INDEX_VAR_NAME, p_vars), (INDEX_HYP_NAME, p_hyps),
+        (INDEX_DOC_COMMENT, p_docs), (INDEX_SPAN, p_spans),
+      ];
       self.write_u64(index.len() as u64)?;
       for (name, ptr) in &index {
         self.write_all(name)?;
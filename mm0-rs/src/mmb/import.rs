@@ -1,26 +1,38 @@
 //! Importer for MMB files into the [`Environment`].
 #![allow(clippy::or_fun_call)] // false positive: clippy#9608
 
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::rc::Rc;
-use crate::{Environment, Modifiers, AtomId, TermId,
+use crate::{DocComment, Environment, Modifiers, AtomId, TermId,
     Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof};
 use crate::elab::proof::{IDedup, ProofKind, ProofHash, build};
-use crate::{FileRef, FileSpan, SliceExt};
-use mm0b_parser::{NumdStmtCmd, UnifyCmd, ProofCmd, BasicMmbFile,
+use crate::{FileRef, FileSpan, SliceExt, Span};
+use crate::elab::{ElabError, environment::AddItemError};
+use mm0b_parser::{NumdStmtCmd, UnifyCmd, ProofCmd, DebugMmbFile,
   ParseError, UnifyIter, ProofIter, exhausted};
 
 
 type Result<T> = std::result::Result<T, ParseError>;
 
+/// Convert an error from adding a declaration to the environment, where `full` is the span
+/// of the declaration in the file. Verification errors already include the declaration name.
+fn add_error<A>(e: AddItemError<A>, full: Span, msg: &'static str) -> ElabError {
+  match e {
+    AddItemError::Verify(e) => ElabError::new_e(full, e),
+    _ => ParseError::StrError(msg, full.start).into(),
+  }
+}
+
 fn parse_unify(
-  file: &BasicMmbFile<'_>, nargs: usize, it: UnifyIter<'_>,
+  file: &DebugMmbFile<'_>, nargs: usize, it: UnifyIter<'_>,
   hyps: Option<&mut Vec<(Option<AtomId>, ExprNode)>>,
   dummy: impl FnMut() -> AtomId,
 ) -> Result<(Box<[ExprNode]>, Vec<ExprNode>, ExprNode)> {
   use ParseError::StrError;
   struct State<'a, F> {
     dummy: F,
-    file: &'a BasicMmbFile<'a>,
+    file: &'a DebugMmbFile<'a>,
     pos: usize,
     it: UnifyIter<'a>,
     heap: Vec<ExprNode>,
@@ -134,7 +146,7 @@ impl<'a> IntoIterator for &'a Dedup {
 }
 
 fn parse_proof(
-  file: &BasicMmbFile<'_>, nargs: usize, it: &mut ProofIter<'_>,
+  file: &DebugMmbFile<'_>, nargs: usize, it: &mut ProofIter<'_>,
   dummy: impl FnMut() -> AtomId,
 ) -> Result<Proof> {
 
@@ -198,7 +210,7 @@ fn parse_proof(
   }
 
   struct State<'a, F> {
-    file: &'a BasicMmbFile<'a>,
+    file: &'a DebugMmbFile<'a>,
     dummy: F,
     de: Dedup,
     stack: Vec<Stack>,
@@ -371,11 +383,26 @@ fn parse_proof(
   Ok(Proof {heap, hyps, store: store.into()})
 }
 
-fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
+fn parse(fref: &FileRef, buf: &[u8], verify: bool, env: &mut Environment) -> crate::elab::Result<()> {
   use ParseError::StrError;
-  let file = BasicMmbFile::parse(buf)?;
+  let file = DebugMmbFile::parse(buf)?;
   let mut it = file.proof();
   let mut start = it.pos;
+  // The location of a declaration in the source file it was compiled from, if known,
+  // otherwise its location in the .mmb file.
+  let mut files = HashMap::new();
+  let mut source = |stmt, fsp: &FileSpan, full: Span| -> (FileSpan, Span) {
+    (|| {
+      let e = file.stmt_span(stmt)?;
+      let path = e.file()?;
+      let file = files.entry(path).or_insert_with(|| {
+        let path = fref.path().parent().map_or_else(|| PathBuf::from(path), |dir| dir.join(path));
+        FileRef::from(path.canonicalize().unwrap_or(path))
+      });
+      Some((FileSpan {file: file.clone(), span: e.span().into()}, e.full().into()))
+    })().unwrap_or_else(|| (fsp.clone(), full))
+  };
+  let doc = |stmt| file.stmt_doc(stmt).map(DocComment::from);
   macro_rules! get_get_var {($list:expr) => {{
     let list = $list;
     move |env: &mut Environment, i: usize| env.get_atom(&list.get(i).as_bytes())
@@ -393,13 +420,14 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
     let (stmt, mut pf) = e?;
     match stmt {
       NumdStmtCmd::Sort {sort_id} => {
-        if !pf.is_null() { return Err(StrError("Next statement incorrect", pf.pos)) }
+        if !pf.is_null() { return Err(StrError("Next statement incorrect", pf.pos).into()) }
         let atom = env.get_atom(file.sort_name(sort_id).as_bytes());
         let span = (start..pf.pos).into();
         let fsp = FileSpan {file: fref.clone(), span};
         let sd = file.sort(sort_id).and_then(|sd| sd.try_into().ok())
           .ok_or(StrError("Step sort overflow", start))?;
-        env.add_sort(atom, fsp, span, sd, None)
+        let (fsp, span) = source(stmt, &fsp, span);
+        env.add_sort(atom, fsp, span, sd, doc(stmt))
           .map_err(|_| StrError("double add sort", start))?;
       }
       NumdStmtCmd::TermDef {term_id, local} => {
@@ -413,22 +441,23 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
           else { Type::Reg(a.sort(), a.deps_unchecked()) }
         )).collect::<Box<[_]>>();
         let ret = td.ret();
-        if ret.bound() { return Err(StrError("bad return type", start)) }
+        if ret.bound() { return Err(StrError("bad return type", start).into()) }
         let kind = if td.def() {
           let (heap, mut store, ret) =
             parse_unify(&file, args.len(), td.unify(), None, || next_var!())?;
           store.push(ret);
           TermKind::Def(Some(Expr {heap, store: store.into()}))
         } else {
-          if !pf.is_null() { return Err(StrError("Next statement incorrect", pf.pos)) }
+          if !pf.is_null() { return Err(StrError("Next statement incorrect", pf.pos).into()) }
           TermKind::Term
         };
         let full = (start..pf.pos).into();
-        env.add_term(Term {
-          atom, span: fsp, full, doc: None, args, kind,
+        let (src, src_full) = source(stmt, &fsp, full);
+        env.try_add_term(verify, atom, &src.clone(), || Term {
+          atom, span: src, full: src_full, doc: doc(stmt), args, kind,
           vis: if local {Modifiers::LOCAL} else {Modifiers::empty()},
           ret: (ret.sort(), ret.deps_unchecked()),
-        }).map_err(|_| StrError("double add term", start))?;
+        }).map_err(|e| add_error(e, full, "double add term"))?;
       }
       NumdStmtCmd::Axiom {thm_id} | NumdStmtCmd::Thm {thm_id, ..} => {
         let atom = env.get_atom(file.thm_name(thm_id).as_bytes());
@@ -451,17 +480,18 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
           match parse_proof(&file, args.len(), &mut pf, || next_var!()) {
             Ok(proof) => ThmKind::Thm(Some(proof)),
             Err(ParseError::SorryError) => ThmKind::Thm(None),
-            Err(e) => return Err(e)
+            Err(e) => return Err(e.into())
           }
         };
         let full = (start..pf.pos).into();
         let vis =
           if matches!(stmt, NumdStmtCmd::Thm {local: false, ..}) {Modifiers::PUB}
           else {Modifiers::empty()};
-        env.add_thm(Thm {
-          atom, span: fsp, full, doc: None, args, kind,
+        let (src, src_full) = source(stmt, &fsp, full);
+        env.try_add_thm(verify, atom, &src.clone(), || Thm {
+          atom, span: src, full: src_full, doc: doc(stmt), args, kind,
           vis, heap, store: store.into(), hyps: hyps.into(), ret,
-        }).map_err(|_| StrError("double add term", start))?;
+        }).map_err(|e| add_error(e, full, "double add thm"))?;
       }
     }
     start = it.pos;
@@ -470,7 +500,12 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
 }
 
 /// Construct an [`Environment`] from an `mmb` file.
-pub fn elab(file: &FileRef, source: &[u8]) -> (crate::elab::Result<()>, Environment) {
+///
+/// If `verify` is true, the definitions and proofs are checked as they are added to the
+/// environment, and elaboration stops at the first declaration that fails to verify.
+/// The error is reported at the location of this declaration in the file.
+/// Otherwise, the proofs in the file are trusted.
+pub fn elab(file: &FileRef, source: &[u8], verify: bool) -> (crate::elab::Result<()>, Environment) {
   let mut env = Environment::new();
-  (parse(file, source, &mut env).map_err(From::from), env)
+  (parse(file, source, verify, &mut env), env)
 }
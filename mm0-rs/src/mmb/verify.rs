@@ -0,0 +1,798 @@
+//! A native verifier for MMB files.
+//!
+//! This is a port of the reference checker [`mm0-c/verifier.c`], together with the part of
+//! [`mm0-c/parser.c`] that matches the statements in the proof file against the specification.
+//! The main difference is that the `.mm0` file is not parsed by hand; it is elaborated into a
+//! [`FrozenEnv`] first, and the statements of that environment are compared against the
+//! non-local statements of the MMB file, in order. The checks themselves (and the error
+//! messages) follow `mm0-c` exactly, so a file should be accepted here if and only if it
+//! is accepted by `mm0-c`, with the exception of the fixed size limits on the stacks and
+//! heaps, which are not enforced here.
+//!
+//! Errors are reported at the byte offset of the offending command in the MMB file.
+//!
+//! [`mm0-c/verifier.c`]: https://github.com/digama0/mm0/blob/master/mm0-c/verifier.c
+//! [`mm0-c/parser.c`]: https://github.com/digama0/mm0/blob/master/mm0-c/parser.c
+
+use mm0b_parser::{BasicMmbFile, MmbFile, NumdStmtCmd, ParseError, ProofCmd, ProofIter,
+  UnifyCmd, UnifyIter, TYPE_BOUND_MASK, TYPE_DEPS_MASK, TYPE_UPPER_MASK};
+use crate::{AtomId, DeclKey, ExprNode, FrozenEnv, Modifiers, SortId, StmtTrace, TermId,
+  TermKind, TermVec, ThmId, ThmKind, Type, u32_as_usize};
+use crate::elab::ElabError;
+
+type Result<T> = std::result::Result<T, ElabError>;
+
+/// The maximum number of sorts supported by the MMB format.
+const MAX_SORTS: u8 = 128;
+
+#[inline] fn ensure(b: bool, pos: usize, msg: &'static str) -> Result<()> {
+  if b { Ok(()) } else { Err(ElabError::new_e(pos, msg)) }
+}
+
+/// Returns true if a value with type `from` can be cast to a value of type `to`.
+/// This requires that the sorts be the same, and additionally if `to` is a
+/// name then so is `from`.
+fn sorts_compatible(from: u64, to: u64) -> bool {
+  let diff = from ^ to;
+  diff & TYPE_UPPER_MASK == 0 ||
+    (diff & !TYPE_BOUND_MASK & TYPE_UPPER_MASK == 0 && from & TYPE_BOUND_MASK != 0)
+}
+
+/// Get the sort of a type (without the bound bit).
+#[inline] fn type_sort(ty: u64) -> u8 {
+  #[allow(clippy::cast_possible_truncation)]
+  { (ty >> 56) as u8 & 0x7F }
+}
+
+/// An expression in the store. Expressions are compared by their index in the store,
+/// so two terms are only considered equal if they were constructed by the same command.
+#[derive(Clone, Copy, Debug)]
+enum StoreExpr {
+  /// A variable (a binder or a dummy), with its type.
+  Var(u64),
+  /// A term application `t e1 ... en`, with its type (the sort and the set of
+  /// bound variables it depends on), where the arguments are `args[start..start+n]`.
+  Term(u64, TermId, usize, usize),
+}
+
+impl StoreExpr {
+  fn ty(self) -> u64 {
+    match self { StoreExpr::Var(ty) | StoreExpr::Term(ty, ..) => ty }
+  }
+}
+
+/// An element of the main stack.
+#[derive(Clone, Copy, Debug)]
+enum StackEl {
+  /// An expression `e`
+  Expr(usize),
+  /// A proof `|- e`
+  Proof(usize),
+  /// A convertibility proof `e1 = e2`, represented as `e2, Conv(e1)` on the stack.
+  Conv(usize),
+  /// A convertibility obligation `e1 =?= e2`, represented as `e2, CoConv(e1)` on the stack.
+  CoConv(usize),
+}
+
+/// An element of the main heap.
+#[derive(Clone, Copy, Debug)]
+enum HeapEl {
+  /// An expression `e`
+  Expr(usize),
+  /// A proof `|- e`
+  Proof(usize),
+  /// A convertibility proof `e1 = e2`
+  Conv(usize, usize),
+}
+
+impl From<HeapEl> for StackEl {
+  fn from(e: HeapEl) -> Self {
+    match e {
+      HeapEl::Expr(e) => StackEl::Expr(e),
+      HeapEl::Proof(e) => StackEl::Proof(e),
+      HeapEl::Conv(..) => unreachable!(),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ProofMode { Def, Thm }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum UnifyMode { Def, Thm, ThmEnd }
+
+/// An expression in the specification, with [`ExprNode::Ref`] resolved.
+#[derive(Clone, Copy, Debug)]
+enum SpecExpr<'a> {
+  /// The `n`th variable in the binder list
+  Var(usize),
+  /// A dummy variable in a definition
+  Dummy(AtomId, SortId),
+  /// A term application, with its arguments
+  App(TermId, &'a [ExprNode]),
+}
+
+/// A statement in the specification, which should be matched against the next
+/// non-local statement in the proof file.
+#[derive(Clone, Copy, Debug)]
+enum SpecStmt {
+  Sort(SortId),
+  Term(TermId),
+  Thm(ThmId),
+}
+
+struct Verifier<'a> {
+  /// The specification that the proof file is checked against.
+  spec: &'a FrozenEnv,
+  /// The proof file.
+  file: &'a BasicMmbFile<'a>,
+  /// The number of sorts, terms and theorems that have been checked so far.
+  num_sorts: u8,
+  num_terms: u32,
+  num_thms: u32,
+  /// The mapping from terms in the specification to terms in the proof file.
+  term_map: TermVec<Option<TermId>>,
+  /// The expression store. This is cleared at the start of every statement.
+  store: Vec<StoreExpr>,
+  /// The arguments of term expressions in the store.
+  args: Vec<usize>,
+  /// The main stack.
+  stack: Vec<StackEl>,
+  /// The main heap.
+  heap: Vec<HeapEl>,
+  /// The hypothesis stack.
+  hstack: Vec<usize>,
+  /// The unify stack.
+  ustack: Vec<usize>,
+  /// The unify heap. The boolean is true for saved terms, which are not
+  /// checked for the dummy disjointness condition.
+  uheap: Vec<(usize, bool)>,
+  /// The next bound variable, as a one-hot bitset.
+  next_bv: u64,
+  /// Scratch space for the dependencies of bound arguments in a term or theorem application.
+  deps: Vec<u64>,
+  /// True if a theorem used `sorry`.
+  uses_sorry: bool,
+}
+
+impl<'a> Verifier<'a> {
+  fn sort_mods(&self, s: u8) -> Modifiers {
+    Modifiers::from_bits_truncate(self.file.sorts[usize::from(s)].0)
+  }
+
+  fn ty(&self, e: usize) -> u64 { self.store[e].ty() }
+
+  fn alloc(&mut self, e: StoreExpr) -> usize { (self.store.len(), self.store.push(e)).0 }
+
+  /// Get the head and the range of arguments (in `self.args`) of a term expression.
+  fn get_term(&self, e: usize, pos: usize) -> Result<(TermId, std::ops::Range<usize>)> {
+    match self.store[e] {
+      StoreExpr::Term(_, t, start, n) => Ok((t, start..start + n)),
+      StoreExpr::Var(_) => Err(ElabError::new_e(pos, "store type error")),
+    }
+  }
+
+  fn pop_stack(&mut self, pos: usize) -> Result<StackEl> {
+    self.stack.pop().ok_or_else(|| ElabError::new_e(pos, "stack underflow"))
+  }
+
+  fn pop_expr(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::Expr(e) = self.pop_stack(pos)? { return Ok(e) }
+    Err(ElabError::new_e(pos, "bad stack slot"))
+  }
+
+  fn pop_proof(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::Proof(e) = self.pop_stack(pos)? { return Ok(e) }
+    Err(ElabError::new_e(pos, "bad stack slot"))
+  }
+
+  fn pop_conv(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::Conv(e) = self.pop_stack(pos)? { return Ok(e) }
+    Err(ElabError::new_e(pos, "bad stack slot"))
+  }
+
+  fn pop_coconv(&mut self, pos: usize) -> Result<usize> {
+    if let StackEl::CoConv(e) = self.pop_stack(pos)? { return Ok(e) }
+    Err(ElabError::new_e(pos, "bad stack slot"))
+  }
+
+  fn pop_ustack(&mut self, pos: usize) -> Result<usize> {
+    self.ustack.pop().ok_or_else(|| ElabError::new_e(pos, "unify stack underflow"))
+  }
+
+  /// Given a list of binders, load the main heap and allocate all the variables.
+  /// Also perform binder validity checking.
+  fn load_args(&mut self, args: &[mm0b_parser::Arg], pos: usize) -> Result<()> {
+    self.heap.clear();
+    self.next_bv = 1;
+    for arg in args {
+      let ty = arg.into_inner();
+      let vars_bitset = ty & TYPE_DEPS_MASK;
+      let sort = type_sort(ty);
+      ensure(sort < self.num_sorts, pos, "bad binder sort")?;
+      if ty & TYPE_BOUND_MASK != 0 {
+        ensure(!self.sort_mods(sort).contains(Modifiers::STRICT), pos,
+          "bound variable in strict sort")?;
+        ensure(vars_bitset == self.next_bv, pos, "bad binder deps")?;
+        self.next_bv *= 2;
+      } else {
+        ensure(vars_bitset & !(self.next_bv - 1) == 0, pos, "bad binder deps")?;
+      }
+      let e = self.alloc(StoreExpr::Var(ty));
+      self.heap.push(HeapEl::Expr(e));
+    }
+    Ok(())
+  }
+
+  /// Load the unify heap with the variables on the main heap.
+  fn load_uheap(&mut self, nargs: usize) {
+    self.uheap.clear();
+    self.uheap.extend(self.heap[..nargs].iter().map(|&e| match e {
+      HeapEl::Expr(e) => (e, false),
+      _ => unreachable!(),
+    }));
+  }
+
+  /// Run a unify command stream, with `tgt` as the expression to be unified.
+  ///
+  /// * `Def`: We are checking that a definition header is correct, or processing an
+  ///   `Unfold` command
+  /// * `Thm`: We are applying a theorem (`Thm`), and need to check the substitution is correct
+  /// * `ThmEnd`: We are checking that a theorem header is correct
+  fn run_unify(&mut self, mode: UnifyMode, mut it: UnifyIter<'_>, tgt: usize) -> Result<()> {
+    self.ustack.clear();
+    self.ustack.push(tgt);
+    loop {
+      let pos = it.pos;
+      let cmd = match it.next() {
+        None => break,
+        Some(Ok(cmd)) => cmd,
+        Some(Err(ParseError::UnifyCmdConv(..))) => return Err(ElabError::new_e(pos,
+          if mode == UnifyMode::Def { "unknown opcode in def statement" }
+          else { "unknown opcode in theorem statement" })),
+        Some(Err(_)) => return Err(ElabError::new_e(pos, "command out of range")),
+      };
+      match cmd {
+        UnifyCmd::Ref(i) => {
+          let &(e, _) = self.uheap.get(u32_as_usize(i))
+            .ok_or_else(|| ElabError::new_e(pos, "bad ref step"))?;
+          ensure(e == self.pop_ustack(pos)?, pos, "unify failure at ref")?
+        }
+        UnifyCmd::Term {tid, save} => {
+          let p = self.pop_ustack(pos)?;
+          let (t, args) = self.get_term(p, pos)?;
+          ensure(t == tid, pos, "unify failure at term")?;
+          self.ustack.extend(self.args[args].iter().rev());
+          if save { self.uheap.push((p, true)) }
+        }
+        UnifyCmd::Dummy(s) => {
+          ensure(mode == UnifyMode::Def, pos,
+            "Dummy command not allowed in theorem statements")?;
+          let p = self.pop_ustack(pos)?;
+          let StoreExpr::Var(ty) = self.store[p] else {
+            return Err(ElabError::new_e(pos, "store type error"))
+          };
+          ensure(ty >> 56 == 0x80 | u64::from(s.0), pos, "unify failure at dummy")?;
+          let deps = ty & TYPE_DEPS_MASK;
+          for &(e, saved) in &self.uheap {
+            ensure(saved || self.ty(e) & deps == 0, pos, "dummy disjoint variable violation")?
+          }
+          self.uheap.push((p, false))
+        }
+        UnifyCmd::Hyp => match mode {
+          UnifyMode::Thm => {
+            let e = self.pop_proof(pos)?;
+            self.ustack.push(e)
+          }
+          UnifyMode::ThmEnd => {
+            ensure(self.ustack.is_empty(), pos, "unfinished unify stack")?;
+            let e = self.hstack.pop()
+              .ok_or_else(|| ElabError::new_e(pos, "hypothesis stack underflow"))?;
+            self.ustack.push(e)
+          }
+          UnifyMode::Def => return Err(ElabError::new_e(pos,
+            "Hyp command not allowed in definition statements")),
+        }
+      }
+    }
+    if mode == UnifyMode::ThmEnd {
+      ensure(self.hstack.is_empty(), it.pos, "unfinished hypothesis stack")?
+    }
+    ensure(self.ustack.is_empty(), it.pos, "unfinished unify stack")
+  }
+
+  /// Check the arguments of a term or theorem application, which are the top `targs.len()`
+  /// elements of the stack. The return value is the index of the first argument
+  /// on the stack and the accumulated dependencies of the regular arguments.
+  ///
+  /// * If `def` is set, the dependencies of a regular argument exclude the bound variables
+  ///   it is allowed to depend on (this is the `FV(e)` calculation in `Def` mode).
+  /// * If `thm` is set, we are applying a theorem: the arguments are loaded into the unify heap,
+  ///   and the disjoint variable conditions are checked.
+  fn check_app_args(&mut self, targs: &[mm0b_parser::Arg], def: bool, thm: bool, pos: usize
+  ) -> Result<(usize, u64)> {
+    ensure(self.stack.len() >= targs.len(), pos, "stack underflow")?;
+    let base = self.stack.len() - targs.len();
+    self.deps.clear();
+    if thm { self.uheap.clear() }
+    let mut accum = 0;
+    for (i, target) in targs.iter().enumerate() {
+      let StackEl::Expr(arg) = self.stack[base + i] else {
+        return Err(ElabError::new_e(pos, "bad stack slot"))
+      };
+      let ty = self.ty(arg);
+      let target = target.into_inner();
+      ensure(sorts_compatible(ty, target), pos, "type mismatch")?;
+      let mut deps = ty & TYPE_DEPS_MASK;
+      if target & TYPE_BOUND_MASK != 0 {
+        if thm {
+          for &(e, _) in &self.uheap {
+            ensure(self.ty(e) & deps == 0, pos, "disjoint variable violation")?
+          }
+        }
+        self.deps.push(deps);
+      } else {
+        for (j, &d) in self.deps.iter().enumerate() {
+          if target & (1 << j) != 0 {
+            if def { deps &= !d }
+          } else if thm {
+            ensure(d & deps == 0, pos, "disjoint variable violation")?
+          }
+        }
+        accum |= deps;
+      }
+      if thm { self.uheap.push((arg, false)) }
+    }
+    Ok((base, accum))
+  }
+
+  /// Run a proof command stream.
+  ///
+  /// * `Def`: We are constructing a definition body
+  /// * `Thm`: We are constructing a theorem proof
+  fn run_proof(&mut self, mode: ProofMode, it: &mut ProofIter<'_>) -> Result<()> {
+    loop {
+      let pos = it.pos;
+      let cmd = match it.next() {
+        None => return Ok(()),
+        Some(Ok(cmd)) => cmd,
+        Some(Err(ParseError::ProofCmdConv(..))) => return Err(ElabError::new_e(pos,
+          if mode == ProofMode::Def { "unknown opcode in def" }
+          else { "unknown opcode in theorem" })),
+        // The iterator fails if it finds an `End` command before the end of the statement
+        Some(Err(_)) => return Err(ElabError::new_e(pos,
+          if self.file.buf.get(pos) == Some(&0) { "Next statement incorrect" }
+          else { "command out of range" })),
+      };
+      match cmd {
+        ProofCmd::Ref(i) => {
+          let s = *self.heap.get(u32_as_usize(i))
+            .ok_or_else(|| ElabError::new_e(pos, "bad ref step"))?;
+          if let HeapEl::Conv(c1, c2) = s {
+            let e1 = self.pop_coconv(pos)?;
+            let e2 = self.pop_expr(pos)?;
+            ensure(c1 == e1 && c2 == e2, pos, "ConvRef unify error")?
+          } else {
+            self.stack.push(s.into())
+          }
+        }
+        ProofCmd::Dummy(s) => {
+          ensure(s.0 < self.num_sorts, pos, "bad dummy sort")?;
+          ensure(!self.sort_mods(s.0).intersects(Modifiers::STRICT | Modifiers::FREE), pos,
+            "dummy variable in strict or free sort")?;
+          ensure(self.next_bv >> 56 == 0, pos,
+            "too many bound variables, please rewrite the verifier")?;
+          let ty = TYPE_BOUND_MASK | u64::from(s.0) << 56 | self.next_bv;
+          self.next_bv *= 2;
+          let e = self.alloc(StoreExpr::Var(ty));
+          self.stack.push(StackEl::Expr(e));
+          self.heap.push(HeapEl::Expr(e));
+        }
+        ProofCmd::Term {tid, save} => {
+          ensure(tid.0 < self.num_terms, pos, "term out of range")?;
+          let t = self.file.term(tid).expect("checked");
+          let (args, ret) = t.args_and_ret().split_at(t.args().len());
+          let (base, mut accum) = self.check_app_args(args, mode == ProofMode::Def, false, pos)?;
+          accum |= u64::from(t.sort().0) << 56;
+          if mode == ProofMode::Def {
+            let target = ret[0].into_inner() & TYPE_DEPS_MASK;
+            for (j, &d) in self.deps.iter().enumerate() {
+              if target & (1 << j) != 0 { accum |= d }
+            }
+          }
+          let start = self.args.len();
+          for &arg in &self.stack[base..] {
+            let StackEl::Expr(arg) = arg else { unreachable!() };
+            self.args.push(arg)
+          }
+          self.stack.truncate(base);
+          let e = self.alloc(StoreExpr::Term(accum, tid, start, args.len()));
+          self.stack.push(StackEl::Expr(e));
+          if save { self.heap.push(HeapEl::Expr(e)) }
+        }
+        ProofCmd::Thm {tid, save} => {
+          ensure(mode != ProofMode::Def, pos, "invalid opcode in def")?;
+          ensure(tid.0 < self.num_thms, pos, "theorem out of range")?;
+          let t = self.file.thm(tid).expect("checked");
+          let e = self.pop_expr(pos)?;
+          let (base, _) = self.check_app_args(t.args(), false, true, pos)?;
+          self.stack.truncate(base);
+          self.run_unify(UnifyMode::Thm, t.unify(), e)?;
+          self.stack.push(StackEl::Proof(e));
+          if save { self.heap.push(HeapEl::Proof(e)) }
+        }
+        ProofCmd::Hyp => {
+          ensure(mode != ProofMode::Def, pos, "invalid opcode in def")?;
+          let e = self.pop_expr(pos)?;
+          ensure(self.sort_mods(type_sort(self.ty(e))).contains(Modifiers::PROVABLE), pos,
+            "hypothesis should have provable sort")?;
+          self.hstack.push(e);
+          self.heap.push(HeapEl::Proof(e));
+        }
+        ProofCmd::Conv => {
+          let e2 = self.pop_proof(pos)?;
+          let e1 = self.pop_expr(pos)?;
+          self.stack.extend([StackEl::Proof(e1), StackEl::Expr(e2), StackEl::CoConv(e1)]);
+        }
+        ProofCmd::Refl => {
+          let e1 = self.pop_coconv(pos)?;
+          let e2 = self.pop_expr(pos)?;
+          ensure(e1 == e2, pos, "Refl unify failure")?
+        }
+        ProofCmd::Sym => {
+          let e1 = self.pop_coconv(pos)?;
+          let e2 = self.pop_expr(pos)?;
+          self.stack.extend([StackEl::Expr(e1), StackEl::CoConv(e2)]);
+        }
+        ProofCmd::Cong => {
+          let e1 = self.pop_coconv(pos)?;
+          let e2 = self.pop_expr(pos)?;
+          let (t1, args1) = self.get_term(e1, pos)?;
+          let (t2, args2) = self.get_term(e2, pos)?;
+          ensure(t1 == t2, pos, "Cong unify error")?;
+          self.stack.extend(self.args[args1].iter().zip(&self.args[args2]).rev()
+            .flat_map(|(&a1, &a2)| [StackEl::Expr(a2), StackEl::CoConv(a1)]));
+        }
+        ProofCmd::Unfold => {
+          let e = self.pop_expr(pos)?;
+          let e1 = self.pop_coconv(pos)?;
+          let (tid, args) = self.get_term(e1, pos)?;
+          let t = self.file.term(tid).expect("checked");
+          ensure(t.def(), pos, "Unfold: not a definition")?;
+          self.uheap.clear();
+          self.uheap.extend(self.args[args].iter().map(|&a| (a, false)));
+          self.run_unify(UnifyMode::Def, t.unify(), e)?;
+          self.stack.push(StackEl::CoConv(e));
+        }
+        ProofCmd::ConvCut => {
+          let e1 = self.pop_coconv(pos)?;
+          let e2 = self.pop_expr(pos)?;
+          self.stack.extend([StackEl::Expr(e2), StackEl::Conv(e1),
+            StackEl::Expr(e2), StackEl::CoConv(e1)]);
+        }
+        ProofCmd::ConvSave => {
+          let e1 = self.pop_conv(pos)?;
+          let e2 = self.pop_expr(pos)?;
+          self.heap.push(HeapEl::Conv(e1, e2));
+        }
+        ProofCmd::Save => match *self.stack.last()
+          .ok_or_else(|| ElabError::new_e(pos, "stack underflow"))? {
+          StackEl::CoConv(_) => return Err(ElabError::new_e(pos, "Can't save proof obligation")),
+          StackEl::Conv(e1) => {
+            let Some(StackEl::Expr(e2) | StackEl::Proof(e2) | StackEl::Conv(e2) |
+              StackEl::CoConv(e2)) = self.stack.len().checked_sub(2).map(|i| self.stack[i])
+            else { return Err(ElabError::new_e(pos, "stack underflow")) };
+            self.heap.push(HeapEl::Conv(e1, e2))
+          }
+          StackEl::Expr(e) => self.heap.push(HeapEl::Expr(e)),
+          StackEl::Proof(e) => self.heap.push(HeapEl::Proof(e)),
+        }
+        ProofCmd::Sorry => {
+          ensure(mode != ProofMode::Def, pos, "invalid opcode in def")?;
+          self.uses_sorry = true;
+          match self.pop_stack(pos)? {
+            StackEl::Expr(e) => self.stack.push(StackEl::Proof(e)),
+            StackEl::Conv(_) => { self.pop_expr(pos)?; }
+            _ => return Err(ElabError::new_e(pos, "bad stack slot")),
+          }
+        }
+      }
+    }
+  }
+
+  /// Resolve a specification expression node, following heap references.
+  fn resolve<'b>(&self, heap: &'b [ExprNode], store: &'b [ExprNode], nargs: usize,
+    mut e: &'b ExprNode
+  ) -> SpecExpr<'b> {
+    loop {
+      match *e {
+        ExprNode::Ref(i) if i < nargs => return SpecExpr::Var(i),
+        ExprNode::Ref(i) => e = &heap[i],
+        ExprNode::Dummy(a, s) => return SpecExpr::Dummy(a, s),
+        ExprNode::App(t, p) =>
+          return SpecExpr::App(t, &store[p..p + self.spec.term(t).args.len()]),
+      }
+    }
+  }
+
+  /// Check that two specification expressions are equal.
+  fn deep_eq(&self, heap: &[ExprNode], store: &[ExprNode], nargs: usize,
+    e1: SpecExpr<'_>, e2: SpecExpr<'_>, pos: usize
+  ) -> Result<()> {
+    match (e1, e2) {
+      (SpecExpr::Var(v1), SpecExpr::Var(v2)) if v1 == v2 => Ok(()),
+      (SpecExpr::Dummy(a1, _), SpecExpr::Dummy(a2, _)) if a1 == a2 => Ok(()),
+      (SpecExpr::App(t1, args1), SpecExpr::App(t2, args2)) if t1 == t2 => {
+        for (a1, a2) in args1.iter().zip(args2) {
+          self.deep_eq(heap, store, nargs,
+            self.resolve(heap, store, nargs, a1), self.resolve(heap, store, nargs, a2), pos)?
+        }
+        Ok(())
+      }
+      _ => Err(ElabError::new_e(pos, "expression mismatch")),
+    }
+  }
+
+  /// Check that the expression `tgt` (and hypotheses `hyps`, for a theorem)
+  /// from the specification matches the unify stream `it` in the proof file.
+  fn check_expr(&self, heap: &[ExprNode], store: &[ExprNode], nargs: usize,
+    mut it: UnifyIter<'_>, tgt: &ExprNode, hyps: &[(Option<AtomId>, ExprNode)]
+  ) -> Result<()> {
+    let mut hyps = hyps.iter().map(|(_, h)| self.resolve(heap, store, nargs, h));
+    let mut ustack = vec![self.resolve(heap, store, nargs, tgt)];
+    let mut uheap = (0..nargs).map(SpecExpr::Var).collect::<Vec<_>>();
+    loop {
+      let pos = it.pos;
+      let cmd = match it.next() {
+        None => break,
+        Some(Ok(cmd)) => cmd,
+        Some(Err(_)) => return Err(ElabError::new_e(pos, "command out of range")),
+      };
+      let mut pop = || ustack.pop().ok_or_else(|| ElabError::new_e(pos, "unify stack underflow"));
+      match cmd {
+        UnifyCmd::Ref(i) => {
+          let e = *uheap.get(u32_as_usize(i))
+            .ok_or_else(|| ElabError::new_e(pos, "bad ref step"))?;
+          self.deep_eq(heap, store, nargs, e, pop()?, pos)?
+        }
+        UnifyCmd::Term {tid, save} => {
+          let p = pop()?;
+          let SpecExpr::App(t, args) = p else {
+            return Err(ElabError::new_e(pos, "store type error"))
+          };
+          ensure(self.term_map[t] == Some(tid), pos, "unify failure at term")?;
+          ustack.extend(args.iter().rev().map(|e| self.resolve(heap, store, nargs, e)));
+          if save { uheap.push(p) }
+        }
+        UnifyCmd::Dummy(s) => {
+          let p = pop()?;
+          match p {
+            SpecExpr::Var(_) => return Err(ElabError::new_e(pos, "expected a dummy")),
+            SpecExpr::App(..) => return Err(ElabError::new_e(pos, "store type error")),
+            SpecExpr::Dummy(_, s2) => ensure(s == s2, pos, "unify failure at dummy")?,
+          }
+          uheap.push(p)
+        }
+        UnifyCmd::Hyp => {
+          let h = hyps.next_back()
+            .ok_or_else(|| ElabError::new_e(pos, "hypothesis number mismatch"))?;
+          ustack.push(h)
+        }
+      }
+    }
+    ensure(hyps.next().is_none(), it.pos, "unfinished hypothesis stack")?;
+    ensure(ustack.is_empty(), it.pos, "unfinished unify stack")
+  }
+
+  /// Check the binders of a term or theorem against the binders in the specification.
+  fn check_binders(args: &[mm0b_parser::Arg], spec_args: &[(Option<AtomId>, Type)], pos: usize
+  ) -> Result<()> {
+    ensure(args.len() == spec_args.len(), pos, "incorrect number of arguments")?;
+    for (arg, (_, ty)) in args.iter().zip(spec_args) {
+      let ok = match *ty {
+        Type::Bound(s) => arg.bound() && arg.sort() == s,
+        Type::Reg(s, deps) => !arg.bound() && arg.sort() == s && arg.deps_unchecked() == deps,
+      };
+      ensure(ok, pos, "variable type does not match theorem")?
+    }
+    Ok(())
+  }
+
+  /// Match a non-local statement in the proof file against the next statement of the
+  /// specification.
+  fn check_spec(&mut self, spec: Option<SpecStmt>, stmt: NumdStmtCmd, pos: usize) -> Result<()> {
+    let spec_name = |sp| match sp {
+      SpecStmt::Sort(s) => format!("sort '{}'", self.spec.sort(s).name),
+      SpecStmt::Term(t) => format!("term '{}'", self.spec.data()[self.spec.term(t).atom].name()),
+      SpecStmt::Thm(t) => format!("theorem '{}'", self.spec.data()[self.spec.thm(t).atom].name()),
+    };
+    let expecting = |msg: &str| ElabError::new_e(pos, match spec {
+      None => format!("{msg}, but the specification has no more statements"),
+      Some(sp) => format!("{msg}, found {} in the specification", spec_name(sp)),
+    });
+    match (stmt, spec) {
+      (NumdStmtCmd::Sort {sort_id}, Some(SpecStmt::Sort(s))) => {
+        ensure(self.spec.sort(s).mods.bits() == self.file.sorts[usize::from(sort_id.0)].0,
+          pos, "sort modifiers do not match")?;
+      }
+      (NumdStmtCmd::Sort {..}, _) => return Err(expecting("expecting a sort")),
+      (NumdStmtCmd::TermDef {term_id, ..}, Some(SpecStmt::Term(t))) => {
+        let td = self.file.term(term_id).expect("checked");
+        let spec_td = self.spec.term(t);
+        match &spec_td.kind {
+          TermKind::Term => ensure(!td.def(), pos, "expecting a term")?,
+          TermKind::Def(_) => ensure(td.def(), pos, "expecting a def")?,
+        }
+        Self::check_binders(td.args(), &spec_td.args, pos)?;
+        let ret = td.ret();
+        ensure(!ret.bound() && ret.sort() == spec_td.ret.0 && ret.deps_unchecked() == spec_td.ret.1,
+          pos, "return type does not match theorem")?;
+        self.term_map[t] = Some(term_id);
+        if let TermKind::Def(Some(e)) = &spec_td.kind {
+          self.check_expr(&e.heap, &e.store, spec_td.args.len(), td.unify(), e.head(), &[])?
+        }
+      }
+      (NumdStmtCmd::TermDef {..}, _) => return Err(expecting("expecting a term/def")),
+      (NumdStmtCmd::Axiom {thm_id} | NumdStmtCmd::Thm {thm_id, ..}, Some(SpecStmt::Thm(t))) => {
+        let spec_td = self.spec.thm(t);
+        match spec_td.kind {
+          ThmKind::Axiom => ensure(matches!(stmt, NumdStmtCmd::Axiom {..}), pos,
+            "expecting an axiom")?,
+          ThmKind::Thm(_) => ensure(matches!(stmt, NumdStmtCmd::Thm {..}), pos,
+            "expecting a theorem")?,
+        }
+        let td = self.file.thm(thm_id).expect("checked");
+        Self::check_binders(td.args(), &spec_td.args, pos)?;
+        self.check_expr(&spec_td.heap, &spec_td.store, spec_td.args.len(), td.unify(),
+          &spec_td.ret, &spec_td.hyps)?
+      }
+      (NumdStmtCmd::Axiom {..}, _) => return Err(expecting("expecting an axiom")),
+      (NumdStmtCmd::Thm {..}, _) => return Err(expecting("expecting a theorem")),
+    }
+    Ok(())
+  }
+
+  /// Verify a single statement in the proof stream.
+  fn verify_stmt(&mut self, stmt: NumdStmtCmd, mut pf: ProofIter<'_>, pos: usize) -> Result<()> {
+    self.store.clear();
+    self.args.clear();
+    self.stack.clear();
+    match stmt {
+      NumdStmtCmd::Sort {sort_id} => {
+        ensure(pf.is_null(), pos, "Next statement incorrect")?;
+        ensure(sort_id.0 < self.file.header.num_sorts, pos, "Step sort overflow")?;
+      }
+      NumdStmtCmd::TermDef {term_id, ..} => {
+        ensure(term_id.0 < self.file.header.num_terms.get(), pos, "Step term overflow")?;
+        let t = self.file.term(term_id)
+          .ok_or_else(|| ElabError::new_e(pos, "bad args pointer"))?;
+        let sort = t.sort().0;
+        ensure(sort < self.num_sorts, pos, "bad sort")?;
+        ensure(!self.sort_mods(sort).contains(Modifiers::PURE), pos, "term in pure sort")?;
+        self.load_args(t.args_and_ret(), pos)?;
+        let ret = t.ret().into_inner();
+        ensure(ret >> 56 == u64::from(sort), pos, "bad return type")?;
+        self.heap.pop();
+        if t.def() {
+          ensure(!pf.is_null(), pos, "Next statement incorrect")?;
+          self.run_proof(ProofMode::Def, &mut pf)?;
+          ensure(self.stack.len() == 1, pf.pos, "stack has != one element")?;
+          let StackEl::Expr(val) = self.stack[0] else {
+            return Err(ElabError::new_e(pf.pos, "bad stack slot"))
+          };
+          let ty = self.ty(val);
+          ensure(sorts_compatible(ty, ret), pf.pos, "type mismatch")?;
+          ensure(ty & TYPE_DEPS_MASK & !ret == 0, pf.pos,
+            "type has unaccounted dependencies")?;
+          self.load_uheap(t.args().len());
+          self.run_unify(UnifyMode::Def, t.unify(), val)?;
+        } else {
+          ensure(pf.is_null(), pos, "Next statement incorrect")?;
+        }
+      }
+      NumdStmtCmd::Axiom {thm_id} | NumdStmtCmd::Thm {thm_id, ..} => {
+        ensure(thm_id.0 < self.file.header.num_thms.get(), pos, "Step theorem overflow")?;
+        let t = self.file.thm(thm_id)
+          .ok_or_else(|| ElabError::new_e(pos, "bad args pointer"))?;
+        self.hstack.clear();
+        self.load_args(t.args(), pos)?;
+        self.run_proof(ProofMode::Thm, &mut pf)?;
+        ensure(self.stack.len() == 1, pf.pos, "stack has != one element")?;
+        let ((NumdStmtCmd::Axiom {..}, StackEl::Expr(val)) |
+          (NumdStmtCmd::Thm {..}, StackEl::Proof(val))) = (stmt, self.stack[0])
+        else { return Err(ElabError::new_e(pf.pos, "bad stack slot")) };
+        ensure(self.sort_mods(type_sort(self.ty(val))).contains(Modifiers::PROVABLE), pf.pos,
+          "conclusion should have provable sort")?;
+        self.load_uheap(t.args().len());
+        self.run_unify(UnifyMode::ThmEnd, t.unify(), val)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Get the name of a statement, if available from the index.
+  fn stmt_name(&self, stmt: NumdStmtCmd) -> Option<&'a str> {
+    match stmt {
+      NumdStmtCmd::Sort {sort_id} => self.file.try_sort_name(sort_id),
+      NumdStmtCmd::TermDef {term_id, ..} => self.file.try_term_name(term_id),
+      NumdStmtCmd::Axiom {thm_id} | NumdStmtCmd::Thm {thm_id, ..} =>
+        self.file.try_thm_name(thm_id),
+    }
+  }
+}
+
+/// Verify an MMB file (the contents of which are passed in `buf`) against the specification
+/// `spec`, an environment obtained by elaborating the `.mm0` file.
+///
+/// Uses of `sorry` in theorems are passed to `report` as warnings, and cause verification
+/// to fail at the end (after all the other checks). Other errors are returned immediately.
+pub fn verify(spec: &FrozenEnv, buf: &[u8], report: &mut dyn FnMut(ElabError)) -> Result<()> {
+  let file = MmbFile::parse(buf).map_err(|e| ElabError::new_e(0, format!("{e}")))?;
+  ensure(file.header.num_sorts <= MAX_SORTS, 0, "Too many sorts")?;
+  let mut spec_stmts = spec.stmts().iter().filter_map(|s| match *s {
+    StmtTrace::Sort(a) => Some(SpecStmt::Sort(spec.data()[a].sort()?)),
+    StmtTrace::Decl(a) => Some(match spec.data()[a].decl()? {
+      DeclKey::Term(t) => SpecStmt::Term(t),
+      DeclKey::Thm(t) => SpecStmt::Thm(t),
+    }),
+    StmtTrace::Global(_) | StmtTrace::OutputString(_) => None,
+  });
+  let mut v = Verifier {
+    spec, file: &file,
+    num_sorts: 0, num_terms: 0, num_thms: 0,
+    term_map: TermVec(vec![None; spec.terms().len()]),
+    store: vec![], args: vec![], stack: vec![], heap: vec![],
+    hstack: vec![], ustack: vec![], uheap: vec![],
+    next_bv: 1, deps: vec![], uses_sorry: false,
+  };
+  let mut it = file.proof();
+  loop {
+    let pos = it.pos;
+    let (stmt, pf) = match it.next() {
+      None => break,
+      Some(Ok(r)) => r,
+      Some(Err(ParseError::StmtCmdConv(_))) =>
+        return Err(ElabError::new_e(pos, "bad statement command")),
+      Some(Err(_)) => return Err(ElabError::new_e(pos, "proof command out of range")),
+    };
+    let sorry = v.uses_sorry;
+    v.uses_sorry = false;
+    let mut res = v.verify_stmt(stmt, pf, pos);
+    if res.is_ok() && !stmt.is_local() {
+      res = v.check_spec(spec_stmts.next(), stmt, pos)
+    }
+    if let Err(mut e) = res {
+      if let Some(name) = v.stmt_name(stmt) {
+        e = ElabError::new_e(e.pos, format!("at {name}: {}", e.kind.msg()))
+      }
+      return Err(e)
+    }
+    if v.uses_sorry {
+      report(ElabError::warn(pos, match v.stmt_name(stmt) {
+        Some(name) => format!("'{name}' uses sorry"),
+        None => "stmt uses sorry".into(),
+      }))
+    }
+    v.uses_sorry |= sorry;
+    match stmt {
+      NumdStmtCmd::Sort {..} => v.num_sorts += 1,
+      NumdStmtCmd::TermDef {..} => v.num_terms += 1,
+      NumdStmtCmd::Axiom {..} | NumdStmtCmd::Thm {..} => v.num_thms += 1,
+    }
+  }
+  let pos = it.pos;
+  ensure(v.num_sorts == file.header.num_sorts, pos, "not all sorts proved")?;
+  ensure(v.num_terms == file.header.num_terms.get(), pos, "not all terms proved")?;
+  ensure(v.num_thms == file.header.num_thms.get(), pos, "not all theorems proved")?;
+  ensure(!v.uses_sorry, pos, "some theorems used sorry")?;
+  if let Some(sp) = spec_stmts.next() {
+    let (kind, name) = match sp {
+      SpecStmt::Sort(s) => ("sort", &spec.sort(s).name),
+      SpecStmt::Term(t) => ("term", spec.data()[spec.term(t).atom].name()),
+      SpecStmt::Thm(t) => ("theorem", spec.data()[spec.thm(t).atom].name()),
+    };
+    return Err(ElabError::new_e(pos,
+      format!("{kind} '{name}' in the specification does not appear in the proof file")))
+  }
+  Ok(())
+}
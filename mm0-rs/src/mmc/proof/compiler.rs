@@ -354,17 +354,14 @@ trait NodeKind: Sized + std::fmt::Debug {
 }
 
 #[allow(clippy::type_complexity)]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 enum MCtxNode<N: NodeKind> {
+  #[default]
   Zero,
   One(N::Leaf),
   Node(Ordering, N::Node, Box<(P<MCtxNode<N>>, P<MCtxNode<N>>)>),
 }
 
-impl<N: NodeKind> Default for MCtxNode<N> {
-  fn default() -> Self { Self::Zero }
-}
-
 #[derive(Debug)]
 struct RegKind<L>(PhantomData<L>);
 
@@ -1244,8 +1241,9 @@ impl ProcProver<'_> {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 enum StmtState<'a> {
+  #[default]
   None,
   Call {
     f: ProcId, abi: &'a ProcAbi, args: &'a [(bool, Operand)],
@@ -1253,10 +1251,6 @@ enum StmtState<'a> {
   },
 }
 
-impl Default for StmtState<'_> {
-  fn default() -> Self { Self::None }
-}
-
 enum InstState {
   None,
   StartSkip,
@@ -23,8 +23,10 @@ fn is_nonatomic_proof(e: &ProofNode) -> bool {
   matches!(e, ProofNode::Thm {..} | ProofNode::Conv(_))
 }
 
+/// Find the values of the dummy variables of a definition, by matching the definition
+/// against `tgt`, the result of unfolding it.
 #[allow(clippy::too_many_arguments)]
-fn build_unfold_map<'a>(env: &FrozenEnv,
+pub(crate) fn build_unfold_map<'a>(env: &FrozenEnv,
   m: &mut HashMap<AtomId, &'a ProofNode>, checked: &mut [bool],
   heap: &[ExprNode], store: &[ExprNode], node: &ExprNode,
   t_heap: &'a [ProofNode], t_store: &'a [ProofNode], mut tgt: &'a ProofNode
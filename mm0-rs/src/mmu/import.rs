@@ -134,13 +134,13 @@ enum DeclKind {
 }
 
 #[derive(Debug)]
-struct Dedup<H: NodeHash> {
+pub(crate) struct Dedup<H: NodeHash> {
   map: HashMap<Rc<H>, usize>,
   vec: Vec<(Rc<H>, bool)>,
 }
 
 impl<H: NodeHash> Dedup<H> {
-  fn new(args: &[(Option<AtomId>, Type)]) -> Dedup<H> {
+  pub(crate) fn new(args: &[(Option<AtomId>, Type)]) -> Dedup<H> {
     let vec: Vec<_> = (0..args.len())
       .map(|i| (Rc::new(H::REF(ProofKind::Expr, i)), true)).collect();
     Dedup {
@@ -151,7 +151,7 @@ impl<H: NodeHash> Dedup<H> {
 
   #[allow(dead_code)] fn iter(&self) -> DedupIter<'_, H> { self.into_iter() }
 
-  fn add(&mut self, v: H) -> usize {
+  pub(crate) fn add(&mut self, v: H) -> usize {
     match self.map.entry(Rc::new(v)) {
       Entry::Vacant(e) => {
         let n = self.vec.len();
@@ -196,7 +196,7 @@ impl<H: NodeHash> IDedup<H> for Dedup<H> {
 }
 
 #[must_use] #[derive(Debug)]
-struct DedupIter<'a, H: NodeHash>(std::slice::Iter<'a, (Rc<H>, bool)>);
+pub(crate) struct DedupIter<'a, H: NodeHash>(std::slice::Iter<'a, (Rc<H>, bool)>);
 
 impl<'a, H: NodeHash> Iterator for DedupIter<'a, H> {
   type Item = (&'a H, bool);
@@ -216,7 +216,7 @@ impl<'a, H: NodeHash> IntoIterator for &'a Dedup<H> {
 }
 
 impl Dedup<ExprHash> {
-  fn map_proof(&self) -> Dedup<ProofHash> {
+  pub(crate) fn map_proof(&self) -> Dedup<ProofHash> {
     self.map_inj(ExprHash::to_proof)
   }
 }
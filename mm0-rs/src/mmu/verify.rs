@@ -0,0 +1,258 @@
+//! Checks an `.mmu` proof file against an `.mm0` specification.
+//!
+//! This is the MMU counterpart of [`mmb::verify`](crate::mmb::verify), and performs the same
+//! check as `mm0-hs verify`. The proof file is imported by [`mmu::import`](super::import) into
+//! an [`Environment`], and then the non-local statements of that environment are matched
+//! one-to-one, in order, against the statements of the specification:
+//!
+//! - sorts must have the same name and modifiers;
+//! - terms and defs must have the same name, kind, binders and return type, and if the
+//!   specification gives a value for the def, the values must agree up to renaming of dummies;
+//! - axioms and theorems must have the same name, kind, binders, hypotheses and conclusion.
+//!
+//! Notations (and coercions) only exist in the `.mm0` file, where they are used to parse the
+//! statements. They can only refer to terms of the specification, so once the terms are matched
+//! by name there is nothing further to check for them.
+//!
+//! Finally, every definition and proof in the file (including the local ones) is re-checked
+//! using [`Environment::verify_termdef`] and [`Environment::verify_thmdef`], with a [`Bound`]
+//! that rules out references to later declarations.
+//!
+//! Errors are reported at the position of the offending statement in the `.mmu` file.
+
+use std::collections::HashMap;
+use crate::{AtomId, DeclKey, Environment, ExprNode, FrozenEnv, Modifiers, SortId, SortVec,
+  StmtTrace, TermId, TermKind, TermVec, ThmId, ThmKind, Type, Span};
+use crate::elab::{ElabError, Result, verify::Bound};
+
+/// An expression with [`ExprNode::Ref`] resolved.
+#[derive(Clone, Copy, Debug)]
+enum ResolvedExpr<'a> {
+  /// The `n`th variable in the binder list
+  Var(usize),
+  /// A dummy variable in a definition
+  Dummy(AtomId, SortId),
+  /// A term application, with its arguments
+  App(TermId, &'a [ExprNode]),
+}
+
+/// An expression context: the heap and store of a declaration, and its number of binders.
+#[derive(Clone, Copy, Debug)]
+struct ExprCtx<'a> {
+  heap: &'a [ExprNode],
+  store: &'a [ExprNode],
+  nargs: usize,
+}
+
+impl<'a> ExprCtx<'a> {
+  fn resolve(self, terms: &TermVec<crate::Term>, mut e: &'a ExprNode) -> ResolvedExpr<'a> {
+    loop {
+      match *e {
+        ExprNode::Ref(i) if i < self.nargs => return ResolvedExpr::Var(i),
+        ExprNode::Ref(i) => e = &self.heap[i],
+        ExprNode::Dummy(a, s) => return ResolvedExpr::Dummy(a, s),
+        ExprNode::App(t, p) =>
+          return ResolvedExpr::App(t, &self.store[p..p + terms[t].args.len()]),
+      }
+    }
+  }
+}
+
+struct Matcher<'a> {
+  /// The specification.
+  spec: &'a FrozenEnv,
+  /// The environment imported from the proof file.
+  env: &'a Environment,
+  /// The sort in the proof file corresponding to each sort of the specification.
+  sort_map: SortVec<Option<SortId>>,
+  /// The term in the proof file corresponding to each term of the specification.
+  term_map: TermVec<Option<TermId>>,
+  /// The correspondence between dummy variables in the current definition.
+  dummies: HashMap<AtomId, AtomId>,
+}
+
+impl Matcher<'_> {
+  fn sort_eq(&self, s1: SortId, s2: SortId) -> bool { self.sort_map[s1] == Some(s2) }
+
+  fn binders_eq(&self, args1: &[(Option<AtomId>, Type)], args2: &[(Option<AtomId>, Type)]) -> bool {
+    args1.len() == args2.len() && args1.iter().zip(args2).all(|((_, ty1), (_, ty2))| {
+      match (*ty1, *ty2) {
+        (Type::Bound(s1), Type::Bound(s2)) => self.sort_eq(s1, s2),
+        (Type::Reg(s1, deps1), Type::Reg(s2, deps2)) => self.sort_eq(s1, s2) && deps1 == deps2,
+        _ => false,
+      }
+    })
+  }
+
+  /// Check that the spec expression `e1` is equal to the proof file expression `e2`,
+  /// up to a consistent renaming of the dummy variables.
+  fn expr_eq(&mut self,
+    ctx1: ExprCtx<'_>, e1: &ExprNode, ctx2: ExprCtx<'_>, e2: &ExprNode
+  ) -> bool {
+    match (ctx1.resolve(self.spec.terms(), e1), ctx2.resolve(&self.env.terms, e2)) {
+      (ResolvedExpr::Var(i), ResolvedExpr::Var(j)) => i == j,
+      (ResolvedExpr::Dummy(a1, s1), ResolvedExpr::Dummy(a2, s2)) => {
+        if !self.sort_eq(s1, s2) { return false }
+        match self.dummies.get(&a1) {
+          Some(&a) => a == a2,
+          None if self.dummies.values().any(|&a| a == a2) => false,
+          None => { self.dummies.insert(a1, a2); true }
+        }
+      }
+      (ResolvedExpr::App(t1, args1), ResolvedExpr::App(t2, args2)) =>
+        self.term_map[t1] == Some(t2) &&
+        args1.iter().zip(args2).all(|(e1, e2)| self.expr_eq(ctx1, e1, ctx2, e2)),
+      _ => false,
+    }
+  }
+
+  /// Match a non-local statement of the proof file against the next statement of the
+  /// specification (if any). `k` is the statement in the proof file, located at `pos`.
+  fn check_spec(&mut self, spec_stmt: Option<&StmtTrace>, k: &StmtTrace, pos: Span) -> Result<()> {
+    let (spec, env) = (self.spec, self.env);
+    let (sa, spec_key) = match spec_stmt {
+      Some(&StmtTrace::Sort(sa)) => (sa, None),
+      Some(&StmtTrace::Decl(sa)) => (sa, Some(spec.data()[sa].decl().expect("decl"))),
+      _ => return Err(ElabError::new_e(pos,
+        "extra statement, but the specification has no more statements")),
+    };
+    let fsp = match spec_key {
+      None => &spec.sort(spec.data()[sa].sort().expect("sort")).span,
+      Some(DeclKey::Term(t)) => &spec.term(t).span,
+      Some(DeclKey::Thm(t)) => &spec.thm(t).span,
+    };
+    let err = |msg: String| Err(ElabError::with_info(pos, msg.into(),
+      vec![(fsp.clone(), "declared here".into())]));
+    let name = spec.data()[sa].name();
+    let (&StmtTrace::Sort(a) | &StmtTrace::Decl(a)) = k else { unreachable!() };
+    if *env.data[a].name != **name {
+      return err(format!("statement '{}' does not match '{name}' in the specification",
+        env.data[a].name))
+    }
+    match (k, spec_key) {
+      (StmtTrace::Sort(_), None) => {
+        let s1 = spec.data()[sa].sort().expect("sort");
+        let s2 = env.data[a].sort.expect("sort");
+        if spec.sort(s1).mods != env.sorts[s2].mods {
+          return err(format!("sort '{name}': modifiers do not match the specification"))
+        }
+        self.sort_map[s1] = Some(s2);
+      }
+      (StmtTrace::Decl(_), Some(DeclKey::Term(t1))) => {
+        let Some(DeclKey::Term(t2)) = env.data[a].decl else {
+          return err(format!("expected term '{name}', found a theorem"))
+        };
+        let (td1, td2) = (spec.term(t1), &env.terms[t2]);
+        match (&td1.kind, &td2.kind) {
+          (TermKind::Term, TermKind::Term) | (TermKind::Def(_), TermKind::Def(_)) => {}
+          (TermKind::Term, _) => return err(format!("'{name}' should be a term")),
+          (TermKind::Def(_), _) => return err(format!("'{name}' should be a def")),
+        }
+        if !self.binders_eq(&td1.args, &td2.args) {
+          return err(format!("term '{name}': binders do not match the specification"))
+        }
+        if !(self.sort_eq(td1.ret.0, td2.ret.0) && td1.ret.1 == td2.ret.1) {
+          return err(format!("term '{name}': return type does not match the specification"))
+        }
+        self.term_map[t1] = Some(t2);
+        if let (TermKind::Def(Some(e1)), TermKind::Def(e2)) = (&td1.kind, &td2.kind) {
+          let Some(e2) = e2 else { return err(format!("def '{name}' has no value")) };
+          self.dummies.clear();
+          let ctx1 = ExprCtx { heap: &e1.heap, store: &e1.store, nargs: td1.args.len() };
+          let ctx2 = ExprCtx { heap: &e2.heap, store: &e2.store, nargs: td2.args.len() };
+          if !self.expr_eq(ctx1, e1.head(), ctx2, e2.head()) {
+            return err(format!("def '{name}': value does not match the specification"))
+          }
+        }
+      }
+      (StmtTrace::Decl(_), Some(DeclKey::Thm(t1))) => {
+        let Some(DeclKey::Thm(t2)) = env.data[a].decl else {
+          return err(format!("expected theorem '{name}', found a term"))
+        };
+        let (td1, td2) = (spec.thm(t1), &env.thms[t2]);
+        match (&td1.kind, &td2.kind) {
+          (ThmKind::Axiom, ThmKind::Axiom) | (ThmKind::Thm(_), ThmKind::Thm(_)) => {}
+          (ThmKind::Axiom, _) => return err(format!("'{name}' should be an axiom")),
+          (ThmKind::Thm(_), _) => return err(format!("'{name}' should be a theorem")),
+        }
+        if !self.binders_eq(&td1.args, &td2.args) {
+          return err(format!("theorem '{name}': binders do not match the specification"))
+        }
+        if td1.hyps.len() != td2.hyps.len() {
+          return err(format!("theorem '{name}': incorrect number of hypotheses"))
+        }
+        let ctx1 = ExprCtx { heap: &td1.heap, store: &td1.store, nargs: td1.args.len() };
+        let ctx2 = ExprCtx { heap: &td2.heap, store: &td2.store, nargs: td2.args.len() };
+        for (i, ((_, h1), (_, h2))) in td1.hyps.iter().zip(&*td2.hyps).enumerate() {
+          if !self.expr_eq(ctx1, h1, ctx2, h2) {
+            return err(format!("theorem '{name}': hypothesis {} does not match the specification",
+              i + 1))
+          }
+        }
+        if !self.expr_eq(ctx1, &td1.ret, ctx2, &td2.ret) {
+          return err(format!("theorem '{name}': conclusion does not match the specification"))
+        }
+      }
+      (StmtTrace::Sort(_), _) => return err(format!("expected declaration '{name}', found a sort")),
+      _ => return err(format!("expected sort '{name}', found a declaration")),
+    }
+    Ok(())
+  }
+}
+
+/// Check the environment `env`, imported from an `.mmu` file, against the specification
+/// `spec`, an environment obtained by elaborating the `.mm0` file.
+///
+/// Statements in `env` are expected to come from a single file, which is where the errors
+/// are located. The first error found is returned.
+pub fn verify(spec: &FrozenEnv, env: &Environment) -> Result<()> {
+  let mut spec_stmts = spec.stmts().iter()
+    .filter(|s| matches!(s, StmtTrace::Sort(_) | StmtTrace::Decl(_)));
+  let mut m = Matcher {
+    spec, env,
+    sort_map: SortVec(vec![None; spec.sorts().len()]),
+    term_map: TermVec(vec![None; spec.terms().len()]),
+    dummies: HashMap::new(),
+  };
+  let (mut num_sorts, mut num_terms, mut num_thms) = (0, 0, 0);
+  let mut last = Span::default();
+  for k in &env.stmts {
+    let (full, local) = match *k {
+      StmtTrace::Sort(a) => {
+        let sd = &env.sorts[env.data[a].sort.expect("sort")];
+        num_sorts += 1;
+        (sd.full, false)
+      }
+      StmtTrace::Decl(a) => {
+        let bound = Bound::new(SortId(num_sorts), TermId(num_terms), ThmId(num_thms));
+        let (full, local, res) = match env.data[a].decl.expect("decl") {
+          DeclKey::Term(t) => {
+            let td = &env.terms[t];
+            num_terms += 1;
+            (td.full, td.vis == Modifiers::LOCAL,
+              env.verify_termdef(&bound, td))
+          }
+          DeclKey::Thm(t) => {
+            let td = &env.thms[t];
+            num_thms += 1;
+            (td.full, td.vis != Modifiers::PUB
+              && matches!(td.kind, ThmKind::Thm(_)), env.verify_thmdef(&bound, td))
+          }
+        };
+        if let Err(e) = res {
+          return Err(ElabError::new_e(full,
+            format!("at {}: {}", env.data[a].name, e.render_to_string(env))))
+        }
+        (full, local)
+      }
+      StmtTrace::Global(_) | StmtTrace::OutputString(_) => continue,
+    };
+    last = full;
+    if !local { m.check_spec(spec_stmts.next(), k, full)? }
+  }
+  if let Some(&StmtTrace::Sort(a) | &StmtTrace::Decl(a)) = spec_stmts.next() {
+    return Err(ElabError::new_e(last, format!(
+      "'{}' in the specification does not appear in the proof file", spec.data()[a].name())))
+  }
+  Ok(())
+}
@@ -1,7 +1,7 @@
 //! Implements the bridge between mm0-rs and an editor via an lsp [`Connection`]
 
 use std::{fs, io};
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, Condvar, LazyLock};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}, Condvar, LazyLock};
 use std::collections::{VecDeque, HashMap, HashSet, hash_map::{Entry, DefaultHasher}};
 use std::hash::{Hash, Hasher};
 use std::thread::{ThreadId, self};
@@ -11,24 +11,24 @@ use futures::channel::oneshot::{Sender as FSender, channel};
 use futures::lock::Mutex as FMutex;
 use lsp_server::{Connection, ErrorCode, Message, Notification, ProtocolError,
   Request, RequestId, Response, ResponseError};
-use serde::ser::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{from_value, to_value};
 use serde_repr::{Serialize_repr, Deserialize_repr};
-use serde::Deserialize;
 #[allow(clippy::wildcard_imports)] use lsp_types::*;
 use crossbeam::channel::{SendError, RecvError};
 #[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
-use mm1_parser::{Ast, parse};
+use mm1_parser::{Ast, parse, whitespace, ast::{self, DeclKind, Formula, SExpr, SExprKind, Stmt, StmtKind}};
 use crate::{ArcList, ArcString, BoxError, FileRef, FileSpan, Span,
   MutexExt, CondvarExt};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
+use crate::mm::import::elab as mm_elab;
 use crate::compiler::FileContents;
-use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, FrozenEnv,
-  FrozenLispKind, FrozenAtomData};
-use crate::elab::{ElabResult, ElaborateBuilder, GoalListener,
-  local_context::InferSort, proof::Subst,
-  lisp::{print::FormatEnv, pretty::Pretty, Syntax, LispKind, Proc, BuiltinProc},
+use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, LispVal, FrozenEnv,
+  FrozenLispKind, FrozenAtomData, TermKind, ThmKind, ExprNode, ProofNode, Type};
+use crate::elab::{ElabResult, ElaborateBuilder, GoalListener, GoalState, axiom_use::AxiomUse,
+  local_context::InferSort, proof::Subst, refine::InferMode,
+  lisp::{print::FormatEnv, pretty::Pretty, Syntax, LispKind, Proc, BuiltinProc, promise::Spawner},
   spans::Spans};
 
 /// The error type returned by server functions.
@@ -172,7 +172,7 @@ async fn elaborate(path: FileRef, start: Option<Position>,
       }
       &mut Some(FileCache::Ready {hash, ref deps, ref res, ..}) => {
         let hasher = &mut DefaultHasher::new();
-        v.hash(hasher);
+        (v, file.generation).hash(hasher);
         let matches = (|| -> bool {
           for path in deps {
             let Some(file) = vfs.get(path) else { return false };
@@ -185,10 +185,10 @@ async fn elaborate(path: FileRef, start: Option<Position>,
         if matches && !matches!(res, ElabResult::Canceled) {
           return Ok(res.clone())
         }
-        let Some(FileCache::Ready {ast, source, deps, res, ..}) = g.take() else { unreachable!() };
+        let Some(FileCache::Ready {ast, source, deps, res, goals, ..}) = g.take() else { unreachable!() };
         if let ElabResult::Ok(_, errors, env) = res {
           (Some((source.clone(), env.clone())),
-            (start.map(|s| (s, source, ast)), Some((errors, env)), deps), vec![])
+            (start.map(|s| (s, source, ast, goals)), Some((errors, env)), deps), vec![])
         } else {
           (None, (None, None, vec![]), vec![])
         }
@@ -199,23 +199,33 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     res
   };
   let (version, text) = file.text.ulock().clone();
-  let old_ast = old_ast.and_then(|(s, old_text, ast)|
-    if text.ptr_eq(&old_text) {Some((s, ast?))} else {None});
+  let (old_ast, old_goals) = match old_ast {
+    Some((s, old_text, ast, goals)) if text.ptr_eq(&old_text) => (ast.map(|ast| (s, ast)), goals),
+    _ => (None, vec![]),
+  };
   let mut hasher = DefaultHasher::new();
-  version.hash(&mut hasher);
+  (version, file.generation).hash(&mut hasher);
   let source = text.clone();
 
   let mut deps = Vec::new();
+  let goals = Arc::new(Mutex::new(vec![]));
+  let mut kept_goals = vec![];
   let (ast, (cyc, toks, errors, env)) = if path.has_extension("mmb") {
-    let (error, env) = mmb_elab(&path, &text);
+    // Imported proofs are trusted here, to keep the server responsive
+    let (error, env) = mmb_elab(&path, &text, false);
     let errors = if let Err(e) = error { vec![e] } else { vec![] };
     (None, (None, vec![], errors, FrozenEnv::new(env)))
   } else if path.has_extension("mmu") {
     let (error, env) = mmu_elab(&path, &text);
     let errors = if let Err(e) = error { vec![e] } else { vec![] };
     (None, (None, vec![], errors, FrozenEnv::new(env)))
+  } else if path.has_extension("mm") {
+    let (error, env) = mm_elab(&path, &text);
+    let errors = if let Err(e) = error { vec![e] } else { vec![] };
+    (None, (None, vec![], errors, FrozenEnv::new(env)))
   } else {
     let (idx, ast) = parse(text.ascii().clone(), old_ast);
+    kept_goals = old_goals.into_iter().filter(|(sp, _)| sp.end <= idx).collect();
     let ast = Arc::new(ast);
     let rd = rd.push(path.clone());
     let elab = ElaborateBuilder {
@@ -242,16 +252,15 @@ async fn elaborate(path: FileRef, start: Option<Position>,
         }
         Ok(recv)
       },
-      recv_goal: start.filter(|_| SERVER.caps.ulock().goal_view)
-        .and_then(|start| ast.source.to_idx(start))
-        .filter(|&pos| pos != 0)
-        .map(|pos| {
-          GoalListener::new(move |elab: &crate::elab::Elaborator, stat| {
-            if elab.spans.stmt().contains(&pos) {
-              log(format!("\n{stat}"));
-            }
-          })
-        }),
+      recv_goal: Some({
+        let goals = goals.clone();
+        GoalListener::new(move |elab: &crate::elab::Elaborator, _| {
+          goals.ulock().push((elab.spans.stmt(), elab.goal_state()))
+        })
+      }),
+      debugger: None,
+      profile: None,
+      spawner: Some(Spawner::new(|job| SERVER.pool.spawn_ok(async move { job() }))),
     }.elab();
     (Some(ast.clone()), elab.await)
   };
@@ -264,14 +273,15 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     let mut srcs = HashMap::new();
     let mut to_loc = |fsp: &FileSpan| -> Location {
       let fc = if fsp.file.ptr_eq(&path) {
-        &source
+        Some(&source)
       } else {
+        // This can be a file that was never loaded, if the span comes from an .mmb import
         srcs.entry(fsp.file.ptr())
-        .or_insert_with(||
-          vfs.0.ulock().get(&fsp.file).expect("missing file")
-            .text.ulock().1.clone())
+        .or_insert_with(|| vfs.get_or_insert(fsp.file.clone()).ok()
+          .map(|(_, f)| f.text.ulock().1.clone()))
+        .as_ref()
       };
-      if let Some(file) = fc.try_ascii() {
+      if let Some(file) = fc.and_then(FileContents::try_ascii) {
         file.to_loc(fsp)
       } else {
         Location {uri: fsp.file.url().clone(), range: Range::default()}
@@ -325,7 +335,12 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     }
   }
   if !is_canceled {
-    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps});
+    let mut goals = std::mem::take(&mut *goals.ulock());
+    // The statements before the first change need not be elaborated again,
+    // in which case their goals are carried over from the last elaboration
+    kept_goals.retain(|(sp, _)| !goals.iter().any(|(sp2, _)| sp2 == sp));
+    goals.append(&mut kept_goals);
+    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps, goals});
     drop(g);
     let downstream = file.downstream.ulock();
     for d in &*downstream {
@@ -375,6 +390,8 @@ enum FileCache {
     ast: Option<Arc<Ast>>,
     res: ElabResult<u64>,
     deps: Vec<FileRef>,
+    /// The proof state at the first failure in each statement with unsolved goals.
+    goals: Vec<(Span, GoalState)>,
   }
 }
 
@@ -387,6 +404,9 @@ enum FileCache {
 pub struct VirtualFile {
   /// File data, saved (true) or unsaved (false)
   text: Mutex<(Option<i32>, FileContents)>,
+  /// A unique number for this copy of the file, so that a file reloaded from disk
+  /// hashes differently from the copy it replaces even though neither has a version
+  generation: u64,
   /// File parse
   parsed: FMutex<Option<FileCache>>,
   /// Files that depend on this one
@@ -399,8 +419,10 @@ impl std::fmt::Debug for VirtualFile {
 
 impl VirtualFile {
   fn new(version: Option<i32>, text: FileContents) -> VirtualFile {
+    static GENERATION: AtomicU64 = AtomicU64::new(0);
     VirtualFile {
       text: Mutex::new((version, text)),
+      generation: GENERATION.fetch_add(1, Ordering::Relaxed),
       parsed: FMutex::new(None),
       downstream: Mutex::new(HashSet::new())
     }
@@ -428,19 +450,27 @@ impl Vfs {
       Entry::Occupied(e) => Ok((e.key().clone(), e.get().clone())),
       Entry::Vacant(e) => {
         let path = e.key().clone();
-        let fc = if path.has_extension("mmb") {
-          FileContents::new_bin_from_file(path.path())?
-        } else {
-          FileContents::new(fs::read_to_string(path.path())?)
-        };
+        let fc = Self::read(&path)?;
         let val = e.insert(Arc::new(VirtualFile::new(None, fc))).clone();
         Ok((path, val))
       }
     }
   }
 
-  fn source(&self, file: &FileRef) -> Arc<LinedString> {
-    self.0.ulock()[file].text.ulock().1.ascii().clone()
+  /// Read the contents of `path` from disk.
+  fn read(path: &FileRef) -> io::Result<FileContents> {
+    Ok(if path.has_extension("mmb") {
+      FileContents::new_bin_from_file(path.path())?
+    } else {
+      FileContents::new(fs::read_to_string(path.path())?)
+    })
+  }
+
+  /// Get the source text of a file, reading it from disk if it is not open.
+  /// Returns `None` for binary files, or if the file can't be read. (Declarations imported from
+  /// an `.mmb` file point to the file it was compiled from, which may not have been loaded.)
+  fn source(&self, file: &FileRef) -> Option<Arc<LinedString>> {
+    self.get_or_insert(file.clone()).ok()?.1.text.ulock().1.try_ascii().cloned()
   }
 
   /// Open a new file at `path`, with initial `version` and `text` contents.
@@ -507,10 +537,39 @@ impl Vfs {
     Ok(())
   }
 
+  /// Reload the file `path` after it was changed or `deleted` on disk, and re-elaborate the
+  /// files that depend on it. Files that are open in the editor are left alone, because
+  /// the editor contents take precedence over the disk.
+  pub fn changed_on_disk(&self, path: &FileRef, deleted: bool) -> Result<()> {
+    let mut vfs = self.0.ulock();
+    let Entry::Occupied(mut e) = vfs.entry(path.clone()) else { return Ok(()) };
+    if e.get().text.ulock().0.is_some() { return Ok(()) }
+    #[allow(clippy::mutable_key_type)]
+    let deps = e.get().downstream.ulock().clone();
+    match if deleted { None } else { Self::read(path).ok() } {
+      Some(fc) => {
+        let file = VirtualFile::new(None, fc);
+        file.downstream.ulock().clone_from(&deps);
+        e.insert(Arc::new(file));
+        drop(vfs);
+      }
+      None => {
+        e.remove();
+        drop(vfs);
+        send_diagnostics(path.url().clone(), None, vec![])?;
+      }
+    }
+    for dep in deps {
+      Job::DepChange(path.clone(), dep, DepChangeReason::Disk).spawn();
+    }
+    Ok(())
+  }
+
   fn update_downstream(&self, old_deps: &[FileRef], deps: &[FileRef], to: &FileRef) {
     for from in old_deps {
       if !deps.contains(from) {
-        let file = self.0.ulock()[from].clone();
+        // The file may have been removed from the VFS after being deleted on disk
+        let Some(file) = self.get(from) else { continue };
         file.downstream.ulock().remove(to);
       }
     }
@@ -523,12 +582,65 @@ impl Vfs {
   }
 }
 
+/// The custom `$/mm0/goals` request, which returns the proof state of the statement
+/// at a position, if it has unsolved goals.
+enum GoalsRequest {}
+
+impl lsp_types::request::Request for GoalsRequest {
+  type Params = TextDocumentPositionParams;
+  type Result = Option<GoalsResponse>;
+  const METHOD: &'static str = "$/mm0/goals";
+}
+
+/// The response to a `$/mm0/goals` request.
+#[derive(Serialize, Deserialize)]
+struct GoalsResponse {
+  /// The range of the statement containing the goals.
+  range: Range,
+  /// The open goals.
+  goals: Vec<Goal>,
+  /// The unassigned metavariables.
+  mvars: Vec<GoalMVar>,
+}
+
+/// A goal in a [`GoalsResponse`].
+#[derive(Serialize, Deserialize)]
+struct Goal {
+  /// The hypotheses and subproofs in the local context.
+  hyps: Vec<GoalHyp>,
+  /// The statement to be proved.
+  target: String,
+}
+
+/// A hypothesis in a [`Goal`].
+#[derive(Serialize, Deserialize)]
+struct GoalHyp {
+  name: String,
+  #[serde(rename = "type")]
+  ty: String,
+}
+
+/// A metavariable in a [`GoalsResponse`].
+#[derive(Serialize, Deserialize)]
+struct GoalMVar {
+  name: String,
+  /// The sort of the metavariable, if known.
+  sort: Option<String>,
+  /// True if the metavariable must be a bound variable.
+  bound: bool,
+}
+
+macro_rules! request {
+  ("$/mm0/goals") => {GoalsRequest};
+  ($s:tt) => {lsp_types::lsp_request!($s)};
+}
+
 macro_rules! request_type {
   ($self:ident, $($s:tt: $name:ident($pat:pat) => $e:expr,)*) => {
     #[derive(Debug)]
     #[allow(clippy::large_enum_variant)]
     enum RequestType {
-      $($name(<lsp_types::lsp_request!($s) as lsp_types::request::Request>::Params),)*
+      $($name(<request!($s) as lsp_types::request::Request>::Params),)*
     }
 
     fn parse_request(Request {id, method, params}: Request) -> Result<Option<(RequestId, RequestType)>> {
@@ -542,7 +654,7 @@ macro_rules! request_type {
       async fn handle($self, req: RequestType) -> Result<()> {
         match req {
           $(RequestType::$name($pat) => {
-            type T = <lsp_types::lsp_request!($s) as lsp_types::request::Request>::Result;
+            type T = <request!($s) as lsp_types::request::Request>::Result;
             $self.finish::<T>($e)
           }),*
         }
@@ -584,8 +696,9 @@ request_type! { self,
   "textDocument/references": References(p) => {
     let ReferenceParams {text_document_position: doc, context, ..} = p;
     let file: FileRef = doc.text_document.uri.into();
-    references(file.clone(), doc.position, context.include_declaration, false,
-      |range| Location { uri: file.url().clone(), range }).await.map(|p| Some(p.1))
+    references(file, doc.position, context.include_declaration, false, true,
+      |file, range| Location { uri: file.url().clone(), range }).await
+      .map(|p| Some(p.into_iter().flat_map(|p| p.2).collect()))
   },
   "textDocument/rename": Rename(RenameParams {text_document_position: doc, new_name, ..}) => {
     let file: FileRef = doc.text_document.uri.into();
@@ -598,8 +711,9 @@ request_type! { self,
   "textDocument/documentHighlight": DocumentHighlight(p) => {
     let DocumentHighlightParams {text_document_position_params: doc, ..} = p;
     let file: FileRef = doc.text_document.uri.into();
-    references(file.clone(), doc.position, true, false,
-      |range| DocumentHighlight { range, kind: None }).await.map(|p| Some(p.1))
+    references(file, doc.position, true, false, false,
+      |_, range| DocumentHighlight { range, kind: None }).await
+      .map(|p| p.into_iter().next().map(|p| p.2))
   },
   "textDocument/semanticTokens/full": SemanticTokens(p) =>
     semantic_tokens(p.text_document.uri.into(), None).await
@@ -609,13 +723,47 @@ request_type! { self,
     semantic_tokens(doc.uri.into(), Some(range)).await
       .map(|r| r.map(SemanticTokensRangeResult::Tokens))
   },
+  "$/mm0/goals": Goals(p) => {
+    let TextDocumentPositionParams {text_document: doc, position} = p;
+    goals(doc.uri.into(), position).await
+  },
+  "textDocument/codeAction": CodeAction(p) => {
+    let CodeActionParams {text_document: doc, context, ..} = p;
+    code_action(doc.uri.into(), context.diagnostics).await
+  },
+  "textDocument/formatting": Formatting(p) => formatting(&p.text_document.uri.into(), None),
+  "textDocument/rangeFormatting": RangeFormatting(p) => {
+    let DocumentRangeFormattingParams {text_document: doc, range, ..} = p;
+    formatting(&doc.uri.into(), Some(range))
+  },
+  "textDocument/prepareCallHierarchy": CallHierarchyPrepare(p) => {
+    let TextDocumentPositionParams {text_document: doc, position} = p.text_document_position_params;
+    prepare_call_hierarchy(doc.uri.into(), position).await
+  },
+  "callHierarchy/incomingCalls": CallHierarchyIncomingCalls(p) => incoming_calls(p.item).await,
+  "callHierarchy/outgoingCalls": CallHierarchyOutgoingCalls(p) => outgoing_calls(p.item).await,
+  "textDocument/signatureHelp": SignatureHelp(p) => {
+    let TextDocumentPositionParams {text_document: doc, position} = p.text_document_position_params;
+    signature_help(doc.uri.into(), position).await
+  },
+  "textDocument/codeLens": CodeLens(p) => code_lens(p.text_document.uri.into()).await,
+  "workspace/executeCommand": ExecuteCommand(p) => execute_command(&p.command, p.arguments).await,
+  "workspace/symbol": WorkspaceSymbol(p) => Ok(Some(workspace_symbol(&p.query))),
+  "textDocument/foldingRange": FoldingRange(p) => folding_range(&p.text_document.uri.into()),
+  "textDocument/selectionRange": SelectionRange(p) => {
+    let SelectionRangeParams {text_document: doc, positions, ..} = p;
+    selection_range(&doc.uri.into(), positions)
+  },
+  "textDocument/inlayHint": InlayHint(p) => {
+    let InlayHintParams {text_document: doc, range, ..} = p;
+    inlay_hint(doc.uri.into(), range).await
+  },
 }
 
 fn send_message<T: Into<Message>>(t: T) -> Result<()> {
   Ok(SERVER.conn.sender.send(t.into())?)
 }
 
-#[allow(unused)]
 fn show_message(typ: MessageType, message: String) -> Result<()> {
   send_message(Notification {
     method: "window/showMessage".to_owned(),
@@ -729,6 +877,391 @@ fn try_old(file: &Arc<VirtualFile>)  -> Option<(FileContents, FrozenEnv)> {
   }))
 }
 
+async fn goals(path: FileRef, pos: Position) -> Result<Option<GoalsResponse>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "goals nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let Some(idx) = text.to_idx(pos) else { return Ok(None) };
+  elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?
+    .into_response_error()?;
+  let g = file.parsed.lock().await;
+  let Some(FileCache::Ready {goals, ..}) = &*g else { return Ok(None) };
+  let Some((sp, st)) = goals.iter().find(|(sp, _)| sp.contains(&idx)) else { return Ok(None) };
+  let hyps = || st.hyps.iter()
+    .map(|(name, ty)| GoalHyp { name: name.clone(), ty: ty.clone() }).collect();
+  Ok(Some(GoalsResponse {
+    range: text.to_range(*sp),
+    goals: st.goals.iter().map(|g| Goal { hyps: hyps(), target: g.clone() }).collect(),
+    mvars: st.mvars.iter().map(|m| GoalMVar {
+      name: m.name.clone(), sort: m.sort.as_ref().map(ToString::to_string), bound: m.bound
+    }).collect(),
+  }))
+}
+
+fn formatting(path: &FileRef, range: Option<Range>) -> Result<Option<Vec<TextEdit>>, ResponseError> {
+  if !(path.has_extension("mm1") || path.has_extension("mm0")) { return Ok(None) }
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "formatting nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let range = match range {
+    None => None,
+    Some(r) => match (text.to_idx(r.start), text.to_idx(r.end)) {
+      (Some(start), Some(end)) => Some((start..end).into()),
+      _ => return Ok(None),
+    }
+  };
+  let (_, ast) = parse(text.clone(), None);
+  Ok(Some(crate::formatter::format(&ast, range).into_iter()
+    .map(|(sp, new_text)| TextEdit { range: text.to_range(sp), new_text })
+    .collect()))
+}
+
+/// Call `f` on every list in `e` (including `e` itself) with its span.
+fn for_each_list(e: &SExpr, f: &mut impl FnMut(Span)) {
+  match &e.k {
+    SExprKind::List(es) | SExprKind::DottedList(es, _) => {
+      f(e.span);
+      for e in es { for_each_list(e, f) }
+      if let SExprKind::DottedList(_, r) = &e.k { for_each_list(r, f) }
+    }
+    SExprKind::DocComment(_, e) => for_each_list(e, f),
+    _ => {}
+  }
+}
+
+/// Call `f` on the foldable regions of `s`: the statement itself, any doc comment attached to
+/// it, and the s-expressions it contains.
+fn fold_stmt(text: &[u8], s: &Stmt, f: &mut impl FnMut(Span, Option<FoldingRangeKind>)) {
+  match &s.k {
+    StmtKind::DocComment(_, s2) => {
+      let mut end = s2.span.start;
+      while end > s.span.start && whitespace(text[end - 1]) { end -= 1 }
+      f((s.span.start..end).into(), Some(FoldingRangeKind::Comment));
+      fold_stmt(text, s2, f)
+    }
+    StmtKind::Annot(e, s2) => {
+      for_each_list(e, &mut |sp| f(sp, None));
+      fold_stmt(text, s2, f)
+    }
+    StmtKind::Decl(d) => {
+      f(s.span, None);
+      if let Some(e) = &d.val { for_each_list(e, &mut |sp| f(sp, None)) }
+    }
+    StmtKind::Do(es) | StmtKind::Inout {hs: es, ..} => {
+      f(s.span, None);
+      for e in es { for_each_list(e, &mut |sp| f(sp, None)) }
+    }
+    _ => f(s.span, None),
+  }
+}
+
+fn folding_range(path: &FileRef) -> Result<Option<Vec<FoldingRange>>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "folding range nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let (_, ast) = parse(text.clone(), None);
+  let mut res = vec![];
+  for s in &ast.stmts {
+    fold_stmt(text.as_bytes(), s, &mut |sp, kind| {
+      let (start, end) = (text.to_pos(sp.start).line, text.to_pos(sp.end).line);
+      if start < end {
+        res.push(FoldingRange { start_line: start, end_line: end, kind, ..Default::default() })
+      }
+    })
+  }
+  // Regions are produced outermost first, so keep only the outermost region on each line
+  res.sort_by_key(|r| r.start_line);
+  res.dedup_by_key(|r| r.start_line);
+  Ok(Some(res))
+}
+
+/// Call `f` on the spans of `fmla` that contain `idx`: the formula, its trimmed interior,
+/// and the parenthesized subterms enclosing `idx`.
+fn formula_spans(text: &[u8], fmla: &Formula, idx: usize, f: &mut impl FnMut(Span)) {
+  f(fmla.0);
+  let Span {mut start, mut end} = fmla.inner();
+  while start < end && whitespace(text[start]) { start += 1 }
+  while start < end && whitespace(text[end - 1]) { end -= 1 }
+  if start <= idx && idx <= end { f((start..end).into()) }
+  let mut stack = vec![];
+  for (i, &c) in text.iter().enumerate().take(end).skip(start) {
+    match c {
+      b'(' => stack.push(i),
+      b')' => if let Some(j) = stack.pop() {
+        if j <= idx && idx <= i + 1 { f((j..i + 1).into()) }
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Call `f` on the spans of the subexpressions of `e` that contain `idx`.
+fn sexpr_spans(text: &[u8], e: &SExpr, idx: usize, f: &mut impl FnMut(Span)) {
+  if !(e.span.start <= idx && idx <= e.span.end) { return }
+  f(e.span);
+  match &e.k {
+    SExprKind::List(es) => for e in es { sexpr_spans(text, e, idx, f) },
+    SExprKind::DottedList(es, r) => {
+      for e in es { sexpr_spans(text, e, idx, f) }
+      sexpr_spans(text, r, idx, f)
+    }
+    SExprKind::DocComment(_, e) => sexpr_spans(text, e, idx, f),
+    SExprKind::Formula(fmla) => formula_spans(text, fmla, idx, f),
+    _ => {}
+  }
+}
+
+/// Call `f` on the spans of the syntactic elements of `s` that contain `idx`.
+fn stmt_spans(text: &[u8], s: &Stmt, idx: usize, f: &mut impl FnMut(Span)) {
+  if !(s.span.start <= idx && idx <= s.span.end) { return }
+  f(s.span);
+  let ty_spans = |ty: &ast::Type, f: &mut dyn FnMut(Span)| match ty {
+    ast::Type::DepType(d) => f(d.span()),
+    ast::Type::Formula(fmla) => formula_spans(text, fmla, idx, &mut |sp| f(sp)),
+  };
+  let contains = |sp: Span, f: &mut dyn FnMut(Span)| if sp.start <= idx && idx <= sp.end { f(sp) };
+  match &s.k {
+    StmtKind::DocComment(_, s) => stmt_spans(text, s, idx, f),
+    StmtKind::Annot(e, s) => {
+      sexpr_spans(text, e, idx, f);
+      stmt_spans(text, s, idx, f)
+    }
+    StmtKind::Decl(d) => {
+      contains(d.id, f);
+      for bi in &d.bis {
+        if let Some(sp) = bi.local { contains(sp, f) }
+        if bi.span.start <= idx && idx <= bi.span.end {
+          f(bi.span);
+          if let Some(ty) = &bi.ty { ty_spans(ty, f) }
+        }
+      }
+      if let Some(ty) = &d.ty {
+        let sp = ty.span();
+        if sp.start <= idx && idx <= sp.end { ty_spans(ty, f) }
+      }
+      if let Some(e) = &d.val { sexpr_spans(text, e, idx, f) }
+    }
+    StmtKind::Do(es) | StmtKind::Inout {hs: es, ..} =>
+      for e in es { sexpr_spans(text, e, idx, f) },
+    _ => {}
+  }
+}
+
+fn selection_range(path: &FileRef, positions: Vec<Position>) -> Result<Option<Vec<SelectionRange>>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "selection range nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let (_, ast) = parse(text.clone(), None);
+  let bytes = text.as_bytes();
+  Ok(Some(positions.into_iter().map(|pos| {
+    let Some(idx) = text.to_idx(pos) else {
+      return SelectionRange { range: Range { start: pos, end: pos }, parent: None }
+    };
+    let mut spans = vec![];
+    // The token under the cursor
+    let delim = |c: u8| whitespace(c) || b"()[]{}$;:'".contains(&c);
+    let (mut start, mut end) = (idx, idx);
+    while start > 0 && !delim(bytes[start - 1]) { start -= 1 }
+    while end < bytes.len() && !delim(bytes[end]) { end += 1 }
+    if start < end { spans.push(Span::from(start..end)) }
+    let (Ok(i) | Err(i)) = ast.stmts.binary_search_by_key(&idx, |s| s.span.start);
+    for s in &ast.stmts[i.saturating_sub(1)..] {
+      if s.span.start > idx { break }
+      stmt_spans(bytes, s, idx, &mut |sp| spans.push(sp));
+    }
+    // Keep a chain of strictly increasing spans, each containing the last
+    spans.sort_by_key(|sp| (sp.end - sp.start, std::cmp::Reverse(sp.start)));
+    let mut chain: Vec<Span> = vec![];
+    for sp in spans {
+      if chain.last().is_none_or(|last| sp != *last && sp.start <= last.start && last.end <= sp.end) {
+        chain.push(sp)
+      }
+    }
+    let mut res = None;
+    for sp in chain.into_iter().rev() {
+      res = Some(SelectionRange { range: text.to_range(sp), parent: res.map(Box::new) })
+    }
+    res.unwrap_or(SelectionRange { range: Range { start: pos, end: pos }, parent: None })
+  }).collect()))
+}
+
+/// Construct a quick fix for `diag` which applies `edits` to the file.
+fn quick_fix(
+  path: &FileRef, version: Option<i32>, text: &LinedString,
+  title: String, diag: &Diagnostic, edits: Vec<(Span, String)>,
+) -> CodeActionOrCommand {
+  CodeActionOrCommand::CodeAction(CodeAction {
+    title,
+    kind: Some(CodeActionKind::QUICKFIX),
+    diagnostics: Some(vec![diag.clone()]),
+    edit: Some(WorkspaceEdit {
+      changes: None,
+      document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+        text_document: OptionalVersionedTextDocumentIdentifier { uri: path.url().clone(), version },
+        edits: edits.into_iter().map(|(sp, new_text)|
+          OneOf::Left(TextEdit { range: text.to_range(sp), new_text })).collect(),
+      }])),
+      change_annotations: None,
+    }),
+    ..Default::default()
+  })
+}
+
+/// Extend a span to cover one space on either side, if there is one, so that removing it
+/// does not leave a double space.
+fn with_space(text: &LinedString, sp: Span) -> Span {
+  let b = text.as_bytes();
+  if b.get(sp.end) == Some(&b' ') { (sp.start..sp.end + 1).into() }
+  else if sp.start > 0 && b[sp.start - 1] == b' ' { (sp.start - 1..sp.end).into() }
+  else { sp }
+}
+
+/// The edit to remove an unused binder, and the name of the binder. `sp` is the span of the
+/// variable name for an unused variable, or of the binder group for an unused hypothesis.
+/// The whole binder group is removed if this is the only variable in it.
+fn remove_binder(text: &LinedString, ast: &Ast, sp: Span) -> Option<(String, Span)> {
+  let bis = ast.stmts_iter().find_map(|s| match &s.k {
+    StmtKind::Decl(d) if d.bis.iter().any(|bi| bi.local == Some(sp) || bi.span == sp) => Some(&d.bis),
+    _ => None,
+  })?;
+  let bi = if let Some(bi) = bis.iter().find(|bi| bi.local == Some(sp)) { bi } else {
+    // we don't know which hypothesis is unused in `(h1 h2: $ a $)`
+    let mut it = bis.iter().filter(|bi| bi.span == sp);
+    let bi = it.next()?;
+    if it.next().is_some() { return None }
+    bi
+  };
+  let local = bi.local?;
+  let group = bis.iter().filter(|bi2| bi2.span == bi.span).count();
+  let name = String::from_utf8_lossy(&text[local]).into_owned();
+  Some((name, with_space(text, if group == 1 { bi.span } else { local })))
+}
+
+/// The edit to remove the parentheses around the math expression at `sp`.
+fn remove_parens(text: &LinedString, sp: Span) -> Option<String> {
+  let inner = text.get(sp.start..sp.end)?.strip_prefix('(')?.strip_suffix(')')?.trim();
+  let b = text.as_bytes();
+  // Keep the tokens around the parentheses separated
+  let pad = |c: Option<&u8>| if c.is_some_and(|&c| c != b' ' && c != b'\n' && c != b'$') { " " } else { "" };
+  Some(format!("{}{inner}{}", pad(sp.start.checked_sub(1).and_then(|i| b.get(i))), pad(b.get(sp.end))))
+}
+
+/// Find the file which declares `name`, in the environment of an open file other than `path`.
+/// This looks for a lisp definition if `lisp` is true, and a sort, term or theorem otherwise.
+/// Files which depend on `path` are skipped, because importing them would create a cycle.
+async fn find_decl_file(path: &FileRef, name: &[u8], lisp: bool) -> Option<FileRef> {
+  let files: Vec<_> = SERVER.vfs.0.ulock().iter()
+    .filter(|&(p, _)| p != path).map(|(_, f)| f.clone()).collect();
+  #[allow(clippy::mutable_key_type)]
+  let mut downstream = HashSet::new();
+  let mut todo = vec![path.clone()];
+  while let Some(p) = todo.pop() {
+    if let Some(file) = SERVER.vfs.get(&p) {
+      for dep in &*file.downstream.ulock() {
+        if downstream.insert(dep.clone()) { todo.push(dep.clone()) }
+      }
+    }
+  }
+  for file in files {
+    let g = file.parsed.lock().await;
+    let Some(FileCache::Ready {res: ElabResult::Ok(_, _, env), ..}) = &*g else { continue };
+    let Some(a) = env.get_atom(name) else { continue };
+    let ad = &env.data()[a];
+    let fsp = match (lisp, ad.decl(), ad.sort(), ad.lisp()) {
+      (true, _, _, Some(ld)) => match ld.src() { Some((fsp, _)) => fsp, None => continue },
+      (false, Some(DeclKey::Term(t)), _, _) => &env.term(t).span,
+      (false, Some(DeclKey::Thm(t)), _, _) => &env.thm(t).span,
+      (false, None, Some(s), _) => &env.sort(s).span,
+      _ => continue,
+    };
+    if fsp.file != *path && !downstream.contains(&fsp.file) { return Some(fsp.file.clone()) }
+  }
+  None
+}
+
+/// The string to use in an `import` statement in `from` to import the file `to`.
+fn import_path(from: &FileRef, to: &FileRef) -> Option<String> {
+  let mut base = from.path().parent()?;
+  let mut up = 0;
+  loop {
+    if let Ok(rest) = to.path().strip_prefix(base) {
+      return Some(format!("{}{}", "../".repeat(up), rest.to_str()?.replace('\\', "/")))
+    }
+    base = base.parent()?;
+    up += 1;
+  }
+}
+
+/// Quick fixes for the diagnostics reported by the elaborator. The client sends the diagnostics
+/// in the requested range back to us, so we recognize them by their message:
+///
+/// * Unused variables and hypotheses are removed from the binder list.
+/// * Unnecessary parentheses are removed.
+/// * A proof with errors or unsolved goals is replaced by `'?`, which leaves the goal open.
+/// * For an unknown identifier which is declared in another open file, an `import` for the
+///   file declaring it is added.
+async fn code_action(path: FileRef, diags: Vec<Diagnostic>) -> Result<Option<CodeActionResponse>, ResponseError> {
+  if !(path.has_extension("mm1") || path.has_extension("mm0")) { return Ok(None) }
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "code action nonexistent file"))?;
+  let (version, text) = {
+    let g = file.text.ulock();
+    (g.0, g.1.ascii().clone())
+  };
+  let (_, ast) = parse(text.clone(), None);
+  let mut actions = vec![];
+  let (mut proofs, mut imports) = (HashSet::new(), HashSet::new());
+  for diag in &diags {
+    let (Some(start), Some(end)) = (text.to_idx(diag.range.start), text.to_idx(diag.range.end))
+    else { continue };
+    let sp: Span = (start..end).into();
+    let msg = diag.message.as_str();
+    if msg == "Unused variable" || msg == "Unused hypothesis" {
+      if let Some((name, del)) = remove_binder(&text, &ast, sp) {
+        let what = if msg == "Unused variable" { "variable" } else { "hypothesis" };
+        actions.push(quick_fix(&path, version, &text,
+          format!("Remove unused {what} '{name}'"), diag, vec![(del, String::new())]))
+      }
+    } else if msg == "unnecessary parentheses" {
+      if let Some(new_text) = remove_parens(&text, sp) {
+        actions.push(quick_fix(&path, version, &text,
+          "Remove unnecessary parentheses".into(), diag, vec![(sp, new_text)]))
+      }
+    } else if diag.severity == Some(DiagnosticSeverity::ERROR) {
+      let thm = ast.stmts_iter().find_map(|s| match &s.k {
+        StmtKind::Decl(d) if d.k == DeclKind::Thm =>
+          d.val.as_ref().filter(|v| v.span.start <= sp.start && sp.end <= v.span.end).map(|v| (d.id, v.span)),
+        _ => None,
+      });
+      if let Some((id, val)) = thm {
+        if proofs.insert(val) {
+          let name = String::from_utf8_lossy(&text[id]);
+          actions.push(quick_fix(&path, version, &text,
+            format!("Replace the proof of '{name}' with '?"), diag, vec![(val, "'?".into())]))
+        }
+      }
+      let lisp = msg.contains("unbound variable");
+      let unknown = lisp || msg.starts_with("unknown ") || msg.ends_with("not found");
+      let name = &text[sp];
+      if unknown && !name.is_empty() && name.iter().all(|&c| c.is_ascii_alphanumeric() || b"_-!?*<>=+/".contains(&c)) {
+        let Some(dep) = find_decl_file(&path, name, lisp).await else { continue };
+        let Some(rel) = import_path(&path, &dep) else { continue };
+        if !imports.insert(rel.clone()) { continue }
+        let edit = match ast.stmts.iter().rev().find(|s| matches!(s.k, StmtKind::Import(..))) {
+          Some(s) => (Span::from(s.span.end..s.span.end), format!("\nimport \"{rel}\";")),
+          None => {
+            let start = ast.stmts.first().map_or(0, |s| s.span.start);
+            ((start..start).into(), format!("import \"{rel}\";\n"))
+          }
+        };
+        actions.push(quick_fix(&path, version, &text, format!("Add import \"{rel}\""), diag, vec![edit]))
+      }
+    }
+  }
+  Ok(Some(actions))
+}
+
 async fn hover(path: FileRef, pos: Position) -> Result<Option<Hover>, ResponseError> {
   macro_rules! or {($ret:expr, $e:expr)  => {match $e {
     Some(x) => x,
@@ -909,6 +1442,113 @@ async fn hover(path: FileRef, pos: Position) -> Result<Option<Hover>, ResponseEr
   }))
 }
 
+/// Collect the position after each theorem application list in `e`, keyed on the start of its
+/// head, so that the statement can be shown after the closing parenthesis.
+fn app_ends(text: &LinedString, e: &SExpr, out: &mut HashMap<usize, usize>) {
+  match &e.k {
+    SExprKind::List(es) | SExprKind::DottedList(es, _) => {
+      let mut it = es.iter().skip_while(|e| matches!(e.k, SExprKind::Atom(_)) &&
+        matches!(&text[e.span], b"!" | b"!!"));
+      if let Some(SExpr { span, k: SExprKind::Atom(_) }) = it.next() {
+        // A tail `@ f x)` ends with the parent's `)`, so we put the hint before it
+        let tail = text.as_bytes()[e.span.start] == b'@';
+        out.insert(span.start, if tail { e.span.end - 1 } else { e.span.end });
+      }
+      for e in es { app_ends(text, e, out) }
+      if let SExprKind::DottedList(_, e) = &e.k { app_ends(text, e, out) }
+    }
+    SExprKind::DocComment(_, e) => app_ends(text, e, out),
+    _ => {}
+  }
+}
+
+async fn inlay_hint(path: FileRef, range: Range) -> Result<Option<Vec<InlayHint>>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "inlay hint nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let start = text.to_idx(range.start).unwrap_or(0);
+  let end = text.to_idx(range.end).unwrap_or(text.len());
+  let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
+  let Some((_, env)) = env.into_response_error()? else { return Ok(None) };
+  // Safety: As in `hover`, we promise not to Rc::clone the data, but `Subst` does.
+  let env = unsafe { env.thaw() };
+  let fe = FormatEnv { source: &text, env };
+
+  // The elaborated spans don't say whether a binder was written with a type,
+  // or where an application ends, so we get this from the syntax
+  let (_, ast) = parse(text.clone(), None);
+  let (mut untyped, mut binders, mut apps) = (HashSet::new(), HashSet::new(), HashMap::new());
+  for s in ast.stmts_iter() {
+    match &s.k {
+      StmtKind::Decl(d) => {
+        for bi in &d.bis {
+          if let Some(sp) = bi.local {
+            binders.insert(sp);
+            if bi.ty.is_none() { untyped.insert(sp); }
+          }
+        }
+        if let Some(e) = &d.val { app_ends(&text, e, &mut apps) }
+      }
+      StmtKind::Do(es) => for e in es { app_ends(&text, e, &mut apps) }
+      _ => {}
+    }
+  }
+
+  let render = |e: &LispVal| {
+    let mut out = ": ".to_owned();
+    fe.pretty(|p| p.expr(e).render_fmt(usize::MAX, &mut out).expect("impossible"));
+    out
+  };
+  let mut hints = vec![];
+  for spans in &env.spans {
+    let stmt = spans.stmt();
+    if stmt.end < start || end < stmt.start { continue }
+    for &(sp, ref k) in spans {
+      if sp.end < start || end < sp.end { continue }
+      let lc = spans.lc.as_ref();
+      if let Some((pos, label)) = (|| Some(match k {
+        &ObjectKind::Var(true, x) if untyped.contains(&sp) => (sp.end, match lc?.vars.get(&x)? {
+          (_, InferSort::Bound { sort, .. }) => format!(": {}", fe.to(sort)),
+          (_, InferSort::Reg { sort, deps, .. }) => {
+            let mut s = format!(": {}", fe.to(sort));
+            for &a in &**deps {
+              s += " ";
+              s += &String::from_utf8_lossy(&env.data[a].name)
+            }
+            s
+          }
+          _ => return None,
+        }),
+        &ObjectKind::Hyp(true, x) if !binders.contains(&sp) => (sp.end, render(&lc?.get_proof(x)?.1)),
+        ObjectKind::Proof(p) => {
+          let pos = *apps.get(&sp.start)?;
+          let mut u = p.uncons();
+          let a = u.next()?.as_atom()?;
+          let Some(DeclKey::Thm(t)) = env.data[a].decl else { return None };
+          let td = &env.thms[t];
+          let mut args = vec![];
+          for _ in 0..td.args.len() { args.push(u.next()?.copy_expr()?) }
+          (pos, render(&Subst::new(env, &td.heap, &td.store, args).subst(&td.ret)))
+        }
+        _ => return None,
+      }))() {
+        hints.push(InlayHint {
+          position: text.to_pos(pos),
+          label: InlayHintLabel::String(label),
+          kind: Some(InlayHintKind::TYPE),
+          text_edits: None,
+          tooltip: None,
+          padding_left: None,
+          padding_right: None,
+          data: None,
+        })
+      }
+    }
+  }
+  Ok(Some(hints))
+}
+
 async fn definition<T>(path: FileRef, pos: Position,
     f: impl Fn(&LinedString, &LinedString, Span, &FileSpan, Span) -> T + Send) ->
     Result<Vec<T>, ResponseError> {
@@ -932,9 +1572,9 @@ async fn definition<T>(path: FileRef, pos: Position,
   for &(sp, ref k) in spans.find_pos(idx) {
     let g = |fsp: &FileSpan, full|
       if fsp.file.ptr_eq(&path) {
-        f(&text, &text, sp, fsp, full)
+        Some(f(&text, &text, sp, fsp, full))
       } else {
-        f(&text, &vfs.source(&fsp.file), sp, fsp, full)
+        Some(f(&text, &*vfs.source(&fsp.file)?, sp, fsp, full))
       };
     let sort = |s| {
       let sd = env.sort(s);
@@ -949,10 +1589,10 @@ async fn definition<T>(path: FileRef, pos: Position,
       g(&td.span, td.full)
     };
     match k {
-      &ObjectKind::Sort(_, s) => res.push(sort(s)),
+      &ObjectKind::Sort(_, s) => res.extend(sort(s)),
       &ObjectKind::Term(_, t) |
-      &ObjectKind::TermNota(t, _) => res.push(term(t)),
-      &ObjectKind::Thm(_, t) => res.push(thm(t)),
+      &ObjectKind::TermNota(t, _) => res.extend(term(t)),
+      &ObjectKind::Thm(_, t) => res.extend(thm(t)),
       ObjectKind::Var(..) |
       ObjectKind::Hyp(..) |
       ObjectKind::LispVar(..) |
@@ -963,86 +1603,61 @@ async fn definition<T>(path: FileRef, pos: Position,
       ObjectKind::Expr(e) => {
         let head = e.uncons().next().unwrap_or(e);
         if let Some(DeclKey::Term(t)) = head.as_atom().and_then(|a| env.data()[a].decl()) {
-          res.push(term(t))
+          res.extend(term(t))
         }
       },
       ObjectKind::Proof(p) =>
         if let Some(DeclKey::Thm(t)) = p.uncons().next()
             .and_then(|head| head.as_atom()).and_then(|a| env.data()[a].decl()) {
-          res.push(thm(t))
+          res.extend(thm(t))
         },
       &ObjectKind::Global(_, _, a) => {
         let ad = &env.data()[a];
         match ad.decl() {
-          Some(DeclKey::Term(t)) => res.push(term(t)),
-          Some(DeclKey::Thm(t)) => res.push(thm(t)),
+          Some(DeclKey::Term(t)) => res.extend(term(t)),
+          Some(DeclKey::Thm(t)) => res.extend(thm(t)),
           None => {}
         }
-        if let Some(s) = ad.sort() {res.push(sort(s))}
+        if let Some(s) = ad.sort() {res.extend(sort(s))}
         if let Some(&(ref fsp, full)) = ad.lisp().as_ref().and_then(|ld| ld.src().as_ref()) {
-          res.push(g(fsp, full))
+          res.extend(g(fsp, full))
         } else if let Some(sp) = ad.graveyard() {
-          res.push(g(&sp.0, sp.1))
+          res.extend(g(&sp.0, sp.1))
         }
       }
       ObjectKind::Import(file) => {
-        res.push(g(&FileSpan {file: file.clone(), span: 0.into()}, 0.into()))
+        res.extend(g(&FileSpan {file: file.clone(), span: 0.into()}, 0.into()))
       },
     }
   }
   Ok(res)
 }
 
-#[allow(deprecated)] // workaround rust#60681
-async fn document_symbol(path: FileRef) -> Result<Option<DocumentSymbolResponse>, ResponseError> {
-  let file = SERVER.vfs.get(&path).ok_or_else(||
-    response_err(ErrorCode::InvalidRequest, "document symbol nonexistent file"))?;
-
-  let maybe_old = if SERVER.elab_on().unwrap_or_default() == ElabOn::Save { try_old(&file) } else { None };
-  let (text, env) = if let Some((contents, frozen)) = maybe_old {
-    (contents.ascii().clone(), frozen)
-  } else {
-    let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
-      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
-    match env.into_response_error()? {
-      None => return Ok(None),
-      Some((_, env)) => (file.text.ulock().1.ascii().clone(), env)
-    }
-  };
-  // Safety: We don't use the env field on `fe` for anything other than printing
-  let fe = unsafe { env.format_env(&text) };
-  let f = |sp, name: &ArcString, desc, full, kind| DocumentSymbol {
-    name: String::from_utf8_lossy(name).into(),
-    detail: Some(desc),
-    kind,
-    #[allow(deprecated)] deprecated: None,
-    range: text.to_range(full),
-    selection_range: text.to_range(sp),
-    children: None,
-    tags: None,
-  };
-  let mut res = vec![];
-  macro_rules! push {($fsp:expr, $($e:expr),*) => {
-    if $fsp.file == path { res.push(f($fsp.span, $($e),*)) }
-  }}
+/// Call `f` on every sort, term, theorem and lisp global declared in `env` (including imports),
+/// with its name span, name, full span and kind. The description is only computed if `fe`
+/// is provided.
+fn for_each_symbol(
+  env: &FrozenEnv, fe: Option<FormatEnv<'_>>,
+  mut f: impl FnMut(&FileSpan, &ArcString, Option<String>, Span, SymbolKind)
+) {
   for s in env.stmts() {
     match *s {
       StmtTrace::Sort(a) => {
         let ad = &env.data()[a];
         let s = ad.sort().expect("env well formed");
         let sd = env.sort(s);
-        push!(sd.span, ad.name(), format!("{sd}"), sd.full, SymbolKind::CLASS)
+        f(&sd.span, ad.name(), fe.map(|_| format!("{sd}")), sd.full, SymbolKind::CLASS)
       }
       StmtTrace::Decl(a) => {
         let ad = &env.data()[a];
         match ad.decl().expect("env well formed") {
           DeclKey::Term(t) => {
             let td = env.term(t);
-            push!(td.span, ad.name(), format!("{}", fe.to(td)), td.full, SymbolKind::CONSTRUCTOR)
+            f(&td.span, ad.name(), fe.map(|fe| format!("{}", fe.to(td))), td.full, SymbolKind::CONSTRUCTOR)
           }
           DeclKey::Thm(t) => {
             let td = env.thm(t);
-            push!(td.span, ad.name(), format!("{}", fe.to(td)), td.full, SymbolKind::METHOD)
+            f(&td.span, ad.name(), fe.map(|fe| format!("{}", fe.to(td))), td.full, SymbolKind::METHOD)
           }
         }
       }
@@ -1051,36 +1666,95 @@ async fn document_symbol(path: FileRef) -> Result<Option<DocumentSymbolResponse>
         if let Some(ld) = ad.lisp() {
           if let Some((ref fsp, full)) = *ld.src() {
             let e = &**ld;
+            let Some(sk) = (|| Some(match e.unwrap() {
+              FrozenLispKind::Atom(_) |
+              FrozenLispKind::MVar(_, _) |
+              FrozenLispKind::Goal(_) => SymbolKind::CONSTANT,
+              r @ (FrozenLispKind::List(_) | FrozenLispKind::DottedList(_, _)) =>
+                if r.is_list() {SymbolKind::ARRAY} else {SymbolKind::OBJECT},
+              FrozenLispKind::Number(_) => SymbolKind::NUMBER,
+              FrozenLispKind::String(_) => SymbolKind::STRING,
+              FrozenLispKind::Bool(_) => SymbolKind::BOOLEAN,
+              FrozenLispKind::Syntax(_) => SymbolKind::EVENT,
+              FrozenLispKind::Undef => return None,
+              FrozenLispKind::Proc(_) => SymbolKind::FUNCTION,
+              FrozenLispKind::Vector(_) => SymbolKind::ARRAY,
+              FrozenLispKind::AtomMap(_) |
+              FrozenLispKind::HashMap(_) |
+              FrozenLispKind::Annot(_, _) |
+              FrozenLispKind::Ref(_) => SymbolKind::OBJECT,
+            }))() else { continue };
             // Safety: We only use the expression for printing and don't Rc::clone it
-            push!(fsp, ad.name(), format!("{}", fe.to(unsafe { e.thaw() })), full,
-              match (|| Some(match e.unwrap() {
-                FrozenLispKind::Atom(_) |
-                FrozenLispKind::MVar(_, _) |
-                FrozenLispKind::Goal(_) => SymbolKind::CONSTANT,
-                r @ (FrozenLispKind::List(_) | FrozenLispKind::DottedList(_, _)) =>
-                  if r.is_list() {SymbolKind::ARRAY} else {SymbolKind::OBJECT},
-                FrozenLispKind::Number(_) => SymbolKind::NUMBER,
-                FrozenLispKind::String(_) => SymbolKind::STRING,
-                FrozenLispKind::Bool(_) => SymbolKind::BOOLEAN,
-                FrozenLispKind::Syntax(_) => SymbolKind::EVENT,
-                FrozenLispKind::Undef => return None,
-                FrozenLispKind::Proc(_) => SymbolKind::FUNCTION,
-                FrozenLispKind::AtomMap(_) |
-                FrozenLispKind::Annot(_, _) |
-                FrozenLispKind::Ref(_) => SymbolKind::OBJECT,
-              }))() {
-                Some(sk) => sk,
-                None => continue,
-              });
+            f(fsp, ad.name(), fe.map(|fe| format!("{}", fe.to(unsafe { e.thaw() }))), full, sk)
           }
         }
       }
       StmtTrace::OutputString(_) => {}
     }
   }
+}
+
+#[allow(deprecated)] // workaround rust#60681
+async fn document_symbol(path: FileRef) -> Result<Option<DocumentSymbolResponse>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "document symbol nonexistent file"))?;
+
+  let maybe_old = if SERVER.elab_on().unwrap_or_default() == ElabOn::Save { try_old(&file) } else { None };
+  let (text, env) = if let Some((contents, frozen)) = maybe_old {
+    (contents.ascii().clone(), frozen)
+  } else {
+    let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
+    match env.into_response_error()? {
+      None => return Ok(None),
+      Some((_, env)) => (file.text.ulock().1.ascii().clone(), env)
+    }
+  };
+  // Safety: We don't use the env field on `fe` for anything other than printing
+  let fe = unsafe { env.format_env(&text) };
+  let mut res = vec![];
+  for_each_symbol(&env, Some(fe), |fsp, name, desc, full, kind| if fsp.file == path {
+    res.push(DocumentSymbol {
+      name: String::from_utf8_lossy(name).into(),
+      detail: desc,
+      kind,
+      #[allow(deprecated)] deprecated: None,
+      range: text.to_range(full),
+      selection_range: text.to_range(fsp.span),
+      children: None,
+      tags: None,
+    })
+  });
   Ok(Some(DocumentSymbolResponse::Nested(res)))
 }
 
+/// Search the symbols declared in all elaborated files for names containing `query`,
+/// ignoring case.
+#[allow(deprecated)] // workaround rust#60681
+fn workspace_symbol(query: &str) -> WorkspaceSymbolResponse {
+  let query = query.to_lowercase();
+  let files: Vec<_> = SERVER.vfs.0.ulock().iter().map(|(p, f)| (p.clone(), f.clone())).collect();
+  let mut res = vec![];
+  for (path, file) in files {
+    let Some((text, env)) = try_old(&file) else { continue };
+    let Some(text) = text.try_ascii() else { continue };
+    for_each_symbol(&env, None, |fsp, name, _, _, kind| {
+      let name = String::from_utf8_lossy(name);
+      if fsp.file == path && name.to_lowercase().contains(&query) {
+        res.push(SymbolInformation {
+          name: name.into(),
+          kind,
+          tags: None,
+          #[allow(deprecated)] deprecated: None,
+          location: Location { uri: path.url().clone(), range: text.to_range(fsp.span) },
+          container_name: None,
+        })
+      }
+    })
+  }
+  WorkspaceSymbolResponse::Flat(res)
+}
+
 #[derive(Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 enum TraceKind {Sort, Decl, Global}
@@ -1130,6 +1804,8 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
         FrozenLispKind::String(_) |
         FrozenLispKind::Bool(_) |
         FrozenLispKind::AtomMap(_) |
+        FrozenLispKind::HashMap(_) |
+        FrozenLispKind::Vector(_) |
         FrozenLispKind::Annot(_, _) |
         FrozenLispKind::Ref(_) => CompletionItemKind::VALUE,
         FrozenLispKind::Syntax(_) => CompletionItemKind::EVENT,
@@ -1214,97 +1890,176 @@ async fn completion_resolve(ci: CompletionItem) -> Result<CompletionItem, Respon
     .ok_or_else(|| response_err(ErrorCode::ContentModified, "completion missing"))
 }
 
-async fn references<T>(
-  path: FileRef, pos: Position, include_self: bool, for_rename: bool,
-  mut f: impl FnMut(Range) -> T + Send
-) -> Result<(Option<i32>, Vec<T>), ResponseError> {
-  macro_rules! or_none {($e:expr)  => {match $e {
-    Some(x) => x,
-    None => return Ok((None, vec![]))
-  }}}
-  #[derive(Copy, Clone, PartialEq, Eq)]
-  enum Key {
-    LispVar(AtomId),
-    Var(AtomId),
-    Hyp(AtomId),
-    Sort(SortId),
-    Term(TermId),
-    Thm(ThmId),
-    Global(AtomId),
-  }
-
-  let file = SERVER.vfs.get(&path).ok_or_else(||
-    response_err(ErrorCode::InvalidRequest, "references: nonexistent file"))?;
+/// Elaborate `path`, returning the file version and text along with the environment.
+/// Returns `None` for binary files, which have no text to locate the declarations in.
+async fn elaborated_env(
+  path: &FileRef
+) -> Result<Option<(Option<i32>, Arc<LinedString>, FrozenEnv)>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "nonexistent file"))?;
   let (version, text) = {
     let (version, ref text) = *file.text.ulock();
-    (version, text.ascii().clone())
+    let Some(text) = text.try_ascii() else { return Ok(None) };
+    (version, text.clone())
   };
-  let idx = or_none!(text.to_idx(pos));
-  let env = elaborate(path, Some(Position::default()), Default::default(), Default::default())
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
     .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
-  let env = or_none!(env.into_response_error()?).1;
-  let spans = or_none!(env.find(idx));
+  Ok(env.into_response_error()?.map(|(_, env)| (version, text, env)))
+}
 
-  let to_key = |k: &ObjectKind| match *k {
-    ObjectKind::Expr(ref e) => {
-      let a = e.uncons().next().unwrap_or(e).as_atom()?;
-      if let Some(DeclKey::Term(t)) = env.data()[a].decl() {
-        Some(Key::Term(t))
-      } else {
-        Some(Key::Var(a))
+/// The files in `todo` together with all the files that (transitively) import them.
+#[allow(clippy::mutable_key_type)]
+fn with_downstream(mut todo: Vec<FileRef>) -> HashSet<FileRef> {
+  let mut files = HashSet::new();
+  while let Some(p) = todo.pop() {
+    if !files.insert(p.clone()) { continue }
+    if let Some(file) = SERVER.vfs.get(&p) { todo.extend(file.downstream.ulock().iter().cloned()) }
+  }
+  files
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum RefKey {
+  LispVar(AtomId),
+  Var(AtomId),
+  Hyp(AtomId),
+  Sort(SortId),
+  Term(TermId),
+  Thm(ThmId),
+  Global(AtomId),
+}
+
+impl RefKey {
+  fn new(env: &FrozenEnv, k: &ObjectKind, for_rename: bool) -> Option<Self> {
+    match *k {
+      ObjectKind::Expr(ref e) => {
+        let a = e.uncons().next().unwrap_or(e).as_atom()?;
+        if let Some(DeclKey::Term(t)) = env.data()[a].decl() {
+          Some(RefKey::Term(t))
+        } else {
+          Some(RefKey::Var(a))
+        }
+      }
+      ObjectKind::Proof(ref p) => {
+        let a = p.uncons().next().unwrap_or(p).as_atom()?;
+        if let Some(DeclKey::Thm(t)) = env.data()[a].decl() {
+          Some(RefKey::Thm(t))
+        } else {
+          Some(RefKey::Var(a))
+        }
       }
+      ObjectKind::Import(_) |
+      ObjectKind::Syntax(_) |
+      ObjectKind::PatternSyntax(_) |
+      ObjectKind::RefineSyntax(_) |
+      ObjectKind::MathComment => None,
+      ObjectKind::TermNota(..) if for_rename => None,
+      ObjectKind::Var(_, a) => Some(RefKey::Var(a)),
+      ObjectKind::Hyp(_, a) => Some(RefKey::Hyp(a)),
+      ObjectKind::LispVar(_, _, a) => Some(RefKey::LispVar(a)),
+      ObjectKind::Sort(_, a) => Some(RefKey::Sort(a)),
+      ObjectKind::Term(_, a) |
+      ObjectKind::TermNota(a, _) => Some(RefKey::Term(a)),
+      ObjectKind::Thm(_, a) => Some(RefKey::Thm(a)),
+      ObjectKind::Global(_, _, a) => Some(RefKey::Global(a)),
     }
-    ObjectKind::Proof(ref p) => {
-      let a = p.uncons().next().unwrap_or(p).as_atom()?;
-      if let Some(DeclKey::Thm(t)) = env.data()[a].decl() {
-        Some(Key::Thm(t))
-      } else {
-        Some(Key::Var(a))
-      }
-    }
-    ObjectKind::Import(_) |
-    ObjectKind::Syntax(_) |
-    ObjectKind::PatternSyntax(_) |
-    ObjectKind::RefineSyntax(_) |
-    ObjectKind::MathComment => None,
-    ObjectKind::TermNota(..) if for_rename => None,
-    ObjectKind::Var(_, a) => Some(Key::Var(a)),
-    ObjectKind::Hyp(_, a) => Some(Key::Hyp(a)),
-    ObjectKind::LispVar(_, _, a) => Some(Key::LispVar(a)),
-    ObjectKind::Sort(_, a) => Some(Key::Sort(a)),
-    ObjectKind::Term(_, a) |
-    ObjectKind::TermNota(a, _) => Some(Key::Term(a)),
-    ObjectKind::Thm(_, a) => Some(Key::Thm(a)),
-    ObjectKind::Global(_, _, a) => Some(Key::Global(a)),
-  };
+  }
+
+  /// The name and declaration site of a global key, which identify it across environments.
+  fn decl(self, env: &FrozenEnv) -> Option<(&ArcString, &FileSpan)> {
+    match self {
+      RefKey::LispVar(_) | RefKey::Var(_) | RefKey::Hyp(_) => None,
+      RefKey::Sort(s) => { let sd = env.sort(s); Some((env.data()[sd.atom].name(), &sd.span)) }
+      RefKey::Term(t) => { let td = env.term(t); Some((env.data()[td.atom].name(), &td.span)) }
+      RefKey::Thm(t) => { let td = env.thm(t); Some((env.data()[td.atom].name(), &td.span)) }
+      RefKey::Global(a) => {
+        let ad = &env.data()[a];
+        Some((ad.name(), &ad.lisp().as_ref()?.src().as_ref()?.0))
+      }
+    }
+  }
+
+  /// Find the key in another environment with the same declaration as `self` in `env`.
+  fn transfer(self, env: &FrozenEnv, env2: &FrozenEnv) -> Option<Self> {
+    let (name, fsp) = self.decl(env)?;
+    let ad = &env2.data()[env2.get_atom(name)?];
+    let key = match self {
+      RefKey::LispVar(_) | RefKey::Var(_) | RefKey::Hyp(_) => return None,
+      RefKey::Sort(_) => RefKey::Sort(ad.sort()?),
+      RefKey::Term(_) => if let Some(DeclKey::Term(t)) = ad.decl() { RefKey::Term(t) } else { return None },
+      RefKey::Thm(_) => if let Some(DeclKey::Thm(t)) = ad.decl() { RefKey::Thm(t) } else { return None },
+      RefKey::Global(_) => RefKey::Global(env2.get_atom(name)?),
+    };
+    (key.decl(env2)?.1 == fsp).then_some(key)
+  }
+}
+
+/// Find the references to the object at `pos`. If `workspace` is true, references to a global
+/// object are also collected in the file that declares it and all files that depend on that file.
+/// The result has an entry (with the file version) for each file containing a reference,
+/// and `path` always comes first.
+async fn references<T>(
+  path: FileRef, pos: Position, include_self: bool, for_rename: bool, workspace: bool,
+  mut f: impl FnMut(&FileRef, Range) -> T + Send
+) -> Result<Vec<(FileRef, Option<i32>, Vec<T>)>, ResponseError> {
+  macro_rules! or_none {($e:expr)  => {match $e {
+    Some(x) => x,
+    None => return Ok(vec![])
+  }}}
+
+  let (version, text, env) = or_none!(elaborated_env(&path).await?);
+  let idx = or_none!(text.to_idx(pos));
+  let spans = or_none!(env.find(idx));
 
   let mut res = vec![];
+  let mut globals = vec![];
   for &(sp, ref k) in spans.find_pos(idx) {
-    let Some(key) = to_key(k) else { continue };
+    let Some(key) = RefKey::new(&env, k, for_rename) else { continue };
     match key {
-      Key::Global(a) if BuiltinProc::from_bytes(env.data()[a].name()).is_some() => continue,
+      RefKey::Global(a) if BuiltinProc::from_bytes(env.data()[a].name()).is_some() => continue,
       _ => {}
     }
     let mut cont = |&(sp2, ref k2)| {
       let eq = match *k2 {
-        ObjectKind::Expr(_) if !matches!(key, Key::Term(_) | Key::Var(_)) => false,
-        ObjectKind::Proof(_) if !matches!(key, Key::Thm(_) | Key::Hyp(_)) => false,
-        _ => Some(key) == to_key(k2),
+        ObjectKind::Expr(_) if !matches!(key, RefKey::Term(_) | RefKey::Var(_)) => false,
+        ObjectKind::Proof(_) if !matches!(key, RefKey::Thm(_) | RefKey::Hyp(_)) => false,
+        _ => Some(key) == RefKey::new(&env, k2, for_rename),
       };
       if eq && (include_self || sp != sp2) {
         let sp2 = if let ObjectKind::TermNota(_, sp2) = *k2 {sp2} else {sp2};
-        res.push(f(text.to_range(sp2)))
+        res.push(f(&path, text.to_range(sp2)))
       }
     };
-    if let Key::Var(_) | Key::Hyp(_) | Key::LispVar(_) = key {
+    if let RefKey::Var(_) | RefKey::Hyp(_) | RefKey::LispVar(_) = key {
       spans.into_iter().for_each(&mut cont);
     } else {
       for spans2 in env.spans() {
         spans2.into_iter().for_each(&mut cont);
       }
+      if workspace { globals.push(key) }
+    }
+  }
+  let mut out = vec![(path.clone(), version, res)];
+  if globals.is_empty() { return Ok(out) }
+
+  // The declaring files of the global keys, and everything downstream of them
+  let decl_files = globals.iter().filter_map(|key| Some(key.decl(&env)?.1.file.clone())).collect();
+  for path2 in with_downstream(decl_files) {
+    if path2 == path { continue }
+    let Some((version2, text2, env2)) = elaborated_env(&path2).await? else { continue };
+    let keys = globals.iter().filter_map(|key| key.transfer(&env, &env2)).collect::<Vec<_>>();
+    let mut res = vec![];
+    for spans2 in env2.spans() {
+      for &(sp2, ref k2) in spans2 {
+        if RefKey::new(&env2, k2, for_rename).is_some_and(|k| keys.contains(&k)) {
+          let sp2 = if let ObjectKind::TermNota(_, sp2) = *k2 {sp2} else {sp2};
+          res.push(f(&path2, text2.to_range(sp2)))
+        }
+      }
     }
+    if !res.is_empty() { out.push((path2, version2, res)) }
   }
-  Ok((version, res))
+  Ok(out)
 }
 
 async fn prepare_rename(path: FileRef, pos: Position) -> Result<Option<PrepareRenameResponse>, ResponseError> {
@@ -1346,18 +2101,393 @@ async fn prepare_rename(path: FileRef, pos: Position) -> Result<Option<PrepareRe
 async fn rename(
   path: FileRef, pos: Position, new_name: String
 ) -> Result<Option<WorkspaceEdit>, ResponseError> {
-  let (version, edits) = references(path.clone(), pos, true, true,
-    |range| OneOf::Left(TextEdit { range, new_text: new_name.clone() })).await?;
+  let refs = references(path, pos, true, true, true,
+    |_, range| OneOf::Left(TextEdit { range, new_text: new_name.clone() })).await?;
   Ok(Some(WorkspaceEdit {
     changes: None,
-    document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
-      text_document: OptionalVersionedTextDocumentIdentifier { uri: path.url().clone(), version },
-      edits,
-    }])),
+    document_changes: Some(DocumentChanges::Edits(refs.into_iter().map(|(path, version, edits)|
+      TextDocumentEdit {
+        text_document: OptionalVersionedTextDocumentIdentifier { uri: path.url().clone(), version },
+        edits,
+      }).collect())),
     change_annotations: None,
   }))
 }
 
+/// The theorems used in the proof of a theorem, or the terms used in the value of a definition.
+fn callees(env: &FrozenEnv, key: DeclKey) -> Vec<DeclKey> {
+  let mut out = vec![];
+  match key {
+    DeclKey::Thm(t) => if let ThmKind::Thm(Some(pf)) = &env.thm(t).kind {
+      for node in pf.heap.iter().chain(&*pf.store) {
+        if let ProofNode::Thm(t2, _) = *node {
+          if !out.contains(&DeclKey::Thm(t2)) { out.push(DeclKey::Thm(t2)) }
+        }
+      }
+    }
+    DeclKey::Term(t) => if let TermKind::Def(Some(e)) = &env.term(t).kind {
+      for node in e.heap.iter().chain(&*e.store) {
+        if let ExprNode::App(t2, _) = *node {
+          if !out.contains(&DeclKey::Term(t2)) { out.push(DeclKey::Term(t2)) }
+        }
+      }
+    }
+  }
+  out
+}
+
+/// The name and declaration site of a term or theorem.
+fn decl_span(env: &FrozenEnv, key: DeclKey) -> (AtomId, &FileSpan, Span) {
+  match key {
+    DeclKey::Term(t) => { let td = env.term(t); (td.atom, &td.span, td.full) }
+    DeclKey::Thm(t) => { let td = env.thm(t); (td.atom, &td.span, td.full) }
+  }
+}
+
+/// Find the term or theorem named `name` in `env`, if it is declared at `fsp`.
+fn find_decl(env: &FrozenEnv, name: &[u8], fsp: &FileSpan) -> Option<DeclKey> {
+  let key = env.data()[env.get_atom(name)?].decl()?;
+  (decl_span(env, key).1 == fsp).then_some(key)
+}
+
+/// The call hierarchy item for a term or theorem, or `None` if it was declared
+/// in a binary file.
+fn call_item(env: &FrozenEnv, key: DeclKey) -> Option<CallHierarchyItem> {
+  let (a, fsp, full) = decl_span(env, key);
+  let text = SERVER.vfs.get(&fsp.file)?.text.ulock().1.try_ascii()?.clone();
+  Some(CallHierarchyItem {
+    name: String::from_utf8_lossy(env.data()[a].name()).into(),
+    kind: if let DeclKey::Thm(_) = key { SymbolKind::METHOD } else { SymbolKind::CONSTRUCTOR },
+    tags: None,
+    detail: None,
+    uri: fsp.file.url().clone(),
+    range: text.to_range(full),
+    selection_range: text.to_range(fsp.span),
+    data: None,
+  })
+}
+
+/// The places in the declaration of `from` that refer to `to`.
+/// `env` must be the environment of the file that declares `from`, with source `text`.
+fn call_ranges(env: &FrozenEnv, text: &LinedString, from: DeclKey, to: DeclKey) -> Vec<Range> {
+  let Some(spans) = Spans::find(env.spans(), decl_span(env, from).1.span.start) else { return vec![] };
+  let key = match to { DeclKey::Term(t) => RefKey::Term(t), DeclKey::Thm(t) => RefKey::Thm(t) };
+  spans.into_iter().filter(|(_, k)| RefKey::new(env, k, false) == Some(key) &&
+    !matches!(k, ObjectKind::Term(..) | ObjectKind::Thm(..))
+  ).map(|&(sp, ref k)| text.to_range(if let ObjectKind::TermNota(_, sp2) = *k {sp2} else {sp}))
+    .collect()
+}
+
+async fn prepare_call_hierarchy(
+  path: FileRef, pos: Position
+) -> Result<Option<Vec<CallHierarchyItem>>, ResponseError> {
+  let Some((_, text, env)) = elaborated_env(&path).await? else { return Ok(None) };
+  let Some(idx) = text.to_idx(pos) else { return Ok(None) };
+  let Some(spans) = env.find(idx) else { return Ok(None) };
+  for (_, k) in spans.find_pos(idx) {
+    let key = match RefKey::new(&env, k, false) {
+      Some(RefKey::Term(t)) => DeclKey::Term(t),
+      Some(RefKey::Thm(t)) => DeclKey::Thm(t),
+      _ => continue,
+    };
+    return Ok(call_item(&env, key).map(|item| vec![item]))
+  }
+  Ok(None)
+}
+
+/// The declarations which use `item`, in the file that declares it and all files downstream of it.
+async fn incoming_calls(
+  item: CallHierarchyItem
+) -> Result<Option<Vec<CallHierarchyIncomingCall>>, ResponseError> {
+  let path: FileRef = item.uri.into();
+  let Some((_, text, env)) = elaborated_env(&path).await? else { return Ok(None) };
+  let Some(idx) = text.to_idx(item.selection_range.start) else { return Ok(None) };
+  let fsp = FileSpan { file: path.clone(), span: (idx..idx + item.name.len()).into() };
+  if find_decl(&env, item.name.as_bytes(), &fsp).is_none() { return Ok(None) }
+  let mut res = vec![];
+  for path2 in with_downstream(vec![path]) {
+    let Some((_, text2, env2)) = elaborated_env(&path2).await? else { continue };
+    let Some(to) = find_decl(&env2, item.name.as_bytes(), &fsp) else { continue };
+    for s in env2.stmts() {
+      let &StmtTrace::Decl(a) = s else { continue };
+      let from = env2.data()[a].decl().expect("env well formed");
+      if decl_span(&env2, from).1.file == path2 && callees(&env2, from).contains(&to) {
+        let Some(from_item) = call_item(&env2, from) else { continue };
+        res.push(CallHierarchyIncomingCall {
+          from: from_item,
+          from_ranges: call_ranges(&env2, &text2, from, to),
+        })
+      }
+    }
+  }
+  Ok(Some(res))
+}
+
+/// The declarations used by `item`, which may come from imported files.
+async fn outgoing_calls(
+  item: CallHierarchyItem
+) -> Result<Option<Vec<CallHierarchyOutgoingCall>>, ResponseError> {
+  let path: FileRef = item.uri.into();
+  let Some((_, text, env)) = elaborated_env(&path).await? else { return Ok(None) };
+  let Some(idx) = text.to_idx(item.selection_range.start) else { return Ok(None) };
+  let fsp = FileSpan { file: path, span: (idx..idx + item.name.len()).into() };
+  let Some(from) = find_decl(&env, item.name.as_bytes(), &fsp) else { return Ok(None) };
+  Ok(Some(callees(&env, from).into_iter().filter_map(|to| Some(CallHierarchyOutgoingCall {
+    to: call_item(&env, to)?,
+    from_ranges: call_ranges(&env, &text, from, to),
+  })).collect()))
+}
+
+/// The command that lists the axioms used by a theorem, with arguments `[uri, name]`.
+const SHOW_AXIOMS: &str = "mm0-rs.showAxioms";
+
+/// The client command that shows a list of locations, with arguments `[uri, position, locations]`.
+const SHOW_REFERENCES: &str = "editor.action.showReferences";
+
+/// Put "uses N axioms" and "referenced by M" lenses above each theorem in the file.
+async fn code_lens(path: FileRef) -> Result<Option<Vec<CodeLens>>, ResponseError> {
+  let Some((_, text, env)) = elaborated_env(&path).await? else { return Ok(None) };
+
+  // Find the declarations using each theorem, here and in the files downstream
+  let mut uses = HashMap::<_, Vec<serde_json::Value>>::new();
+  for path2 in with_downstream(vec![path.clone()]) {
+    let Some((_, text2, env2)) = elaborated_env(&path2).await? else { continue };
+    for s in env2.stmts() {
+      let &StmtTrace::Decl(a) = s else { continue };
+      let from = env2.data()[a].decl().expect("env well formed");
+      let fsp2 = decl_span(&env2, from).1;
+      if fsp2.file != path2 { continue }
+      let loc = Location { uri: path2.url().clone(), range: text2.to_range(fsp2.span) };
+      let loc = serde_json::to_value(loc).expect("serializable");
+      for to in callees(&env2, from) {
+        let (b, fsp, _) = decl_span(&env2, to);
+        if fsp.file == path { uses.entry(env2.data()[b].name().clone()).or_default().push(loc.clone()) }
+      }
+    }
+  }
+
+  // Safety: `AxiomUse` only reads the proofs, and doesn't Rc::clone anything
+  let thawed = unsafe { env.thaw() };
+  let (_, mut axuse) = AxiomUse::new(thawed);
+  let mut res = vec![];
+  for s in env.stmts() {
+    let &StmtTrace::Decl(a) = s else { continue };
+    let ad = &env.data()[a];
+    let Some(DeclKey::Thm(t)) = ad.decl() else { continue };
+    let td = env.thm(t);
+    if td.span.file != path || matches!(td.kind, ThmKind::Axiom) { continue }
+    let range = text.to_range(td.span.span);
+    let axioms = axuse.get(thawed, t);
+    let n = axioms.iter().filter(|&i| i != 0).count();
+    let mut title = format!("uses {n} axiom{}", if n == 1 {""} else {"s"});
+    if axioms.contains(0) { title += " and sorry" }
+    let name = String::from_utf8_lossy(ad.name()).into_owned();
+    res.push(CodeLens {
+      range,
+      command: Some(Command {
+        title,
+        command: SHOW_AXIOMS.into(),
+        arguments: Some(vec![path.url().as_str().into(), name.into()]),
+      }),
+      data: None,
+    });
+    let locs = uses.remove(ad.name()).unwrap_or_default();
+    res.push(CodeLens {
+      range,
+      command: Some(Command {
+        title: format!("referenced by {}", locs.len()),
+        command: SHOW_REFERENCES.into(),
+        arguments: Some(vec![
+          path.url().as_str().into(),
+          serde_json::to_value(range.start).expect("serializable"),
+          locs.into(),
+        ]),
+      }),
+      data: None,
+    });
+  }
+  Ok(Some(res))
+}
+
+async fn execute_command(
+  command: &str, args: Vec<serde_json::Value>
+) -> Result<Option<serde_json::Value>, ResponseError> {
+  match command {
+    SHOW_AXIOMS => {
+      let (uri, name): (Uri, String) = from_value(args.into()).map_err(|e|
+        response_err(ErrorCode::InvalidParams, format!("bad JSON {e:?}")))?;
+      let Some((_, _, env)) = elaborated_env(&uri.into()).await? else { return Ok(None) };
+      let Some(DeclKey::Thm(t)) = env.get_atom(name.as_bytes()).and_then(|a| env.data()[a].decl()) else {
+        return Err(response_err(ErrorCode::InvalidParams, format!("unknown theorem {name}")))
+      };
+      // Safety: `AxiomUse` only reads the proofs, and doesn't Rc::clone anything
+      let thawed = unsafe { env.thaw() };
+      let (to_tid, mut axuse) = AxiomUse::new(thawed);
+      let axioms = axuse.get(thawed, t).iter().map(|i| if i == 0 { "sorry".into() } else {
+        String::from_utf8_lossy(env.data()[env.thm(to_tid[i]).atom].name()).into_owned()
+      }).collect::<Vec<_>>();
+      let msg = if axioms.is_empty() {
+        format!("{name} uses no axioms")
+      } else {
+        format!("{name} uses: {}", axioms.join(", "))
+      };
+      show_message(MessageType::INFO, msg)
+        .map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
+      Ok(Some(axioms.into()))
+    }
+    _ => Err(response_err(ErrorCode::MethodNotFound, format!("unknown command {command}")))
+  }
+}
+
+/// Find the lists which are open at `idx`, from the outside in, by scanning `text` from `start`.
+/// Each list is given as the spans of its elements before `idx`, and whether they are atoms.
+/// This works on incomplete input, which is the usual situation while typing a proof.
+fn open_lists(text: &[u8], start: usize, idx: usize) -> Vec<Vec<(Span, bool)>> {
+  let is_delim = |c: u8| c.is_ascii_whitespace() || b"()[]{}$\"'`,".contains(&c);
+  // Each entry is the list start, its elements, and whether it is an `@` tail
+  let mut stack = vec![(start, vec![], false)];
+  let mut i = start;
+  while i < idx {
+    match text[i] {
+      b'(' | b'[' | b'{' => { stack.push((i, vec![], false)); i += 1 }
+      b')' | b']' | b'}' => {
+        i += 1;
+        while stack.len() > 1 {
+          let (start, _, tail) = stack.pop().expect("nonempty");
+          stack.last_mut().expect("nonempty").1.push(((start..i).into(), false));
+          if !tail { break }
+        }
+      }
+      b'$' | b'"' => {
+        let close = text[i];
+        let mut j = i + 1;
+        while j < text.len() && text[j] != close { j += if close == b'"' && text[j] == b'\\' {2} else {1} }
+        j = (j + 1).min(text.len());
+        stack.last_mut().expect("nonempty").1.push(((i..j).into(), false));
+        i = j
+      }
+      b'-' if text.get(i + 1) == Some(&b'-') =>
+        i = memchr::memchr(b'\n', &text[i..]).map_or(text.len(), |j| i + j),
+      c if c.is_ascii_whitespace() || b"'`,".contains(&c) => i += 1,
+      _ => {
+        let j = text[i..].iter().position(|&c| is_delim(c)).map_or(text.len(), |j| i + j);
+        if &text[i..j] == b"@" {
+          stack.push((i, vec![], true))
+        } else {
+          stack.last_mut().expect("nonempty").1.push(((i..j).into(), true))
+        }
+        i = j
+      }
+    }
+  }
+  stack.into_iter().skip(1).map(|(_, es, _)| es).collect()
+}
+
+/// Show the signature of the theorem being applied in a `refine` proof at the cursor,
+/// taking into account which arguments are explicit in the `!` and `!!` forms.
+async fn signature_help(path: FileRef, pos: Position) -> Result<Option<SignatureHelp>, ResponseError> {
+  use std::fmt::Write;
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "signature help nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let Some(idx) = text.to_idx(pos) else { return Ok(None) };
+  let env = if let Some((_, env)) = try_old(&file) { env } else {
+    let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{e:?}")))?;
+    let Some((_, env)) = env.into_response_error()? else { return Ok(None) };
+    env
+  };
+  // Start scanning after the last complete statement before the cursor
+  let (_, ast) = parse(text.clone(), None);
+  let start = ast.stmts.iter().map(|s| s.span.end).take_while(|&end| end <= idx).last().unwrap_or(0);
+  let lists = open_lists(text.as_bytes(), start, idx);
+  let Some((im, t, args)) = lists.iter().rev().find_map(|es| {
+    let (im, es) = match es.first() {
+      Some(&(sp, true)) if &text[sp] == b"!" => (InferMode::Explicit, &es[1..]),
+      Some(&(sp, true)) if &text[sp] == b"!!" => (InferMode::BoundOnly, &es[1..]),
+      _ => (InferMode::Regular, &**es),
+    };
+    let (&(head, true), args) = es.split_first()? else { return None };
+    let Some(DeclKey::Thm(t)) = env.data()[env.get_atom(&text[head])?].decl() else { return None };
+    Some((im, t, args))
+  }) else { return Ok(None) };
+
+  // Safety: We don't use the env field on `fe` for anything other than printing
+  let fe = unsafe { env.format_env(&text) };
+  let td = env.thm(t);
+  let mut label = format!("{}", fe.to(&td.atom));
+  let mut parameters = vec![];
+  let mut push = |label: &mut String, s: &str, param: bool| {
+    label.push(' ');
+    let start = label.encode_utf16().count().try_into().expect("overflow");
+    label.push_str(s);
+    if param {
+      let end = label.encode_utf16().count().try_into().expect("overflow");
+      parameters.push(ParameterInformation { label: ParameterLabel::LabelOffsets([start, end]), documentation: None })
+    }
+    parameters.len()
+  };
+  let (mut bvars, mut n) = (vec![], 0);
+  for (a, ty) in &td.args {
+    let (s, bound) = match *ty {
+      Type::Bound(s) => {
+        bvars.push(a.unwrap_or(AtomId::UNDER));
+        (format!("{{{}: {}}}", fe.to(a), fe.to(&s)), true)
+      }
+      Type::Reg(s, ds) => {
+        let mut out = format!("({}: {}", fe.to(a), fe.to(&s));
+        for (i, x) in bvars.iter().enumerate() {
+          if ds & (1 << i) != 0 { write!(out, " {}", fe.to(x)).expect("impossible") }
+        }
+        out.push(')');
+        (out, false)
+      }
+    };
+    n = push(&mut label, &s, match im {
+      InferMode::Regular => false,
+      InferMode::Explicit => true,
+      InferMode::BoundOnly => bound,
+    })
+  }
+  let mut heap = vec![];
+  fe.binders(&td.args, &mut heap, &mut vec![]);
+  for e in &td.heap[heap.len()..] {
+    let e = fe.expr_node(&heap, &mut None, &td.store, e);
+    heap.push(e)
+  }
+  let render = |e: &ExprNode| {
+    let mut out = String::new();
+    let e = fe.expr_node(&heap, &mut None, &td.store, e);
+    fe.pretty(|p| p.expr(&e).render_fmt(usize::MAX, &mut out).expect("impossible"));
+    out
+  };
+  for (h, e) in &td.hyps {
+    n = push(&mut label, &format!("({}: {})", fe.to(h), render(e)), true)
+  }
+  let active = args.iter().take_while(|e| e.0.end < idx).count();
+  let extra = active >= n;
+  if extra { push(&mut label, "...", true); }
+  write!(label, ": {}", render(&td.ret)).expect("impossible");
+  if extra {
+    parameters.last_mut().expect("nonempty").documentation =
+      Some(Documentation::String("extra arguments are passed to `refine-extra-args`".into()));
+  }
+  let active = Some(active.min(parameters.len() - 1).try_into().expect("overflow"));
+  Ok(Some(SignatureHelp {
+    signatures: vec![SignatureInformation {
+      label,
+      documentation: td.doc.as_ref().map(|doc| Documentation::MarkupContent(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: trim_margin(doc),
+      })),
+      parameters: Some(parameters),
+      active_parameter: active,
+    }],
+    active_signature: Some(0),
+    active_parameter: active,
+  }))
+}
+
 macro_rules! token_types {
   ($([$e:literal]: const $name:ident => $val:path;)*) => {
     const _: () = { let mut _n = 0; $(assert!(_n == $e); _n += 1;)* };
@@ -1515,52 +2645,46 @@ impl std::fmt::Debug for Server {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { "Server".fmt(f) }
 }
 
-/// Options for [`Server`]; for example, whether to apply changes and
-/// elaborate on change or save.
-/// The fields are `Option<T>` rather than defaults for each T since it might
-/// be useful to tell whether a certain option has been set by the user or left
-/// as the default. If they were just T, `T::Default` could mean that the user selected
-/// a value that's the same as the default, or it could mean that it was untouched.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct InitOptions {
-  extra_capabilities: Option<ClientCapabilitiesExt>,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ClientCapabilitiesExt {
-  goal_view: Option<bool>,
-}
-
 struct ClientCapabilities {
   reg_id: Option<RequestId>,
   definition_location_links: bool,
-  goal_view: bool,
+  watched_files: bool,
 }
 
 impl ClientCapabilities {
-  fn new(params: InitializeParams) -> ClientCapabilities {
+  fn new(params: &InitializeParams) -> ClientCapabilities {
     let dll = match params.capabilities.text_document.as_ref()
       .and_then(|d| d.definition.as_ref()) {
       Some(&GotoCapability {link_support: Some(b), ..}) => b,
       _ => false
     };
-    let goal_view = params.initialization_options
-      .and_then(|o| from_value(o).ok()).and_then(|o: InitOptions| o.extra_capabilities)
-      .and_then(|c| c.goal_view).unwrap_or(false);
-    ClientCapabilities { reg_id: None, definition_location_links: dll, goal_view }
+    let watched_files = matches!(params.capabilities.workspace.as_ref()
+      .and_then(|w| w.did_change_watched_files.as_ref()),
+      Some(&DidChangeWatchedFilesClientCapabilities {dynamic_registration: Some(true), ..}));
+    ClientCapabilities { reg_id: None, definition_location_links: dll, watched_files }
   }
 
   fn register(&mut self) -> Result<()> {
     assert!(self.reg_id.is_none());
-    let regs = vec![
+    let mut regs = vec![
       Registration {
         id: String::new(),
         method: "workspace/didChangeConfiguration".into(),
         register_options: None,
       }
     ];
+    if self.watched_files {
+      regs.push(Registration {
+        id: "watch".into(),
+        method: "workspace/didChangeWatchedFiles".into(),
+        register_options: Some(to_value(DidChangeWatchedFilesRegistrationOptions {
+          watchers: vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String("**/*.{mm,mm0,mm1,mmb,mmu}".into()),
+            kind: None,
+          }]
+        })?),
+      })
+    }
 
     if !regs.is_empty() {
       register_capability("regs".into(), regs)?;
@@ -1574,7 +2698,7 @@ impl ClientCapabilities {
   }
 }
 
-enum DepChangeReason { Open, Close, Elab }
+enum DepChangeReason { Open, Close, Elab, Disk }
 
 impl std::fmt::Display for DepChangeReason {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1582,6 +2706,7 @@ impl std::fmt::Display for DepChangeReason {
       Self::Open => write!(f, "open"),
       Self::Close => write!(f, "close"),
       Self::Elab => write!(f, "elaboration"),
+      Self::Disk => write!(f, "change on disk"),
     }
   }
 }
@@ -1706,19 +2831,16 @@ impl std::default::Default for ServerOptions {
 
 /// Enum for use in [`ServerOptions`] showing when the user wants changes to be applied
 /// and the new file to be elaborated.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "camelCase")]
 enum ElabOn {
   /// Apply changes and elaborate every time a change is received from the server
+  #[default]
   Change,
   /// Apply changes and elaborate when a save message is received.
   Save,
 }
 
-impl std::default::Default for ElabOn {
-  fn default() -> Self { Self::Change }
-}
-
 fn send_config_request() -> Result<()> {
   use lsp_types::request::{WorkspaceConfiguration, Request};
   let params = lsp_types::ConfigurationParams {
@@ -1752,6 +2874,26 @@ impl Server {
         references_provider: Some(OneOf::Left(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
         document_symbol_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        call_hierarchy_provider: Some(true.into()),
+        signature_help_provider: Some(SignatureHelpOptions {
+          trigger_characters: Some(vec![" ".into(), "(".into()]),
+          ..Default::default()
+        }),
+        code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
+        execute_command_provider: Some(ExecuteCommandOptions {
+          commands: vec![SHOW_AXIOMS.into()],
+          ..Default::default()
+        }),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+          code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+          ..Default::default()
+        })),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
         rename_provider: Some(OneOf::Right(RenameOptions {
           prepare_provider: Some(true),
           work_done_progress_options: Default::default(),
@@ -1768,7 +2910,7 @@ impl Server {
         ..Default::default()
       })?
     )?)?;
-    Ok((conn, ClientCapabilities::new(params)))
+    Ok((conn, ClientCapabilities::new(&params)))
   }
 
   #[cfg(target_arch = "wasm32")]
@@ -1780,7 +2922,6 @@ impl Server {
     let caps = ClientCapabilities {
       reg_id: None,
       definition_location_links: true,
-      goal_view: false,
     };
     Ok((conn, caps))
   }
@@ -1881,6 +3022,14 @@ impl Server {
                 }
               }
               DidChangeConfiguration::METHOD => send_config_request()?,
+              DidChangeWatchedFiles::METHOD => {
+                let DidChangeWatchedFilesParams {changes} = from_value(notif.params)?;
+                for FileEvent {uri, typ} in changes {
+                  let path = uri.into();
+                  log!("change on disk {:?}", path);
+                  vfs.changed_on_disk(&path, typ == FileChangeType::DELETED)?;
+                }
+              }
               _ => {}
             }
           }
@@ -0,0 +1,78 @@
+//! The standalone (command line) MM0 verifier interface.
+//!
+//! `mm0-rs verify spec.mm0 proof.mmb` checks a compiled proof file against its specification,
+//! like the external checker [`mm0-c`] does, but without leaving the `mm0-rs` toolchain.
+//! The `.mm0` file is elaborated as usual (so it may use `import`, unlike `mm0-c`), and
+//! the proof file is then checked by [`mmb::verify`](crate::mmb::verify), which is a
+//! port of the `mm0-c` verifier.
+//!
+//! `mm0-rs verify spec.mm0 proof.mmu` does the same for the textual `.mmu` format, which
+//! was previously only checked by [`mm0-hs`]. The proof file is imported and then checked by
+//! [`mmu::verify`](crate::mmu::verify).
+//!
+//! [`mm0-c`]: https://github.com/digama0/mm0/tree/master/mm0-c
+//! [`mm0-hs`]: https://github.com/digama0/mm0/tree/master/mm0-hs
+use std::{fs, io};
+use annotate_snippets::Renderer;
+use crate::compiler::{self, FileContents};
+use crate::{ElabError, FileRef};
+
+/// Verify MMB or MMU proofs against an MM0 specification
+#[derive(clap::Args, Debug)]
+pub struct Args {
+  /// Hide diagnostic messages
+  #[clap(short, long)]
+  pub quiet: bool,
+  /// Sets the specification file (.mm0)
+  pub spec: String,
+  /// Sets the proof file (.mmb or .mmu)
+  pub proof: String,
+}
+
+impl Args {
+  /// Main entry point for `mm0-rs verify` subcommand.
+  ///
+  /// See the [module documentation](self) for the purpose of this command.
+  ///
+  /// # Arguments
+  ///
+  /// `mm0-rs verify <spec.mm0> <proof.mmb>`, where:
+  ///
+  /// - `spec.mm0` is the specification, an MM0 file
+  /// - `proof.mmb` (or `proof.mmu`) is the proof file to check against the specification.
+  ///   The file extension is used to determine the format.
+  ///
+  /// The process exits with code 1 if the specification has errors or the proof
+  /// file does not verify.
+  pub fn main(self) -> io::Result<()> {
+    compiler::set_quiet(self.quiet);
+    let spec: FileRef = fs::canonicalize(self.spec)?.into();
+    let env = match compiler::elab_for_result(spec)?.1 {
+      Some(env) if !compiler::has_errors() => env,
+      _ => std::process::exit(1),
+    };
+    let path: FileRef = fs::canonicalize(self.proof)?.into();
+    if path.has_extension("mmu") {
+      let proof = match compiler::elab_for_result(path.clone())?.1 {
+        Some(proof) if !compiler::has_errors() => proof,
+        _ => std::process::exit(1),
+      };
+      // Safety: the environment is only read, on this thread
+      if let Err(e) = crate::mmu::verify::verify(&env, unsafe { proof.thaw() }) {
+        compiler::print_error(&path, &e)?;
+        std::process::exit(1)
+      }
+      if !self.quiet { println!("verified {path}") }
+      return Ok(())
+    }
+    let buf = FileContents::new_bin_from_file(path.path())?;
+    let print = |e: &ElabError| e.to_snippet_no_source(&path, e.pos,
+      |s| println!("{}\n", Renderer::styled().render(s)));
+    if let Err(e) = crate::mmb::verify::verify(&env, &buf, &mut |e| print(&e)) {
+      print(&e);
+      std::process::exit(1)
+    }
+    if !self.quiet { println!("verified {path}") }
+    Ok(())
+  }
+}
@@ -0,0 +1,52 @@
+//! Helpers shared by the integration tests, which run the `mm0-rs` binary.
+// Each test file is its own crate, and uses only some of these
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A command running the `mm0-rs` binary under test.
+pub fn mm0_rs() -> Command { Command::new(env!("CARGO_BIN_EXE_mm0-rs")) }
+
+/// The `examples` directory of the repository.
+pub fn examples() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples") }
+
+/// Run a command, returning whether it succeeded and its stdout and stderr.
+pub fn run(cmd: &mut Command) -> (bool, String) {
+  let out = cmd.output().unwrap();
+  let text = String::from_utf8_lossy(&out.stdout).into_owned() + &String::from_utf8_lossy(&out.stderr);
+  (out.status.success(), text)
+}
+
+/// A scratch directory for the files of one test, which is deleted when dropped.
+pub struct Scratch(PathBuf);
+
+impl Scratch {
+  /// Create an empty scratch directory. `name` should be unique among the tests.
+  pub fn new(name: &str) -> Self {
+    let dir = std::env::temp_dir().join(format!("mm0-rs_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    Scratch(dir)
+  }
+
+  /// The path of `file` in the scratch directory.
+  pub fn join(&self, file: &str) -> PathBuf { self.0.join(file) }
+
+  /// Write `contents` to `file` in the scratch directory, returning its path.
+  pub fn write(&self, file: &str, contents: &str) -> PathBuf {
+    let path = self.join(file);
+    std::fs::write(&path, contents).unwrap();
+    path
+  }
+}
+
+impl Drop for Scratch {
+  fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
+
+/// Compile the MM1 source `src`, returning whether it succeeded and the diagnostics.
+pub fn compile_mm1(name: &str, src: &str) -> (bool, String) {
+  let dir = Scratch::new(name);
+  run(mm0_rs().arg("compile").arg(dir.write("test.mm1", src)))
+}
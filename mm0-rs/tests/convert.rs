@@ -0,0 +1,28 @@
+mod common;
+use std::path::Path;
+use common::{examples, mm0_rs, run, Scratch};
+
+/// Run `mm0-rs convert --check-roundtrip`, and check that the output matches the input.
+fn convert(input: &Path, output: &Path) {
+  let (ok, text) = run(mm0_rs().args(["convert", "--check-roundtrip"]).arg(input).arg(output));
+  assert!(ok, "{text}");
+  assert!(text.contains("round trip ok"), "{text}");
+}
+
+#[test]
+fn mmu_mmb_mmu() {
+  let dir = Scratch::new("convert_mmu_mmb_mmu");
+  let (mmb, mmu) = (dir.join("peano.mmb"), dir.join("peano.mmu"));
+  convert(&examples().join("peano.mmu"), &mmb);
+  convert(&mmb, &mmu);
+}
+
+#[test]
+fn mmb_mmu_mmb() {
+  let dir = Scratch::new("convert_mmb_mmu_mmb");
+  let (mmb, mmu, mmb2) = (dir.join("peano.mmb"), dir.join("peano.mmu"), dir.join("peano2.mmb"));
+  let (ok, text) = run(mm0_rs().current_dir(examples()).args(["compile", "peano.mm1"]).arg(&mmb));
+  assert!(ok, "{text}");
+  convert(&mmb, &mmu);
+  convert(&mmu, &mmb2);
+}
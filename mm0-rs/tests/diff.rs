@@ -0,0 +1,40 @@
+mod common;
+use common::{mm0_rs, run, Scratch};
+
+// `id0` is first, because index 0 has a shorter encoding than the other indices
+const HEADER: &str = "provable sort wff;\nterm im: wff > wff > wff;\naxiom id0 (a: wff): $ im a a $;\n";
+const ID1: &str = "axiom id1 (a: wff): $ im a a $;\n";
+const ID2: &str = "axiom id2 (a: wff): $ im a a $;\n";
+
+/// Compile `src` to `name.mmb` in the scratch directory.
+fn compile(dir: &Scratch, name: &str, src: &str) -> std::path::PathBuf {
+  let mmb = dir.join(&format!("{name}.mmb"));
+  let (ok, text) = run(mm0_rs().arg("compile").arg(dir.write(&format!("{name}.mm1"), src)).arg(&mmb));
+  assert!(ok, "{text}");
+  mmb
+}
+
+/// A proof that uses a different theorem is changed, even though it has the same size.
+#[test]
+fn proof_changed_same_size() {
+  let dir = Scratch::new("diff_proof_changed");
+  let old = compile(&dir, "old", &format!("{HEADER}{ID1}{ID2}theorem t (a: wff): $ im a a $ = 'id1;"));
+  let new = compile(&dir, "new", &format!("{HEADER}{ID1}{ID2}theorem t (a: wff): $ im a a $ = 'id2;"));
+  let (ok, text) = run(mm0_rs().arg("diff").arg(&old).arg(&new));
+  assert!(!ok, "{text}");
+  assert!(text.contains("* theorem t: proof 7 -> 7 bytes (+0)"), "{text}");
+  assert!(text.contains("1 proofs changed"), "{text}");
+}
+
+/// Moving a theorem changes its index, but not the proofs that use it.
+#[test]
+fn proof_renumbered_unchanged() {
+  let dir = Scratch::new("diff_proof_renumbered");
+  let old = compile(&dir, "old", &format!("{HEADER}{ID1}{ID2}theorem t (a: wff): $ im a a $ = 'id1;"));
+  let new = compile(&dir, "new", &format!("{HEADER}{ID2}{ID1}theorem t (a: wff): $ im a a $ = 'id1;"));
+  let (ok, text) = run(mm0_rs().arg("diff").arg(&old).arg(&new));
+  assert!(!ok, "{text}");
+  assert!(text.contains("1 moved"), "{text}");
+  assert!(!text.contains("* theorem t"), "{text}");
+  assert!(text.contains("theorems: 0 added, 0 removed, 1 moved, 0 statements changed, 0 proofs changed"), "{text}");
+}
@@ -0,0 +1,33 @@
+mod common;
+use common::{mm0_rs, run, Scratch};
+
+#[test]
+fn format_and_check() {
+  let dir = Scratch::new("fmt_format_and_check");
+  let file = dir.write("test.mm1", "\
+    provable sort   wff;\n\
+    term  im (a b: wff): wff;\n\
+    infixr im: $->$ prec 25;\n\
+    do { (def (foo x)   (if x 1   2)) -- comment\n  (foo 1) };\n");
+  let (ok, text) = run(mm0_rs().args(["fmt", "--check"]).arg(&file));
+  assert!(!ok, "{text}");
+  let (ok, text) = run(mm0_rs().arg("fmt").arg(&file));
+  assert!(ok, "{text}");
+  assert_eq!(std::fs::read_to_string(&file).unwrap(), "\
+    provable sort wff;\n\
+    term im (a b: wff): wff;\n\
+    infixr im: $ -> $ prec 25;\n\
+    do {\n  (def (foo x) (if x 1 2)) -- comment\n  (foo 1)\n};\n");
+  let (ok, text) = run(mm0_rs().args(["fmt", "--check"]).arg(&file));
+  assert!(ok, "{text}");
+}
+
+#[test]
+fn comment_after_at() {
+  let dir = Scratch::new("fmt_comment_after_at");
+  let file = dir.write("test.mm1", "do {\n  (def (foo x) (if x 1 @   -- trailing\n    (bar x)))\n};\n");
+  let (ok, text) = run(mm0_rs().arg("fmt").arg(&file));
+  assert!(ok, "{text}");
+  let text = std::fs::read_to_string(&file).unwrap();
+  assert!(text.contains("(if x 1 @ -- trailing\n"), "{text}");
+}
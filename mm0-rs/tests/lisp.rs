@@ -0,0 +1,31 @@
+mod common;
+use common::compile_mm1;
+
+/// Run the lisp expressions in `body`, each of which should raise an error if its test fails.
+fn check(name: &str, body: &str) {
+  let (ok, text) = compile_mm1(name, &format!("do {{\n{body}\n(print 'lisp-ok)\n}};\n"));
+  assert!(ok, "{text}");
+  assert!(text.contains("lisp-ok"), "{text}");
+}
+
+#[test]
+fn insert_returns_undef() {
+  check("lisp_insert_returns_undef", "
+    (def m (atom-map!))
+    (if (def? (insert m 'a 1)) (error \"insert returned a value\"))
+    (if (not {(lookup m 'a) = 1}) (error \"insert did not modify the map\"))");
+}
+
+#[test]
+fn hash_maps_and_vectors() {
+  check("lisp_hash_maps_and_vectors", "
+    (def h (hash-map! '[(1 2) x]))
+    (insert! h '(3) 'y)
+    (if (not (== (lookup h '(1 2)) 'x)) (error \"hash map lookup failed\"))
+    (if (def? (lookup h '(4))) (error \"hash map lookup of a missing key\"))
+    (def v (vector! 3 1))
+    (vector-push! v 2)
+    (if (not (== (vector->list (sort < v)) '(1 2 3))) (error \"vector sort failed\"))
+    (if (not {(vector-pop! v) = 2}) (error \"vector pop failed\"))
+    (if (not {(vector-len v) = 2}) (error \"vector length is wrong\"))");
+}
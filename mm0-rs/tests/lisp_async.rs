@@ -0,0 +1,46 @@
+mod common;
+use common::{compile_mm1, examples};
+
+#[test]
+fn async_await() {
+  let (ok, text) = compile_mm1("async_await", "do {
+    (def p (async (fn (x) {x + 1}) 41))
+    (if {(await p) = 42} (print 'async-ok) (error \"wrong result\"))
+  };\n");
+  assert!(ok, "{text}");
+  assert!(text.contains("async-ok"), "{text}");
+}
+
+#[test]
+fn async_pending_argument() {
+  let (ok, text) = compile_mm1("async_pending_argument", "do {
+    (def p (async (fn () 42)))
+    (def q (async (fn (x) {(await x) + 1}) p))
+    (if {(await q) == 43} (print 'async-ok) (error \"wrong result\"))
+  };\n");
+  assert!(ok, "{text}");
+  assert!(text.contains("async-ok"), "{text}");
+}
+
+#[test]
+fn async_pending_global() {
+  let (ok, text) = compile_mm1("async_pending_global", "do {
+    (def p (async (fn () 42)))
+    (await (async (fn () (await p))))
+  };\n");
+  assert!(!ok);
+  assert!(text.contains("async result was not awaited before it was copied"), "{text}");
+}
+
+#[test]
+fn async_with_mmc_compiler() {
+  let compiler = examples().join("compiler.mm1").canonicalize().unwrap();
+  let (ok, text) = compile_mm1("async_with_mmc_compiler", &format!("import {compiler:?};
+  do {{
+    (if {{(await (async (fn (x) {{x + 1}}) 41)) = 42}} (print 'async-ok) (error \"wrong result\"))
+    (await (async (fn () ((get! mmc-compiler) 'add))))
+  }};\n"));
+  assert!(!ok);
+  assert!(text.contains("async-ok"), "{text}");
+  assert!(text.contains("this procedure cannot be used in async"), "{text}");
+}
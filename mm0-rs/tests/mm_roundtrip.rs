@@ -0,0 +1,28 @@
+mod common;
+use common::{examples, mm0_rs, run, Scratch};
+
+#[test]
+fn import_unsupported_eq() {
+  let dir = Scratch::new("mm_import_unsupported_eq");
+  dir.write("bad.mm", "\
+    $c ( ) wff |- == $.\n\
+    $v a b $.\n\
+    wa $f wff a $.\n\
+    wb $f wff b $.\n\
+    foo $a == a b $.\n");
+  let (ok, text) = run(mm0_rs().arg("compile").arg(dir.write("import.mm1", "import \"bad.mm\";\n")));
+  assert!(!ok);
+  assert!(text.contains("unsupported axiom of the '==' typecode"), "{text}");
+}
+
+#[test]
+fn export_import_peano() {
+  let dir = Scratch::new("mm_roundtrip_peano");
+  let mm = dir.join("peano.mm");
+  let (ok, text) = run(mm0_rs().current_dir(examples()).args(["compile", "peano.mm1"]).arg(&mm));
+  assert!(ok, "{text}");
+  let (ok, text) = run(mm0_rs().arg("compile").arg(dir.write("import.mm1", "import \"peano.mm\";\n")).arg(dir.join("import.mmb")));
+  assert!(ok, "{text}");
+  assert!(!text.contains("error"), "{text}");
+  assert!(text.contains("2689 ax/thm"), "{text}");
+}
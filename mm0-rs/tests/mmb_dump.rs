@@ -0,0 +1,42 @@
+mod common;
+use common::{examples, mm0_rs, run, Scratch};
+
+#[test]
+fn dump_peano_no_errors() {
+  let dir = Scratch::new("mmb_dump_peano");
+  let mmb = dir.join("peano.mmb");
+  let (ok, text) = run(mm0_rs().current_dir(examples()).args(["compile", "peano.mm1"]).arg(&mmb));
+  assert!(ok, "{text}");
+  let (ok, text) = run(mm0_rs().arg("mmb-dump").arg(&mmb));
+  assert!(ok, "{text}");
+  assert!(text.contains("sort s0 wff"), "{text}");
+  let errors = text.lines().filter(|l| l.trim_start().starts_with("error:")).collect::<Vec<_>>();
+  assert!(errors.is_empty(), "unexpected errors: {errors:#?}");
+}
+
+/// The stack depths agree with the verifier, where a conversion obligation takes two stack
+/// slots: `conv` replaces `e1, |- e2` by `|- e1, e2, CoConv(e1)`.
+#[test]
+fn dump_conv_depth() {
+  let dir = Scratch::new("mmb_dump_conv_depth");
+  let mmb = dir.join("peano.mmb");
+  let (ok, text) = run(mm0_rs().current_dir(examples()).args(["compile", "peano.mm1"]).arg(&mmb));
+  assert!(ok, "{text}");
+  let out = mm0_rs().args(["mmb-dump", "--json"]).arg(&mmb).output().unwrap();
+  assert!(out.status.success());
+  let dump: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+  let mut convs = 0;
+  for stmt in dump["stmts"].as_array().unwrap() {
+    if stmt["kind"] != "theorem" { continue }
+    let cmds = stmt["proof"]["cmds"].as_array().unwrap();
+    let depths = cmds.iter().map(|c| c["depth"].as_u64().unwrap()).collect::<Vec<_>>();
+    for (i, c) in cmds.iter().enumerate().skip(1) {
+      if c["cmd"] == "conv" {
+        assert_eq!(depths[i], depths[i - 1] + 1, "{}", stmt["name"]);
+        convs += 1;
+      }
+    }
+    assert_eq!(depths.last(), Some(&1), "{}", stmt["name"]);
+  }
+  assert!(convs > 0);
+}
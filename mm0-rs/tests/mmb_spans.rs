@@ -0,0 +1,21 @@
+mod common;
+use std::path::Path;
+use common::{examples, mm0_rs, run, Scratch};
+use mm0b_parser::{DebugMmbFile, NumdStmtCmd};
+
+#[test]
+fn span_paths_relative_to_output() {
+  let dir = Scratch::new("mmb_spans");
+  let out = dir.join("out");
+  std::fs::create_dir(&out).unwrap();
+  let mmb = out.join("peano.mmb");
+  let (ok, text) = run(mm0_rs().current_dir(examples()).args(["compile", "peano.mm1"]).arg(&mmb));
+  assert!(ok, "{text}");
+  let buf = std::fs::read(&mmb).unwrap();
+  let file = DebugMmbFile::parse(&buf).unwrap();
+  let (stmt, _) = file.proof().next().unwrap().unwrap();
+  assert!(matches!(stmt, NumdStmtCmd::Sort {..}));
+  let path = file.stmt_span(stmt).unwrap().file().unwrap();
+  assert!(Path::new(path).is_relative(), "{path}");
+  assert_eq!(out.join(path).canonicalize().unwrap(), examples().join("peano.mm1").canonicalize().unwrap());
+}
@@ -1,14 +1,49 @@
-import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine } from 'vscode';
+import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine, OutputChannel, Uri } from 'vscode';
 
 import {
 	LanguageClient,
 	LanguageClientOptions,
+	RequestType,
+	TextDocumentPositionParams,
 	ServerOptions,
 	ErrorAction,
 	CloseAction
 } from 'vscode-languageclient/node';
 
 let client: LanguageClient;
+let goalsChannel: OutputChannel | undefined;
+
+interface Goals {
+	range: { start: { line: number, character: number }, end: { line: number, character: number } };
+	goals: { hyps: { name: string, type: string }[], target: string }[];
+	mvars: { name: string, sort: string | null, bound: boolean }[];
+}
+
+const goalsRequest = new RequestType<TextDocumentPositionParams, Goals | null, void>('$/mm0/goals');
+
+async function showGoals() {
+	const editor = window.activeTextEditor;
+	if (!editor || editor.document.languageId !== 'metamath-zero') { return; }
+	const goals = await client.sendRequest(goalsRequest, {
+		textDocument: { uri: editor.document.uri.toString() },
+		position: editor.selection.active
+	});
+	if (!goalsChannel) { goalsChannel = window.createOutputChannel('MM0 Goals'); }
+	goalsChannel.clear();
+	if (!goals) {
+		goalsChannel.appendLine('no goals');
+	} else {
+		for (const g of goals.goals) {
+			for (const h of g.hyps) { goalsChannel.appendLine(`${h.name}: ${h.type}`); }
+			goalsChannel.appendLine(`|- ${g.target}\n`);
+		}
+		for (const m of goals.mvars) {
+			const sort = m.sort === null ? '' : m.bound ? ` {${m.sort}}` : ` ${m.sort}`;
+			goalsChannel.appendLine(`${m.name}:${sort}`);
+		}
+	}
+	goalsChannel.show(true);
+}
 
 function startClient() {
 	let config = workspace.getConfiguration('metamath-zero');
@@ -25,7 +60,21 @@ function startClient() {
 	let clientOptions: LanguageClientOptions = {
 		// Register the server for MM0 files
 		documentSelector: [{ scheme: 'file', language: 'metamath-zero' }],
-		initializationOptions: { extraCapabilities: { goalView: true } }
+		middleware: {
+			// The server sends the arguments to 'editor.action.showReferences' as JSON,
+			// but VS Code expects a Uri, a Position and Locations
+			async provideCodeLenses(document, token, next) {
+				const lenses = await next(document, token);
+				for (const lens of lenses ?? []) {
+					const cmd = lens.command;
+					if (cmd?.command !== 'editor.action.showReferences' || !cmd.arguments) { continue; }
+					const [uri, pos, locs] = cmd.arguments;
+					const conv = client.protocol2CodeConverter;
+					cmd.arguments = [Uri.parse(uri), conv.asPosition(pos), locs.map((l: any) => conv.asLocation(l))];
+				}
+				return lenses;
+			}
+		}
 	};
 
 	// Create the language client and start the client.
@@ -56,7 +105,8 @@ export function activate(context: ExtensionContext) {
 		commands.registerCommand('metamath-zero.shutdownServer',
 		  () => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath-zero.restartServer',
-			() => client.stop().then(startClient, startClient))
+			() => client.stop().then(startClient, startClient)),
+		commands.registerCommand('metamath-zero.showGoals', showGoals)
 	);
 }
 